@@ -1,24 +1,143 @@
 use anyhow::Result;
 use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEventKind};
 use ratatui::{
-    layout::Rect,
+    layout::{Margin, Position, Rect},
     widgets::{ListState, ScrollbarState},
 };
-use std::path::PathBuf;
-use std::sync::mpsc;
-use std::thread;
-use std::time::SystemTime;
-
-use crate::compare::{DirectoryComparison, FileNode, FileStatus};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::compare::{
+    ownership, permission_bits, DirectoryComparison, FileNode, FileStatus,
+};
+use crate::config::Keymap;
+use crate::copy::{CopyBackend, CopyJob, CopyMessage, CopyProgress, OverwritePolicy};
+use crate::digest::{short_hex, FolderHash};
+use crate::filter::{FilterExpr, PatternSet};
+use crate::history::SavedView;
+use crate::manifest::{file_hash, HashAlgorithm};
+use crate::oplog::SessionLog;
+use crate::ui::ToolbarAction;
+use crate::utils::Worker;
 // use crate::utils::{log_error, log_info};
 
-#[derive(PartialEq)]
+/// Files larger than this are not read for the preview pane.
+const PREVIEW_MAX_BYTES: u64 = 1024 * 1024;
+/// How long the preview's diff may search for the smallest one before
+/// settling for a rougher one
+const PREVIEW_DIFF_DEADLINE: Duration = Duration::from_millis(200);
+/// File previews kept for moving back onto a file, see `preview_cache`
+const PREVIEW_CACHE_ENTRIES: usize = 256;
+/// How long a message flashed on the bottom row stays there
+const FLASH_TIME: Duration = Duration::from_millis(1500);
+/// How often the hash popup hears how far hashing has got
+const HASH_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A flattened row of one panel.
+#[derive(Debug, Clone)]
+pub struct FileItem {
+    /// Indented name with its icon; blank for placeholders and the section
+    /// headers of the grouped layout
+    pub display_name: String,
+    pub status: FileStatus,
+    /// Path relative to the roots
+    pub path: Arc<Path>,
+    pub is_dir: bool,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    /// Permission bits, see [`crate::compare::permission_bits`]
+    pub mode: Option<u32>,
+    /// Owner and group ids, see [`crate::compare::ownership`]
+    pub owner: Option<(u32, u32)>,
+}
+
+/// Whether the row is the blank counterpart of an entry on the other side.
+/// Section headers of the grouped layout are blank too, but aren't.
+fn is_placeholder(
+    items: &[FileItem],
+    group_headers: &[(usize, FileStatus, usize)],
+    row: usize,
+) -> bool {
+    items
+        .get(row)
+        .is_some_and(|item| item.display_name.trim().is_empty())
+        && !group_headers.iter().any(|(header, ..)| *header == row)
+}
+
+/// The row nearest to `row` that isn't a placeholder, looking `forward`
+/// first; `row` itself when there is none.
+fn nearest_entry(
+    items: &[FileItem],
+    group_headers: &[(usize, FileStatus, usize)],
+    row: usize,
+    forward: bool,
+) -> usize {
+    let entry = |row: &usize| !is_placeholder(items, group_headers, *row);
+    let (ahead, behind) = if forward {
+        ((row..items.len()).find(entry), (0..row).rev().find(entry))
+    } else {
+        (
+            (0..=row).rev().find(entry),
+            (row + 1..items.len()).find(entry),
+        )
+    };
+    ahead.or(behind).unwrap_or(row)
+}
+
+/// Size and mtime of a file, to tell whether an external tool saved it;
+/// `None` when it doesn't exist.
+fn file_stamp(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// The status a file preview was built for, with the stamps of both sides.
+type PreviewStamp = (FileStatus, [Option<(u64, Option<SystemTime>)>; 2]);
+
+/// A file's similarity in percent, with the mtimes of both sides it was
+/// computed for.
+type Similarity = (Option<SystemTime>, Option<SystemTime>, Option<u8>);
+
+/// The worker computing similarities, and where its results arrive.
+type SimilarityWorker = (Worker, mpsc::Receiver<(Arc<Path>, Similarity)>);
+
+/// The worker counting the changed lines of a diffstat, and where each
+/// file's count arrives.
+type DiffStatWorker = (Worker, mpsc::Receiver<(PathBuf, Option<(usize, usize)>)>);
+
+/// A file's detected type, with the mtime it was detected for.
+type FileType = (Option<SystemTime>, Option<&'static str>);
+
+#[derive(PartialEq, Clone, Copy)]
 pub enum AppMode {
     DirectoryView,
     #[allow(dead_code)]
     FileView,
     CopyConfirm,
+    /// A confirmed copy is running in the background
+    Copying,
     DeleteConfirm,
+    /// Choosing how to equalize the selected file's timestamps
+    TimestampConfirm,
+    /// Choosing which side's permissions the other side gets
+    PermissionsConfirm,
+    /// Typing the name for a new folder or a renamed entry
+    NameInput,
+    /// Typing a `:` command
+    CommandLine,
+    /// Asking whether to quit while a rescan or copy is running
+    QuitConfirm,
+    /// Showing the hashes of the selected file
+    HashView,
+    /// Showing what was changed in the folders this session
+    SessionLog,
+    /// Showing the lines changed in each file below a folder
+    DiffStat,
+    /// Asking whether to put back the backups a killed copy left
+    RestoreConfirm,
 }
 
 #[derive(PartialEq, Clone, Copy)]
@@ -28,12 +147,170 @@ pub enum FilterMode {
     DifferentNotOrphans,
 }
 
+impl FilterMode {
+    /// The mode the `f` key switches to from this one.
+    pub fn next(self) -> Self {
+        match self {
+            FilterMode::All => FilterMode::Different,
+            FilterMode::Different => FilterMode::DifferentNotOrphans,
+            FilterMode::DifferentNotOrphans => FilterMode::All,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FilterMode::All => "All Files",
+            FilterMode::Different => "Different Only",
+            FilterMode::DifferentNotOrphans => "Diff Only (No Orphans)",
+        }
+    }
+}
+
+/// How the panels list the entries.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub enum ListLayout {
+    /// Folders with their contents
+    #[default]
+    Tree,
+    /// The differing files by relative path
+    Flat,
+    /// The flat list in a collapsible section per status
+    Grouped,
+}
+
+/// How the empty row across from an entry that exists on one side only
+/// is shown.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub enum PlaceholderRows {
+    /// Blank, so the panels line up row by row
+    #[default]
+    Blank,
+    /// The name of the missing entry, dimmed
+    Ghost,
+    /// Left out, so each panel only lists what exists on its side
+    Hidden,
+}
+
+/// Order of the rows in the flat list.
+#[derive(PartialEq, Clone, Copy, Default)]
+pub enum SortOrder {
+    /// Tree order
+    #[default]
+    Path,
+    /// Changed files first, then left-only, then right-only entries
+    Status,
+    /// Largest first, by the bigger of the two sides
+    Size,
+}
+
+/// View state applied once when the TUI starts (from command line flags).
+#[derive(Clone, Default)]
+pub struct ViewOptions {
+    /// `None` keeps the filter last used on this pair (or shows all)
+    pub filter_mode: Option<FilterMode>,
+    pub expand_all: bool,
+    /// Entry to select, relative to the roots or an absolute path inside either root
+    pub select: Option<PathBuf>,
+    /// `None` keeps the setting last used on this pair (or blank rows)
+    pub placeholder_rows: Option<PlaceholderRows>,
+    /// Algorithm of the hashes shown with `H`, from the config file
+    pub hash: HashAlgorithm,
+    /// Refuse everything that changes the folders (`--read-only`)
+    pub read_only: bool,
+    /// Also append the session log to this file (`--session-log`)
+    pub session_log: Option<PathBuf>,
+    /// Restore the display settings last used on this pair, and save them
+    /// again on quit
+    pub remember: bool,
+}
+
 enum RefreshMessage {
     Progress(String, f64),
-    Complete(DirectoryComparison),
+    Complete(Box<DirectoryComparison>),
+    Targets(Vec<DirectoryComparison>),
+    /// Statuses of the files read in full, the entries that couldn't be, and
+    /// the folders they were all in
+    Verified(Vec<(PathBuf, FileStatus)>, Vec<PathBuf>, Vec<PathBuf>),
+    /// What a copy wrote and whether it went to the right, then the path
+    /// compared again with its nodes on both sides, see `rescan_copy`
+    Copied(PathBuf, bool, Result<CopyRescan, String>),
     Error(String),
 }
 
+type CopyRescan = Box<(PathBuf, FileNode, FileNode)>;
+
+/// How far along a comparison is, from the status lines it reports.
+pub fn progress_percentage(msg: &str) -> f64 {
+    if msg.contains("Starting") {
+        0.0
+    } else if msg.contains("Scanning left") {
+        0.05
+    } else if msg.contains("Scanning right") {
+        0.15
+    } else if msg.contains("Scanning...") {
+        if let Some(files_pos) = msg.find(" files") {
+            let search_str = &msg[..files_pos];
+            if let Some(space_pos) = search_str.rfind(' ') {
+                if let Ok(count) = search_str[space_pos + 1..].parse::<f64>() {
+                    (count / 1000.0).min(0.2) + 0.05
+                } else {
+                    0.1
+                }
+            } else {
+                0.1
+            }
+        } else {
+            0.1
+        }
+    } else if msg.contains("Files to compare:") {
+        0.25
+    } else if msg.contains("Progress:") {
+        if let Some(slash_pos) = msg.find('/') {
+            let current_str = msg[9..slash_pos].trim();
+            let total_str = msg[slash_pos + 1..].trim();
+
+            if let (Ok(current), Ok(total)) = (current_str.parse::<f64>(), total_str.parse::<f64>())
+            {
+                0.25 + (current / total) * 0.75
+            } else {
+                0.5
+            }
+        } else {
+            0.5
+        }
+    } else if msg.contains("Comparing...") {
+        if let Some(slash_pos) = msg.find('/') {
+            let search_str = &msg[..slash_pos];
+            if let Some(space_pos) = search_str.rfind(' ') {
+                if let (Ok(current), Ok(total)) = (
+                    search_str[space_pos + 1..].parse::<f64>(),
+                    msg[slash_pos + 1..].parse::<f64>(),
+                ) {
+                    0.25 + (current / total) * 0.7
+                } else {
+                    0.5
+                }
+            } else {
+                0.5
+            }
+        } else if let Some(count) = msg
+            .split_whitespace()
+            .nth(1)
+            .and_then(|count| count.parse::<f64>().ok())
+        {
+            // Scanned while compared, so there's no total: creep
+            // towards the end without reaching it
+            0.05 + 0.9 * count / (count + 10_000.0)
+        } else {
+            0.5
+        }
+    } else if msg.contains("Complete") {
+        1.0
+    } else {
+        0.5
+    }
+}
+
 #[derive(Clone)]
 pub struct CopyInfo {
     pub source_path: PathBuf,
@@ -41,7 +318,137 @@ pub struct CopyInfo {
     pub file_count: usize,
     pub folder_count: usize,
     pub total_bytes: u64,
+    /// Bytes the copy would actually write with the chosen options, see
+    /// `crate::copy::transfer_bytes`; `None` while they're being counted
+    pub transfer_bytes: Option<u64>,
     pub from_left_to_right: bool,
+    /// Files at the target that differ from the source and would be replaced
+    pub overwrite_count: usize,
+    pub overwrite_policy: OverwritePolicy,
+    /// Differing descendants of a folder, relative to it, when the folder
+    /// also holds unchanged entries
+    pub differences: Option<Vec<PathBuf>>,
+    /// Copy only `differences` instead of the whole folder
+    pub only_differences: bool,
+}
+
+/// Which way to equalize modification times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSync {
+    /// Give the right file the left file's time
+    LeftToRight,
+    /// Give the left file the right file's time
+    RightToLeft,
+    /// Give the older file the newer file's time
+    BothToNewer,
+}
+
+impl TimestampSync {
+    pub const ALL: [TimestampSync; 3] = [
+        TimestampSync::LeftToRight,
+        TimestampSync::RightToLeft,
+        TimestampSync::BothToNewer,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TimestampSync::LeftToRight => "Left time to right",
+            TimestampSync::RightToLeft => "Right time to left",
+            TimestampSync::BothToNewer => "Both to the newer time",
+        }
+    }
+
+    /// The next choice, wrapping around; `forward = false` goes back.
+    pub fn cycle(self, forward: bool) -> Self {
+        let index = Self::ALL.iter().position(|s| *s == self).unwrap_or(0);
+        let len = Self::ALL.len();
+        let next = if forward { index + 1 } else { index + len - 1 };
+        Self::ALL[next % len]
+    }
+}
+
+#[derive(Clone)]
+pub struct TimestampInfo {
+    /// Relative path of the file present on both sides
+    pub path: Arc<Path>,
+    pub left_modified: Option<SystemTime>,
+    pub right_modified: Option<SystemTime>,
+    pub choice: TimestampSync,
+}
+
+/// One side's hash in a `HashInfo`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum HashSide {
+    /// The file isn't on this side
+    Missing,
+    /// Being worked out: the bytes read so far and the file's size
+    Hashing(u64, u64),
+    /// The hash, or the error when the file couldn't be read
+    Done(Result<String, String>),
+}
+
+/// The hashes of the selected file on each side, filled in by a worker.
+#[derive(Clone)]
+pub struct HashInfo {
+    pub path: Arc<Path>,
+    pub algorithm: HashAlgorithm,
+    pub left: HashSide,
+    pub right: HashSide,
+    /// The detected type of the file on each side, left first
+    pub types: [Option<&'static str>; 2],
+    /// What the last copy key did
+    pub copied: Option<String>,
+}
+
+/// Lines changed per file below a folder pair, see `App::show_diffstat`.
+pub struct DiffStat {
+    /// Relative to the roots, empty for the roots themselves
+    pub folder: PathBuf,
+    /// Differing files with the lines removed and added, `None` for binary
+    /// and very large ones, as they're counted
+    pub files: Vec<(PathBuf, Option<(usize, usize)>)>,
+    /// Differing files in all, more than `files` while they're counted
+    pub total: usize,
+    /// Lines scrolled down from the top
+    pub scroll: usize,
+}
+
+#[derive(Clone)]
+pub struct PermissionsInfo {
+    /// Relative path of the entry present on both sides
+    pub path: Arc<Path>,
+    pub is_dir: bool,
+    pub left_mode: Option<u32>,
+    pub right_mode: Option<u32>,
+    /// Copy the left permissions to the right, or the other way round
+    pub to_right: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameAction {
+    CreateFolder,
+    Rename,
+}
+
+/// The `:` prompt at the bottom of the screen.
+#[derive(Clone, Default)]
+pub struct CommandLine {
+    pub input: String,
+    /// Why the last command failed, shown until the input changes
+    pub error: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct NameInputInfo {
+    pub action: NameAction,
+    pub is_left: bool,
+    /// Folder the name goes into, relative to the active side's root
+    pub parent: PathBuf,
+    /// Current name of the entry being renamed, as it is on disk
+    pub original: Option<OsString>,
+    pub input: String,
+    /// Why the last attempt failed, shown until the input changes
+    pub error: Option<String>,
 }
 
 #[derive(Clone)]
@@ -56,49 +463,148 @@ pub struct DeleteInfo {
 pub struct App {
     pub comparison: DirectoryComparison,
     pub mode: AppMode,
+    /// Where saying no to the quit question goes back to
+    pub mode_before_quit: AppMode,
     pub active_panel: usize,
     pub left_list_state: ListState,
     pub right_list_state: ListState,
-    pub left_items: Vec<(
-        String,
-        FileStatus,
-        PathBuf,
-        bool,
-        Option<u64>,
-        Option<SystemTime>,
-    )>,
-    pub right_items: Vec<(
-        String,
-        FileStatus,
-        PathBuf,
-        bool,
-        Option<u64>,
-        Option<SystemTime>,
-    )>,
+    pub left_items: Vec<FileItem>,
+    pub right_items: Vec<FileItem>,
     pub file_diff: String,
     pub filter_mode: FilterMode,
     pub is_refreshing: bool,
     pub refresh_progress: String,
     pub refresh_percentage: f64,
     refresh_rx: Option<mpsc::Receiver<RefreshMessage>>,
+    refresh_worker: Option<Worker>,
     pub left_scrollbar_state: ScrollbarState,
     pub right_scrollbar_state: ScrollbarState,
     pub viewport_height: u16,
-    pub toolbar_area: Rect,
+    /// Toolbar buttons and where they were last drawn, for mouse clicks
+    pub toolbar_buttons: Vec<(ToolbarAction, Rect)>,
+    /// Where the left and right panels were last drawn, borders included
+    pub panel_areas: [Rect; 2],
+    /// Last position of the mouse pointer, for shading the row under it
+    pub mouse_position: Option<Position>,
     pub copy_info: Option<CopyInfo>,
+    copy_rx: Option<mpsc::Receiver<CopyMessage>>,
+    copy_worker: Option<Worker>,
+    /// Latest progress of the running copy
+    pub copy_progress: Option<CopyProgress>,
+    /// Why the last copy failed, shown until dismissed
+    pub copy_error: Option<String>,
+    /// Backups of replaced files that killed copies left where the running
+    /// one wrote, offered for restoring once it ends
+    pub stale_backups: Vec<PathBuf>,
     pub delete_info: Option<DeleteInfo>,
+    pub timestamp_info: Option<TimestampInfo>,
+    pub hash_info: Option<HashInfo>,
+    pub hash_algorithm: HashAlgorithm,
+    /// Copying, deleting, renaming and the like are off, for auditing
+    pub read_only: bool,
+    /// Every change made to the folders, shown with `J`
+    pub session_log: SessionLog,
+    /// Lines the session log popup is scrolled up from its end
+    pub session_log_scroll: usize,
+    pub diffstat: Option<DiffStat>,
+    pub permissions_info: Option<PermissionsInfo>,
+    pub name_input: Option<NameInputInfo>,
+    pub command_line: Option<CommandLine>,
+    /// Short message on the bottom row, like the filter mode after `f`
+    pub flash: Option<(String, Instant)>,
+    /// Only show entries matching this expression (and the folders leading
+    /// to them), set with `:filter`
+    pub name_filter: Option<FilterExpr>,
+    /// Leave out `.DS_Store`, `Thumbs.db`, swap and backup files, see
+    /// `PatternSet::noise`, toggled with `i`
+    pub hide_noise: bool,
+    noise: PatternSet,
+    /// Folder both panels are re-rooted on, relative to the roots
+    pub zoom: Option<PathBuf>,
     saved_left_selection: Option<usize>,
     saved_right_selection: Option<usize>,
     saved_active_panel: usize,
     saved_expansion_state: Option<(FileNode, FileNode)>,
     saved_filter_mode: Option<FilterMode>,
+    /// Comparisons of the source against the inactive targets when more than
+    /// two directories were given; `comparison` always holds the active one.
+    target_comparisons: Vec<DirectoryComparison>,
+    pub active_target: usize,
+    target_status_maps: Vec<HashMap<PathBuf, FileStatus>>,
+    /// Per left row, the status of that path in every target (N-way mode only)
+    pub target_markers: Vec<Vec<Option<FileStatus>>>,
+    /// Show the diff/preview of the selected entry below the panels
+    pub show_preview: bool,
+    /// Stack the panels top and bottom instead of side by side
+    pub vertical_layout: bool,
+    /// Scroll the selection to the middle of the panels on the next draw,
+    /// after a jump that could have left it on the edge
+    pub recenter: bool,
+    /// Moving the selection in one panel moves the other one along; off to
+    /// scroll through two differently structured subtrees independently
+    pub panels_locked: bool,
+    /// Show exact byte counts instead of human-readable sizes
+    pub raw_sizes: bool,
+    /// Show the permission bits of each entry
+    pub show_permissions: bool,
+    /// Show the owner and group of each entry
+    pub show_owner: bool,
+    /// Show how similar the two sides of each changed text file are
+    pub show_similarity: bool,
+    /// Similarity of changed text files, filled in as rows come into view
+    similarities: HashMap<Arc<Path>, Similarity>,
+    /// Works out the similarities `update_similarities` asked for, off the
+    /// UI thread
+    similarity_worker: Option<SimilarityWorker>,
+    /// Hashes the file of the hash popup, see `show_hashes`
+    hash_worker: Option<(Worker, mpsc::Receiver<(bool, HashSide)>)>,
+    diffstat_worker: Option<DiffStatWorker>,
+    /// A file manager ran in the foreground, see `take_screen_replaced`
+    screen_replaced: bool,
+    /// Counts what the copy popup's choices would transfer, see
+    /// `update_transfer_bytes`
+    transfer_worker: Option<(Worker, mpsc::Receiver<u64>)>,
+    /// Show the detected type of each file
+    pub show_file_type: bool,
+    /// Detected file types by side (true for the left) and path, filled in
+    /// as rows come into view
+    file_types: HashMap<(bool, Arc<Path>), FileType>,
+    /// Entries compared again in full with `start_verify` since the last
+    /// refresh, marked in the panels
+    pub verified: HashSet<Arc<Path>>,
+    pub layout: ListLayout,
+    pub sort_order: SortOrder,
+    pub placeholder_rows: PlaceholderRows,
+    /// Sections folded away in the grouped layout
+    pub collapsed_groups: Vec<FileStatus>,
+    /// Section header rows of the grouped layout: row, status and number of
+    /// entries. The rows themselves are placeholders without a path.
+    pub group_headers: Vec<(usize, FileStatus, usize)>,
+    /// Pair the display settings are saved for on quit, see `ViewOptions::remember`
+    view_key: Option<(PathBuf, PathBuf)>,
+    /// Entry that `file_diff` currently previews
+    preview_key: Option<(Arc<Path>, FileStatus)>,
+    /// Previews of files built before, so holding a key down over large
+    /// ones doesn't diff each again; rebuilt once either side changes
+    preview_cache: HashMap<Arc<Path>, (PreviewStamp, String)>,
+    /// Files that differ in the active comparison, see `DirectoryComparison::difference_count`
+    pub difference_count: usize,
+    /// Rebindable keys from the config file
+    pub keymap: Keymap,
+    /// How copies are carried out, from the config file
+    pub copy_backend: CopyBackend,
+    /// Driven without a terminal (see `Headless`), so files aren't opened
+    /// in the external diff tool
+    pub headless: bool,
 }
 
 impl App {
     pub fn new(comparison: DirectoryComparison) -> Self {
+        let show_permissions = comparison.options.compare_permissions;
         let mut app = Self {
             comparison,
             mode: AppMode::DirectoryView,
+            mode_before_quit: AppMode::DirectoryView,
             active_panel: 0,
             left_list_state: ListState::default(),
             right_list_state: ListState::default(),
@@ -110,17 +616,73 @@ impl App {
             refresh_progress: String::new(),
             refresh_percentage: 0.0,
             refresh_rx: None,
+            refresh_worker: None,
             left_scrollbar_state: ScrollbarState::default(),
             right_scrollbar_state: ScrollbarState::default(),
             viewport_height: 24,
-            toolbar_area: Rect::default(),
+            toolbar_buttons: Vec::new(),
+            panel_areas: [Rect::default(); 2],
+            mouse_position: None,
             copy_info: None,
+            copy_rx: None,
+            copy_worker: None,
+            copy_progress: None,
+            copy_error: None,
+            stale_backups: Vec::new(),
             delete_info: None,
+            timestamp_info: None,
+            permissions_info: None,
+            name_input: None,
+            command_line: None,
+            panels_locked: true,
+            hash_info: None,
+            hash_algorithm: HashAlgorithm::default(),
+            read_only: false,
+            session_log: SessionLog::default(),
+            session_log_scroll: 0,
+            diffstat: None,
+            recenter: false,
+            flash: None,
+            name_filter: None,
+            hide_noise: false,
+            noise: PatternSet::noise(),
+            zoom: None,
             saved_left_selection: None,
             saved_right_selection: None,
             saved_active_panel: 0,
             saved_expansion_state: None,
             saved_filter_mode: None,
+            target_comparisons: Vec::new(),
+            active_target: 0,
+            target_status_maps: Vec::new(),
+            target_markers: Vec::new(),
+            show_preview: false,
+            vertical_layout: false,
+            raw_sizes: false,
+            show_permissions,
+            show_owner: false,
+            show_similarity: false,
+            similarities: HashMap::new(),
+            similarity_worker: None,
+            hash_worker: None,
+            diffstat_worker: None,
+            screen_replaced: false,
+            transfer_worker: None,
+            show_file_type: false,
+            file_types: HashMap::new(),
+            verified: HashSet::new(),
+            layout: ListLayout::default(),
+            sort_order: SortOrder::default(),
+            placeholder_rows: PlaceholderRows::default(),
+            collapsed_groups: Vec::new(),
+            group_headers: Vec::new(),
+            view_key: None,
+            preview_key: None,
+            preview_cache: HashMap::new(),
+            difference_count: 0,
+            keymap: Keymap::default(),
+            copy_backend: CopyBackend::default(),
+            headless: false,
         };
 
         app.update_file_lists();
@@ -129,10 +691,62 @@ impl App {
     }
 
     pub fn update_file_lists(&mut self) {
-        self.left_items =
-            Self::flatten_tree_with_filter(&self.comparison.left_tree, 0, self.filter_mode);
-        self.right_items =
-            Self::flatten_tree_with_filter(&self.comparison.right_tree, 0, self.filter_mode);
+        // Contents may have changed (copy, refresh), so rebuild the preview
+        self.preview_key = None;
+        let quiet = if self.hide_noise {
+            let (count, quiet) = self.noise_summary();
+            self.difference_count = count;
+            quiet
+        } else {
+            self.difference_count = self.comparison.difference_count();
+            HashSet::new()
+        };
+        let visible = (self.name_filter.is_some() || self.hide_noise).then(|| self.visible_paths());
+
+        // A zoomed-in folder that's gone (after a refresh) zooms back out
+        let (left_tree, right_tree) = (&self.comparison.left_tree, &self.comparison.right_tree);
+        let zoomed = self
+            .zoom
+            .as_deref()
+            .and_then(|path| Some((left_tree.find(path)?, right_tree.find(path)?)));
+        if zoomed.is_none() {
+            self.zoom = None;
+        }
+        let (left_root, right_root) = zoomed.unwrap_or((left_tree, right_tree));
+        let root_path = self.zoom.as_deref().unwrap_or(Path::new(""));
+
+        self.group_headers.clear();
+        if self.layout == ListLayout::Tree {
+            self.left_items = Self::flatten_tree_with_filter(
+                left_root,
+                root_path,
+                0,
+                self.filter_mode,
+                visible.as_ref(),
+                &quiet,
+            );
+            self.right_items = Self::flatten_tree_with_filter(
+                right_root,
+                root_path,
+                0,
+                self.filter_mode,
+                visible.as_ref(),
+                &quiet,
+            );
+        } else {
+            let mut rows = Self::flat_differences(
+                left_root,
+                right_root,
+                root_path,
+                self.filter_mode,
+                visible.as_ref(),
+                self.sort_order,
+            );
+            if self.layout == ListLayout::Grouped {
+                rows = self.group_by_status(rows);
+            }
+            (self.left_items, self.right_items) = rows.into_iter().unzip();
+        }
 
         self.left_scrollbar_state = self
             .left_scrollbar_state
@@ -140,26 +754,332 @@ impl App {
         self.right_scrollbar_state = self
             .right_scrollbar_state
             .content_length(self.right_items.len());
+
+        self.update_target_markers();
+    }
+
+    pub fn apply_view_options(&mut self, view: &ViewOptions) {
+        let mut filter_mode = FilterMode::All;
+        if view.remember {
+            let key = (
+                self.comparison.left_dir.clone(),
+                self.comparison.right_dir.clone(),
+            );
+            if let Some(saved) = crate::history::load_view(&key.0, &key.1) {
+                filter_mode = saved.filter_mode;
+                self.raw_sizes = saved.raw_sizes;
+                self.show_permissions = saved.show_permissions;
+                self.show_owner = saved.show_owner;
+                self.vertical_layout = saved.vertical_layout;
+                self.show_preview = saved.show_preview;
+                self.show_similarity = saved.show_similarity;
+                self.show_file_type = saved.show_file_type;
+                self.layout = saved.layout;
+                self.sort_order = saved.sort_order;
+                self.placeholder_rows = saved.placeholder_rows;
+            }
+            self.view_key = Some(key);
+        }
+        self.filter_mode = view.filter_mode.unwrap_or(filter_mode);
+        self.hash_algorithm = view.hash;
+        self.read_only = view.read_only;
+        self.session_log = SessionLog::new(view.session_log.clone());
+        if let Some(placeholder_rows) = view.placeholder_rows {
+            self.placeholder_rows = placeholder_rows;
+        }
+        if view.expand_all {
+            self.expand_all();
+        } else {
+            self.update_file_lists();
+        }
+
+        if let Some(select) = &view.select {
+            let relative = self
+                .comparison
+                .relative_on(true, select)
+                .or_else(|| self.comparison.relative_on(false, select))
+                .unwrap_or_else(|| select.clone());
+            self.select_path(&relative);
+        }
+    }
+
+    /// Save the display settings for the pair the view was restored from.
+    pub fn save_view(&self) {
+        let Some((left, right)) = &self.view_key else {
+            return;
+        };
+        let view = SavedView {
+            filter_mode: self.filter_mode,
+            raw_sizes: self.raw_sizes,
+            show_permissions: self.show_permissions,
+            show_owner: self.show_owner,
+            vertical_layout: self.vertical_layout,
+            show_preview: self.show_preview,
+            show_similarity: self.show_similarity,
+            show_file_type: self.show_file_type,
+            layout: self.layout,
+            sort_order: self.sort_order,
+            placeholder_rows: self.placeholder_rows,
+        };
+        if let Err(e) = crate::history::save_view(left, right, &view) {
+            crate::utils::log_error(&format!("Failed to save the view: {}", e));
+        }
+    }
+
+    /// Expand the ancestors of a relative path and move the selection of both
+    /// panels onto it. Returns false when the path is not visible.
+    pub fn select_path(&mut self, relative_path: &Path) -> bool {
+        let mut ancestors: Vec<PathBuf> = relative_path
+            .ancestors()
+            .skip(1)
+            .filter(|p| !p.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .collect();
+        ancestors.reverse();
+
+        for ancestor in &ancestors {
+            for tree in [
+                &mut self.comparison.left_tree,
+                &mut self.comparison.right_tree,
+            ] {
+                if let Some(node) = tree.find_mut(ancestor) {
+                    if node.is_dir {
+                        node.expanded = true;
+                    }
+                }
+            }
+        }
+        self.update_file_lists();
+
+        let index = self
+            .left_items
+            .iter()
+            .position(|item| *item.path == *relative_path)
+            .or_else(|| {
+                self.right_items
+                    .iter()
+                    .position(|item| *item.path == *relative_path)
+            });
+
+        match index {
+            Some(index) => {
+                self.select_row(index);
+                self.recenter = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the selection to the folder containing the current row, optionally
+    /// collapsing that folder on the way out.
+    pub fn select_parent(&mut self, collapse: bool) {
+        let Some(FileItem { path, .. }) = self.get_selected_item() else {
+            return;
+        };
+        let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+        else {
+            return;
+        };
+
+        if collapse {
+            for is_left in [true, false] {
+                if let Some(node) = self.find_node_by_path(&parent, is_left) {
+                    node.expanded = false;
+                }
+            }
+        }
+        self.select_path(&parent);
     }
 
+    /// Select the same row in both panels (clamped to each list's length).
+    pub fn select_row(&mut self, index: usize) {
+        if !self.left_items.is_empty() {
+            let left = index.min(self.left_items.len() - 1);
+            self.left_list_state.select(Some(left));
+            self.left_scrollbar_state = self.left_scrollbar_state.position(left);
+        }
+        if !self.right_items.is_empty() {
+            let right = index.min(self.right_items.len() - 1);
+            self.right_list_state.select(Some(right));
+            self.right_scrollbar_state = self.right_scrollbar_state.position(right);
+        }
+    }
+
+    /// Add further targets compared against the same source (left) directory.
+    pub fn add_targets(&mut self, comparisons: Vec<DirectoryComparison>) {
+        self.target_comparisons.extend(comparisons);
+        self.target_status_maps = (0..self.target_count())
+            .map(|index| Self::build_status_map(&self.target_comparison(index).left_tree))
+            .collect();
+        self.update_file_lists();
+    }
+
+    pub fn target_count(&self) -> usize {
+        self.target_comparisons.len() + 1
+    }
+
+    pub fn is_multi_target(&self) -> bool {
+        !self.target_comparisons.is_empty()
+    }
+
+    pub fn target_comparison(&self, index: usize) -> &DirectoryComparison {
+        match index.cmp(&self.active_target) {
+            std::cmp::Ordering::Equal => &self.comparison,
+            std::cmp::Ordering::Less => &self.target_comparisons[index],
+            std::cmp::Ordering::Greater => &self.target_comparisons[index - 1],
+        }
+    }
+
+    /// Show the next target in the right panel.
+    pub fn cycle_target(&mut self) {
+        if !self.is_multi_target() {
+            return;
+        }
+
+        let active = self.active_target;
+        let next = (active + 1) % self.target_count();
+        let slot = if next > active { next - 1 } else { next };
+
+        let incoming = self.target_comparisons.remove(slot);
+        let outgoing = std::mem::replace(&mut self.comparison, incoming);
+        let insert_at = if next > active { active } else { active - 1 };
+        self.target_comparisons.insert(insert_at, outgoing);
+        self.active_target = next;
+
+        // Keep the folder expansion of the source tree when switching targets
+        let (previous_left, previous_right) = (
+            self.target_comparison(active).left_tree.clone(),
+            self.target_comparison(active).right_tree.clone(),
+        );
+        Self::restore_expansion_state_safe(&mut self.comparison.left_tree, &previous_left);
+        Self::restore_expansion_state_safe(&mut self.comparison.right_tree, &previous_right);
+
+        self.update_file_lists();
+    }
+
+    fn build_status_map(tree: &FileNode) -> HashMap<PathBuf, FileStatus> {
+        fn walk(node: &FileNode, path: &Path, map: &mut HashMap<PathBuf, FileStatus>) {
+            for child in &node.children {
+                let child_path = path.join(&*child.name);
+                walk(child, &child_path, map);
+                map.insert(child_path, child.status);
+            }
+        }
+
+        let mut map = HashMap::new();
+        walk(tree, Path::new(""), &mut map);
+        map
+    }
+
+    fn update_target_markers(&mut self) {
+        if !self.is_multi_target() {
+            self.target_markers.clear();
+            return;
+        }
+
+        // The active comparison changes with copies and edits, so always rebuild it
+        if let Some(active_map) = self.target_status_maps.get_mut(self.active_target) {
+            *active_map = Self::build_status_map(&self.comparison.left_tree);
+        }
+
+        self.target_markers = self
+            .left_items
+            .iter()
+            .map(|FileItem { path, .. }| {
+                self.target_status_maps
+                    .iter()
+                    .map(|map| map.get(&**path).copied())
+                    .collect()
+            })
+            .collect();
+    }
+
+    /// Paths that match `name_filter` and aren't noise (when hidden), with
+    /// the folders leading to them. Both trees have the same paths, so the
+    /// panels stay aligned.
+    fn visible_paths(&self) -> HashSet<PathBuf> {
+        let mut visible = HashSet::new();
+        for entry in self.comparison.entries() {
+            let is_dir = entry.left.or(entry.right).is_some_and(|meta| meta.is_dir);
+            let shown = self
+                .name_filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(&entry))
+                && !(self.hide_noise && self.is_noise(&entry.rel_path, is_dir));
+            if shown {
+                for path in entry.rel_path.ancestors() {
+                    if !visible.insert(path.to_path_buf()) {
+                        break;
+                    }
+                }
+            }
+        }
+        visible
+    }
+
+    /// Whether `path` is, or lies inside, a file or folder `hide_noise`
+    /// leaves out.
+    fn is_noise(&self, path: &Path, is_dir: bool) -> bool {
+        self.noise.matches(path, is_dir)
+            || path
+                .ancestors()
+                .skip(1)
+                .any(|folder| self.noise.matches(folder, true))
+    }
+
+    /// The number of differing files that aren't noise, and the folders
+    /// that only differ because of noise inside them, shown as the same.
+    fn noise_summary(&self) -> (usize, HashSet<PathBuf>) {
+        let mut count = 0;
+        let mut noisy = HashSet::new();
+        let mut real = HashSet::new();
+        for entry in self.comparison.entries() {
+            if entry.status == FileStatus::Same {
+                continue;
+            }
+            let is_dir = entry.left.or(entry.right).is_some_and(|meta| meta.is_dir);
+            let folders = if self.is_noise(&entry.rel_path, is_dir) {
+                &mut noisy
+            } else if is_dir && entry.status == FileStatus::Different {
+                // Different because of what's inside, counted there
+                continue;
+            } else {
+                count += usize::from(!is_dir);
+                &mut real
+            };
+            folders.extend(entry.rel_path.ancestors().skip(1).map(Path::to_path_buf));
+        }
+        noisy.retain(|folder| !real.contains(folder));
+        (count, noisy)
+    }
+
+    /// The rows for `node`, at `path`, and its expanded descendants.
     fn flatten_tree_with_filter(
         node: &FileNode,
+        path: &Path,
         depth: usize,
         filter: FilterMode,
-    ) -> Vec<(
-        String,
-        FileStatus,
-        PathBuf,
-        bool,
-        Option<u64>,
-        Option<SystemTime>,
-    )> {
+        visible: Option<&HashSet<PathBuf>>,
+        quiet: &HashSet<PathBuf>,
+    ) -> Vec<FileItem> {
         let mut items = Vec::new();
 
+        // The root of the view is open even if it's a collapsed (zoomed) folder
         if depth == 0 {
-            if node.is_dir && node.expanded {
+            if node.is_dir {
                 for child in &node.children {
-                    items.extend(Self::flatten_tree_with_filter(child, 1, filter));
+                    items.extend(Self::flatten_tree_with_filter(
+                        child,
+                        &path.join(&*child.name),
+                        1,
+                        filter,
+                        visible,
+                        quiet,
+                    ));
                 }
             }
             return items;
@@ -167,178 +1087,635 @@ impl App {
 
         let indent = "  ".repeat(depth - 1);
 
-        let icon = if node.name.is_empty() {
+        let icons = crate::icons::icons();
+        let icon = if !node.exists {
             ""
         } else if node.is_dir {
             if node.expanded {
-                "📂"
+                icons.folder_open
             } else {
-                "📁"
+                icons.folder_closed
             }
         } else {
-            "📄"
+            icons.file
         };
 
-        let display_name = if node.name.is_empty() {
-            format!("{}", indent)
+        let display_name = if !node.exists {
+            indent
         } else if icon.is_empty() {
-            format!("{}{}", indent, node.name)
+            format!("{}{}", indent, node.display_name())
         } else {
-            format!("{}{} {}", indent, icon, node.name)
+            format!("{}{} {}", indent, icon, node.display_name())
         };
 
+        let status = if node.status == FileStatus::Different && quiet.contains(path) {
+            FileStatus::Same
+        } else {
+            node.status
+        };
         let should_include = match filter {
             FilterMode::All => true,
             FilterMode::Different => {
                 matches!(
-                    node.status,
+                    status,
                     FileStatus::Different | FileStatus::LeftOnly | FileStatus::RightOnly
                 )
             }
             FilterMode::DifferentNotOrphans => {
-                matches!(node.status, FileStatus::Different)
+                matches!(status, FileStatus::Different)
             }
-        };
+        } && visible.is_none_or(|paths| paths.contains(path));
 
         if should_include {
-            items.push((
+            items.push(FileItem {
                 display_name,
-                node.status,
-                node.path.clone(),
-                node.is_dir,
-                node.size,
-                node.modified,
-            ));
+                status,
+                path: Arc::from(path),
+                is_dir: node.is_dir,
+                size: node.size,
+                modified: node.modified,
+                mode: node.mode,
+                owner: node.owner,
+            });
         }
 
         if node.is_dir && node.expanded {
             for child in &node.children {
-                items.extend(Self::flatten_tree_with_filter(child, depth + 1, filter));
+                items.extend(Self::flatten_tree_with_filter(
+                    child,
+                    &path.join(&*child.name),
+                    depth + 1,
+                    filter,
+                    visible,
+                    quiet,
+                ));
             }
         }
 
         items
     }
 
-    pub fn handle_mouse_click(&mut self, x: u16, y: u16) {
-        if y >= self.toolbar_area.y
-            && y <= self.toolbar_area.y + self.toolbar_area.height
-            && x >= self.toolbar_area.x
-            && x < self.toolbar_area.x + self.toolbar_area.width
-        {
-            let relative_x = x - self.toolbar_area.x;
-
-            if relative_x <= 16 {
-                self.filter_mode = FilterMode::All;
-                self.update_file_lists();
-            } else if relative_x <= 34 {
-                self.filter_mode = FilterMode::Different;
-                self.update_file_lists();
-            } else if relative_x <= 52 {
-                self.filter_mode = FilterMode::DifferentNotOrphans;
-                self.update_file_lists();
-            } else if relative_x <= 71 {
-                self.expand_all();
-            } else if relative_x <= 92 {
-                self.collapse_all();
-            } else if relative_x <= 109 {
-                self.start_refresh();
-            } else if relative_x <= 129 {
-                self.swap_panels();
-            } else if relative_x <= 143 {
-                if self.can_copy() {
-                    self.prepare_copy();
-                }
-            } else if relative_x <= 166 {
-                if self.can_delete() {
-                    self.prepare_delete();
-                }
+    /// Rows of the flat list: every differing file below the roots (and
+    /// differing folders without children), named by relative path,
+    /// regardless of which folders are expanded.
+    fn flat_differences(
+        left_root: &FileNode,
+        right_root: &FileNode,
+        root_path: &Path,
+        filter: FilterMode,
+        visible: Option<&HashSet<PathBuf>>,
+        sort_order: SortOrder,
+    ) -> Vec<(FileItem, FileItem)> {
+        let mut rows = Vec::new();
+        Self::collect_differences(left_root, right_root, root_path, filter, visible, &mut rows);
+
+        // Sorted as pairs so both panels stay aligned
+        match sort_order {
+            SortOrder::Path => {}
+            SortOrder::Status => rows.sort_by_key(|(left, _)| match left.status {
+                FileStatus::Different => 0,
+                FileStatus::LeftOnly => 1,
+                FileStatus::RightOnly => 2,
+                FileStatus::Same => 3,
+            }),
+            SortOrder::Size => {
+                rows.sort_by_key(|(left, right)| std::cmp::Reverse(left.size.max(right.size)))
             }
         }
+        rows
     }
 
-    pub fn calculate_half_page(&self) -> i32 {
-        let available_height = self.viewport_height.saturating_sub(5);
-        std::cmp::max(1, (available_height / 2) as i32)
+    /// Put a header row in front of the rows of each status, and leave out
+    /// the rows of collapsed sections.
+    fn group_by_status(&mut self, rows: Vec<(FileItem, FileItem)>) -> Vec<(FileItem, FileItem)> {
+        let statuses = [
+            FileStatus::Different,
+            FileStatus::LeftOnly,
+            FileStatus::RightOnly,
+        ];
+        let mut sections: [Vec<(FileItem, FileItem)>; 3] = Default::default();
+        for (left, right) in rows {
+            if let Some(index) = statuses.iter().position(|status| *status == left.status) {
+                sections[index].push((left, right));
+            }
+        }
+
+        let mut grouped = Vec::new();
+        for (status, section) in statuses.into_iter().zip(sections) {
+            if section.is_empty() {
+                continue;
+            }
+            self.group_headers
+                .push((grouped.len(), status, section.len()));
+            let header = FileItem {
+                display_name: String::new(),
+                status,
+                path: Arc::from(Path::new("")),
+                is_dir: false,
+                size: None,
+                modified: None,
+                mode: None,
+                owner: None,
+            };
+            grouped.push((header.clone(), header));
+            if !self.collapsed_groups.contains(&status) {
+                grouped.extend(section);
+            }
+        }
+        grouped
     }
 
-    pub fn get_selected_item(
-        &self,
-    ) -> Option<&(
-        String,
-        FileStatus,
-        PathBuf,
-        bool,
-        Option<u64>,
-        Option<SystemTime>,
-    )> {
-        let items = if self.active_panel == 0 {
-            &self.left_items
-        } else {
-            &self.right_items
-        };
+    /// The status of the selected section header in the grouped layout.
+    fn selected_group(&self) -> Option<FileStatus> {
         let state = if self.active_panel == 0 {
             &self.left_list_state
         } else {
             &self.right_list_state
         };
+        let index = state.selected()?;
+        self.group_headers
+            .iter()
+            .find(|(row, _, _)| *row == index)
+            .map(|(_, status, _)| *status)
+    }
 
-        if let Some(selected) = state.selected() {
-            if selected < items.len() {
-                return Some(&items[selected]);
-            }
+    /// Fold or unfold a section of the grouped layout, keeping its header
+    /// selected.
+    pub fn toggle_group(&mut self, status: FileStatus) {
+        if let Some(index) = self.collapsed_groups.iter().position(|s| *s == status) {
+            self.collapsed_groups.remove(index);
+        } else {
+            self.collapsed_groups.push(status);
+        }
+        self.update_file_lists();
+        if let Some((row, _, _)) = self.group_headers.iter().find(|(_, s, _)| *s == status) {
+            self.select_row(*row);
         }
-        None
     }
 
-    pub fn can_copy(&self) -> bool {
-        if let Some((name, status, _path, _is_dir, _size, _modified)) = self.get_selected_item() {
-            if name.is_empty() {
-                return false;
+    fn collect_differences(
+        left: &FileNode,
+        right: &FileNode,
+        path: &Path,
+        filter: FilterMode,
+        visible: Option<&HashSet<PathBuf>>,
+        rows: &mut Vec<(FileItem, FileItem)>,
+    ) {
+        for (left, right) in left.children.iter().zip(&right.children) {
+            let path = path.join(&*left.name);
+            if left.is_dir && !left.children.is_empty() {
+                Self::collect_differences(left, right, &path, filter, visible, rows);
+                continue;
             }
-
-            match status {
-                FileStatus::LeftOnly => self.active_panel == 0,
-                FileStatus::RightOnly => self.active_panel == 1,
-                FileStatus::Different | FileStatus::Same => true,
+            let differs = match filter {
+                FilterMode::All | FilterMode::Different => left.status != FileStatus::Same,
+                FilterMode::DifferentNotOrphans => left.status == FileStatus::Different,
+            };
+            if differs && visible.is_none_or(|paths| paths.contains(&path)) {
+                let path: Arc<Path> = Arc::from(path);
+                rows.push((
+                    Self::flat_item(left, path.clone()),
+                    Self::flat_item(right, path),
+                ));
             }
+        }
+    }
+
+    fn flat_item(node: &FileNode, path: Arc<Path>) -> FileItem {
+        let icons = crate::icons::icons();
+        let display_name = if !node.exists {
+            String::new()
         } else {
-            false
+            let icon = if node.is_dir {
+                icons.folder_closed
+            } else {
+                icons.file
+            };
+            crate::icons::labeled(icon, &crate::utils::escape_name(&*path))
+        };
+        FileItem {
+            display_name,
+            status: node.status,
+            path,
+            is_dir: node.is_dir,
+            size: node.size,
+            modified: node.modified,
+            mode: node.mode,
+            owner: node.owner,
         }
     }
 
-    fn find_node_by_path(&mut self, path: &PathBuf, is_left: bool) -> Option<&mut FileNode> {
-        let tree = if is_left {
-            &mut self.comparison.left_tree
+    /// Show the entries in `layout`, keeping the selected entry. Choosing
+    /// the current layout again goes back to the tree.
+    pub fn toggle_layout(&mut self, layout: ListLayout) {
+        let selected = self
+            .get_selected_item()
+            .map(|item| item.path.to_path_buf())
+            .filter(|path| !path.as_os_str().is_empty());
+        self.layout = if self.layout == layout {
+            ListLayout::Tree
         } else {
-            &mut self.comparison.right_tree
+            layout
         };
-        Self::find_node_in_tree(tree, path)
+        if !selected.is_some_and(|path| self.select_path(&path)) {
+            self.update_file_lists();
+            self.select_row(0);
+        }
     }
 
-    fn find_node_in_tree<'a>(
-        node: &'a mut FileNode,
-        target_path: &PathBuf,
-    ) -> Option<&'a mut FileNode> {
-        let exact_path_match = &node.path == target_path;
+    /// Show the entries of `mode`. The selected entry, or the nearest one
+    /// still shown, stays on the same line of the panels.
+    pub fn set_filter_mode(&mut self, mode: FilterMode) {
+        let left = self.active_panel == 0;
+        let (items, state) = if left {
+            (&self.left_items, &self.left_list_state)
+        } else {
+            (&self.right_items, &self.right_list_state)
+        };
+        let selected = state.selected().unwrap_or(0);
+        let line = (state.offset()..selected)
+            .filter(|row| !self.hides_row(left, *row))
+            .count();
+        // Rows nearest to the selection first, alternating below and above
+        let mut nearest: Vec<usize> = (0..items.len()).collect();
+        nearest.sort_by_key(|row| (row.abs_diff(selected), *row < selected));
+        let nearest: Vec<PathBuf> = nearest
+            .into_iter()
+            .filter_map(|row| self.row_path(row))
+            .collect();
+
+        self.filter_mode = mode;
+        self.update_file_lists();
 
-        if exact_path_match {
-            return Some(node);
-        }
+        let rows: HashMap<PathBuf, usize> = (0..self.left_items.len().max(self.right_items.len()))
+            .filter_map(|row| Some((self.row_path(row)?, row)))
+            .collect();
+        let Some(row) = nearest.iter().find_map(|path| rows.get(path).copied()) else {
+            self.select_row(0);
+            return;
+        };
+        self.select_row(row);
+        let offset = self.rows_back(left, row, line);
+        *self.left_list_state.offset_mut() = offset;
+        *self.right_list_state.offset_mut() = offset;
+        // Off a group header or hidden row
+        self.move_selection(0);
+    }
+
+    /// The path of the entry on a row, from whichever side has it.
+    fn row_path(&self, row: usize) -> Option<PathBuf> {
+        [&self.left_items, &self.right_items]
+            .into_iter()
+            .filter_map(|items| items.get(row))
+            .find(|item| !item.display_name.trim().is_empty() && !item.path.as_os_str().is_empty())
+            .map(|item| item.path.to_path_buf())
+    }
 
-        for child in &mut node.children {
-            if let Some(found) = Self::find_node_in_tree(child, target_path) {
-                return Some(found);
+    /// The row `count` shown rows above `row` in a panel, or the first one.
+    fn rows_back(&self, left: bool, mut row: usize, count: usize) -> usize {
+        let mut shown = 0;
+        while row > 0 && shown < count {
+            row -= 1;
+            if !self.hides_row(left, row) {
+                shown += 1;
             }
         }
-
-        None
+        row
     }
 
-    pub fn toggle_folder(&mut self) {
-        if let Some((_, _, path, is_dir, _, _)) = self.get_selected_item() {
-            if *is_dir {
-                let path = path.clone();
+    /// Scroll both panels so the selected row is in the middle, without
+    /// leaving empty lines at the bottom.
+    pub fn center_selection(&mut self) {
+        self.recenter = false;
+        let mut offsets = [None, None];
+        for (panel, offset) in offsets.iter_mut().enumerate() {
+            let (items, state) = if panel == 0 {
+                (&self.left_items, &self.left_list_state)
+            } else {
+                (&self.right_items, &self.right_list_state)
+            };
+            let height = self.panel_areas[panel].height.saturating_sub(2) as usize;
+            if let Some(selected) = state.selected() {
+                *offset = Some(
+                    self.rows_back(panel == 0, selected, height / 2)
+                        .min(self.rows_back(panel == 0, items.len(), height)),
+                );
+            }
+        }
+        if let Some(offset) = offsets[0] {
+            *self.left_list_state.offset_mut() = offset;
+        }
+        if let Some(offset) = offsets[1] {
+            *self.right_list_state.offset_mut() = offset;
+        }
+    }
+
+    /// Fit the view to a terminal resized to `width` x `height` before it is
+    /// drawn again: lay the panels out anew, drop the toolbar buttons' old
+    /// places so clicks can't hit them there, and keep each selection in
+    /// range without empty lines below the last row.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let (toolbar, panels, _) =
+            crate::ui::directory_layout(self, Rect::new(0, 0, width, height));
+        self.viewport_height = toolbar.height + panels[0].height;
+        self.panel_areas = panels;
+        self.toolbar_buttons.clear();
+
+        for panel in 0..2 {
+            let left = panel == 0;
+            let rows = self.panel_areas[panel].height.saturating_sub(2) as usize;
+            let len = if left {
+                self.left_items.len()
+            } else {
+                self.right_items.len()
+            };
+            let last_page = self.rows_back(left, len, rows);
+            let (state, scrollbar) = if left {
+                (&mut self.left_list_state, &mut self.left_scrollbar_state)
+            } else {
+                (&mut self.right_list_state, &mut self.right_scrollbar_state)
+            };
+            let selected = state.selected().map(|row| row.min(len.saturating_sub(1)));
+            state.select(selected.filter(|_| len > 0));
+            *state.offset_mut() = state.offset().min(last_page);
+            if let Some(selected) = state.selected() {
+                if selected < state.offset() {
+                    *state.offset_mut() = selected;
+                }
+                *scrollbar = scrollbar.position(selected);
+            }
+        }
+    }
+
+    /// Switch between blank, ghost and hidden placeholder rows.
+    pub fn cycle_placeholder_rows(&mut self) {
+        self.placeholder_rows = match self.placeholder_rows {
+            PlaceholderRows::Blank => PlaceholderRows::Ghost,
+            PlaceholderRows::Ghost => PlaceholderRows::Hidden,
+            PlaceholderRows::Hidden => PlaceholderRows::Blank,
+        };
+        // Hidden rows scroll the panels apart; line them up again
+        let offset = self
+            .left_list_state
+            .offset()
+            .min(self.right_list_state.offset());
+        *self.left_list_state.offset_mut() = offset;
+        *self.right_list_state.offset_mut() = offset;
+        // Off a row that just disappeared from the active panel
+        self.move_selection(0);
+    }
+
+    /// Whether the row is left out of a panel because placeholders are hidden.
+    pub fn hides_row(&self, left: bool, row: usize) -> bool {
+        let items = if left {
+            &self.left_items
+        } else {
+            &self.right_items
+        };
+        self.placeholder_rows == PlaceholderRows::Hidden
+            && is_placeholder(items, &self.group_headers, row)
+    }
+
+    /// Sort the flat list by the next order, switching to it if needed.
+    pub fn cycle_sort_order(&mut self) {
+        self.sort_order = match self.sort_order {
+            SortOrder::Path => SortOrder::Status,
+            SortOrder::Status => SortOrder::Size,
+            SortOrder::Size => SortOrder::Path,
+        };
+        if self.layout == ListLayout::Tree {
+            self.toggle_layout(ListLayout::Flat);
+        } else {
+            self.update_file_lists();
+            self.select_row(0);
+        }
+    }
+
+    pub fn handle_mouse_click(&mut self, x: u16, y: u16) {
+        let clicked = self
+            .toolbar_buttons
+            .iter()
+            .find(|(_, rect)| rect.contains(Position::new(x, y)))
+            .map(|(action, _)| *action);
+        let Some(action) = clicked else {
+            if self.mode == AppMode::DirectoryView {
+                if let Some((panel, row)) = self.row_at(Position::new(x, y)) {
+                    self.active_panel = panel;
+                    if self.panels_locked {
+                        self.select_row(row);
+                    } else if panel == 0 {
+                        self.left_list_state.select(Some(row));
+                    } else {
+                        self.right_list_state.select(Some(row));
+                    }
+                    // Off a group header
+                    self.move_selection(0);
+                }
+            }
+            return;
+        };
+
+        match action {
+            ToolbarAction::ShowAll => {
+                self.set_filter_mode(FilterMode::All);
+            }
+            ToolbarAction::ShowDifferent => {
+                self.set_filter_mode(FilterMode::Different);
+            }
+            ToolbarAction::ShowDiffOnly => {
+                self.set_filter_mode(FilterMode::DifferentNotOrphans);
+            }
+            ToolbarAction::ExpandAll => self.expand_all(),
+            ToolbarAction::CollapseAll => self.collapse_all(),
+            ToolbarAction::Refresh => self.start_refresh(),
+            ToolbarAction::Swap => self.swap_panels(),
+            ToolbarAction::Copy => {
+                if self.can_copy() {
+                    self.prepare_copy();
+                }
+            }
+            ToolbarAction::Delete => {
+                if self.can_delete() {
+                    self.prepare_delete();
+                }
+            }
+        }
+    }
+
+    /// Remember where the mouse pointer is; true when it moved onto another
+    /// row and the shading has to be redrawn.
+    pub fn move_mouse(&mut self, x: u16, y: u16) -> bool {
+        let position = Position::new(x, y);
+        let before = self.mouse_position.and_then(|old| self.row_at(old));
+        self.mouse_position = Some(position);
+        self.mode == AppMode::DirectoryView && self.row_at(position) != before
+    }
+
+    /// The row under the mouse pointer, shaded in both panels.
+    pub fn hovered_row(&self) -> Option<usize> {
+        self.row_at(self.mouse_position?).map(|(_, row)| row)
+    }
+
+    /// The panel and row drawn at `position`, if it is on an entry.
+    fn row_at(&self, position: Position) -> Option<(usize, usize)> {
+        let panel = self
+            .panel_areas
+            .iter()
+            .position(|area| area.inner(Margin::new(1, 1)).contains(position))?;
+        let line = (position.y - self.panel_areas[panel].y - 1) as usize;
+        let (items, state) = if panel == 0 {
+            (&self.left_items, &self.left_list_state)
+        } else {
+            (&self.right_items, &self.right_list_state)
+        };
+        (state.offset()..items.len())
+            .filter(|row| !self.hides_row(panel == 0, *row))
+            .nth(line)
+            .map(|row| (panel, row))
+    }
+
+    /// The line of the panel's list `row` is drawn on, if it is in view.
+    pub fn row_line(&self, panel: usize, row: usize) -> Option<u16> {
+        let (items, state) = if panel == 0 {
+            (&self.left_items, &self.left_list_state)
+        } else {
+            (&self.right_items, &self.right_list_state)
+        };
+        let height = self.panel_areas[panel].height.saturating_sub(2) as usize;
+        if row < state.offset() || row >= items.len() || self.hides_row(panel == 0, row) {
+            return None;
+        }
+        let line = (state.offset()..row)
+            .filter(|shown| !self.hides_row(panel == 0, *shown))
+            .count();
+        (line < height).then_some(line as u16)
+    }
+
+    /// "N differences" for the toolbar and window titles.
+    pub fn difference_summary(&self) -> String {
+        match self.difference_count {
+            1 => "1 difference".to_string(),
+            n => format!("{} differences", n),
+        }
+    }
+
+    /// Timing of the last scan for the toolbar, e.g. `1200 paths in 3.4s
+    /// (scan 1.2s, compare 2.2s), 45M read, 353 paths/s`. Empty before a
+    /// full scan has finished.
+    pub fn stats_summary(&self) -> String {
+        if let Some(file) = &self.comparison.loaded_from {
+            return format!("Saved result {}", file.display());
+        }
+        let stats = &self.comparison.stats;
+        if stats.paths == 0 {
+            return String::new();
+        }
+        format!(
+            "{} paths in {:.1}s (scan {:.1}s, compare {:.1}s), {} read, {:.0} paths/s",
+            stats.paths,
+            stats.total_time().as_secs_f64(),
+            stats.scan_time.as_secs_f64(),
+            stats.compare_time.as_secs_f64(),
+            self.format_size(Some(stats.bytes_read)).trim(),
+            stats.paths_per_sec()
+        )
+    }
+
+    /// The first warning of the last scan, like a skipped /proc, and how
+    /// many more there are.
+    pub fn warning_summary(&self) -> Option<String> {
+        let warnings = &self.comparison.warnings;
+        let first = warnings.first()?;
+        Some(match warnings.len() {
+            1 => first.clone(),
+            n => format!("{} (+{} more)", first, n - 1),
+        })
+    }
+
+    /// Terminal window title, e.g. `tudiff: left ⇄ right (3 differences)`.
+    pub fn window_title(&self) -> String {
+        format!(
+            "tudiff: {} {} {} ({})",
+            crate::utils::escape_name(&self.comparison.left_dir),
+            crate::icons::icons().pair_separator,
+            crate::utils::escape_name(&self.comparison.right_dir),
+            self.difference_summary()
+        )
+    }
+
+    /// Size as shown in the panels and popups, following the `B` toggle.
+    pub fn format_size(&self, size: Option<u64>) -> String {
+        if self.raw_sizes {
+            crate::utils::format_exact_size(size)
+        } else {
+            crate::utils::format_file_size(size)
+        }
+    }
+
+    pub fn calculate_half_page(&self) -> i32 {
+        let available_height = self.viewport_height.saturating_sub(5);
+        std::cmp::max(1, (available_height / 2) as i32)
+    }
+
+    pub fn get_selected_item(&self) -> Option<&FileItem> {
+        let items = if self.active_panel == 0 {
+            &self.left_items
+        } else {
+            &self.right_items
+        };
+        let state = if self.active_panel == 0 {
+            &self.left_list_state
+        } else {
+            &self.right_list_state
+        };
+
+        if let Some(selected) = state.selected() {
+            if selected < items.len() {
+                return Some(&items[selected]);
+            }
+        }
+        None
+    }
+
+    pub fn can_copy(&self) -> bool {
+        self.copy_source(self.active_panel == 0).is_some()
+    }
+
+    /// The entry on the source side of the selected row when copying towards
+    /// the right (or left) panel, or `None` when that side has nothing there.
+    fn copy_source(&self, to_right: bool) -> Option<&FileItem> {
+        // There is nothing to copy from or into a manifest or a saved result
+        if self.comparison.has_manifest() || self.comparison.loaded_from.is_some() {
+            return None;
+        }
+        let state = if self.active_panel == 0 {
+            &self.left_list_state
+        } else {
+            &self.right_list_state
+        };
+        let items = if to_right {
+            &self.left_items
+        } else {
+            &self.right_items
+        };
+        items
+            .get(state.selected()?)
+            .filter(|item| !item.display_name.trim().is_empty())
+    }
+
+    fn find_node_by_path(&mut self, path: &Path, is_left: bool) -> Option<&mut FileNode> {
+        let tree = if is_left {
+            &mut self.comparison.left_tree
+        } else {
+            &mut self.comparison.right_tree
+        };
+        tree.find_mut(path)
+    }
+
+    pub fn toggle_folder(&mut self) {
+        if let Some(FileItem { path, is_dir, .. }) = self.get_selected_item() {
+            if *is_dir {
+                let path = path.clone();
                 let current_selected = if self.active_panel == 0 {
                     self.left_list_state.selected()
                 } else {
@@ -366,6 +1743,34 @@ impl App {
         }
     }
 
+    /// Make `panel` the active one, carrying the selected row over.
+    fn focus_panel(&mut self, panel: usize) {
+        if !self.panels_locked {
+            self.active_panel = panel;
+        } else if self.active_panel == 1 && panel == 0 {
+            if let Some(right_selected) = self.right_list_state.selected() {
+                if right_selected < self.left_items.len() {
+                    self.left_list_state.select(Some(right_selected));
+                } else if !self.left_items.is_empty() {
+                    self.left_list_state.select(Some(self.left_items.len() - 1));
+                }
+            }
+        } else if self.active_panel == 0 && panel == 1 {
+            if let Some(left_selected) = self.left_list_state.selected() {
+                if left_selected < self.right_items.len() {
+                    self.right_list_state.select(Some(left_selected));
+                } else if !self.right_items.is_empty() {
+                    self.right_list_state
+                        .select(Some(self.right_items.len() - 1));
+                }
+            }
+        }
+        self.active_panel = panel;
+        if self.placeholder_rows == PlaceholderRows::Hidden {
+            self.move_selection(0);
+        }
+    }
+
     pub fn move_selection(&mut self, delta: i32) {
         let current_state = if self.active_panel == 0 {
             &mut self.left_list_state
@@ -383,11 +1788,16 @@ impl App {
         }
 
         let current_selected = current_state.selected().unwrap_or(0);
-        let new_selected = if delta > 0 {
+        let mut new_selected = if delta > 0 {
             std::cmp::min(current_selected + delta as usize, current_items.len() - 1)
         } else {
             current_selected.saturating_sub((-delta) as usize)
         };
+        // Hidden rows can't be selected in this panel; go on past them
+        if self.placeholder_rows == PlaceholderRows::Hidden {
+            new_selected =
+                nearest_entry(current_items, &self.group_headers, new_selected, delta >= 0);
+        }
 
         current_state.select(Some(new_selected));
 
@@ -408,7 +1818,7 @@ impl App {
             &self.left_items
         };
 
-        if !opposite_items.is_empty() {
+        if !opposite_items.is_empty() && self.panels_locked {
             let sync_selected = std::cmp::min(new_selected, opposite_items.len() - 1);
             opposite_state.select(Some(sync_selected));
 
@@ -452,7 +1862,7 @@ impl App {
                 &self.left_items
             };
 
-            if !opposite_items.is_empty() {
+            if !opposite_items.is_empty() && self.panels_locked {
                 opposite_state.select(Some(0));
                 if self.active_panel == 0 {
                     self.right_scrollbar_state = self.right_scrollbar_state.position(0);
@@ -496,7 +1906,7 @@ impl App {
                 &self.left_items
             };
 
-            if !opposite_items.is_empty() {
+            if !opposite_items.is_empty() && self.panels_locked {
                 let opposite_last_index = opposite_items.len() - 1;
                 opposite_state.select(Some(opposite_last_index));
                 if self.active_panel == 0 {
@@ -543,7 +1953,8 @@ impl App {
     }
 
     pub fn start_refresh(&mut self) {
-        if self.is_refreshing {
+        // A saved result has no folders to scan again
+        if self.is_refreshing || self.comparison.loaded_from.is_some() {
             return;
         }
 
@@ -553,90 +1964,151 @@ impl App {
         let (tx, rx) = mpsc::channel();
         self.refresh_rx = Some(rx);
 
-        let left_dir = self.comparison.left_dir.clone();
-        let right_dir = self.comparison.right_dir.clone();
+        // Unchanged folders keep the results they have now
+        let previous = self.comparison.previous_scan();
+        let other_targets: Vec<(PathBuf, PathBuf)> = self
+            .target_comparisons
+            .iter()
+            .map(|c| (c.left_dir.clone(), c.right_dir.clone()))
+            .collect();
+
+        self.refresh_worker = Some(Worker::spawn(move |cancel| {
+            let target_options = previous.options().clone();
+            let result = DirectoryComparison::rescan_with_progress(&previous, &cancel, |msg| {
+                let _ = tx.send(RefreshMessage::Progress(
+                    msg.to_string(),
+                    progress_percentage(msg),
+                ));
+            });
 
-        thread::spawn(move || {
-            let result = DirectoryComparison::new_with_progress(left_dir, right_dir, |msg| {
-                let percentage = if msg.contains("Starting") {
-                    0.0
-                } else if msg.contains("Scanning left") {
-                    0.05
-                } else if msg.contains("Scanning right") {
-                    0.15
-                } else if msg.contains("Scanning...") {
-                    if let Some(files_pos) = msg.find(" files") {
-                        let search_str = &msg[..files_pos];
-                        if let Some(space_pos) = search_str.rfind(' ') {
-                            if let Ok(count) = search_str[space_pos + 1..].parse::<f64>() {
-                                (count / 1000.0).min(0.2) + 0.05
-                            } else {
-                                0.1
-                            }
-                        } else {
-                            0.1
-                        }
-                    } else {
-                        0.1
-                    }
-                } else if msg.contains("Files to compare:") {
-                    0.25
-                } else if msg.contains("Progress:") {
-                    if let Some(slash_pos) = msg.find('/') {
-                        let current_str = msg[9..slash_pos].trim();
-                        let total_str = msg[slash_pos + 1..].trim();
-
-                        if let (Ok(current), Ok(total)) =
-                            (current_str.parse::<f64>(), total_str.parse::<f64>())
-                        {
-                            0.25 + (current / total) * 0.75
-                        } else {
-                            0.5
+            if !other_targets.is_empty() {
+                let _ = tx.send(RefreshMessage::Progress(
+                    "Comparing other targets...".to_string(),
+                    1.0,
+                ));
+                let rescanned: Result<Vec<_>> = other_targets
+                    .into_iter()
+                    .map(|(left, right)| {
+                        if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                            anyhow::bail!("comparison cancelled");
                         }
-                    } else {
-                        0.5
+                        DirectoryComparison::new_silent_with_options(
+                            left,
+                            right,
+                            target_options.clone(),
+                        )
+                    })
+                    .collect();
+                match rescanned {
+                    Ok(comparisons) => {
+                        let _ = tx.send(RefreshMessage::Targets(comparisons));
                     }
-                } else if msg.contains("Comparing...") {
-                    if let Some(slash_pos) = msg.find('/') {
-                        let search_str = &msg[..slash_pos];
-                        if let Some(space_pos) = search_str.rfind(' ') {
-                            if let (Ok(current), Ok(total)) = (
-                                search_str[space_pos + 1..].parse::<f64>(),
-                                msg[slash_pos + 1..].parse::<f64>(),
-                            ) {
-                                0.25 + (current / total) * 0.7
-                            } else {
-                                0.5
-                            }
-                        } else {
-                            0.5
-                        }
-                    } else {
-                        0.5
+                    Err(e) => {
+                        let _ = tx.send(RefreshMessage::Error(format!("Error: {}", e)));
+                        return;
                     }
-                } else if msg.contains("Complete") {
-                    1.0
-                } else {
-                    0.5
-                };
-                let _ = tx.send(RefreshMessage::Progress(msg.to_string(), percentage));
-            });
+                }
+            }
 
             match result {
                 Ok(comparison) => {
-                    let _ = tx.send(RefreshMessage::Complete(comparison));
+                    let _ = tx.send(RefreshMessage::Complete(Box::new(comparison)));
                 }
                 Err(e) => {
                     let _ = tx.send(RefreshMessage::Error(format!("Error: {}", e)));
                 }
             }
-        });
+        }));
     }
 
-    pub fn check_refresh_progress(&mut self) {
-        if self.refresh_rx.is_none() {
+    /// Compare the selected file, or every file in the selected folder,
+    /// again by reading both sides in full, ignoring the quick checks and
+    /// the comparison index. Runs like a refresh; what was checked is marked
+    /// as verified afterwards.
+    pub fn start_verify(&mut self) {
+        if self.is_refreshing || self.comparison.loaded_from.is_some() {
+            return;
+        }
+        let Some(FileItem { path, .. }) = self.get_selected_item() else {
+            return;
+        };
+        let (Some(left), Some(right)) = (
+            self.comparison.left_tree.find(path),
+            self.comparison.right_tree.find(path),
+        ) else {
+            return;
+        };
+        let (mut files, mut folders) = (Vec::new(), Vec::new());
+        Self::verify_candidates(left, right, path, &mut files, &mut folders);
+        if files.is_empty() && folders.is_empty() {
+            return;
+        }
+
+        self.is_refreshing = true;
+        self.refresh_progress = "Starting verification...".to_string();
+        self.refresh_percentage = 0.0;
+
+        let (tx, rx) = mpsc::channel();
+        self.refresh_rx = Some(rx);
+
+        let left_dir = self.comparison.left_dir.clone();
+        let right_dir = self.comparison.right_dir.clone();
+        let options = self.comparison.options.clone();
+
+        self.refresh_worker = Some(Worker::spawn(move |cancel| {
+            let total = files.len();
+            let result = DirectoryComparison::verify(
+                &left_dir,
+                &right_dir,
+                &files,
+                &options,
+                &cancel,
+                |path, done| {
+                    let _ = tx.send(RefreshMessage::Progress(
+                        format!(
+                            "Verifying {}/{}: {}",
+                            done + 1,
+                            total,
+                            crate::utils::escape_name(path)
+                        ),
+                        done as f64 / total as f64,
+                    ));
+                },
+            );
+            let _ = tx.send(match result {
+                Ok((statuses, skipped)) => RefreshMessage::Verified(statuses, skipped, folders),
+                Err(e) => RefreshMessage::Error(format!("Error: {}", e)),
+            });
+        }));
+    }
+
+    /// Paths of the entries present on both sides, from this pair down.
+    fn verify_candidates(
+        left: &FileNode,
+        right: &FileNode,
+        path: &Path,
+        files: &mut Vec<PathBuf>,
+        folders: &mut Vec<PathBuf>,
+    ) {
+        if !left.exists || !right.exists {
+            return;
+        }
+        if !left.is_dir || !right.is_dir {
+            files.push(path.to_path_buf());
             return;
         }
+        folders.push(path.to_path_buf());
+        for (left_child, right_child) in left.children.iter().zip(&right.children) {
+            let child_path = path.join(&*left_child.name);
+            Self::verify_candidates(left_child, right_child, &child_path, files, folders);
+        }
+    }
+
+    /// Apply messages from a running refresh. Returns whether anything changed.
+    pub fn check_refresh_progress(&mut self) -> bool {
+        if self.refresh_rx.is_none() {
+            return false;
+        }
 
         let mut messages = Vec::new();
         if let Some(rx) = &self.refresh_rx {
@@ -645,14 +2117,75 @@ impl App {
             }
         }
 
+        let changed = !messages.is_empty();
         for msg in messages {
             match msg {
                 RefreshMessage::Progress(message, percentage) => {
                     self.refresh_progress = message;
                     self.refresh_percentage = percentage;
                 }
+                RefreshMessage::Targets(comparisons) => {
+                    self.target_comparisons = comparisons;
+                    self.target_status_maps = (0..self.target_count())
+                        .map(|index| {
+                            Self::build_status_map(&self.target_comparison(index).left_tree)
+                        })
+                        .collect();
+                }
+                RefreshMessage::Verified(statuses, skipped, folders) => {
+                    for (path, status) in statuses {
+                        for tree in [
+                            &mut self.comparison.left_tree,
+                            &mut self.comparison.right_tree,
+                        ] {
+                            if let Some(node) = tree.find_mut(&path) {
+                                node.status = status;
+                            }
+                        }
+                        // So a refresh keeps it if the folder is unchanged
+                        self.comparison.digests.set_file_status(&path, status);
+                        self.verified.insert(Arc::from(path));
+                    }
+                    // A folder only counts when everything in it was read
+                    self.verified.extend(
+                        folders
+                            .into_iter()
+                            .filter(|folder| !skipped.iter().any(|path| path.starts_with(folder)))
+                            .map(Arc::<Path>::from),
+                    );
+                    self.comparison.update_folder_statuses();
+                    if let Some(first) = skipped.first() {
+                        self.flash(format!(
+                            "{} {} not read in full, e.g. {}",
+                            skipped.len(),
+                            if skipped.len() == 1 {
+                                "entry was"
+                            } else {
+                                "entries were"
+                            },
+                            crate::utils::escape_name(first)
+                        ));
+                    }
+
+                    self.update_file_lists();
+                    self.is_refreshing = false;
+                    self.refresh_progress.clear();
+                    self.refresh_rx = None;
+                    self.refresh_worker = None;
+                    break;
+                }
+                RefreshMessage::Copied(copied, to_right, result) => {
+                    self.is_refreshing = false;
+                    self.refresh_progress.clear();
+                    self.refresh_rx = None;
+                    self.refresh_worker = None;
+                    self.apply_copy_rescan(&copied, to_right, result);
+                    break;
+                }
                 RefreshMessage::Complete(comparison) => {
-                    self.comparison = comparison;
+                    self.comparison = *comparison;
+                    // Quick checks again, so nothing counts as verified
+                    self.verified.clear();
 
                     self.comparison.left_tree.expanded = true;
                     self.comparison.right_tree.expanded = true;
@@ -661,6 +2194,7 @@ impl App {
                     self.is_refreshing = false;
                     self.refresh_progress.clear();
                     self.refresh_rx = None;
+                    self.refresh_worker = None;
 
                     if self.saved_expansion_state.is_some() {
                         self.restore_saved_state_safe();
@@ -673,39 +2207,57 @@ impl App {
                         format!("Refresh failed: {} (Press F5 to retry)", error);
                     self.is_refreshing = false;
                     self.refresh_rx = None;
+                    self.refresh_worker = None;
                     // log_error(&format!("Directory refresh failed: {}", error));
                     break;
                 }
             }
         }
+
+        changed
     }
 
     pub fn swap_panels(&mut self) {
-        std::mem::swap(
-            &mut self.comparison.left_dir,
-            &mut self.comparison.right_dir,
-        );
-        std::mem::swap(
-            &mut self.comparison.left_tree,
-            &mut self.comparison.right_tree,
-        );
+        // In N-way mode the left panel is always the shared source
+        if self.is_multi_target() {
+            return;
+        }
+        // Mapped folders only exist under other names on the right
+        if !self.comparison.options.path_map.is_empty() {
+            self.flash("Panels can't be swapped with --map".to_string());
+            return;
+        }
+
+        self.comparison.swap_sides();
         self.update_file_lists();
     }
 
     pub fn prepare_copy(&mut self) {
-        if let Some((_, _, path, is_dir, size, _)) = self.get_selected_item() {
-            let from_left_to_right = self.active_panel == 0;
+        self.prepare_copy_towards(self.active_panel == 0);
+    }
 
-            let source_path = if from_left_to_right {
-                self.comparison.left_dir.join(path)
+    /// Ask to copy the selected row towards the right (or left) panel, no
+    /// matter which panel is active.
+    pub fn prepare_copy_towards(&mut self, from_left_to_right: bool) {
+        if self.refuse_read_only("copying") {
+            return;
+        }
+        if let Some(FileItem {
+            path, is_dir, size, ..
+        }) = self.copy_source(from_left_to_right)
+        {
+            let source_tree = if from_left_to_right {
+                &self.comparison.left_tree
             } else {
-                self.comparison.right_dir.join(path)
+                &self.comparison.right_tree
             };
-
-            let target_path = if from_left_to_right {
-                self.comparison.right_dir.join(path)
-            } else {
-                self.comparison.left_dir.join(path)
+            let (Some(source_path), Some(target_path)) = (
+                self.comparison.side_path(from_left_to_right, path),
+                self.comparison.side_path(!from_left_to_right, path),
+            ) else {
+                let message = Self::unmapped(path);
+                self.flash(message);
+                return;
             };
 
             let (file_count, folder_count, total_bytes) = if *is_dir {
@@ -714,360 +2266,641 @@ impl App {
                 (1, 0, size.unwrap_or(0))
             };
 
+            let differences = source_tree
+                .find(path)
+                .filter(|node| node.is_dir)
+                .and_then(|node| {
+                    let mut entries = Vec::new();
+                    let left_out = Self::differing_entries(node, Path::new(""), &mut entries);
+                    // Offer it only when it actually leaves something out
+                    left_out.then_some(entries)
+                });
+
+            let overwrite_count = source_tree.find(path).map_or(0, Self::count_overwritten);
+
             self.copy_info = Some(CopyInfo {
                 source_path,
                 target_path,
                 file_count,
                 folder_count,
                 total_bytes,
+                transfer_bytes: None,
                 from_left_to_right,
+                overwrite_count,
+                overwrite_policy: OverwritePolicy::default(),
+                differences,
+                only_differences: false,
             });
+            self.update_transfer_bytes();
 
             self.mode = AppMode::CopyConfirm;
         }
     }
 
-    fn calculate_dir_stats(&self, dir_path: &std::path::Path) -> (usize, usize, u64) {
-        use std::fs;
-
-        let mut file_count = 0;
-        let mut folder_count = 1;
-        let mut total_bytes = 0;
-
-        if let Ok(entries) = fs::read_dir(dir_path) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_dir() {
-                    let (sub_files, sub_folders, sub_bytes) = self.calculate_dir_stats(&path);
-                    file_count += sub_files;
-                    folder_count += sub_folders;
-                    total_bytes += sub_bytes;
-                } else {
-                    file_count += 1;
-                    if let Ok(metadata) = entry.metadata() {
-                        total_bytes += metadata.len();
-                    }
+    /// Collect the descendants of `node` that differ from the other side:
+    /// missing entries as a whole, differing folders entry by entry. Returns
+    /// whether any `Same` entry was left out.
+    fn differing_entries(node: &FileNode, path: &Path, entries: &mut Vec<PathBuf>) -> bool {
+        let mut left_out = false;
+        for child in &node.children {
+            // Placeholders stand for entries that only exist on the other side
+            if !child.exists {
+                continue;
+            }
+            let child_path = path.join(&*child.name);
+            match child.status {
+                FileStatus::Same => left_out = true,
+                FileStatus::Different if child.is_dir => {
+                    left_out |= Self::differing_entries(child, &child_path, entries);
                 }
+                _ => entries.push(child_path),
             }
         }
-
-        (file_count, folder_count, total_bytes)
+        left_out
     }
 
-    pub fn execute_copy(&mut self) -> Result<()> {
-        if let Some(copy_info) = self.copy_info.clone() {
-            use std::fs;
+    /// Files at or below `node` that exist on the other side too and differ
+    /// from it, which a copy would overwrite; identical ones aren't counted.
+    fn count_overwritten(node: &FileNode) -> usize {
+        if node.is_dir {
+            node.children.iter().map(Self::count_overwritten).sum()
+        } else {
+            usize::from(node.status == FileStatus::Different)
+        }
+    }
 
-            self.save_current_state();
+    /// Switch between copying the whole folder and only its differences,
+    /// recounting what the popup shows.
+    pub fn toggle_copy_only_differences(&mut self) {
+        use std::fs;
 
-            if copy_info.source_path.is_dir() {
-                self.copy_dir_all(&copy_info.source_path, &copy_info.target_path)?;
-            } else {
-                if let Some(parent) = copy_info.target_path.parent() {
-                    fs::create_dir_all(parent)?;
+        let Some(copy_info) = &self.copy_info else {
+            return;
+        };
+        let Some(differences) = &copy_info.differences else {
+            return;
+        };
+        let only_differences = !copy_info.only_differences;
+        let source_tree = if copy_info.from_left_to_right {
+            &self.comparison.left_tree
+        } else {
+            &self.comparison.right_tree
+        };
+        let source_node = self
+            .comparison
+            .relative_on(copy_info.from_left_to_right, &copy_info.source_path)
+            .and_then(|relative| source_tree.find(&relative));
+        let overwritten = |entry: &Path| {
+            source_node
+                .and_then(|node| node.find(entry))
+                .map_or(0, Self::count_overwritten)
+        };
+
+        let (file_count, folder_count, total_bytes, overwrite_count) = if only_differences {
+            let mut stats = (0, 1, 0, 0);
+            for entry in differences {
+                let source = copy_info.source_path.join(entry);
+                match fs::symlink_metadata(&source) {
+                    Ok(metadata) if metadata.is_dir() => {
+                        let (files, folders, bytes) = self.calculate_dir_stats(&source);
+                        stats.0 += files;
+                        stats.1 += folders;
+                        stats.2 += bytes;
+                    }
+                    Ok(metadata) => {
+                        stats.0 += 1;
+                        if metadata.is_file() {
+                            stats.2 += metadata.len();
+                        }
+                    }
+                    Err(_) => continue,
                 }
-                fs::copy(&copy_info.source_path, &copy_info.target_path)?;
-                self.preserve_file_attributes(&copy_info.source_path, &copy_info.target_path)?;
+                stats.3 += overwritten(entry);
             }
+            stats
+        } else {
+            let (files, folders, bytes) = self.calculate_dir_stats(&copy_info.source_path);
+            (files, folders, bytes, overwritten(Path::new("")))
+        };
 
-            // Wait for filesystem sync
-            std::thread::sleep(std::time::Duration::from_millis(100));
-
-            // Partial update instead of full refresh
-            self.partial_update_after_copy(&copy_info)?;
+        if let Some(copy_info) = &mut self.copy_info {
+            copy_info.only_differences = only_differences;
+            copy_info.file_count = file_count;
+            copy_info.folder_count = folder_count;
+            copy_info.total_bytes = total_bytes;
+            copy_info.overwrite_count = overwrite_count;
         }
-
-        self.copy_info = None;
-        self.mode = AppMode::DirectoryView;
-        Ok(())
+        self.update_transfer_bytes();
     }
 
-    fn copy_dir_all(&self, src: &std::path::Path, dst: &std::path::Path) -> Result<()> {
-        use std::fs;
-
-        fs::create_dir_all(dst)?;
+    /// Recount what the confirmed copy would write after its options changed.
+    /// That means reading the whole source, so it happens on a worker and
+    /// `check_transfer_progress` fills the count in.
+    fn update_transfer_bytes(&mut self) {
+        self.stop_transfer_count();
+        let Some(copy_info) = &self.copy_info else {
+            return;
+        };
+        let job = self.copy_job(copy_info);
+        if let Some(copy_info) = &mut self.copy_info {
+            copy_info.transfer_bytes = None;
+        }
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker::spawn(move |cancel| {
+            let bytes = crate::copy::transfer_bytes(&job, &cancel);
+            if !cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                let _ = tx.send(bytes);
+            }
+        });
+        self.transfer_worker = Some((worker, rx));
+    }
 
-        for entry in fs::read_dir(src)? {
-            let entry = entry?;
-            let src_path = entry.path();
-            let dst_path = dst.join(entry.file_name());
+    fn stop_transfer_count(&mut self) {
+        if let Some((worker, _)) = self.transfer_worker.take() {
+            worker.cancel();
+        }
+    }
 
-            if src_path.is_dir() {
-                self.copy_dir_all(&src_path, &dst_path)?;
-                self.preserve_file_attributes(&src_path, &dst_path)?;
-            } else {
-                fs::copy(&src_path, &dst_path)?;
-                self.preserve_file_attributes(&src_path, &dst_path)?;
+    /// Put the count `update_transfer_bytes` started into the copy popup
+    /// once it's done. Returns whether it changed.
+    pub fn check_transfer_progress(&mut self) -> bool {
+        let Some((_, rx)) = &self.transfer_worker else {
+            return false;
+        };
+        match rx.try_recv() {
+            Ok(bytes) => {
+                self.transfer_worker = None;
+                if let Some(copy_info) = &mut self.copy_info {
+                    copy_info.transfer_bytes = Some(bytes);
+                }
+                true
+            }
+            Err(mpsc::TryRecvError::Empty) => false,
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.transfer_worker = None;
+                false
             }
         }
-
-        self.preserve_file_attributes(src, dst)?;
-
-        Ok(())
     }
 
-    fn preserve_file_attributes(&self, src: &std::path::Path, dst: &std::path::Path) -> Result<()> {
-        use std::fs;
-
-        let src_metadata = fs::metadata(src)?;
-
-        if src_metadata.is_file() {
-            let modified_time = src_metadata.modified()?;
-            let dst_file = fs::File::open(dst)?;
-            dst_file.set_modified(modified_time)?;
+    fn copy_job(&self, copy_info: &CopyInfo) -> CopyJob {
+        CopyJob {
+            source: copy_info.source_path.clone(),
+            target: copy_info.target_path.clone(),
+            file_count: copy_info.file_count,
+            total_bytes: copy_info.total_bytes,
+            policy: copy_info.overwrite_policy,
+            mtime_tolerance: self.comparison.options.mtime_tolerance,
+            entries: copy_info
+                .differences
+                .clone()
+                .filter(|_| copy_info.only_differences),
+            backend: self.copy_backend,
         }
-
-        Ok(())
     }
 
-    fn partial_update_after_copy(&mut self, copy_info: &CopyInfo) -> Result<()> {
-        use crate::compare::FileStatus;
+    fn calculate_dir_stats(&self, dir_path: &std::path::Path) -> (usize, usize, u64) {
         use std::fs;
 
-        // Calculate paths first
-        let from_left_to_right = copy_info.from_left_to_right;
-        let (source_dir, target_dir) = if from_left_to_right {
-            (self.comparison.left_dir.clone(), self.comparison.right_dir.clone())
-        } else {
-            (self.comparison.right_dir.clone(), self.comparison.left_dir.clone())
-        };
+        let mut file_count = 0;
+        let mut folder_count = 1;
+        let mut total_bytes = 0;
 
-        let source_relative = copy_info.source_path.strip_prefix(&source_dir)
-            .unwrap_or(&copy_info.source_path).to_path_buf();
-        let target_relative = copy_info.target_path.strip_prefix(&target_dir)
-            .unwrap_or(&copy_info.target_path).to_path_buf();
+        if let Ok(entries) = fs::read_dir(dir_path) {
+            for entry in entries.flatten() {
+                // Symlinks count as files: copy and delete leave their targets alone
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    let (sub_files, sub_folders, sub_bytes) =
+                        self.calculate_dir_stats(&entry.path());
+                    file_count += sub_files;
+                    folder_count += sub_folders;
+                    total_bytes += sub_bytes;
+                } else {
+                    file_count += 1;
+                    if metadata.is_file() {
+                        total_bytes += metadata.len();
+                    }
+                }
+            }
+        }
 
-        // Check if files are the same
-        let are_same = Self::check_if_files_same_static(
-            &copy_info.source_path,
-            &copy_info.target_path
-        )?;
+        (file_count, folder_count, total_bytes)
+    }
 
-        let new_status = if are_same {
-            FileStatus::Same
+    /// Start copying the confirmed entry on a worker thread; progress is
+    /// picked up by `check_copy_progress`.
+    pub fn execute_copy(&mut self) -> Result<()> {
+        if let Some(copy_info) = self.copy_info.clone() {
+            self.stop_transfer_count();
+            self.save_current_state();
+            let mut job = self.copy_job(&copy_info);
+            let (backend, fallback) = job.backend.usable();
+            // Kept for later copies, and for the quit popup to know
+            self.copy_backend = backend;
+            job.backend = backend;
+            if let Some(message) = fallback {
+                crate::utils::log_info(&message);
+                self.flash(message);
+            }
+            let (rx, worker) = crate::copy::spawn(job);
+            self.copy_rx = Some(rx);
+            self.copy_worker = Some(worker);
+            self.copy_progress = Some(CopyProgress {
+                file_count: copy_info.file_count,
+                total_bytes: copy_info.total_bytes,
+                ..CopyProgress::default()
+            });
+            self.copy_error = None;
+            self.stale_backups.clear();
+            self.mode = AppMode::Copying;
         } else {
-            FileStatus::Different
-        };
+            self.mode = AppMode::DirectoryView;
+        }
+        Ok(())
+    }
 
-        // Get target file metadata
-        let target_metadata = fs::metadata(&copy_info.target_path).ok();
-        let target_size = target_metadata.as_ref().and_then(|m| {
-            if m.is_file() { Some(m.len()) } else { None }
-        });
-        let target_modified = target_metadata.as_ref().and_then(|m| m.modified().ok());
+    /// Whether a rescan or copy is still running in the background.
+    pub fn is_busy(&self) -> bool {
+        self.is_refreshing || self.copy_rx.is_some()
+    }
 
-        // Update nodes
-        if from_left_to_right {
-            // Update parent folder names (empty nodes)
-            Self::update_parent_folder_names(&mut self.comparison.right_tree, &target_relative, &target_dir);
+    /// Quit, unless a rescan or copy is running: then ask first. Returns
+    /// whether to quit right away.
+    fn request_quit(&mut self) -> bool {
+        if self.is_busy() {
+            self.mode_before_quit = self.mode;
+            self.mode = AppMode::QuitConfirm;
+            return false;
+        }
+        true
+    }
 
-            // Update right tree
-            if let Some(target_node) = Self::find_node_in_tree_by_path(
-                &mut self.comparison.right_tree,
-                &target_relative
-            ) {
-                // If it was an empty node, update name and metadata
-                if target_node.name.is_empty() {
-                    if let Some(file_name) = copy_info.target_path.file_name() {
-                        target_node.name = file_name.to_string_lossy().to_string();
-                    }
-                }
-                target_node.status = new_status;
-                target_node.size = target_size;
-                target_node.modified = target_modified;
+    /// Stop a running rescan or copy and wait for its thread to finish, so
+    /// nothing is written after the app is gone. A cancelled copy is rolled
+    /// back.
+    pub fn cancel_background_work(&mut self) {
+        if let Some(worker) = self.refresh_worker.take() {
+            worker.cancel();
+        }
+        if let Some(worker) = self.copy_worker.take() {
+            worker.cancel();
+        }
+        if let Some((worker, _)) = self.similarity_worker.take() {
+            worker.cancel();
+        }
+        self.stop_transfer_count();
+        self.stop_hashing();
+        self.stop_diffstat();
+        self.refresh_rx = None;
+        self.copy_rx = None;
+        self.is_refreshing = false;
+    }
 
-                if let Some(source_node) = Self::find_node_in_tree_by_path(
-                    &mut self.comparison.left_tree,
-                    &source_relative
-                ) {
-                    source_node.status = new_status;
-                }
+    /// Go back to the directory view from `mode`, or once the quit question
+    /// is answered if it's open.
+    fn leave_mode(&mut self, mode: AppMode) {
+        if self.mode == AppMode::QuitConfirm {
+            if self.mode_before_quit == mode {
+                self.mode_before_quit = AppMode::DirectoryView;
             }
-            // Update parent statuses
-            Self::update_parent_statuses_static(&mut self.comparison.left_tree, &source_relative);
-            Self::update_parent_statuses_static(&mut self.comparison.right_tree, &target_relative);
         } else {
-            // Update parent folder names (empty nodes)
-            Self::update_parent_folder_names(&mut self.comparison.left_tree, &target_relative, &target_dir);
-
-            // Update left tree
-            if let Some(target_node) = Self::find_node_in_tree_by_path(
-                &mut self.comparison.left_tree,
-                &target_relative
-            ) {
-                // If it was an empty node, update name and metadata
-                if target_node.name.is_empty() {
-                    if let Some(file_name) = copy_info.target_path.file_name() {
-                        target_node.name = file_name.to_string_lossy().to_string();
-                    }
-                }
-                target_node.status = new_status;
-                target_node.size = target_size;
-                target_node.modified = target_modified;
-
-                if let Some(source_node) = Self::find_node_in_tree_by_path(
-                    &mut self.comparison.right_tree,
-                    &source_relative
-                ) {
-                    source_node.status = new_status;
-                }
-            }
-            // Update parent statuses
-            Self::update_parent_statuses_static(&mut self.comparison.right_tree, &source_relative);
-            Self::update_parent_statuses_static(&mut self.comparison.left_tree, &target_relative);
+            self.mode = AppMode::DirectoryView;
         }
+    }
 
-        // Update UI
-        self.update_file_lists();
+    /// Lock or unlock the panels; locking moves the other panel back to the
+    /// active panel's row.
+    pub fn toggle_panel_lock(&mut self) {
+        // Either way both panels start out on the active panel's row
+        let unlock = self.panels_locked;
+        self.panels_locked = true;
+        self.move_selection(0);
+        self.panels_locked = !unlock;
+        if self.panels_locked {
+            self.flash("Panels locked: moving in one moves the other".to_string());
+        } else {
+            self.flash("Panels unlocked: each one scrolls on its own (L to re-lock)".to_string());
+        }
+    }
 
-        // Restore saved state
-        if self.saved_expansion_state.is_some() {
-            self.restore_saved_state_safe();
+    /// Hide or show noise like `.DS_Store`, see `hide_noise`, keeping the
+    /// selection where it is.
+    pub fn toggle_noise(&mut self) {
+        self.hide_noise = !self.hide_noise;
+        self.set_filter_mode(self.filter_mode);
+        if self.hide_noise {
+            self.flash(format!("Hiding {}", self.noise.patterns().join(" ")));
+        } else {
+            self.flash("Showing all files again".to_string());
         }
+    }
 
-        Ok(())
+    /// Switch to the next filter mode and flash its name.
+    pub fn cycle_filter_mode(&mut self) {
+        self.set_filter_mode(self.filter_mode.next());
+        self.flash(format!("Filter: {}", self.filter_mode.label()));
     }
 
-    fn update_parent_folder_names(tree: &mut FileNode, child_path: &std::path::Path, base_dir: &std::path::Path) {
-        // Collect all parent paths
-        let mut parent_paths = Vec::new();
-        let mut current_path = child_path;
+    /// Show `message` on the bottom row for a moment.
+    pub fn flash(&mut self, message: String) {
+        self.flash = Some((message, Instant::now()));
+    }
 
-        while let Some(parent) = current_path.parent() {
-            if parent.as_os_str().is_empty() {
-                break;
-            }
-            parent_paths.push(parent.to_path_buf());
-            current_path = parent;
+    /// Drop the flashed message once it has been shown long enough; true
+    /// when the screen needs redrawing.
+    pub fn expire_flash(&mut self) -> bool {
+        if self
+            .flash
+            .as_ref()
+            .is_some_and(|(_, since)| since.elapsed() >= FLASH_TIME)
+        {
+            self.flash = None;
+            return true;
         }
+        false
+    }
+
+    /// Apply messages from a running copy. Returns whether anything changed.
+    pub fn check_copy_progress(&mut self) -> bool {
+        let Some(rx) = &self.copy_rx else {
+            return false;
+        };
 
-        // Set names for empty parent folder nodes
-        for parent_path in parent_paths {
-            if let Some(parent_node) = Self::find_node_in_tree_by_path(tree, &parent_path) {
-                if parent_node.name.is_empty() && parent_node.is_dir {
-                    // Get actual folder name from filesystem
-                    let full_path = base_dir.join(&parent_path);
-                    if let Some(folder_name) = full_path.file_name() {
-                        parent_node.name = folder_name.to_string_lossy().to_string();
+        let messages: Vec<CopyMessage> = rx.try_iter().collect();
+        let changed = !messages.is_empty();
+        for msg in messages {
+            match msg {
+                CopyMessage::Progress(progress) => self.copy_progress = Some(progress),
+                CopyMessage::StaleBackup(backup) => {
+                    crate::utils::log_info(&format!(
+                        "Found a backup left by a killed copy: {}",
+                        backup.display()
+                    ));
+                    self.stale_backups.push(backup);
+                }
+                CopyMessage::Complete => {
+                    self.copy_rx = None;
+                    self.copy_worker = None;
+                    let progress = self.copy_progress.take();
+                    if let Some(copy_info) = self.copy_info.take() {
+                        self.session_log.record(
+                            "copy",
+                            Self::copy_detail(&copy_info, progress.as_ref()),
+                            progress.map(|progress| progress.copied_bytes),
+                        );
+                        self.rescan_copy(&copy_info);
                     }
+                    self.leave_mode(AppMode::Copying);
+                    self.offer_restore();
+                    break;
+                }
+                CopyMessage::Error(error) => {
+                    // Stay in the popup so the error can be read; Esc closes it
+                    self.copy_rx = None;
+                    self.copy_worker = None;
+                    if let Some(copy_info) = self.copy_info.take() {
+                        let detail = Self::copy_detail(&copy_info, self.copy_progress.as_ref());
+                        self.session_log.record_failure("copy", detail, &error);
+                    }
+                    self.copy_error = Some(error);
+                    break;
                 }
             }
         }
+        changed
     }
 
-    fn find_node_in_tree_by_path<'a>(
-        node: &'a mut FileNode,
-        target_path: &std::path::Path,
-    ) -> Option<&'a mut FileNode> {
-        // Compare current node path with target path
-        if node.path.ends_with(target_path) || target_path == node.path.as_path() {
-            return Some(node);
+    /// What a copy wrote, for the session log.
+    fn copy_detail(copy_info: &CopyInfo, progress: Option<&CopyProgress>) -> String {
+        let mut detail = format!(
+            "{} -> {}",
+            crate::utils::escape_name(&copy_info.source_path),
+            crate::utils::escape_name(&copy_info.target_path)
+        );
+        if copy_info.only_differences {
+            detail.push_str(", only differences");
         }
-
-        // Search child nodes recursively
-        for child in &mut node.children {
-            if let Some(found) = Self::find_node_in_tree_by_path(child, target_path) {
-                return Some(found);
+        if let Some(progress) = progress {
+            detail.push_str(&format!(", {} files", progress.files_done));
+            if progress.files_skipped > 0 {
+                detail.push_str(&format!(" ({} skipped)", progress.files_skipped));
             }
         }
-
-        None
+        detail
     }
 
-    fn check_if_files_same_static(left_path: &PathBuf, right_path: &PathBuf) -> Result<bool> {
-        use std::fs;
-        use crate::compare::DirectoryComparison;
-
-        if !left_path.exists() || !right_path.exists() {
-            return Ok(false);
+    /// Add a change made to the folders to the session log, whether it
+    /// worked or not.
+    fn log_operation<T, E: std::fmt::Display>(
+        &mut self,
+        action: &'static str,
+        detail: String,
+        bytes: Option<u64>,
+        result: &std::result::Result<T, E>,
+    ) {
+        match result {
+            Ok(_) => self.session_log.record(action, detail, bytes),
+            Err(e) => self
+                .session_log
+                .record_failure(action, detail, &format!("{:#}", e)),
         }
+    }
 
-        let left_meta = fs::metadata(left_path)?;
-        let right_meta = fs::metadata(right_path)?;
+    /// Close the popup of a failed copy and rescan, since rolling it back
+    /// may not have undone everything.
+    pub fn dismiss_copy_error(&mut self) {
+        self.copy_error = None;
+        self.copy_progress = None;
+        self.mode = AppMode::DirectoryView;
+        self.start_refresh();
+        self.offer_restore();
+    }
 
-        // For directories, return false (need to compare children)
-        if left_meta.is_dir() || right_meta.is_dir() {
-            return Ok(false);
+    /// Ask whether to restore the backups the copy came across, once it's
+    /// over and the directory view is back.
+    fn offer_restore(&mut self) {
+        if !self.stale_backups.is_empty() && self.mode == AppMode::DirectoryView {
+            self.mode = AppMode::RestoreConfirm;
         }
+    }
 
-        DirectoryComparison::files_are_same_public(left_path, right_path, &left_meta, &right_meta)
+    /// Put the backups a killed copy left back under their own names, and
+    /// compare everything again.
+    pub fn restore_backups(&mut self) {
+        let backups = std::mem::take(&mut self.stale_backups);
+        let mut failed = 0;
+        for backup in &backups {
+            let result = crate::copy::restore_backup(backup);
+            self.log_operation("restore", crate::utils::escape_name(backup), None, &result);
+            if let Err(e) = result {
+                crate::utils::log_error(&format!(
+                    "Failed to restore {}: {:#}",
+                    backup.display(),
+                    e
+                ));
+                failed += 1;
+            }
+        }
+        self.mode = AppMode::DirectoryView;
+        if failed > 0 {
+            self.flash(format!(
+                "Couldn't restore {} of {} backups, see the log",
+                failed,
+                backups.len()
+            ));
+        } else {
+            self.flash(format!("Restored {} backups", backups.len()));
+        }
+        self.refresh_again();
     }
 
-    fn update_parent_statuses_static(tree: &mut FileNode, child_path: &std::path::Path) {
-        use crate::compare::FileStatus;
+    /// Leave the backups a killed copy left where they are. They stay out
+    /// of comparisons and are offered again by the next copy there.
+    pub fn keep_backups(&mut self) {
+        let count = self.stale_backups.len();
+        self.stale_backups.clear();
+        self.mode = AppMode::DirectoryView;
+        self.flash(format!("Kept {} backups, see the log for where", count));
+    }
 
-        // Collect all parent paths
-        let mut parent_paths = Vec::new();
-        let mut current_path = child_path;
+    /// Start a full refresh, stopping a running one first: it may have read
+    /// the folders before they were changed.
+    fn refresh_again(&mut self) {
+        if let Some(worker) = self.refresh_worker.take() {
+            worker.cancel();
+            self.refresh_rx = None;
+            self.is_refreshing = false;
+        }
+        self.start_refresh();
+    }
 
-        while let Some(parent) = current_path.parent() {
-            if parent.as_os_str().is_empty() {
-                break;
-            }
-            parent_paths.push(parent.to_path_buf());
-            current_path = parent;
+    /// Compare what was copied again on the refresh worker, together with
+    /// the folders the copy had to create; `check_refresh_progress` puts it
+    /// in place and moves the selection onto it in the target panel.
+    fn rescan_copy(&mut self, copy_info: &CopyInfo) {
+        // The running one may have read the target before the copy
+        if self.refresh_worker.is_some() {
+            self.refresh_again();
+            return;
         }
 
-        // Update parent statuses in reverse order
-        parent_paths.reverse();
-        for parent_path in parent_paths {
-            if let Some(parent_node) = Self::find_node_in_tree_by_path(tree, &parent_path) {
-                // Check children statuses to determine parent status
-                let child_statuses: Vec<FileStatus> = parent_node.children.iter()
-                    .map(|c| c.status)
-                    .collect();
+        let relative = self
+            .comparison
+            .relative_on(!copy_info.from_left_to_right, &copy_info.target_path)
+            .unwrap_or_else(|| copy_info.target_path.clone());
+        self.verified.retain(|path| !path.starts_with(&relative));
 
-                if child_statuses.is_empty() {
-                    continue;
-                }
+        // Folders that only existed on the source side were created too
+        let target_tree = if copy_info.from_left_to_right {
+            &self.comparison.right_tree
+        } else {
+            &self.comparison.left_tree
+        };
+        let rescan = relative
+            .ancestors()
+            .filter(|path| !path.as_os_str().is_empty())
+            .filter(|path| target_tree.find(path).is_some_and(|node| !node.exists))
+            .last()
+            .unwrap_or(&relative)
+            .to_path_buf();
 
-                let has_different = child_statuses.iter().any(|&s| s == FileStatus::Different);
-                let has_left_only = child_statuses.iter().any(|&s| s == FileStatus::LeftOnly);
-                let has_right_only = child_statuses.iter().any(|&s| s == FileStatus::RightOnly);
-                let has_same = child_statuses.iter().any(|&s| s == FileStatus::Same);
+        self.is_refreshing = true;
+        self.refresh_progress = "Comparing the copy again...".to_string();
+        self.refresh_percentage = 0.0;
 
-                let new_status = if has_different {
-                    FileStatus::Different
-                } else if has_left_only && has_right_only {
-                    FileStatus::Different
-                } else if has_left_only && has_same {
-                    FileStatus::Different
-                } else if has_right_only && has_same {
-                    FileStatus::Different
-                } else if has_left_only {
-                    FileStatus::LeftOnly
-                } else if has_right_only {
-                    FileStatus::RightOnly
-                } else {
-                    FileStatus::Same
-                };
+        let (tx, rx) = mpsc::channel();
+        self.refresh_rx = Some(rx);
 
-                parent_node.status = new_status;
-            }
+        let left_dir = self.comparison.left_dir.clone();
+        let right_dir = self.comparison.right_dir.clone();
+        let options = self.comparison.options.clone();
+        let to_right = copy_info.from_left_to_right;
+
+        self.refresh_worker = Some(Worker::spawn(move |cancel| {
+            let result = DirectoryComparison::rescan_path(
+                &left_dir,
+                &right_dir,
+                &rescan,
+                &options,
+                &cancel,
+                |msg| {
+                    let _ = tx.send(RefreshMessage::Progress(
+                        msg.to_string(),
+                        progress_percentage(msg),
+                    ));
+                },
+            );
+            let result = result
+                .map(|(left, right)| Box::new((rescan, left, right)))
+                .map_err(|e| format!("{:#}", e));
+            let _ = tx.send(RefreshMessage::Copied(relative, to_right, result));
+        }));
+    }
+
+    /// Put a copy compared again by `rescan_copy` in place, or rescan
+    /// everything if that failed.
+    fn apply_copy_rescan(
+        &mut self,
+        copied: &Path,
+        to_right: bool,
+        result: Result<CopyRescan, String>,
+    ) {
+        let replaced = result.map_err(anyhow::Error::msg).and_then(|nodes| {
+            let (path, left, right) = *nodes;
+            self.comparison.replace_path(&path, left, right)
+        });
+        if let Err(e) = replaced {
+            crate::utils::log_error(&format!(
+                "Failed to compare the copy again, rescanning: {:#}",
+                e
+            ));
+            self.start_refresh();
+            return;
         }
+
+        if self.saved_expansion_state.is_some() {
+            self.restore_saved_state_safe();
+        } else {
+            self.update_file_lists();
+        }
+        self.active_panel = if to_right { 1 } else { 0 };
+        self.select_path(copied);
     }
 
     pub fn cancel_copy(&mut self) {
+        self.stop_transfer_count();
         self.copy_info = None;
         self.mode = AppMode::DirectoryView;
     }
 
     pub fn can_delete(&self) -> bool {
-        if let Some((name, _status, _path, _is_dir, _size, _modified)) = self.get_selected_item() {
-            !name.is_empty()
+        if self.comparison.is_manifest(self.active_panel == 0)
+            || self.comparison.loaded_from.is_some()
+        {
+            return false;
+        }
+        if let Some(item) = self.get_selected_item() {
+            !item.display_name.trim().is_empty()
         } else {
             false
         }
     }
 
     pub fn prepare_delete(&mut self) {
-        if let Some((_, _, path, is_dir, size, _)) = self.get_selected_item() {
+        if self.refuse_read_only("deleting") {
+            return;
+        }
+        if let Some(FileItem {
+            path, is_dir, size, ..
+        }) = self.get_selected_item()
+        {
             let is_left = self.active_panel == 0;
 
-            let full_path = if is_left {
-                self.comparison.left_dir.join(path)
-            } else {
-                self.comparison.right_dir.join(path)
+            let Some(full_path) = self.comparison.side_path(is_left, path) else {
+                return;
             };
 
             let (file_count, folder_count, total_bytes) = if *is_dir {
@@ -1094,11 +2927,20 @@ impl App {
 
             self.save_current_state();
 
-            if delete_info.path.is_dir() {
-                fs::remove_dir_all(&delete_info.path)?;
+            let result = if delete_info.path.is_dir() {
+                fs::remove_dir_all(&delete_info.path)
             } else {
-                fs::remove_file(&delete_info.path)?;
+                fs::remove_file(&delete_info.path)
+            };
+            let mut detail = crate::utils::escape_name(&delete_info.path);
+            if delete_info.folder_count > 0 {
+                detail.push_str(&format!(
+                    ", {} files in {} folders",
+                    delete_info.file_count, delete_info.folder_count
+                ));
             }
+            self.log_operation("delete", detail, Some(delete_info.total_bytes), &result);
+            result?;
 
             // Wait for filesystem sync
             std::thread::sleep(std::time::Duration::from_millis(200));
@@ -1107,7 +2949,9 @@ impl App {
             let left_dir = self.comparison.left_dir.clone();
             let right_dir = self.comparison.right_dir.clone();
 
-            match DirectoryComparison::new_silent(left_dir, right_dir) {
+            let options = self.comparison.options.clone();
+
+            match DirectoryComparison::new_silent_with_options(left_dir, right_dir, options) {
                 Ok(new_comparison) => {
                     self.comparison = new_comparison;
                     self.comparison.left_tree.expanded = true;
@@ -1135,53 +2979,1183 @@ impl App {
         self.mode = AppMode::DirectoryView;
     }
 
-    fn save_current_state(&mut self) {
-        self.saved_left_selection = self.left_list_state.selected();
-        self.saved_right_selection = self.right_list_state.selected();
-        self.saved_active_panel = self.active_panel;
-
-        self.saved_filter_mode = Some(self.filter_mode);
+    /// The selected row when its entry is present on both sides.
+    fn selected_pair(&self) -> Option<(&FileItem, &FileItem)> {
+        if self.comparison.has_manifest() || self.comparison.loaded_from.is_some() {
+            return None;
+        }
+        let state = if self.active_panel == 0 {
+            &self.left_list_state
+        } else {
+            &self.right_list_state
+        };
+        let index = state.selected()?;
+        let left = self.left_items.get(index)?;
+        let right = self.right_items.get(index)?;
+        (!left.display_name.trim().is_empty() && !right.display_name.trim().is_empty())
+            .then_some((left, right))
+    }
 
-        self.saved_expansion_state = Some((
-            self.comparison.left_tree.clone(),
-            self.comparison.right_tree.clone(),
-        ));
+    /// The selected row when it is a file present on both sides.
+    fn selected_file_pair(&self) -> Option<(&FileItem, &FileItem)> {
+        self.selected_pair()
+            .filter(|(left, right)| !left.is_dir && !right.is_dir)
     }
 
-    fn restore_saved_state_safe(&mut self) {
-        if let Some(original_filter) = self.saved_filter_mode.take() {
-            self.filter_mode = original_filter;
+    /// Hash the selected file on both sides and show the hashes.
+    pub fn show_hashes(&mut self) {
+        if self.comparison.has_manifest() || self.comparison.loaded_from.is_some() {
+            self.flash("Hashes need both folders on disk".to_string());
+            return;
         }
+        let Some(row) = (if self.active_panel == 0 {
+            self.left_list_state.selected()
+        } else {
+            self.right_list_state.selected()
+        }) else {
+            return;
+        };
+        let sides = [
+            (self.left_items.get(row), true),
+            (self.right_items.get(row), false),
+        ];
+        let Some(path) = sides
+            .iter()
+            .filter_map(|(item, _)| *item)
+            .find(|item| !item.display_name.trim().is_empty() && !item.is_dir)
+            .map(|item| item.path.clone())
+        else {
+            self.flash("Select a file to show its hashes".to_string());
+            return;
+        };
+        let algorithm = self.hash_algorithm;
+        let present = sides.map(|(item, left)| {
+            item.filter(|item| !item.display_name.trim().is_empty())
+                .and_then(|_| self.comparison.side_path(left, &path))
+        });
+        let [left, right] = present.clone().map(|file| match file {
+            Some(file) => HashSide::Hashing(0, std::fs::metadata(file).map_or(0, |m| m.len())),
+            None => HashSide::Missing,
+        });
+        let types = present
+            .clone()
+            .map(|file| file.and_then(|file| crate::filetype::detect(&file)));
+        self.hash_info = Some(HashInfo {
+            path,
+            algorithm,
+            left,
+            right,
+            types,
+            copied: None,
+        });
+        self.mode = AppMode::HashView;
 
-        self.active_panel = self.saved_active_panel;
-
-        use std::io::Write;
-        let mut debug = format!(
-            "=== BEFORE restoration ===\nLeft tree expanded: {}\nRight tree expanded: {}\n",
-            self.comparison.left_tree.expanded, self.comparison.right_tree.expanded
-        );
-
-        if let Some((saved_left_tree, saved_right_tree)) = self.saved_expansion_state.take() {
-            debug.push_str(&format!(
-                "Restoring from saved state - Left: {}, Right: {}\n",
-                saved_left_tree.expanded, saved_right_tree.expanded
-            ));
-
-            debug.push_str("=== Current Right Tree (before restore) ===\n");
-            Self::debug_tree_structure(&self.comparison.right_tree, 0, &mut debug);
+        // Big files take a while, so they're read off the UI thread
+        self.stop_hashing();
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker::spawn(move |cancel| {
+            for (file, left) in present.into_iter().zip([true, false]) {
+                let Some(file) = file else {
+                    continue;
+                };
+                let total = std::fs::metadata(&file).map_or(0, |m| m.len());
+                let mut reported = Instant::now();
+                let result = file_hash(&file, algorithm, &cancel, |done| {
+                    if reported.elapsed() >= HASH_PROGRESS_INTERVAL {
+                        reported = Instant::now();
+                        let _ = tx.send((left, HashSide::Hashing(done, total)));
+                    }
+                });
+                if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                    return;
+                }
+                let _ = tx.send((left, HashSide::Done(result.map_err(|e| format!("{:#}", e)))));
+            }
+        });
+        self.hash_worker = Some((worker, rx));
+    }
 
-            debug.push_str("=== Saved Right Tree ===\n");
-            Self::debug_tree_structure(&saved_right_tree, 0, &mut debug);
+    fn stop_hashing(&mut self) {
+        if let Some((worker, _)) = self.hash_worker.take() {
+            worker.cancel();
+        }
+    }
 
-            Self::restore_expansion_state_safe(&mut self.comparison.left_tree, &saved_left_tree);
-            Self::restore_expansion_state_safe(&mut self.comparison.right_tree, &saved_right_tree);
+    /// Put what the worker `show_hashes` started has hashed into the popup.
+    /// Returns whether it changed.
+    pub fn check_hash_progress(&mut self) -> bool {
+        let Some((_, rx)) = &self.hash_worker else {
+            return false;
+        };
+        let mut changed = false;
+        loop {
+            match rx.try_recv() {
+                Ok((left, side)) => {
+                    if let Some(info) = &mut self.hash_info {
+                        *(if left {
+                            &mut info.left
+                        } else {
+                            &mut info.right
+                        }) = side;
+                    }
+                    changed = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => return changed,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.hash_worker = None;
+                    return changed;
+                }
+            }
+        }
+    }
 
-            debug.push_str("=== Current Right Tree (after restore) ===\n");
-            Self::debug_tree_structure(&self.comparison.right_tree, 0, &mut debug);
+    /// Count the lines removed and added in every differing file below the
+    /// selected folder pair, or below the panels' roots when a file is
+    /// selected, for a sense of how much changed between two versions.
+    pub fn show_diffstat(&mut self) {
+        if self.comparison.has_manifest() || self.comparison.loaded_from.is_some() {
+            self.flash("A diffstat needs both folders on disk".to_string());
+            return;
         }
+        let folder = match self.get_selected_item() {
+            Some(item) if item.is_dir && !item.display_name.trim().is_empty() => {
+                item.path.to_path_buf()
+            }
+            _ => self.zoom.clone().unwrap_or_default(),
+        };
+        let files = self.comparison.diffstat_files(&folder);
+        self.diffstat = Some(DiffStat {
+            folder,
+            files: Vec::new(),
+            total: files.len(),
+            scroll: 0,
+        });
+        self.mode = AppMode::DiffStat;
 
-        self.comparison.left_tree.expanded = true;
-        self.comparison.right_tree.expanded = true;
+        // Diffing many or big files takes a while, so it's off the UI thread
+        self.stop_diffstat();
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker::spawn(move |cancel| {
+            for (path, [left, right]) in files {
+                if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                    return;
+                }
+                let changes = DirectoryComparison::line_changes(left.as_deref(), right.as_deref());
+                if tx.send((path, changes)).is_err() {
+                    return;
+                }
+            }
+        });
+        self.diffstat_worker = Some((worker, rx));
+    }
+
+    fn stop_diffstat(&mut self) {
+        if let Some((worker, _)) = self.diffstat_worker.take() {
+            worker.cancel();
+        }
+    }
+
+    /// Put the line counts the worker `show_diffstat` started has made
+    /// into the popup. Returns whether it changed.
+    pub fn check_diffstat_progress(&mut self) -> bool {
+        let Some((_, rx)) = &self.diffstat_worker else {
+            return false;
+        };
+        let mut changed = false;
+        loop {
+            match rx.try_recv() {
+                Ok(file) => {
+                    if let Some(diffstat) = &mut self.diffstat {
+                        diffstat.files.push(file);
+                    }
+                    changed = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => return changed,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.diffstat_worker = None;
+                    return changed;
+                }
+            }
+        }
+    }
+
+    /// Open the list of changes made to the folders this session, scrolled
+    /// to the latest.
+    pub fn show_session_log(&mut self) {
+        self.session_log_scroll = 0;
+        self.mode = AppMode::SessionLog;
+    }
+
+    /// Copy one side's hash to the clipboard.
+    fn copy_hash(&mut self, left: bool) {
+        let Some(info) = &mut self.hash_info else {
+            return;
+        };
+        let side = if left { "left" } else { "right" };
+        let hash = if left { &info.left } else { &info.right };
+        info.copied = Some(match hash {
+            HashSide::Done(Ok(_)) if self.headless => format!("Copied the {} hash", side),
+            HashSide::Done(Ok(hash)) => match crate::terminal::copy_to_clipboard(hash) {
+                Ok(()) => format!("Copied the {} hash", side),
+                Err(e) => format!("Couldn't copy: {}", e),
+            },
+            _ => format!("No {} hash to copy", side),
+        });
+    }
+
+    pub fn can_equalize_timestamps(&self) -> bool {
+        self.selected_file_pair().is_some()
+    }
+
+    pub fn prepare_equalize_timestamps(&mut self) {
+        use std::fs;
+
+        if self.refuse_read_only("changing timestamps") {
+            return;
+        }
+        let Some((FileItem { path, .. }, _)) = self.selected_file_pair() else {
+            return;
+        };
+        let path = path.clone();
+        let modified = |left: bool| {
+            let file = self.comparison.side_path(left, &path)?;
+            fs::metadata(file).and_then(|m| m.modified()).ok()
+        };
+        let choice = if self.active_panel == 0 {
+            TimestampSync::LeftToRight
+        } else {
+            TimestampSync::RightToLeft
+        };
+
+        self.timestamp_info = Some(TimestampInfo {
+            left_modified: modified(true),
+            right_modified: modified(false),
+            path,
+            choice,
+        });
+        self.mode = AppMode::TimestampConfirm;
+    }
+
+    /// Set one file's modification time to the other's without touching
+    /// the contents, then compare the pair again.
+    pub fn execute_equalize_timestamps(&mut self) -> Result<()> {
+        use std::fs;
+
+        let Some(info) = self.timestamp_info.take() else {
+            self.mode = AppMode::DirectoryView;
+            return Ok(());
+        };
+        self.mode = AppMode::DirectoryView;
+
+        let (Some(left_time), Some(right_time)) = (info.left_modified, info.right_modified) else {
+            anyhow::bail!("modification time of {} unavailable", info.path.display());
+        };
+        let (to_left, time) = match info.choice {
+            TimestampSync::LeftToRight => (false, left_time),
+            TimestampSync::RightToLeft => (true, right_time),
+            TimestampSync::BothToNewer if left_time >= right_time => (false, left_time),
+            TimestampSync::BothToNewer => (true, right_time),
+        };
+
+        let Some(target) = self.comparison.side_path(to_left, &info.path) else {
+            anyhow::bail!("{}", Self::unmapped(&info.path));
+        };
+        let result = fs::File::open(&target).and_then(|file| file.set_modified(time));
+        let detail = format!(
+            "{} to {}",
+            crate::utils::escape_name(&target),
+            chrono::DateTime::<chrono::Local>::from(time).format("%Y-%m-%d %H:%M:%S")
+        );
+        self.log_operation("timestamp", detail, None, &result);
+        result?;
+        self.recompare_path(&info.path)
+    }
+
+    pub fn cancel_equalize_timestamps(&mut self) {
+        self.timestamp_info = None;
+        self.mode = AppMode::DirectoryView;
+    }
+
+    /// Files and folders alike, as long as both sides have the entry and
+    /// agree on what it is.
+    pub fn can_equalize_permissions(&self) -> bool {
+        !self.read_only
+            && self
+                .selected_pair()
+                .is_some_and(|(left, right)| left.is_dir == right.is_dir)
+    }
+
+    pub fn prepare_equalize_permissions(&mut self) {
+        use std::fs;
+
+        if self.refuse_read_only("changing permissions") || !self.can_equalize_permissions() {
+            return;
+        }
+        let Some((FileItem { path, is_dir, .. }, _)) = self.selected_pair() else {
+            return;
+        };
+        let (path, is_dir) = (path.clone(), *is_dir);
+        let mode = |left: bool| {
+            let file = self.comparison.side_path(left, &path)?;
+            fs::metadata(file).ok().and_then(|m| permission_bits(&m))
+        };
+
+        self.permissions_info = Some(PermissionsInfo {
+            left_mode: mode(true),
+            right_mode: mode(false),
+            path,
+            is_dir,
+            to_right: self.active_panel == 0,
+        });
+        self.mode = AppMode::PermissionsConfirm;
+    }
+
+    /// Give the entry on one side the permissions of its counterpart; for a
+    /// folder, every entry inside that exists on both sides too.
+    pub fn execute_equalize_permissions(&mut self) -> Result<()> {
+        use std::fs;
+
+        let Some(info) = self.permissions_info.take() else {
+            self.mode = AppMode::DirectoryView;
+            return Ok(());
+        };
+        self.mode = AppMode::DirectoryView;
+
+        let (Some(source_root), Some(target_root)) = (
+            self.comparison.side_path(info.to_right, &info.path),
+            self.comparison.side_path(!info.to_right, &info.path),
+        ) else {
+            anyhow::bail!("{}", Self::unmapped(&info.path));
+        };
+        let detail = format!(
+            "{} from {}",
+            crate::utils::escape_name(&target_root),
+            crate::utils::escape_name(&source_root)
+        );
+
+        if !info.is_dir {
+            let result = fs::metadata(&source_root)
+                .and_then(|source| fs::set_permissions(&target_root, source.permissions()));
+            self.log_operation("permissions", detail, None, &result);
+            result?;
+            return self.recompare_path(&info.path);
+        }
+
+        // Folders last: dropping their execute bit first would lock us out
+        // of their contents
+        let mut changed = 0;
+        let result = walkdir::WalkDir::new(&source_root)
+            .contents_first(true)
+            .into_iter()
+            .try_for_each(|entry| -> Result<()> {
+                let entry = entry?;
+                // set_permissions follows links, which would change their targets
+                if entry.path_is_symlink() {
+                    return Ok(());
+                }
+                let relative = entry.path().strip_prefix(&source_root)?;
+                let target = target_root.join(relative);
+                match fs::symlink_metadata(&target) {
+                    Ok(existing)
+                        if !existing.is_symlink()
+                            && existing.is_dir() == entry.file_type().is_dir() =>
+                    {
+                        fs::set_permissions(&target, entry.metadata()?.permissions())?;
+                        changed += 1;
+                    }
+                    _ => {}
+                }
+                Ok(())
+            });
+        let detail = format!("{}, {} entries", detail, changed);
+        self.log_operation("permissions", detail, None, &result);
+        result?;
+
+        // Statuses of everything inside may have changed
+        self.save_current_state();
+        self.start_refresh();
+        Ok(())
+    }
+
+    pub fn cancel_equalize_permissions(&mut self) {
+        self.permissions_info = None;
+        self.mode = AppMode::DirectoryView;
+    }
+
+    /// Start naming a new folder on the active side: inside the selected
+    /// folder, or next to the selected file.
+    pub fn prepare_create_folder(&mut self) {
+        if self.refuse_read_only("creating folders")
+            || self.comparison.is_manifest(self.active_panel == 0)
+            || self.comparison.loaded_from.is_some()
+        {
+            return;
+        }
+        let parent = match self.get_selected_item() {
+            Some(item) if item.is_dir && !item.display_name.trim().is_empty() => {
+                item.path.to_path_buf()
+            }
+            Some(item) => item
+                .path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            None => PathBuf::new(),
+        };
+
+        self.name_input = Some(NameInputInfo {
+            action: NameAction::CreateFolder,
+            is_left: self.active_panel == 0,
+            parent,
+            original: None,
+            input: String::new(),
+            error: None,
+        });
+        self.mode = AppMode::NameInput;
+    }
+
+    /// Whether `--read-only` rules out `action`, flashing so when it does.
+    /// Everything that changes the folders asks this first.
+    fn refuse_read_only(&mut self, action: &str) -> bool {
+        if self.read_only {
+            self.flash(format!("Read-only: {} is disabled", action));
+        }
+        self.read_only
+    }
+
+    /// Why nothing can be done at `path` on the right: a folder mapped
+    /// with `--map` has taken its name there.
+    fn unmapped(path: &Path) -> String {
+        format!(
+            "{} has no place on the right: a mapped folder has its name",
+            crate::utils::escape_name(path)
+        )
+    }
+
+    pub fn can_rename(&self) -> bool {
+        self.can_delete()
+    }
+
+    pub fn prepare_rename(&mut self) {
+        if self.refuse_read_only("renaming") {
+            return;
+        }
+        let Some(FileItem {
+            display_name: name,
+            path,
+            ..
+        }) = self.get_selected_item()
+        else {
+            return;
+        };
+        if name.trim().is_empty() {
+            return;
+        }
+        let original = path.file_name().unwrap_or_default().to_os_string();
+        // Editing the escaped text would rename to the escapes themselves
+        let Some(input) = original
+            .to_str()
+            .filter(|raw| crate::utils::escape_name(raw) == *raw)
+            .map(str::to_string)
+        else {
+            self.flash(format!(
+                "Cannot rename '{}': its name has characters shown escaped",
+                crate::utils::escape_name(&original)
+            ));
+            return;
+        };
+
+        self.name_input = Some(NameInputInfo {
+            action: NameAction::Rename,
+            is_left: self.active_panel == 0,
+            parent: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            input,
+            original: Some(original),
+            error: None,
+        });
+        self.mode = AppMode::NameInput;
+    }
+
+    /// Create or rename; on failure the input stays open with the reason.
+    pub fn execute_name_input(&mut self) {
+        use std::fs;
+
+        let Some(info) = self.name_input.as_mut() else {
+            self.mode = AppMode::DirectoryView;
+            return;
+        };
+        let name = info.input.as_str();
+        if matches!(name, "" | "." | "..") || name.contains(std::path::is_separator) {
+            info.error = Some(format!("'{}' is not a valid name", name));
+            return;
+        }
+
+        let Some(dir) = self.comparison.side_path(info.is_left, &info.parent) else {
+            info.error = Some(Self::unmapped(&info.parent));
+            return;
+        };
+        let target = dir.join(name);
+        let (result, operation) = match &info.original {
+            // Left as it was
+            Some(original) if original == name => (Ok(()), None),
+            // rename would silently replace an existing file
+            _ if fs::symlink_metadata(&target).is_ok() => {
+                info.error = Some(format!("'{}' already exists", name));
+                return;
+            }
+            Some(original) => {
+                let source = dir.join(original);
+                let detail = format!(
+                    "{} -> {}",
+                    crate::utils::escape_name(&source),
+                    crate::utils::escape_name(&target)
+                );
+                (fs::rename(&source, &target), Some(("rename", detail)))
+            }
+            None => (
+                fs::create_dir(&target),
+                Some(("mkdir", crate::utils::escape_name(&target))),
+            ),
+        };
+        if let Some((action, detail)) = operation {
+            self.log_operation(action, detail, None, &result);
+        }
+        if let Err(e) = result {
+            if let Some(info) = self.name_input.as_mut() {
+                info.error = Some(e.to_string());
+            }
+            return;
+        }
+
+        let info = self.name_input.take().expect("checked above");
+        self.mode = AppMode::DirectoryView;
+        if info
+            .original
+            .as_ref()
+            .is_some_and(|original| *original == *info.input)
+        {
+            return;
+        }
+
+        // Open the folder the new one went into so it shows up
+        if info.action == NameAction::CreateFolder {
+            for tree in [
+                &mut self.comparison.left_tree,
+                &mut self.comparison.right_tree,
+            ] {
+                if let Some(node) = tree.find_mut(&info.parent) {
+                    node.expanded = true;
+                }
+            }
+        }
+        self.save_current_state();
+        self.start_refresh();
+    }
+
+    pub fn cancel_name_input(&mut self) {
+        self.name_input = None;
+        self.mode = AppMode::DirectoryView;
+    }
+
+    fn handle_name_input_key(&mut self, key: crossterm::event::KeyEvent) {
+        let Some(info) = self.name_input.as_mut() else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => self.cancel_name_input(),
+            KeyCode::Enter => self.execute_name_input(),
+            KeyCode::Backspace => {
+                info.input.pop();
+                info.error = None;
+            }
+            KeyCode::Char(c)
+                if !key
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                info.input.push(c);
+                info.error = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Re-root both panels on the selected folder, without rescanning.
+    pub fn zoom_in(&mut self) {
+        let Some(path) = self
+            .get_selected_item()
+            .filter(|item| item.is_dir && !item.display_name.trim().is_empty())
+            .map(|item| item.path.to_path_buf())
+        else {
+            return;
+        };
+        self.zoom = Some(path);
+        self.update_file_lists();
+        self.select_row(0);
+    }
+
+    /// Go back up one level from a zoomed-in folder, selecting it.
+    pub fn zoom_out(&mut self) {
+        let Some(path) = self.zoom.take() else {
+            return;
+        };
+        self.zoom = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map(Path::to_path_buf);
+        self.update_file_lists();
+        self.select_path(&path);
+    }
+
+    /// Exclude pattern matching exactly the selected entry. Patterns are
+    /// text, so a byte that isn't UTF-8 becomes a `?`, which matches it.
+    fn selected_pattern(&self) -> Option<String> {
+        let path = &self.get_selected_item()?.path;
+        if path.as_os_str().is_empty() {
+            return None;
+        }
+        let mut pattern = String::from("/");
+        for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+            pattern.push_str(&globset::escape(chunk.valid()));
+            pattern.extend(chunk.invalid().iter().map(|_| '?'));
+        }
+        Some(pattern)
+    }
+
+    /// The selected folder on each side, or the one containing the selected
+    /// file. A side without it gets the closest folder above that it has.
+    fn selected_folders(&self) -> (PathBuf, PathBuf) {
+        let folder = match self.get_selected_item() {
+            Some(item) if item.is_dir => item.path.to_path_buf(),
+            Some(item) => item
+                .path
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+            None => PathBuf::new(),
+        };
+        let existing = |left: bool| {
+            folder
+                .ancestors()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .filter_map(|dir| self.comparison.side_path(left, dir))
+                .find(|dir| dir.is_dir())
+                .unwrap_or_else(|| {
+                    let root = if left {
+                        &self.comparison.left_dir
+                    } else {
+                        &self.comparison.right_dir
+                    };
+                    root.clone()
+                })
+        };
+        (existing(true), existing(false))
+    }
+
+    /// Open the selected folder on both sides in a file manager or a tmux
+    /// split, see `terminal::open_both_sides`.
+    pub fn open_both_sides(&mut self) -> Result<()> {
+        // A file manager or shell could change anything
+        if self.read_only {
+            anyhow::bail!("read-only: opening the folders outside tudiff is disabled");
+        }
+        if self.headless {
+            return Ok(());
+        }
+        if self.comparison.has_manifest() {
+            anyhow::bail!("a manifest has no folders to open");
+        }
+        if self.comparison.loaded_from.is_some() {
+            anyhow::bail!("a saved result has no folders to open");
+        }
+        let (left, right) = self.selected_folders();
+        self.screen_replaced |= crate::terminal::open_both_sides(&left, &right)?;
+        Ok(())
+    }
+
+    /// Whether a program drew over the screen since the last call, so all
+    /// of it has to be drawn again.
+    pub fn take_screen_replaced(&mut self) -> bool {
+        std::mem::take(&mut self.screen_replaced)
+    }
+
+    /// Diff the selected file against any other file, e.g. an old backup
+    /// copy. A folder stands for the file of the same name in it.
+    pub fn compare_with(&mut self, other: &str) -> Result<()> {
+        if self.comparison.loaded_from.is_some() {
+            anyhow::bail!("a saved result has no files to compare");
+        }
+        let Some(FileItem {
+            display_name: name,
+            path,
+            is_dir,
+            ..
+        }) = self.get_selected_item()
+        else {
+            anyhow::bail!("nothing selected");
+        };
+        if name.trim().is_empty() {
+            anyhow::bail!("nothing on this side to compare");
+        }
+        if *is_dir {
+            anyhow::bail!("{} is a folder", path.display());
+        }
+        let path = path.clone();
+        let selected = self
+            .comparison
+            .side_path(self.active_panel == 0, &path)
+            .filter(|selected| selected.is_file())
+            .ok_or_else(|| anyhow::anyhow!("{} is not a file on disk", path.display()))?;
+        let mut other = PathBuf::from(crate::picker::expand_home(other));
+        if other.is_dir() {
+            other.push(selected.file_name().unwrap_or_default());
+        }
+        if !other.is_file() {
+            anyhow::bail!("{} is not a file", other.display());
+        }
+        if self.headless {
+            return Ok(());
+        }
+
+        let before = file_stamp(&selected);
+        crate::terminal::launch_external_editor(
+            &FileStatus::Different,
+            &selected,
+            &other,
+            self.read_only,
+        )?;
+        // The selected file may have been edited inside vimdiff
+        if self.comparison.has_manifest() || file_stamp(&selected) == before {
+            return Ok(());
+        }
+        self.recompare_path(&path)?;
+        self.flash(format!(
+            "{} changed, compared again",
+            crate::utils::escape_name(&*path)
+        ));
+        Ok(())
+    }
+
+    /// Never show the selected entry again this session, e.g. a noisy
+    /// build folder found halfway through a review.
+    pub fn exclude_selected(&mut self) {
+        let Some(pattern) = self.selected_pattern() else {
+            return;
+        };
+        if let Err(e) = self.exclude(&[pattern]) {
+            crate::utils::log_error(&format!("Failed to exclude the selection: {:#}", e));
+        }
+    }
+
+    /// Add exclude patterns to every comparison, dropping what they match
+    /// from the panels and the difference count.
+    fn exclude(&mut self, patterns: &[String]) -> Result<()> {
+        self.comparison.exclude(patterns)?;
+        for comparison in &mut self.target_comparisons {
+            comparison.exclude(patterns)?;
+        }
+        if self.is_multi_target() {
+            self.target_status_maps = (0..self.target_count())
+                .map(|index| Self::build_status_map(&self.target_comparison(index).left_tree))
+                .collect();
+        }
+
+        // The rows below move up into the excluded one's place
+        let selected = if self.active_panel == 0 {
+            self.left_list_state.selected()
+        } else {
+            self.right_list_state.selected()
+        };
+        self.update_file_lists();
+        self.select_row(selected.unwrap_or(0));
+        self.move_selection(0);
+        Ok(())
+    }
+
+    pub fn open_command_line(&mut self) {
+        self.command_line = Some(CommandLine::default());
+        self.mode = AppMode::CommandLine;
+    }
+
+    /// Open the command line with `input` already typed, for commands that
+    /// need an argument.
+    pub fn open_command_line_with(&mut self, input: &str) {
+        self.command_line = Some(CommandLine {
+            input: input.to_string(),
+            error: None,
+        });
+        self.mode = AppMode::CommandLine;
+    }
+
+    pub fn close_command_line(&mut self) {
+        self.command_line = None;
+        self.mode = AppMode::DirectoryView;
+    }
+
+    /// Returns true when the key asked the app to quit.
+    fn handle_command_line_key(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        let Some(line) = self.command_line.as_mut() else {
+            return false;
+        };
+        match key.code {
+            KeyCode::Esc => self.close_command_line(),
+            KeyCode::Backspace if line.input.is_empty() => self.close_command_line(),
+            KeyCode::Backspace => {
+                line.input.pop();
+                line.error = None;
+            }
+            KeyCode::Enter => {
+                let input = line.input.clone();
+                // Commands may open a popup of their own
+                self.close_command_line();
+                match self.run_command(&input) {
+                    Ok(quit) => return quit,
+                    Err(error) => {
+                        self.command_line = Some(CommandLine {
+                            input,
+                            error: Some(error),
+                        });
+                        self.mode = AppMode::CommandLine;
+                    }
+                }
+            }
+            KeyCode::Char(c)
+                if !key
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                line.input.push(c);
+                line.error = None;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Run a `:` command through the same actions as the key bindings.
+    /// Returns true for `:quit`.
+    pub fn run_command(&mut self, input: &str) -> Result<bool, String> {
+        let input = input.trim();
+        let (command, args) = input.split_once(' ').unwrap_or((input, ""));
+        let args = args.trim();
+
+        match command {
+            "" => {}
+            "q" | "quit" => return Ok(self.request_quit()),
+            "filter" => match args {
+                "all" => self.set_filter_mode(FilterMode::All),
+                "different" => self.set_filter_mode(FilterMode::Different),
+                "diff-only" => self.set_filter_mode(FilterMode::DifferentNotOrphans),
+                "" | "off" => self.name_filter = None,
+                expression => {
+                    let filter = FilterExpr::parse(expression).map_err(|e| format!("{:#}", e))?;
+                    self.name_filter = Some(filter);
+                }
+            },
+            "exclude" => {
+                let patterns = if args.is_empty() {
+                    let pattern = self.selected_pattern();
+                    vec![pattern.ok_or_else(|| "Nothing selected to exclude".to_string())?]
+                } else {
+                    args.split_whitespace().map(String::from).collect()
+                };
+                self.exclude(&patterns).map_err(|e| format!("{:#}", e))?;
+                return Ok(false);
+            }
+            "copy" => {
+                let to_right = match args {
+                    "right" => true,
+                    "left" => false,
+                    _ => return Err("Usage: copy right|left".to_string()),
+                };
+                if self.copy_source(to_right).is_none() {
+                    return Err("Nothing to copy that way".to_string());
+                }
+                self.prepare_copy_towards(to_right);
+                return Ok(false);
+            }
+            "delete" if self.can_delete() => {
+                self.prepare_delete();
+                return Ok(false);
+            }
+            "mkdir" => {
+                self.prepare_create_folder();
+                if let (Some(info), false) = (self.name_input.as_mut(), args.is_empty()) {
+                    info.input = args.to_string();
+                }
+                return Ok(false);
+            }
+            "rename" if self.can_rename() => {
+                self.prepare_rename();
+                return Ok(false);
+            }
+            "delete" | "rename" => return Err("Nothing selected to do that with".to_string()),
+            "refresh" => {
+                self.start_refresh();
+                return Ok(false);
+            }
+            "verify" => {
+                self.start_verify();
+                return Ok(false);
+            }
+            "open" => {
+                self.open_both_sides().map_err(|e| format!("{:#}", e))?;
+                return Ok(false);
+            }
+            "compare" => {
+                if args.is_empty() {
+                    return Err("Usage: compare PATH".to_string());
+                }
+                self.compare_with(args).map_err(|e| format!("{:#}", e))?;
+                return Ok(false);
+            }
+            "expand" => {
+                self.expand_all();
+                return Ok(false);
+            }
+            "collapse" => {
+                self.collapse_all();
+                return Ok(false);
+            }
+            "swap" => {
+                self.swap_panels();
+                return Ok(false);
+            }
+            "preview" => {
+                self.toggle_preview();
+                return Ok(false);
+            }
+            "noise" => {
+                self.toggle_noise();
+                return Ok(false);
+            }
+            "zoom" if args.is_empty() => {
+                self.zoom = None;
+            }
+            "zoom" => {
+                let path = Path::new(args.trim_matches('/'));
+                let is_dir = |tree: &FileNode| tree.find(path).is_some_and(|node| node.is_dir);
+                if !is_dir(&self.comparison.left_tree) && !is_dir(&self.comparison.right_tree) {
+                    return Err(format!("{} is not a folder in the comparison", args));
+                }
+                self.zoom = Some(path.to_path_buf());
+                self.update_file_lists();
+                self.select_row(0);
+                return Ok(false);
+            }
+            "log" => {
+                self.show_session_log();
+                return Ok(false);
+            }
+            "diffstat" => {
+                self.show_diffstat();
+                return Ok(false);
+            }
+            "hash" => {
+                if !args.is_empty() {
+                    self.hash_algorithm = HashAlgorithm::parse(args).map_err(|e| e.to_string())?;
+                }
+                self.show_hashes();
+                return Ok(false);
+            }
+            "select" | "goto" => {
+                let path = Path::new(args.trim_start_matches('/'));
+                if !self.select_path(path) {
+                    return Err(format!("{} is not in the comparison", args));
+                }
+                return Ok(false);
+            }
+            "flat" => {
+                self.toggle_layout(ListLayout::Flat);
+                return Ok(false);
+            }
+            "group" => {
+                self.toggle_layout(ListLayout::Grouped);
+                return Ok(false);
+            }
+            "sort" => {
+                self.sort_order = match args {
+                    "path" => SortOrder::Path,
+                    "status" => SortOrder::Status,
+                    "size" => SortOrder::Size,
+                    _ => return Err("Usage: sort path|status|size".to_string()),
+                };
+                if self.layout == ListLayout::Tree {
+                    self.toggle_layout(ListLayout::Flat);
+                    return Ok(false);
+                }
+            }
+            "theme" => {
+                if !crate::theme::set_palette(args) {
+                    return Err(format!(
+                        "Unknown theme: {} (one of {})",
+                        args,
+                        crate::theme::palette_names()
+                    ));
+                }
+                return Ok(false);
+            }
+            "export" => {
+                let (format, path) = args.split_once(' ').unwrap_or((args, ""));
+                let path = Path::new(path.trim());
+                let result = match format {
+                    "json" if !path.as_os_str().is_empty() => self.export_json(path),
+                    "text" if path.as_os_str().is_empty() => {
+                        self.copy_visible_text();
+                        Ok(())
+                    }
+                    "text" => std::fs::write(path, self.visible_text()).map_err(Into::into),
+                    _ => return Err("Usage: export json FILE | export text [FILE]".to_string()),
+                };
+                result.map_err(|e| format!("Export failed: {:#}", e))?;
+                return Ok(false);
+            }
+            command => return Err(format!("Unknown command: {}", command)),
+        }
+
+        // Only the filters (and zooming out) fall through to here; keep the
+        // selection in range
+        self.update_file_lists();
+        let selected = if self.active_panel == 0 {
+            self.left_list_state.selected()
+        } else {
+            self.right_list_state.selected()
+        };
+        self.select_row(selected.unwrap_or(0));
+        Ok(false)
+    }
+
+    /// The panels as plain text under the roots, one line per row with its
+    /// status like `--simple` prints the tree, to paste the view as it is.
+    pub fn visible_text(&self) -> String {
+        let mut text = format!(
+            "Left:  {}\nRight: {}\n",
+            crate::utils::escape_name(&self.comparison.left_dir),
+            crate::utils::escape_name(&self.comparison.right_dir)
+        );
+        if let Some(zoom) = &self.zoom {
+            text.push_str(&format!("Zoom:  {}\n", crate::utils::escape_name(zoom)));
+        }
+        text.push('\n');
+        for line in self.visible_lines() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+
+    fn visible_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for row in 0..self.left_items.len().max(self.right_items.len()) {
+            if let Some((_, status, count)) = self.group_headers.iter().find(|(r, ..)| *r == row) {
+                let label = match status {
+                    FileStatus::Different => "Different",
+                    FileStatus::LeftOnly => "Left only",
+                    FileStatus::RightOnly => "Right only",
+                    FileStatus::Same => "Same",
+                };
+                lines.push(format!("{} ({})", label, count));
+                continue;
+            }
+            // Rows are aligned, so whichever side has the entry names it
+            let item = [&self.left_items, &self.right_items]
+                .into_iter()
+                .filter_map(|items| items.get(row))
+                .find(|item| !item.display_name.trim().is_empty());
+            if let Some(item) = item {
+                let marker = crate::theme::status_marker(item.status);
+                lines.push(format!("{} [{}]", item.display_name, marker));
+            }
+        }
+        lines
+    }
+
+    /// Put `visible_text` on the clipboard and say how much it was.
+    pub fn copy_visible_text(&mut self) {
+        let text = self.visible_text();
+        let rows = self.visible_lines().len();
+        let copied = if self.headless {
+            Ok(())
+        } else {
+            crate::terminal::copy_to_clipboard(&text)
+        };
+        match copied {
+            Ok(()) => self.flash(format!("Copied {} rows as text", rows)),
+            Err(e) => self.flash(format!("Couldn't copy: {}", e)),
+        }
+    }
+
+    /// Write every compared entry with its status and metadata as JSON.
+    #[cfg(feature = "serde")]
+    fn export_json(&self, path: &Path) -> Result<()> {
+        #[derive(serde::Serialize)]
+        struct Export<'a> {
+            left: &'a Path,
+            right: &'a Path,
+            entries: Vec<crate::compare::DiffEntry>,
+        }
+
+        let export = Export {
+            left: &self.comparison.left_dir,
+            right: &self.comparison.right_dir,
+            entries: self.comparison.entries().collect(),
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&export)?)?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "serde"))]
+    fn export_json(&self, _path: &Path) -> Result<()> {
+        anyhow::bail!("exporting needs the `serde` feature")
+    }
+
+    fn save_current_state(&mut self) {
+        self.saved_left_selection = self.left_list_state.selected();
+        self.saved_right_selection = self.right_list_state.selected();
+        self.saved_active_panel = self.active_panel;
+
+        self.saved_filter_mode = Some(self.filter_mode);
+
+        self.saved_expansion_state = Some((
+            self.comparison.left_tree.clone(),
+            self.comparison.right_tree.clone(),
+        ));
+    }
+
+    fn restore_saved_state_safe(&mut self) {
+        if let Some(original_filter) = self.saved_filter_mode.take() {
+            self.filter_mode = original_filter;
+        }
+
+        self.active_panel = self.saved_active_panel;
+
+        use std::io::Write;
+        let mut debug = format!(
+            "=== BEFORE restoration ===\nLeft tree expanded: {}\nRight tree expanded: {}\n",
+            self.comparison.left_tree.expanded, self.comparison.right_tree.expanded
+        );
+
+        if let Some((saved_left_tree, saved_right_tree)) = self.saved_expansion_state.take() {
+            debug.push_str(&format!(
+                "Restoring from saved state - Left: {}, Right: {}\n",
+                saved_left_tree.expanded, saved_right_tree.expanded
+            ));
+
+            debug.push_str("=== Current Right Tree (before restore) ===\n");
+            Self::debug_tree_structure(&self.comparison.right_tree, 0, &mut debug);
+
+            debug.push_str("=== Saved Right Tree ===\n");
+            Self::debug_tree_structure(&saved_right_tree, 0, &mut debug);
+
+            Self::restore_expansion_state_safe(&mut self.comparison.left_tree, &saved_left_tree);
+            Self::restore_expansion_state_safe(&mut self.comparison.right_tree, &saved_right_tree);
+
+            debug.push_str("=== Current Right Tree (after restore) ===\n");
+            Self::debug_tree_structure(&self.comparison.right_tree, 0, &mut debug);
+        }
+
+        self.comparison.left_tree.expanded = true;
+        self.comparison.right_tree.expanded = true;
 
         debug.push_str(&format!(
             "=== AFTER restoration ===\nLeft tree expanded: {}\nRight tree expanded: {}\n",
@@ -1218,139 +4192,274 @@ impl App {
         self.saved_right_selection = None;
         self.saved_expansion_state = None;
         self.saved_filter_mode = None;
+        self.recenter = true;
     }
 
+    /// Open the folders that are open in `saved_tree`, a tree at the same
+    /// place.
     fn restore_expansion_state_safe(current_tree: &mut FileNode, saved_tree: &FileNode) {
-        if current_tree.is_dir && saved_tree.is_dir && current_tree.path == saved_tree.path {
+        if current_tree.is_dir && saved_tree.is_dir {
             current_tree.expanded = saved_tree.expanded;
         }
 
-        for current_child in &mut current_tree.children {
-            if let Some(saved_child) = saved_tree.children.iter().find(|child| {
-                child.path == current_child.path && child.is_dir == current_child.is_dir
-            }) {
-                Self::restore_expansion_state_safe(current_child, saved_child);
+        for current_child in &mut current_tree.children {
+            if let Some(saved_child) = saved_tree.children.iter().find(|child| {
+                child.name == current_child.name && child.is_dir == current_child.is_dir
+            }) {
+                Self::restore_expansion_state_safe(current_child, saved_child);
+            }
+        }
+    }
+
+    fn debug_tree_structure(node: &FileNode, depth: usize, output: &mut String) {
+        let indent = "  ".repeat(depth);
+        output.push_str(&format!(
+            "{}[{}] expanded:{} status:{:?}\n",
+            indent,
+            node.display_name(),
+            node.expanded,
+            node.status
+        ));
+
+        for child in &node.children {
+            Self::debug_tree_structure(child, depth + 1, output);
+        }
+    }
+
+    pub fn handle_key_event(&mut self, key: crossterm::event::KeyEvent) -> anyhow::Result<bool> {
+        if key.kind == KeyEventKind::Press && self.mode == AppMode::DirectoryView {
+            for (binding, to_right) in [
+                (self.keymap.copy_right, true),
+                (self.keymap.copy_left, false),
+            ] {
+                if binding.matches(&key) {
+                    if self.copy_source(to_right).is_some() {
+                        self.prepare_copy_towards(to_right);
+                    }
+                    return Ok(false);
+                }
+            }
+        }
+
+        if self.mode == AppMode::CommandLine {
+            if key.kind == KeyEventKind::Press {
+                return Ok(self.handle_command_line_key(key));
+            }
+            return Ok(false);
+        }
+
+        if self.mode == AppMode::HashView {
+            if key.kind == KeyEventKind::Press {
+                match key.code {
+                    KeyCode::Char('l') | KeyCode::Left => self.copy_hash(true),
+                    KeyCode::Char('r') | KeyCode::Right => self.copy_hash(false),
+                    KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') | KeyCode::Char('H') => {
+                        self.stop_hashing();
+                        self.hash_info = None;
+                        self.mode = AppMode::DirectoryView;
+                    }
+                    _ => {}
+                }
+            }
+            return Ok(false);
+        }
+
+        if self.mode == AppMode::SessionLog {
+            if key.kind == KeyEventKind::Press {
+                match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        let last = self.session_log.operations.len().saturating_sub(1);
+                        self.session_log_scroll = (self.session_log_scroll + 1).min(last);
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        self.session_log_scroll = self.session_log_scroll.saturating_sub(1);
+                    }
+                    KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') | KeyCode::Char('J') => {
+                        self.mode = AppMode::DirectoryView;
+                    }
+                    _ => {}
+                }
+            }
+            return Ok(false);
+        }
+
+        if self.mode == AppMode::DiffStat {
+            if key.kind == KeyEventKind::Press {
+                let Some(diffstat) = &mut self.diffstat else {
+                    self.mode = AppMode::DirectoryView;
+                    return Ok(false);
+                };
+                let last = diffstat.files.len().saturating_sub(1);
+                match key.code {
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        diffstat.scroll = diffstat.scroll.saturating_sub(1);
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        diffstat.scroll = (diffstat.scroll + 1).min(last);
+                    }
+                    KeyCode::PageUp => diffstat.scroll = diffstat.scroll.saturating_sub(10),
+                    KeyCode::PageDown => diffstat.scroll = (diffstat.scroll + 10).min(last),
+                    // Stops the counting too, if it's still going
+                    KeyCode::Esc | KeyCode::Enter | KeyCode::Char('q') | KeyCode::Char('D') => {
+                        self.stop_diffstat();
+                        self.diffstat = None;
+                        self.mode = AppMode::DirectoryView;
+                    }
+                    _ => {}
+                }
+            }
+            return Ok(false);
+        }
+
+        if self.mode == AppMode::QuitConfirm {
+            if key.kind == KeyEventKind::Press {
+                match key.code {
+                    KeyCode::Char('y') | KeyCode::Enter => {
+                        self.cancel_background_work();
+                        return Ok(true);
+                    }
+                    KeyCode::Char('n') | KeyCode::Esc => {
+                        self.mode = self.mode_before_quit;
+                        self.offer_restore();
+                    }
+                    _ => {}
+                }
+            }
+            return Ok(false);
+        }
+
+        // Typed characters are part of the name, not commands
+        if self.mode == AppMode::NameInput {
+            if key.kind == KeyEventKind::Press {
+                self.handle_name_input_key(key);
+            }
+            return Ok(false);
+        }
+
+        if key.kind == KeyEventKind::Press && self.mode == AppMode::TimestampConfirm {
+            if let Some(info) = &mut self.timestamp_info {
+                let forward = match key.code {
+                    KeyCode::Tab | KeyCode::Down | KeyCode::Char('j') => Some(true),
+                    KeyCode::BackTab | KeyCode::Up | KeyCode::Char('k') => Some(false),
+                    _ => None,
+                };
+                if let Some(forward) = forward {
+                    info.choice = info.choice.cycle(forward);
+                    return Ok(false);
+                }
             }
         }
-    }
 
-    fn debug_tree_structure(node: &FileNode, depth: usize, output: &mut String) {
-        let indent = "  ".repeat(depth);
-        output.push_str(&format!(
-            "{}[{}] expanded:{} status:{:?}\n",
-            indent, node.name, node.expanded, node.status
-        ));
+        if key.kind == KeyEventKind::Press && self.mode == AppMode::PermissionsConfirm {
+            if let Some(info) = &mut self.permissions_info {
+                if matches!(
+                    key.code,
+                    KeyCode::Tab
+                        | KeyCode::BackTab
+                        | KeyCode::Up
+                        | KeyCode::Down
+                        | KeyCode::Char('j')
+                        | KeyCode::Char('k')
+                ) {
+                    info.to_right = !info.to_right;
+                    return Ok(false);
+                }
+            }
+        }
 
-        for child in &node.children {
-            Self::debug_tree_structure(child, depth + 1, output);
+        if key.kind == KeyEventKind::Press && self.mode == AppMode::CopyConfirm {
+            if key.code == KeyCode::Char('d') {
+                self.toggle_copy_only_differences();
+                return Ok(false);
+            }
+            if let Some(copy_info) = self.copy_info.as_mut().filter(|c| c.overwrite_count > 0) {
+                let forward = match key.code {
+                    KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => Some(true),
+                    KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => Some(false),
+                    _ => None,
+                };
+                if let Some(forward) = forward {
+                    copy_info.overwrite_policy = copy_info.overwrite_policy.cycle(forward);
+                    self.update_transfer_bytes();
+                    return Ok(false);
+                }
+            }
         }
-    }
 
-    pub fn handle_key_event(&mut self, key: crossterm::event::KeyEvent) -> anyhow::Result<bool> {
         if key.kind == KeyEventKind::Press {
             match key.code {
                 KeyCode::Char('q') | KeyCode::Esc => {
-                    if self.mode == AppMode::CopyConfirm {
+                    if self.mode == AppMode::Copying {
+                        if self.copy_error.is_some() {
+                            self.dismiss_copy_error();
+                        } else if key.code == KeyCode::Char('q') {
+                            return Ok(self.request_quit());
+                        }
+                    } else if self.mode == AppMode::CopyConfirm {
                         self.cancel_copy();
                     } else if self.mode == AppMode::DeleteConfirm {
                         self.cancel_delete();
+                    } else if self.mode == AppMode::TimestampConfirm {
+                        self.cancel_equalize_timestamps();
+                    } else if self.mode == AppMode::PermissionsConfirm {
+                        self.cancel_equalize_permissions();
+                    } else if self.mode == AppMode::RestoreConfirm {
+                        self.keep_backups();
                     } else {
-                        return Ok(true); // Signal to exit
+                        return Ok(self.request_quit()); // Signal to exit
                     }
                 }
-                KeyCode::Delete => {
-                    if self.mode == AppMode::DirectoryView && self.can_delete() {
-                        self.prepare_delete();
-                    }
+                KeyCode::Delete if self.mode == AppMode::DirectoryView && self.can_delete() => {
+                    self.prepare_delete();
                 }
-                KeyCode::Left => {
-                    if self.mode == AppMode::DirectoryView {
-                        if self.active_panel == 1 {
-                            if let Some(right_selected) = self.right_list_state.selected() {
-                                if right_selected < self.left_items.len() {
-                                    self.left_list_state.select(Some(right_selected));
-                                } else if !self.left_items.is_empty() {
-                                    self.left_list_state.select(Some(self.left_items.len() - 1));
-                                }
-                            }
-                        }
-                        self.active_panel = 0;
-                    }
+                KeyCode::Left if self.mode == AppMode::DirectoryView => {
+                    self.focus_panel(0);
                 }
-                KeyCode::Right => {
-                    if self.mode == AppMode::DirectoryView {
-                        if self.active_panel == 0 {
-                            if let Some(left_selected) = self.left_list_state.selected() {
-                                if left_selected < self.right_items.len() {
-                                    self.right_list_state.select(Some(left_selected));
-                                } else if !self.right_items.is_empty() {
-                                    self.right_list_state
-                                        .select(Some(self.right_items.len() - 1));
-                                }
-                            }
-                        }
-                        self.active_panel = 1;
-                    }
+                KeyCode::Right if self.mode == AppMode::DirectoryView => {
+                    self.focus_panel(1);
                 }
-                KeyCode::Up => {
-                    if self.mode == AppMode::DirectoryView {
-                        self.move_selection(-1);
-                    }
+                KeyCode::Up if self.mode == AppMode::DirectoryView => {
+                    self.move_selection(-1);
                 }
-                KeyCode::Down => {
-                    if self.mode == AppMode::DirectoryView {
-                        self.move_selection(1);
-                    }
+                KeyCode::Down if self.mode == AppMode::DirectoryView => {
+                    self.move_selection(1);
                 }
-                KeyCode::Char('k') => {
-                    if self.mode == AppMode::DirectoryView {
-                        self.move_selection(-1);
-                    }
+                KeyCode::Char('k') if self.mode == AppMode::DirectoryView => {
+                    self.move_selection(-1);
                 }
-                KeyCode::Char('j') => {
-                    if self.mode == AppMode::DirectoryView {
-                        self.move_selection(1);
-                    }
+                KeyCode::Char('j') if self.mode == AppMode::DirectoryView => {
+                    self.move_selection(1);
                 }
-                KeyCode::PageUp => {
-                    if self.mode == AppMode::DirectoryView {
-                        let half_page = self.calculate_half_page();
-                        self.move_selection(-half_page);
-                    }
+                KeyCode::PageUp if self.mode == AppMode::DirectoryView => {
+                    let half_page = self.calculate_half_page();
+                    self.move_selection(-half_page);
                 }
-                KeyCode::PageDown => {
-                    if self.mode == AppMode::DirectoryView {
-                        let half_page = self.calculate_half_page();
-                        self.move_selection(half_page);
-                    }
+                KeyCode::PageDown if self.mode == AppMode::DirectoryView => {
+                    let half_page = self.calculate_half_page();
+                    self.move_selection(half_page);
                 }
-                KeyCode::Home => {
+                KeyCode::Home
                     if key.modifiers.contains(KeyModifiers::CONTROL)
-                        && self.mode == AppMode::DirectoryView
-                    {
-                        // Ctrl+Home: Go to top
-                        self.scroll_to_top();
-                    }
+                        && self.mode == AppMode::DirectoryView =>
+                {
+                    // Ctrl+Home: Go to top
+                    self.scroll_to_top();
                 }
-                KeyCode::End => {
+                KeyCode::End
                     if key.modifiers.contains(KeyModifiers::CONTROL)
-                        && self.mode == AppMode::DirectoryView
-                    {
-                        // Ctrl+End: Go to bottom
-                        self.scroll_to_bottom();
-                    }
+                        && self.mode == AppMode::DirectoryView =>
+                {
+                    // Ctrl+End: Go to bottom
+                    self.scroll_to_bottom();
                 }
                 KeyCode::Char('1') => {
-                    self.filter_mode = FilterMode::All;
-                    self.update_file_lists();
+                    self.set_filter_mode(FilterMode::All);
                 }
                 KeyCode::Char('2') => {
-                    self.filter_mode = FilterMode::Different;
-                    self.update_file_lists();
+                    self.set_filter_mode(FilterMode::Different);
                 }
                 KeyCode::Char('3') => {
-                    self.filter_mode = FilterMode::DifferentNotOrphans;
-                    self.update_file_lists();
+                    self.set_filter_mode(FilterMode::DifferentNotOrphans);
                 }
                 KeyCode::Char('+') => {
                     self.expand_all();
@@ -1361,36 +4470,122 @@ impl App {
                 KeyCode::Char('s') => {
                     self.swap_panels();
                 }
-                KeyCode::F(5) => {
-                    if self.mode == AppMode::DirectoryView {
-                        self.start_refresh();
+                KeyCode::Char('H') if self.mode == AppMode::DirectoryView => {
+                    self.show_hashes();
+                }
+                KeyCode::Char('J') if self.mode == AppMode::DirectoryView => {
+                    self.show_session_log();
+                }
+                KeyCode::Char('D') if self.mode == AppMode::DirectoryView => {
+                    self.show_diffstat();
+                }
+                KeyCode::Char('Y') if self.mode == AppMode::DirectoryView => {
+                    self.copy_visible_text();
+                }
+                KeyCode::Char('L') if self.mode == AppMode::DirectoryView => {
+                    self.toggle_panel_lock();
+                }
+                KeyCode::Char('t') if self.mode == AppMode::DirectoryView => {
+                    self.cycle_target();
+                }
+                KeyCode::Char('v') if self.mode == AppMode::DirectoryView => {
+                    self.vertical_layout = !self.vertical_layout;
+                }
+                KeyCode::Char('p') if self.mode == AppMode::DirectoryView => {
+                    self.toggle_preview();
+                }
+                KeyCode::Char('m') if self.mode == AppMode::DirectoryView => {
+                    self.show_permissions = !self.show_permissions;
+                }
+                KeyCode::Char('o') if self.mode == AppMode::DirectoryView => {
+                    self.show_owner = !self.show_owner;
+                }
+                KeyCode::Char('i') if self.mode == AppMode::DirectoryView => {
+                    self.toggle_noise();
+                }
+                KeyCode::Char('e')
+                    if self.mode == AppMode::DirectoryView && self.can_equalize_timestamps() =>
+                {
+                    self.prepare_equalize_timestamps();
+                }
+                KeyCode::Char('z') if self.mode == AppMode::DirectoryView => {
+                    self.zoom_in();
+                }
+                KeyCode::Char('Z') if self.mode == AppMode::DirectoryView => {
+                    self.zoom_out();
+                }
+                KeyCode::Char(':') if self.mode == AppMode::DirectoryView => {
+                    self.open_command_line();
+                }
+                KeyCode::Char('N') if self.mode == AppMode::DirectoryView => {
+                    self.prepare_create_folder();
+                }
+                KeyCode::F(2) if self.mode == AppMode::DirectoryView && self.can_rename() => {
+                    self.prepare_rename();
+                }
+                KeyCode::Char('M')
+                    if self.mode == AppMode::DirectoryView && self.can_equalize_permissions() =>
+                {
+                    self.prepare_equalize_permissions();
+                }
+                KeyCode::Char('u') | KeyCode::Backspace if self.mode == AppMode::DirectoryView => {
+                    self.select_parent(false);
+                }
+                KeyCode::Char('U') if self.mode == AppMode::DirectoryView => {
+                    self.select_parent(true);
+                }
+                KeyCode::Char('V') if self.mode == AppMode::DirectoryView => {
+                    self.start_verify();
+                }
+                KeyCode::Char('x') if self.mode == AppMode::DirectoryView => {
+                    self.exclude_selected();
+                }
+                KeyCode::Char('c') if self.mode == AppMode::DirectoryView => {
+                    self.open_command_line_with("compare ");
+                }
+                KeyCode::Char('O') if self.mode == AppMode::DirectoryView => {
+                    if let Err(e) = self.open_both_sides() {
+                        crate::utils::log_error(&format!("Failed to open both sides: {:#}", e));
+                        self.flash(format!("Couldn't open both sides: {:#}", e));
                     }
                 }
-                KeyCode::Char('r') => {
+                KeyCode::Char('g') if self.mode == AppMode::DirectoryView => {
+                    self.cycle_placeholder_rows();
+                }
+                KeyCode::Char('%') if self.mode == AppMode::DirectoryView => {
+                    self.show_similarity = !self.show_similarity;
+                }
+                KeyCode::Char('T') if self.mode == AppMode::DirectoryView => {
+                    self.show_file_type = !self.show_file_type;
+                }
+                KeyCode::Char('B') if self.mode == AppMode::DirectoryView => {
+                    self.raw_sizes = !self.raw_sizes;
+                }
+                KeyCode::Char('F') if self.mode == AppMode::DirectoryView => {
+                    self.toggle_layout(ListLayout::Flat);
+                }
+                KeyCode::Char('G') if self.mode == AppMode::DirectoryView => {
+                    self.toggle_layout(ListLayout::Grouped);
+                }
+                KeyCode::Char('S') if self.mode == AppMode::DirectoryView => {
+                    self.cycle_sort_order();
+                }
+                KeyCode::F(5) if self.mode == AppMode::DirectoryView => {
+                    self.start_refresh();
+                }
+                KeyCode::Char('r')
                     if key
                         .modifiers
                         .contains(crossterm::event::KeyModifiers::CONTROL)
                         && self.mode == AppMode::DirectoryView
                         && self.active_panel == 0
-                        && self.can_copy()
-                    {
-                        self.prepare_copy();
-                    }
+                        && self.can_copy() =>
+                {
+                    self.prepare_copy();
                 }
-                KeyCode::Char('h') => {
-                    if self.mode == AppMode::DirectoryView {
-                        // vim-style navigation: h = left
-                        if self.active_panel == 1 {
-                            if let Some(right_selected) = self.right_list_state.selected() {
-                                if right_selected < self.left_items.len() {
-                                    self.left_list_state.select(Some(right_selected));
-                                } else if !self.left_items.is_empty() {
-                                    self.left_list_state.select(Some(self.left_items.len() - 1));
-                                }
-                            }
-                        }
-                        self.active_panel = 0;
-                    }
+                KeyCode::Char('h') if self.mode == AppMode::DirectoryView => {
+                    // vim-style navigation: h = left
+                    self.focus_panel(0);
                 }
                 KeyCode::Char('l') => {
                     if key
@@ -1403,17 +4598,7 @@ impl App {
                         self.prepare_copy();
                     } else if self.mode == AppMode::DirectoryView {
                         // vim-style navigation: l = right
-                        if self.active_panel == 0 {
-                            if let Some(left_selected) = self.left_list_state.selected() {
-                                if left_selected < self.right_items.len() {
-                                    self.right_list_state.select(Some(left_selected));
-                                } else if !self.right_items.is_empty() {
-                                    self.right_list_state
-                                        .select(Some(self.right_items.len() - 1));
-                                }
-                            }
-                        }
-                        self.active_panel = 1;
+                        self.focus_panel(1);
                     }
                 }
                 KeyCode::Char('f') => {
@@ -1425,38 +4610,71 @@ impl App {
                         // Ctrl+F: Page down (forward)
                         let half_page = self.calculate_half_page();
                         self.move_selection(half_page);
+                    } else if self.mode == AppMode::DirectoryView {
+                        self.cycle_filter_mode();
                     }
                 }
-                KeyCode::Char('b') => {
+                KeyCode::Char('b')
                     if key
                         .modifiers
                         .contains(crossterm::event::KeyModifiers::CONTROL)
-                        && self.mode == AppMode::DirectoryView
-                    {
-                        // Ctrl+B: Page up (backward)
-                        let half_page = self.calculate_half_page();
-                        self.move_selection(-half_page);
-                    }
+                        && self.mode == AppMode::DirectoryView =>
+                {
+                    // Ctrl+B: Page up (backward)
+                    let half_page = self.calculate_half_page();
+                    self.move_selection(-half_page);
                 }
                 KeyCode::Enter => {
                     if self.mode == AppMode::DirectoryView {
-                        if let Some((_, status, path, is_dir, _, _)) = self.get_selected_item() {
+                        if let Some(status) = self.selected_group() {
+                            self.toggle_group(status);
+                        } else if let Some(FileItem {
+                            status,
+                            path,
+                            is_dir,
+                            ..
+                        }) = self.get_selected_item()
+                        {
                             if *is_dir {
                                 self.toggle_folder();
                             } else if path.to_string_lossy() != "" {
                                 let status = *status;
                                 let path = path.clone();
-                                self.handle_file_comparison(status, path)?;
+                                self.handle_file_comparison(status, &path)?;
                             }
                         }
                     } else if self.mode == AppMode::CopyConfirm {
                         if let Err(e) = self.execute_copy() {
-                            eprintln!("Copy failed: {}", e);
+                            crate::utils::log_error(&format!("Failed to copy: {:#}", e));
+                            self.flash(format!("Couldn't copy: {:#}", e));
                         }
                     } else if self.mode == AppMode::DeleteConfirm {
                         if let Err(e) = self.execute_delete() {
-                            eprintln!("Delete failed: {}", e);
+                            crate::utils::log_error(&format!("Failed to delete: {:#}", e));
+                            self.flash(format!("Couldn't delete: {:#}", e));
+                        }
+                    } else if self.mode == AppMode::TimestampConfirm {
+                        if let Err(e) = self.execute_equalize_timestamps() {
+                            crate::utils::log_error(&format!(
+                                "Failed to equalize timestamps: {:#}",
+                                e
+                            ));
+                            self.flash(format!("Couldn't equalize timestamps: {:#}", e));
                         }
+                    } else if self.mode == AppMode::PermissionsConfirm {
+                        if let Err(e) = self.execute_equalize_permissions() {
+                            crate::utils::log_error(&format!(
+                                "Failed to equalize permissions: {:#}",
+                                e
+                            ));
+                            self.flash(format!("Couldn't equalize permissions: {:#}", e));
+                        }
+                    } else if self.mode == AppMode::Copying {
+                        if self.copy_error.is_some() {
+                            self.dismiss_copy_error();
+                        }
+                    } else if self.mode == AppMode::RestoreConfirm {
+                        self.restore_backups();
                     } else {
                         self.mode = AppMode::DirectoryView;
                     }
@@ -1469,28 +4687,512 @@ impl App {
 
     pub fn handle_mouse_event(&mut self, mouse: crossterm::event::MouseEvent) {
         match mouse.kind {
-            MouseEventKind::Down(MouseButton::Left) => {
+            MouseEventKind::Down(MouseButton::Left) if self.mode != AppMode::Copying => {
                 self.handle_mouse_click(mouse.column, mouse.row);
             }
-            MouseEventKind::ScrollUp => {
-                if self.mode == AppMode::DirectoryView {
-                    self.move_selection(-3); // Scroll up 3 lines
+            MouseEventKind::ScrollUp if self.mode == AppMode::DirectoryView => {
+                self.move_selection(-3); // Scroll up 3 lines
+            }
+            MouseEventKind::ScrollDown if self.mode == AppMode::DirectoryView => {
+                self.move_selection(3); // Scroll down 3 lines
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_file_comparison(&mut self, status: FileStatus, path: &Path) -> anyhow::Result<()> {
+        if self.headless || self.comparison.loaded_from.is_some() {
+            return Ok(());
+        }
+        let left_path = self.comparison.left_path(path);
+        // Nothing is at the empty path, as there is nothing on the right
+        // where a mapped folder took the name
+        let right_path = self.comparison.right_path(path).unwrap_or_default();
+        let before = [file_stamp(&left_path), file_stamp(&right_path)];
+
+        // Only the real file can be opened; rescan since it may get edited
+        if self.comparison.has_manifest() {
+            let status = if self.comparison.is_manifest(true) {
+                FileStatus::RightOnly
+            } else {
+                FileStatus::LeftOnly
+            };
+            crate::terminal::launch_external_editor(
+                &status,
+                &left_path,
+                &right_path,
+                self.read_only,
+            )?;
+            if [file_stamp(&left_path), file_stamp(&right_path)] != before {
+                self.save_current_state();
+                self.start_refresh();
+            }
+            return Ok(());
+        }
+
+        crate::terminal::launch_external_editor(&status, &left_path, &right_path, self.read_only)?;
+
+        // The file may have been edited inside vimdiff, so refresh just this entry
+        if [file_stamp(&left_path), file_stamp(&right_path)] == before {
+            return Ok(());
+        }
+        self.recompare_path(path)?;
+        self.flash(format!(
+            "{} changed, compared again",
+            crate::utils::escape_name(path)
+        ));
+        Ok(())
+    }
+
+    /// Compute the similarity of the changed text files among `rows` that
+    /// haven't been computed yet (or changed since).
+    pub fn update_similarities(&mut self, rows: std::ops::Range<usize>) {
+        // The next draw after it's done asks for whatever is still missing
+        if self.similarity_worker.is_some() {
+            return;
+        }
+        let mut jobs = Vec::new();
+        for row in rows {
+            let (Some(left), Some(right)) = (self.left_items.get(row), self.right_items.get(row))
+            else {
+                break;
+            };
+            if left.status != FileStatus::Different
+                || left.is_dir
+                || self.comparison.has_manifest()
+                || self.comparison.loaded_from.is_some()
+            {
+                continue;
+            }
+            let path = &left.path;
+            if self
+                .similarities
+                .get(path)
+                .is_some_and(|(l, r, _)| *l == left.modified && *r == right.modified)
+            {
+                continue;
+            }
+            let Some(right_path) = self.comparison.right_path(path) else {
+                continue;
+            };
+            jobs.push((
+                path.clone(),
+                self.comparison.left_path(path),
+                right_path,
+                left.modified,
+                right.modified,
+            ));
+        }
+        if jobs.is_empty() {
+            return;
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let worker = Worker::spawn(move |cancel| {
+            for (path, left_path, right_path, left_modified, right_modified) in jobs {
+                if cancel.load(std::sync::atomic::Ordering::Relaxed) {
+                    return;
+                }
+                let percent = DirectoryComparison::similarity(&left_path, &right_path)
+                    .map(|ratio| (ratio * 100.0).floor() as u8);
+                if tx
+                    .send((path, (left_modified, right_modified, percent)))
+                    .is_err()
+                {
+                    return;
                 }
             }
-            MouseEventKind::ScrollDown => {
-                if self.mode == AppMode::DirectoryView {
-                    self.move_selection(3); // Scroll down 3 lines
+        });
+        self.similarity_worker = Some((worker, rx));
+    }
+
+    /// Take in the similarities worked out since the last call. Returns
+    /// whether any arrived or the worker finished, so the screen needs
+    /// drawing again.
+    pub fn check_similarity_progress(&mut self) -> bool {
+        let Some((_, rx)) = &self.similarity_worker else {
+            return false;
+        };
+        let mut changed = false;
+        loop {
+            match rx.try_recv() {
+                Ok((path, similarity)) => {
+                    self.similarities.insert(path, similarity);
+                    changed = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => return changed,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.similarity_worker = None;
+                    return true;
                 }
             }
-            _ => {}
         }
     }
 
-    fn handle_file_comparison(&mut self, status: FileStatus, path: PathBuf) -> anyhow::Result<()> {
-        let left_path = self.comparison.left_dir.join(&path);
-        let right_path = self.comparison.right_dir.join(&path);
+    /// The similarity of the row in percent, if `update_similarities` has
+    /// computed it.
+    pub fn similarity(&self, row: usize) -> Option<u8> {
+        let (left, right) = (self.left_items.get(row)?, self.right_items.get(row)?);
+        let (left_modified, right_modified, percent) = self.similarities.get(&left.path)?;
+        let current = *left_modified == left.modified && *right_modified == right.modified;
+        if left.status != FileStatus::Different || !current {
+            return None;
+        }
+        *percent
+    }
+
+    /// Detect the type of the files among `rows` on both sides that haven't
+    /// been detected yet (or changed since).
+    pub fn update_file_types(&mut self, rows: std::ops::Range<usize>) {
+        if self.comparison.loaded_from.is_some() {
+            return;
+        }
+        for row in rows {
+            for left in [true, false] {
+                let items = if left {
+                    &self.left_items
+                } else {
+                    &self.right_items
+                };
+                let Some(item) = items.get(row) else {
+                    continue;
+                };
+                if item.is_dir || item.display_name.trim().is_empty() {
+                    continue;
+                }
+                let key = (left, item.path.clone());
+                let modified = item.modified;
+                if self
+                    .file_types
+                    .get(&key)
+                    .is_some_and(|(detected, _)| *detected == modified)
+                {
+                    continue;
+                }
+                let detected = self
+                    .comparison
+                    .side_path(left, &key.1)
+                    .and_then(|file| crate::filetype::detect(&file));
+                self.file_types.insert(key, (modified, detected));
+            }
+        }
+    }
+
+    /// The detected type of the file on the `left` or right side of the row,
+    /// if `update_file_types` has detected it.
+    pub fn file_type(&self, left: bool, row: usize) -> Option<&'static str> {
+        let items = if left {
+            &self.left_items
+        } else {
+            &self.right_items
+        };
+        let item = items.get(row)?;
+        let (modified, detected) = self.file_types.get(&(left, item.path.clone()))?;
+        if *modified != item.modified || item.is_dir || item.display_name.trim().is_empty() {
+            return None;
+        }
+        *detected
+    }
+
+    pub fn toggle_preview(&mut self) {
+        self.show_preview = !self.show_preview;
+        self.preview_key = None;
+    }
+
+    /// Rebuild `file_diff` when the preview is shown and the selection moved.
+    pub fn update_preview(&mut self) {
+        if !self.show_preview {
+            return;
+        }
+
+        // Section headers of the grouped layout have no entry to preview
+        let selected = self
+            .get_selected_item()
+            .filter(|item| !item.path.as_os_str().is_empty())
+            .map(|item| (item.path.clone(), item.status, item.is_dir));
+        let key = selected
+            .as_ref()
+            .map(|(path, status, _)| (path.clone(), *status));
+        if key == self.preview_key {
+            return;
+        }
+
+        self.file_diff = match &selected {
+            Some((path, status, false)) => self.cached_file_preview(path, *status),
+            Some((path, status, true)) => self.build_preview(path, *status, true),
+            None => String::new(),
+        };
+        self.preview_key = key;
+    }
+
+    /// `build_preview` of a file, from `preview_cache` while neither side
+    /// changed.
+    fn cached_file_preview(&mut self, path: &Arc<Path>, status: FileStatus) -> String {
+        let right_path = self.comparison.right_path(path).unwrap_or_default();
+        let stamp = (
+            status,
+            [
+                file_stamp(&self.comparison.left_path(path)),
+                file_stamp(&right_path),
+            ],
+        );
+        if let Some((built_for, preview)) = self.preview_cache.get(path) {
+            if *built_for == stamp {
+                return preview.clone();
+            }
+        }
+        let preview = self.build_preview(path, status, false);
+        if self.preview_cache.len() >= PREVIEW_CACHE_ENTRIES {
+            self.preview_cache.clear();
+        }
+        self.preview_cache
+            .insert(path.clone(), (stamp, preview.clone()));
+        preview
+    }
+
+    fn build_preview(&self, path: &Path, status: FileStatus, is_dir: bool) -> String {
+        let left_path = self.comparison.left_path(path);
+        // Empty, and so missing, where a mapped folder took the name
+        let right_path = self.comparison.right_path(path).unwrap_or_default();
+
+        if is_dir {
+            let summary = match status {
+                FileStatus::Same => "All contents are identical",
+                FileStatus::Different => "Contains changes",
+                FileStatus::LeftOnly => "Only in the left directory",
+                FileStatus::RightOnly => "Only in the right directory",
+            };
+            let folder = crate::icons::icons().folder_closed;
+            let mut preview = format!(
+                "{}\n\n{}",
+                crate::icons::labeled(folder, &crate::utils::escape_name(path)),
+                summary
+            );
+            preview.push_str(&self.folder_stats(path));
+            if let Some((left, right)) = self.comparison.digests.get(path) {
+                let hash = |hash: Option<FolderHash>| {
+                    hash.map_or_else(|| "-".to_string(), |hash| short_hex(&hash))
+                };
+                preview.push_str(&format!(
+                    "\n\nListing hash\n  left   {}\n  right  {}",
+                    hash(left),
+                    hash(right)
+                ));
+                if left.is_some() && left == right {
+                    preview.push_str("\n  Names, sizes, times and modes match on both sides");
+                }
+            }
+            return preview;
+        }
+
+        if let Some(file) = &self.comparison.loaded_from {
+            let summary = match status {
+                FileStatus::Same => "Files were identical",
+                FileStatus::Different => "Files differed",
+                FileStatus::LeftOnly => "Only in left",
+                FileStatus::RightOnly => "Only in right",
+            };
+            return format!(
+                "{}\n\nThe contents aren't part of the saved result {}",
+                summary,
+                file.display()
+            );
+        }
+
+        format!(
+            "{}{}",
+            Self::type_line(&left_path, &right_path),
+            self.file_pair_preview(path, status, &left_path, &right_path)
+        )
+    }
+
+    /// What the files at both paths are, see `filetype::detect`, told apart
+    /// when they differ: a cheap explanation for two same-named files of
+    /// wildly different sizes. Empty when neither can be read.
+    fn type_line(left_path: &Path, right_path: &Path) -> String {
+        match [left_path, right_path].map(crate::filetype::detect) {
+            [Some(left), Some(right)] if left != right => {
+                format!("Type: left {}, right {}\n\n", left, right)
+            }
+            [Some(kind), _] | [None, Some(kind)] => format!("Type: {}\n\n", kind),
+            [None, None] => String::new(),
+        }
+    }
+
+    /// The preview of a file: its diff, or its contents when only one side
+    /// has it or both are the same.
+    fn file_pair_preview(
+        &self,
+        path: &Path,
+        status: FileStatus,
+        left_path: &Path,
+        right_path: &Path,
+    ) -> String {
+        use std::fs;
+
+        if self.comparison.has_manifest() {
+            let manifest_on_left = self.comparison.is_manifest(true);
+            let file = if manifest_on_left {
+                right_path
+            } else {
+                left_path
+            };
+            let summary = match (status, manifest_on_left) {
+                (FileStatus::Same, _) => "Matches the manifest",
+                (FileStatus::Different, _) => "Does not match the hash in the manifest",
+                (FileStatus::LeftOnly, true) | (FileStatus::RightOnly, false) => {
+                    return "Listed in the manifest but missing".to_string();
+                }
+                (FileStatus::LeftOnly, false) | (FileStatus::RightOnly, true) => {
+                    "Not listed in the manifest"
+                }
+            };
+            return format!("{}\n\n{}", summary, Self::file_preview(file));
+        }
+
+        match status {
+            FileStatus::Different => {
+                let too_large = [left_path, right_path].into_iter().find_map(|file| {
+                    fs::metadata(file)
+                        .ok()
+                        .filter(|meta| meta.len() > PREVIEW_MAX_BYTES)
+                        .map(|meta| meta.len())
+                });
+                match too_large {
+                    Some(size) => format!(
+                        "File too large to preview ({})",
+                        self.format_size(Some(size)).trim()
+                    ),
+                    None => DirectoryComparison::unified_diff(
+                        path,
+                        left_path,
+                        right_path,
+                        Some(Instant::now() + PREVIEW_DIFF_DEADLINE),
+                    )
+                    .unwrap_or_else(|e| format!("Cannot diff: {}", e)),
+                }
+            }
+            FileStatus::LeftOnly => {
+                format!("Only in left\n\n{}", Self::file_preview(left_path))
+            }
+            FileStatus::RightOnly => {
+                format!("Only in right\n\n{}", Self::file_preview(right_path))
+            }
+            FileStatus::Same => {
+                format!("Files are identical\n\n{}", Self::file_preview(left_path))
+            }
+        }
+    }
+
+    /// How many files below a folder are in each status, and how many bytes
+    /// the differing ones hold on each side: what a copy of the whole
+    /// folder would move, against reviewing its files one by one.
+    fn folder_stats(&self, dir: &Path) -> String {
+        let (mut same, mut different, mut left_only, mut right_only) = (0, 0, 0, 0);
+        let (mut left_bytes, mut right_bytes) = (0, 0);
+        for entry in self.comparison.entries_below(dir) {
+            if entry.left.or(entry.right).is_some_and(|meta| meta.is_dir) {
+                continue;
+            }
+            match entry.status {
+                FileStatus::Same => {
+                    same += 1;
+                    continue;
+                }
+                FileStatus::Different => different += 1,
+                FileStatus::LeftOnly => left_only += 1,
+                FileStatus::RightOnly => right_only += 1,
+            }
+            left_bytes += entry.left.and_then(|meta| meta.size).unwrap_or(0);
+            right_bytes += entry.right.and_then(|meta| meta.size).unwrap_or(0);
+        }
+        if same + different + left_only + right_only == 0 {
+            return "\n\nNo files below".to_string();
+        }
+        format!(
+            "\n\nFiles below\n  {} same, {} different, {} left only, {} right only\n  \
+             Differing bytes  left {}  right {}",
+            same,
+            different,
+            left_only,
+            right_only,
+            self.format_size(Some(left_bytes)).trim(),
+            self.format_size(Some(right_bytes)).trim()
+        )
+    }
+
+    /// The start of a file for the preview pane.
+    fn file_preview(path: &Path) -> String {
+        use std::io::Read;
+
+        let mut buffer = Vec::new();
+        let read = std::fs::File::open(path)
+            .and_then(|file| file.take(PREVIEW_MAX_BYTES).read_to_end(&mut buffer));
+        if let Err(e) = read {
+            return format!("Cannot read file: {}", e);
+        }
+        if buffer.contains(&0) {
+            return "Binary file".to_string();
+        }
+        String::from_utf8_lossy(&buffer).into_owned()
+    }
+
+    /// Re-run the file comparison for a single relative path and propagate the
+    /// result to both trees and their ancestor folders, without a full rescan.
+    pub fn recompare_path(&mut self, relative_path: &Path) -> Result<()> {
+        use std::fs;
+
+        let left_path = self.comparison.left_path(relative_path);
+        // Empty, and so missing, where a mapped folder took the name
+        let right_path = self
+            .comparison
+            .right_path(relative_path)
+            .unwrap_or_default();
+
+        let left_metadata = fs::metadata(&left_path).ok();
+        let right_metadata = fs::metadata(&right_path).ok();
+
+        let new_status = match (&left_metadata, &right_metadata) {
+            (Some(left), Some(right)) => {
+                if left.is_dir() || right.is_dir() {
+                    return Ok(());
+                }
+                if DirectoryComparison::files_are_same_public(
+                    &left_path,
+                    &right_path,
+                    left,
+                    right,
+                    &self.comparison.options,
+                )? {
+                    FileStatus::Same
+                } else {
+                    FileStatus::Different
+                }
+            }
+            (Some(_), None) => FileStatus::LeftOnly,
+            (None, Some(_)) => FileStatus::RightOnly,
+            (None, None) => return Ok(()),
+        };
+
+        let target = relative_path.to_path_buf();
+        self.verified.remove(relative_path);
+        for (tree, metadata) in [
+            (&mut self.comparison.left_tree, &left_metadata),
+            (&mut self.comparison.right_tree, &right_metadata),
+        ] {
+            if let Some(node) = tree.find_mut(&target) {
+                node.status = new_status;
+                if let Some(meta) = metadata {
+                    node.size = Some(meta.len());
+                    node.modified = meta.modified().ok();
+                    node.mode = permission_bits(meta);
+                    node.owner = ownership(meta);
+                }
+            }
+        }
+        self.comparison.update_ancestor_statuses(relative_path);
 
-        crate::terminal::launch_external_editor(&status, &left_path, &right_path)?;
+        self.update_file_lists();
         Ok(())
     }
 }
@@ -0,0 +1,269 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::compare::{CompareOptions, DiffEntry, FileStatus, Meta};
+
+/// First line of a checkpoint file; bump the version when the format changes.
+const HEADER: &str = "tudiff-checkpoint 1";
+
+/// The paths a comparison of two roots has finished, written while it runs
+/// so an interrupted scan of a huge tree can be resumed instead of started
+/// over. Both roots are walked in path order, so the entries always cover
+/// everything up to the last one and the next run can skip that part.
+pub struct Checkpoint {
+    file: Option<PathBuf>,
+    resumed: Vec<DiffEntry>,
+    writer: Option<BufWriter<File>>,
+}
+
+/// The options that decide which paths are compared and how; a checkpoint
+/// written with other ones is thrown away.
+fn fingerprint(options: &CompareOptions) -> String {
+    format!(
+        "{:?} {:?} {:?} {} {} {:?} {} {} {:?} {}",
+        options.filter.include.patterns(),
+        options.filter.exclude.patterns(),
+        options.mtime_tolerance,
+        options.one_file_system,
+        options.follow_links,
+        options.pseudo_fs,
+        options.compare_permissions,
+        options.ignore_comments,
+        options.normalizers,
+        options.metadata_only
+    )
+}
+
+/// `status left right path`, tab separated. A side is `-` when the path
+/// doesn't exist there, otherwise `kind size mtime mode uid:gid` with `-`
+/// for the unknown ones.
+fn format_entry(entry: &DiffEntry, path: &str) -> String {
+    let side = |meta: Option<Meta>| {
+        let Some(meta) = meta else {
+            return "-".to_string();
+        };
+        let field = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        format!(
+            "{} {} {} {} {}",
+            if meta.is_dir { "d" } else { "f" },
+            field(meta.size.map(|size| size.to_string())),
+            field(
+                meta.modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|since| since.as_nanos().to_string())
+            ),
+            field(meta.mode.map(|mode| format!("{:o}", mode))),
+            field(meta.owner.map(|(uid, gid)| format!("{}:{}", uid, gid)))
+        )
+    };
+    let status = match entry.status {
+        FileStatus::Same => "S",
+        FileStatus::Different => "D",
+        FileStatus::LeftOnly => "L",
+        FileStatus::RightOnly => "R",
+    };
+    format!(
+        "{}\t{}\t{}\t{}\n",
+        status,
+        side(entry.left),
+        side(entry.right),
+        path
+    )
+}
+
+fn parse_side(field: &str) -> Option<Option<Meta>> {
+    if field == "-" {
+        return Some(None);
+    }
+    let mut parts = field.split(' ');
+    let is_dir = match parts.next()? {
+        "d" => true,
+        "f" => false,
+        _ => return None,
+    };
+    let mut next = || parts.next().filter(|part| *part != "-");
+    let size = next().map(str::parse).transpose().ok()?;
+    let modified = next()
+        .map(str::parse::<u128>)
+        .transpose()
+        .ok()?
+        .map(|nanos| {
+            UNIX_EPOCH
+                + Duration::new(
+                    (nanos / 1_000_000_000) as u64,
+                    (nanos % 1_000_000_000) as u32,
+                )
+        });
+    let mode = next()
+        .map(|mode| u32::from_str_radix(mode, 8))
+        .transpose()
+        .ok()?;
+    let owner = match next() {
+        Some(owner) => {
+            let (uid, gid) = owner.split_once(':')?;
+            Some((uid.parse().ok()?, gid.parse().ok()?))
+        }
+        None => None,
+    };
+    Some(Some(Meta {
+        is_dir,
+        size,
+        modified,
+        mode,
+        owner,
+    }))
+}
+
+fn parse_entry(line: &str) -> Option<DiffEntry> {
+    let mut fields = line.splitn(4, '\t');
+    let status = match fields.next()? {
+        "S" => FileStatus::Same,
+        "D" => FileStatus::Different,
+        "L" => FileStatus::LeftOnly,
+        "R" => FileStatus::RightOnly,
+        _ => return None,
+    };
+    let left = parse_side(fields.next()?)?;
+    let right = parse_side(fields.next()?)?;
+    let rel_path = PathBuf::from(fields.next()?);
+    Some(DiffEntry {
+        rel_path,
+        status,
+        left,
+        right,
+    })
+}
+
+/// Whether both sides of a recorded entry still have the size, mtime and
+/// the rest they had when it was compared.
+fn still_current(entry: &DiffEntry, left: &Path, right: &Path, options: &CompareOptions) -> bool {
+    let stat = |path: PathBuf| {
+        let metadata = if options.follow_links {
+            fs::metadata(path)
+        } else {
+            fs::symlink_metadata(path)
+        };
+        metadata.ok().map(|metadata| Meta::from_metadata(&metadata))
+    };
+    let right_path = options.path_map.to_right(&entry.rel_path);
+    stat(left.join(&entry.rel_path)) == entry.left
+        && right_path.and_then(|path| stat(right.join(path))) == entry.right
+}
+
+impl Checkpoint {
+    /// Load what an interrupted comparison of the two roots left behind and
+    /// keep recording after it, when `options.resume` asks for it. Entries
+    /// are kept up to the first one whose files changed since; without a
+    /// usable checkpoint, recording starts from scratch.
+    pub fn open(left: &Path, right: &Path, options: &CompareOptions) -> Self {
+        let file = crate::utils::pair_state_file("checkpoint", left, right);
+        let fingerprint = fingerprint(options);
+        let prelude = format!("{}\n{}\n", HEADER, fingerprint);
+
+        // A run that was killed may have left half a line at the end
+        let mut resumed = Vec::new();
+        let mut valid_len = 0;
+        let content = file
+            .as_ref()
+            .filter(|_| options.resume)
+            .and_then(|file| fs::read_to_string(file).ok());
+        if let Some(entries) = content.as_deref().and_then(|c| c.strip_prefix(&prelude)) {
+            valid_len = prelude.len();
+            for line in entries.split_inclusive('\n') {
+                let Some(entry) = line
+                    .strip_suffix('\n')
+                    .and_then(parse_entry)
+                    .filter(|entry| still_current(entry, left, right, options))
+                else {
+                    break;
+                };
+                resumed.push(entry);
+                valid_len += line.len();
+            }
+        }
+
+        let writer = file.as_ref().and_then(|file| {
+            Self::writer(file, &prelude, valid_len)
+                .inspect_err(|e| {
+                    crate::utils::log_error(&format!("Failed to open the checkpoint: {:#}", e))
+                })
+                .ok()
+        });
+        Self {
+            file,
+            resumed,
+            writer,
+        }
+    }
+
+    /// The file cut back to its first `keep` bytes, positioned at the end.
+    fn writer(file: &Path, prelude: &str, keep: usize) -> Result<BufWriter<File>> {
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        let mut handle = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(file)
+            .with_context(|| format!("opening {}", file.display()))?;
+        handle.set_len(keep as u64)?;
+        handle.seek(SeekFrom::End(0))?;
+        let mut writer = BufWriter::new(handle);
+        if keep == 0 {
+            writer.write_all(prelude.as_bytes())?;
+        }
+        Ok(writer)
+    }
+
+    /// The last path the previous run finished; the walk resumes after it.
+    pub fn resume_after(&self) -> Option<PathBuf> {
+        self.resumed.last().map(|entry| entry.rel_path.clone())
+    }
+
+    /// The entries of the previous run, in path order.
+    pub fn take_resumed(&mut self) -> Vec<DiffEntry> {
+        std::mem::take(&mut self.resumed)
+    }
+
+    /// Add a finished path. Entries must arrive in path order; once one
+    /// can't be written, recording stops so the file stays a prefix.
+    pub fn record(&mut self, entry: &DiffEntry) {
+        let Some(writer) = self.writer.as_mut() else {
+            return;
+        };
+        let written = match entry.rel_path.to_str().filter(|path| !path.contains('\n')) {
+            Some(path) => writer
+                .write_all(format_entry(entry, path).as_bytes())
+                .map_err(anyhow::Error::from),
+            None => Err(anyhow!("the path can't be stored")),
+        };
+        if let Err(e) = written {
+            crate::utils::log_error(&format!(
+                "Checkpoint stops before {}: {}",
+                entry.rel_path.display(),
+                e
+            ));
+            self.writer = None;
+        }
+    }
+
+    /// The comparison finished, so there is nothing left to resume.
+    pub fn complete(mut self) {
+        self.writer = None;
+        if let Some(file) = &self.file {
+            if let Err(e) = fs::remove_file(file) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    crate::utils::log_error(&format!(
+                        "Failed to remove the checkpoint {}: {}",
+                        file.display(),
+                        e
+                    ));
+                }
+            }
+        }
+    }
+}
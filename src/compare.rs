@@ -1,18 +1,239 @@
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 use crc32fast::Hasher as Crc32Hasher;
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
 use std::fs;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 
+use crate::checkpoint::Checkpoint;
+use crate::digest::{DigestBuilder, FolderDigests};
+use crate::elevate::{is_permission_denied, UnreadablePolicy};
+use crate::filter::{PathFilter, PatternSet};
+use crate::index::ComparisonIndex;
+use crate::manifest::{is_manifest_path, Manifest, ManifestEntry};
+use crate::normalize::Normalizers;
+use crate::pathmap::PathMap;
+use crate::pseudofs::{pseudo_filesystem, MountTracker, PseudoFsPolicy};
+use crate::utils::ProgressMode;
+
+/// Largest file whose similarity is computed; line diffs of bigger files
+/// would stall the UI.
+const SIMILARITY_MAX_BYTES: u64 = 1024 * 1024;
+
+/// How long a similarity's line diff may search for the best match before
+/// settling for a rougher one.
+const SIMILARITY_DEADLINE: Duration = Duration::from_millis(200);
+
+/// Read size of `verify_files`, per side.
+const VERIFY_CHUNK_SIZE: usize = 256 * 1024;
+
+/// What `DirectoryComparison::verify` found: the statuses of the files it
+/// read in full, and the paths it couldn't.
+pub type Verification = (Vec<(PathBuf, FileStatus)>, Vec<PathBuf>);
+
 #[allow(dead_code)]
 pub trait ProgressCallback: Send + Sync {
     fn update(&self, message: &str);
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Settings that control how two directory trees are compared.
+#[derive(Debug, Clone)]
+pub struct CompareOptions {
+    /// Largest modification time difference still treated as equal. Copies onto
+    /// filesystems with coarse timestamps (FAT: 2s, many network shares: 1s) round
+    /// the preserved mtime, so exact equality would keep flagging them.
+    pub mtime_tolerance: Duration,
+    /// Include/exclude patterns applied while scanning both roots
+    pub filter: PathFilter,
+    /// Do not descend into directories on a different filesystem than the root
+    pub one_file_system: bool,
+    /// Compare what symlinks point to instead of the links, descending into
+    /// linked folders; links that loop back are skipped with a warning
+    pub follow_links: bool,
+    /// Compare only these relative paths instead of walking the roots
+    pub files_from: Option<Vec<PathBuf>>,
+    /// Treat entries whose permission bits differ as different
+    pub compare_permissions: bool,
+    /// For S3 objects without an MD5 ETag, also compare the first and last
+    /// 64 KiB instead of trusting equal sizes
+    pub s3_ranged_check: bool,
+    /// Commands whose output is compared instead of the raw bytes, by extension
+    pub normalizers: Normalizers,
+    /// Compare source and config files without their comments and blank lines
+    pub ignore_comments: bool,
+    /// Reuse the results of the last scan of these roots for files whose size
+    /// and modification time haven't changed, see `index::ComparisonIndex`
+    pub use_index: bool,
+    /// Record finished paths while scanning and pick up an interrupted scan
+    /// of the same roots where it stopped, see `checkpoint::Checkpoint`
+    pub checkpoint: bool,
+    /// Pick up the entries of an interrupted scan that are still current;
+    /// without it a checkpoint is only written
+    pub resume: bool,
+    /// What to do with folders on /proc, /sys and the like
+    pub pseudo_fs: PseudoFsPolicy,
+    /// What to do with folders and files the scan isn't allowed to read
+    pub unreadable: UnreadablePolicy,
+    /// Once a scan passes this many paths, ask `limit_prompt` whether to go
+    /// on; a guard against comparing the wrong, enormous folders
+    pub max_entries: Option<usize>,
+    /// Asks the user at `max_entries`; without one the scan goes on with a
+    /// warning
+    pub limit_prompt: Option<LimitPrompt>,
+    /// Treat files of the same size and modification time as the same
+    /// without reading them
+    pub metadata_only: bool,
+    /// Folders named differently on the right, compared with their
+    /// counterparts on the left under the left's names
+    pub path_map: PathMap,
+}
+
+/// What a scan does once it has passed `CompareOptions::max_entries`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitChoice {
+    Continue,
+    /// Go on, deciding the remaining files by size and modification time
+    MetadataOnly,
+    Abort,
+}
+
+/// Asks what to do when a scan reaches the entry limit, given the number of
+/// paths seen so far. Called from the scanning thread, and blocks it until
+/// answered.
+#[derive(Clone)]
+pub struct LimitPrompt(pub Arc<dyn Fn(usize) -> LimitChoice + Send + Sync>);
+
+impl std::fmt::Debug for LimitPrompt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LimitPrompt")
+    }
+}
+
+/// The entry limit of a running scan, and whether the user chose to skip
+/// reading files once it was reached.
+struct EntryLimit {
+    max: Option<usize>,
+    metadata_only: bool,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self {
+            mtime_tolerance: Duration::from_secs(2),
+            filter: PathFilter::default(),
+            one_file_system: false,
+            follow_links: false,
+            files_from: None,
+            compare_permissions: false,
+            s3_ranged_check: false,
+            normalizers: Normalizers::default(),
+            ignore_comments: false,
+            use_index: false,
+            checkpoint: false,
+            resume: false,
+            pseudo_fs: PseudoFsPolicy::Skip,
+            unreadable: UnreadablePolicy::Report,
+            max_entries: None,
+            limit_prompt: None,
+            metadata_only: false,
+            path_map: PathMap::default(),
+        }
+    }
+}
+
+/// Fail once a cancellable comparison has been asked to stop.
+fn check_cancelled(cancel: Option<&AtomicBool>) -> Result<()> {
+    if cancel.is_some_and(|cancel| cancel.load(AtomicOrdering::Relaxed)) {
+        bail!("comparison cancelled");
+    }
+    Ok(())
+}
+
+/// Permission bits (`0o7777`) of an entry, on platforms that have them.
+pub fn permission_bits(metadata: &fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Owner and group ids of an entry, on platforms that have them.
+pub fn ownership(metadata: &fs::Metadata) -> Option<(u32, u32)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.uid(), metadata.gid()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Returns true when both times are known and differ by no more than `tolerance`.
+pub fn mtimes_equal(
+    left: Option<SystemTime>,
+    right: Option<SystemTime>,
+    tolerance: Duration,
+) -> bool {
+    match (left, right) {
+        (Some(left), Some(right)) => {
+            let delta = left
+                .duration_since(right)
+                .unwrap_or_else(|e| e.duration());
+            delta <= tolerance
+        }
+        _ => false,
+    }
+}
+
+/// How two roots overlap, if one is the other or lies inside it once
+/// symlinks are resolved; comparing them reads the inner one twice, and with
+/// `--follow-links` can go round in circles.
+pub fn root_overlap(left: &Path, right: &Path) -> Option<String> {
+    use crate::utils::escape_name;
+
+    let left_real = fs::canonicalize(left).ok()?;
+    let right_real = fs::canonicalize(right).ok()?;
+    if left_real == right_real {
+        Some(format!(
+            "{} and {} are the same folder",
+            escape_name(left),
+            escape_name(right)
+        ))
+    } else if right_real.starts_with(&left_real) {
+        Some(format!(
+            "{} is inside {}",
+            escape_name(right),
+            escape_name(left)
+        ))
+    } else if left_real.starts_with(&right_real) {
+        Some(format!(
+            "{} is inside {}",
+            escape_name(left),
+            escape_name(right)
+        ))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FileStatus {
     Same,      // File exists on both sides and is identical
     Different, // File exists on both sides but is different
@@ -21,55 +242,74 @@ pub enum FileStatus {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileNode {
-    pub name: String,
-    pub path: PathBuf,
+    /// The entry's own name (a root's is its directory's), shared with the
+    /// node at the same position in the other tree. Paths aren't stored:
+    /// they're put together from the names on the way down, see [`find`].
+    ///
+    /// [`find`]: FileNode::find
+    pub name: Arc<OsStr>,
+    /// False for the placeholder standing in for an entry only the other
+    /// side has
+    pub exists: bool,
     pub is_dir: bool,
     pub status: FileStatus,
     pub children: Vec<FileNode>,
     pub expanded: bool,
     pub size: Option<u64>,
     pub modified: Option<SystemTime>,
+    /// Permission bits, see [`permission_bits`]
+    pub mode: Option<u32>,
+    /// Owner and group ids, see [`ownership`]
+    pub owner: Option<(u32, u32)>,
 }
 
 impl FileNode {
-    pub fn new(name: String, path: PathBuf, is_dir: bool, status: FileStatus) -> Self {
+    pub fn new(name: Arc<OsStr>, exists: bool, is_dir: bool, status: FileStatus) -> Self {
         Self {
             name,
-            path,
+            exists,
             is_dir,
             status,
             children: Vec::new(),
             expanded: false, // All directories start collapsed by default
             size: None,
             modified: None,
+            mode: None,
+            owner: None,
         }
     }
 
     pub fn new_with_metadata(
-        name: String,
-        path: PathBuf,
+        name: Arc<OsStr>,
+        exists: bool,
         is_dir: bool,
         status: FileStatus,
         metadata: Option<&fs::Metadata>,
     ) -> Self {
-        let (size, modified) = if let Some(meta) = metadata {
-            let size = if is_dir { None } else { Some(meta.len()) };
-            let modified = meta.modified().ok();
-            (size, modified)
-        } else {
-            (None, None)
-        };
+        let meta = metadata.map(Meta::from_metadata);
+        Self::new_with_meta(name, exists, is_dir, status, meta.as_ref())
+    }
 
+    fn new_with_meta(
+        name: Arc<OsStr>,
+        exists: bool,
+        is_dir: bool,
+        status: FileStatus,
+        meta: Option<&Meta>,
+    ) -> Self {
         Self {
             name,
-            path,
+            exists,
             is_dir,
             status,
             children: Vec::new(),
             expanded: false,
-            size,
-            modified,
+            size: meta.and_then(|meta| if is_dir { None } else { meta.size }),
+            modified: meta.and_then(|meta| meta.modified),
+            mode: meta.and_then(|meta| meta.mode),
+            owner: meta.and_then(|meta| meta.owner),
         }
     }
 
@@ -78,393 +318,2091 @@ impl FileNode {
             self.expanded = !self.expanded;
         }
     }
+
+    /// The name as shown, with control characters and bytes that aren't
+    /// UTF-8 escaped; empty for placeholders.
+    pub fn display_name(&self) -> String {
+        if self.exists {
+            crate::utils::escape_name(&*self.name)
+        } else {
+            String::new()
+        }
+    }
+
+    /// This node or the descendant with the given path relative to it.
+    pub fn find(&self, path: &Path) -> Option<&FileNode> {
+        path.components().try_fold(self, |node, component| {
+            node.children
+                .iter()
+                .find(|child| *child.name == *component.as_os_str())
+        })
+    }
+
+    pub fn find_mut(&mut self, path: &Path) -> Option<&mut FileNode> {
+        path.components().try_fold(self, |node, component| {
+            node.children
+                .iter_mut()
+                .find(|child| *child.name == *component.as_os_str())
+        })
+    }
 }
 
-pub struct DirectoryComparison {
-    pub left_tree: FileNode,
-    pub right_tree: FileNode,
-    pub left_dir: PathBuf,
-    pub right_dir: PathBuf,
+/// One side of a [`DiffEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Meta {
+    pub is_dir: bool,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    pub mode: Option<u32>,
+    pub owner: Option<(u32, u32)>,
 }
 
-impl DirectoryComparison {
-    pub fn new(left_dir: PathBuf, right_dir: PathBuf) -> Result<Self> {
-        Self::new_with_logging(left_dir, right_dir, true)
+/// A compared path with the metadata of each side that has it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiffEntry {
+    pub rel_path: PathBuf,
+    pub status: FileStatus,
+    pub left: Option<Meta>,
+    pub right: Option<Meta>,
+}
+
+/// Depth-first iterator over both trees, see [`DirectoryComparison::entries`].
+pub struct Entries<'a> {
+    stack: Vec<(&'a FileNode, &'a FileNode, PathBuf)>,
+}
+
+impl<'a> Entries<'a> {
+    fn push_children(&mut self, left: &'a FileNode, right: &'a FileNode, path: &Path) {
+        self.stack.extend(
+            left.children
+                .iter()
+                .zip(&right.children)
+                .rev()
+                .map(|(left, right)| (left, right, path.join(&*left.name))),
+        );
     }
+}
 
-    #[allow(dead_code)]
-    pub fn new_silent(left_dir: PathBuf, right_dir: PathBuf) -> Result<Self> {
-        Self::new_with_logging(left_dir, right_dir, false)
+impl Iterator for Entries<'_> {
+    type Item = DiffEntry;
+
+    fn next(&mut self) -> Option<DiffEntry> {
+        let (left, right, rel_path) = self.stack.pop()?;
+        self.push_children(left, right, &rel_path);
+
+        let meta = |node: &FileNode| {
+            node.exists.then_some(Meta {
+                is_dir: node.is_dir,
+                size: node.size,
+                modified: node.modified,
+                mode: node.mode,
+                owner: node.owner,
+            })
+        };
+        Some(DiffEntry {
+            rel_path,
+            status: left.status,
+            left: meta(left),
+            right: meta(right),
+        })
     }
+}
 
-    pub fn new_with_progress<F>(
-        left_dir: PathBuf,
-        right_dir: PathBuf,
-        mut progress_callback: F,
-    ) -> Result<Self>
-    where
-        F: FnMut(&str),
-    {
-        crate::utils::log_debug(&format!(
-            "Starting comparison: {} vs {}",
-            left_dir.display(),
-            right_dir.display()
-        ));
+impl Meta {
+    pub(crate) fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let is_dir = metadata.is_dir();
+        Self {
+            is_dir,
+            size: if is_dir { None } else { Some(metadata.len()) },
+            modified: metadata.modified().ok(),
+            mode: permission_bits(metadata),
+            owner: ownership(metadata),
+        }
+    }
+}
 
-        progress_callback("Starting directory scan...");
+/// The previous results for the files of folders whose listing hashes are
+/// unchanged since that comparison.
+struct Reuse<'a> {
+    previous: &'a FolderDigests,
+    folders: HashSet<PathBuf>,
+}
 
-        progress_callback("Scanning left directory...");
-        let left_files = match Self::collect_files_with_progress(&left_dir, &mut progress_callback)
-        {
-            Ok(files) => files,
-            Err(e) => {
-                crate::utils::log_error(&format!(
-                    "Failed to collect left files from {}: {}",
-                    left_dir.display(),
-                    e
-                ));
-                return Err(e);
-            }
-        };
+impl Reuse<'_> {
+    fn status(
+        &self,
+        path: &Path,
+        left: Option<&fs::Metadata>,
+        right: Option<&fs::Metadata>,
+    ) -> Option<FileStatus> {
+        let is_file = |meta: Option<&fs::Metadata>| meta.is_some_and(|meta| meta.is_file());
+        if !is_file(left) || !is_file(right) || !self.folders.contains(path.parent()?) {
+            return None;
+        }
+        Some(self.previous.file_status(path))
+    }
+}
 
-        progress_callback("Scanning right directory...");
-        let right_files =
-            match Self::collect_files_with_progress(&right_dir, &mut progress_callback) {
-                Ok(files) => files,
-                Err(e) => {
-                    crate::utils::log_error(&format!(
-                        "Failed to collect right files from {}: {}",
-                        right_dir.display(),
-                        e
-                    ));
-                    return Err(e);
-                }
-            };
+/// Hands entries of a running comparison to a callback once their status is
+/// final. Folders present on both sides are held back until their last
+/// descendant has been seen, because their status depends on the children.
+struct EntryStream<'a> {
+    callback: &'a mut dyn FnMut(DiffEntry),
+    open_dirs: Vec<(DiffEntry, Vec<FileStatus>)>,
+}
 
-        progress_callback("Comparing files...");
-        let (left_tree, right_tree) = match Self::compare_trees_with_progress(
-            &left_dir,
-            &right_dir,
-            &left_files,
-            &right_files,
-            &mut progress_callback,
-        ) {
-            Ok(trees) => trees,
-            Err(e) => {
-                crate::utils::log_error(&format!("Failed to compare trees: {}", e));
-                return Err(e);
-            }
-        };
+impl EntryStream<'_> {
+    /// Paths must arrive in sorted order, so a folder's descendants follow it.
+    fn push(&mut self, entry: DiffEntry) {
+        while self
+            .open_dirs
+            .last()
+            .is_some_and(|(dir, _)| !entry.rel_path.starts_with(&dir.rel_path))
+        {
+            self.close_last();
+        }
 
-        progress_callback("Complete!");
-        crate::utils::log_debug("Comparison completed successfully");
+        let is_dir = entry.left.or(entry.right).is_some_and(|meta| meta.is_dir);
+        if is_dir && entry.status == FileStatus::Same {
+            self.open_dirs.push((entry, Vec::new()));
+        } else {
+            self.emit(entry);
+        }
+    }
 
-        Ok(Self {
-            left_tree,
-            right_tree,
-            left_dir,
-            right_dir,
-        })
+    fn finish(&mut self) {
+        while !self.open_dirs.is_empty() {
+            self.close_last();
+        }
     }
 
-    fn new_with_logging(
-        left_dir: PathBuf,
-        right_dir: PathBuf,
-        enable_logging: bool,
-    ) -> Result<Self> {
-        let left_files = Self::collect_files(&left_dir, enable_logging)?;
-        let right_files = Self::collect_files(&right_dir, enable_logging)?;
-        let (left_tree, right_tree) = Self::compare_trees(
-            &left_dir,
-            &right_dir,
-            &left_files,
-            &right_files,
-            enable_logging,
-        )?;
+    fn close_last(&mut self) {
+        if let Some((mut dir, child_statuses)) = self.open_dirs.pop() {
+            dir.status = DirectoryComparison::folder_status(dir.status, &child_statuses);
+            self.emit(dir);
+        }
+    }
 
-        Ok(Self {
-            left_tree,
-            right_tree,
-            left_dir,
-            right_dir,
-        })
+    fn emit(&mut self, entry: DiffEntry) {
+        if let Some((parent, child_statuses)) = self.open_dirs.last_mut() {
+            if entry.rel_path.parent() == Some(parent.rel_path.as_path()) {
+                child_statuses.push(entry.status);
+            }
+        }
+        (self.callback)(entry);
     }
+}
 
-    fn collect_files(dir: &Path, enable_logging: bool) -> Result<HashMap<PathBuf, fs::Metadata>> {
-        let mut files = HashMap::new();
-        let mut count = 0;
+/// Where a running comparison reports what it is doing.
+enum Progress<'a> {
+    /// Nothing is reported (refreshes, re-scans after file operations)
+    Silent,
+    /// Dots or JSON events on stderr, depending on the `ProgressMode`
+    Stderr,
+    /// Status lines for the refresh popup
+    Callback(&'a mut dyn FnMut(&str)),
+}
 
-        for entry in WalkDir::new(dir) {
-            let entry = entry?;
-            let relative_path = entry.path().strip_prefix(dir)?.to_path_buf();
-            let metadata = entry.metadata()?;
-            files.insert(relative_path, metadata);
+impl Progress<'_> {
+    fn message(&mut self, message: &str) {
+        if let Progress::Callback(callback) = self {
+            callback(message);
+        }
+    }
 
-            count += 1;
-            if enable_logging && count % 100 == 0 {
-                eprint!(".");
+    /// Called for every scanned entry, and once more when `finished`.
+    fn scanned(&mut self, dir: &Path, count: usize, finished: bool) {
+        match self {
+            Progress::Silent => {}
+            Progress::Callback(callback) => {
+                if !finished && count.is_multiple_of(50) {
+                    callback(&format!("Scanning... {} files", count));
+                }
             }
+            Progress::Stderr => match crate::utils::progress_mode() {
+                ProgressMode::Quiet => {}
+                ProgressMode::Dots if finished => {
+                    if count >= 100 {
+                        eprintln!();
+                    }
+                }
+                ProgressMode::Dots => {
+                    if count.is_multiple_of(100) {
+                        eprint!(".");
+                    }
+                }
+                ProgressMode::Json => {
+                    if finished || count.is_multiple_of(100) {
+                        eprintln!(
+                            r#"{{"event":"scan","dir":{},"entries":{},"finished":{}}}"#,
+                            crate::utils::json_string(&dir.to_string_lossy()),
+                            count,
+                            finished
+                        );
+                    }
+                }
+            },
         }
+    }
 
-        if enable_logging && count >= 100 {
-            eprintln!();
+    /// `total` is `None` when the trees are scanned while being compared.
+    fn compare_started(&mut self, total: Option<usize>) {
+        match self {
+            Progress::Silent => {}
+            Progress::Callback(callback) => {
+                if let Some(total) = total {
+                    callback(&format!("Files to compare: {}", total));
+                }
+                callback("Processing paths...");
+            }
+            Progress::Stderr => self.comparing(None, 0, total),
         }
+    }
 
-        Ok(files)
+    /// Called before comparing file contents; `path` is `None` when the
+    /// comparison phase starts.
+    fn comparing(&mut self, path: Option<&Path>, processed: usize, total: Option<usize>) {
+        if path.is_some() && (processed == 0 || !processed.is_multiple_of(100)) {
+            return;
+        }
+        match self {
+            Progress::Silent => {}
+            Progress::Callback(callback) => match total {
+                Some(total) => callback(&format!("Comparing... {}/{}", processed, total)),
+                None => callback(&format!("Comparing... {} paths", processed)),
+            },
+            Progress::Stderr => match (crate::utils::progress_mode(), path) {
+                (ProgressMode::Quiet, _) => {}
+                (ProgressMode::Dots, None) => match total {
+                    Some(total) => eprintln!(
+                        "{} Processing {} unique paths...",
+                        crate::icons::icons().processing,
+                        total
+                    ),
+                    None => eprintln!("{} Processing paths...", crate::icons::icons().processing),
+                },
+                (ProgressMode::Dots, Some(path)) => {
+                    eprintln!(
+                        "   {} Comparing file: {}",
+                        crate::icons::icons().comparing,
+                        path.display()
+                    )
+                }
+                (ProgressMode::Json, path) => eprintln!(
+                    r#"{{"event":"compare","processed":{},"total":{},"path":{}}}"#,
+                    processed,
+                    total.map_or("null".to_string(), |total| total.to_string()),
+                    path.map_or("null".to_string(), |p| crate::utils::json_string(
+                        &p.to_string_lossy()
+                    ))
+                ),
+            },
+        }
     }
 
-    fn collect_files_with_progress<F>(
-        dir: &Path,
-        progress_callback: &mut F,
-    ) -> Result<HashMap<PathBuf, fs::Metadata>>
-    where
-        F: FnMut(&str),
-    {
-        let mut files = HashMap::new();
-        let mut count = 0;
+    fn compared(&mut self, processed: usize, total: Option<usize>) {
+        if let (Progress::Callback(callback), Some(total)) = (self, total) {
+            if processed.is_multiple_of(10) || processed == total {
+                callback(&format!("Progress: {}/{}", processed, total));
+            }
+        }
+    }
 
-        for entry in WalkDir::new(dir) {
-            let entry = entry?;
-            let relative_path = entry.path().strip_prefix(dir)?.to_path_buf();
-            let metadata = entry.metadata()?;
-            files.insert(relative_path, metadata);
+    fn done(&mut self, total: usize) {
+        if let Progress::Stderr = self {
+            if crate::utils::progress_mode() == ProgressMode::Json {
+                eprintln!(r#"{{"event":"done","total":{}}}"#, total);
+            }
+        }
+    }
 
-            count += 1;
-            if count % 50 == 0 {
-                progress_callback(&format!("Scanning... {} files", count));
+    fn stats(&mut self, stats: &CompareStats) {
+        if let Progress::Stderr = self {
+            if crate::utils::progress_mode() == ProgressMode::Json {
+                eprintln!("{}", stats.to_json());
             }
         }
+    }
+}
 
-        Ok(files)
+/// Builds one side's tree from paths in sorted order. Sorting keeps every
+/// folder's descendants together, so only the branch leading to the previous
+/// path has to be remembered and no children are ever searched.
+struct TreeBuilder {
+    root: FileNode,
+    /// Child indices from `root` down to the most recently inserted node
+    open: Vec<usize>,
+}
+
+impl TreeBuilder {
+    fn new(root: FileNode) -> Self {
+        Self {
+            root,
+            open: Vec::new(),
+        }
     }
 
-    fn compare_trees(
-        left_dir: &Path,
-        right_dir: &Path,
-        left_files: &HashMap<PathBuf, fs::Metadata>,
-        right_files: &HashMap<PathBuf, fs::Metadata>,
-        enable_logging: bool,
-    ) -> Result<(FileNode, FileNode)> {
-        let left_name = left_dir
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        let right_name = right_dir
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-
-        let mut left_root =
-            FileNode::new(left_name, left_dir.to_path_buf(), true, FileStatus::Same);
-        let mut right_root =
-            FileNode::new(right_name, right_dir.to_path_buf(), true, FileStatus::Same);
+    /// Insert the node at `path`, named `name` (the last component of the
+    /// path, shared with the other tree's builder). `exists` is false for a
+    /// placeholder.
+    fn insert(
+        &mut self,
+        path: &Path,
+        name: &Arc<OsStr>,
+        exists: bool,
+        is_dir: bool,
+        status: FileStatus,
+        meta: Option<&Meta>,
+    ) {
+        let components: Vec<_> = path.components().collect();
+        let Self { root, open } = self;
 
-        // Root directory starts expanded
-        left_root.expanded = true;
-        right_root.expanded = true;
+        // Follow the part of the open branch this path shares
+        let mut current = root;
+        let mut depth = 0;
+        while let (Some(&index), Some(component)) = (open.get(depth), components.get(depth)) {
+            if *current.children[index].name != *component.as_os_str() {
+                break;
+            }
+            current = &mut current.children[index];
+            depth += 1;
+        }
+        open.truncate(depth);
 
-        // Collect all unique paths
-        let mut all_paths = std::collections::BTreeSet::new();
-        all_paths.extend(left_files.keys().cloned());
-        all_paths.extend(right_files.keys().cloned());
+        if depth == components.len() {
+            // Already created as an intermediate folder
+            current.status = status;
+            return;
+        }
 
-        let total_paths = all_paths.len();
-        if enable_logging {
-            eprintln!("🔀 Processing {} unique paths...", total_paths);
+        // Create the missing folders and the node itself
+        for i in depth..components.len() {
+            let new_child = if i == components.len() - 1 {
+                FileNode::new_with_meta(name.clone(), exists, is_dir, status, meta)
+            } else {
+                let name = components[i].as_os_str().into();
+                FileNode::new(name, true, true, FileStatus::Same)
+            };
+
+            current.children.push(new_child);
+            let new_index = current.children.len() - 1;
+            open.push(new_index);
+            current = &mut current.children[new_index];
         }
+    }
 
-        // Convert paths to tree structure
-        let mut processed = 0;
-        for path in all_paths {
-            if path.as_os_str().is_empty() {
-                continue; // Skip root path
-            }
+    fn finish(self) -> FileNode {
+        self.root
+    }
+}
 
-            let left_exists = left_files.contains_key(&path);
-            let right_exists = right_files.contains_key(&path);
-            let left_meta = left_files.get(&path);
-            let right_meta = right_files.get(&path);
+/// A path below both roots with the metadata of the sides it exists on.
+type MetadataPair = (PathBuf, Option<fs::Metadata>, Option<fs::Metadata>);
+type Listing = HashMap<PathBuf, fs::Metadata>;
+
+/// One side's entries in path order, narrowed down by the filter the same
+/// way `collect_files` does. With include patterns, folders are held back
+/// until a matching file below them turns up, so only the branch being
+/// walked is kept in memory.
+struct SortedWalk<'a> {
+    root: &'a Path,
+    filter: &'a PathFilter,
+    entries: Box<dyn Iterator<Item = walkdir::Result<walkdir::DirEntry>> + 'a>,
+    /// Paths up to this one were compared by an earlier run
+    resume_after: Option<&'a Path>,
+    /// Folders without a matching file so far, outermost first
+    pending: Vec<(PathBuf, fs::Metadata)>,
+    ready: VecDeque<(PathBuf, fs::Metadata)>,
+}
 
-            let status = match (left_exists, right_exists) {
-                (true, true) => {
-                    if left_meta.unwrap().is_file() && right_meta.unwrap().is_file() {
-                        // Compare file contents
-                        let left_path = left_dir.join(&path);
-                        let right_path = right_dir.join(&path);
+impl<'a> SortedWalk<'a> {
+    fn new(root: &'a Path, options: &'a CompareOptions, resume_after: Option<&'a Path>) -> Self {
+        Self {
+            root,
+            filter: &options.filter,
+            entries: Box::new(DirectoryComparison::walk_from(
+                root,
+                root,
+                options,
+                resume_after,
+            )),
+            resume_after,
+            pending: Vec::new(),
+            ready: VecDeque::new(),
+        }
+    }
+}
 
-                        if enable_logging && processed % 100 == 0 && processed > 0 {
-                            eprintln!("   🔍 Comparing file: {}", path.display());
-                        }
+impl Iterator for SortedWalk<'_> {
+    type Item = Result<(PathBuf, fs::Metadata)>;
 
-                        if Self::files_are_same(
-                            &left_path,
-                            &right_path,
-                            left_meta.unwrap(),
-                            right_meta.unwrap(),
-                        )? {
-                            FileStatus::Same
-                        } else {
-                            FileStatus::Different
-                        }
-                    } else {
-                        FileStatus::Same // Assume directories are same for now
-                    }
-                }
-                (true, false) => FileStatus::LeftOnly,
-                (false, true) => FileStatus::RightOnly,
-                (false, false) => unreachable!(),
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.ready.pop_front() {
+                return Some(Ok(entry));
+            }
+            let entry = match self.entries.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e.into())),
             };
+            let relative_path = match entry.path().strip_prefix(self.root) {
+                Ok(path) => path.to_path_buf(),
+                Err(e) => return Some(Err(e.into())),
+            };
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => return Some(Err(e.into())),
+            };
+            if !self.filter.is_included(&relative_path, metadata.is_dir())
+                || self.resume_after.is_some_and(|last| relative_path <= last)
+            {
+                continue;
+            }
+            if self.filter.include.is_empty() || relative_path.as_os_str().is_empty() {
+                return Some(Ok((relative_path, metadata)));
+            }
 
-            let is_dir = left_meta
-                .map(|m| m.is_dir())
-                .or(right_meta.map(|m| m.is_dir()))
-                .unwrap_or(false);
-            let name = path
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
-
-            // Insert only items that exist in each panel
-            // For LeftOnly/RightOnly, insert empty nodes on opposite side for alignment
-            match status {
-                FileStatus::Same | FileStatus::Different => {
-                    // Exists on both sides
-                    Self::insert_into_tree(
-                        &mut left_root,
-                        &path,
-                        name.clone(),
-                        is_dir,
-                        status,
-                        true,
-                        left_meta,
-                    )?;
-                    Self::insert_into_tree(
-                        &mut right_root,
-                        &path,
-                        name,
-                        is_dir,
-                        status,
-                        true,
-                        right_meta,
-                    )?;
+            // Folders the walk has left never got a matching file
+            while self
+                .pending
+                .last()
+                .is_some_and(|(dir, _)| !relative_path.starts_with(dir))
+            {
+                self.pending.pop();
+            }
+            if metadata.is_dir() {
+                self.pending.push((relative_path, metadata));
+            } else {
+                self.ready.extend(self.pending.drain(..));
+                self.ready.push_back((relative_path, metadata));
+            }
+        }
+    }
+}
+
+/// How far a side's walk may get ahead of the merge, in entries.
+const WALK_AHEAD: usize = 1024;
+
+/// A `SortedWalk` running on its own thread, so both sides are read at
+/// once. What it warned about moves over to the merging thread when the
+/// walk is done.
+struct WalkThread<'scope> {
+    entries: std::sync::mpsc::Receiver<Result<(PathBuf, fs::Metadata)>>,
+    thread: Option<std::thread::ScopedJoinHandle<'scope, (Vec<String>, Vec<PathBuf>)>>,
+}
+
+impl<'scope> WalkThread<'scope> {
+    fn spawn<'env>(
+        scope: &'scope std::thread::Scope<'scope, 'env>,
+        root: &'env Path,
+        options: &'env CompareOptions,
+        resume_after: Option<&'env Path>,
+    ) -> Self {
+        let (tx, rx) = std::sync::mpsc::sync_channel(WALK_AHEAD);
+        let thread = scope.spawn(move || {
+            for entry in SortedWalk::new(root, options, resume_after) {
+                let failed = entry.is_err();
+                // Nobody is listening once the merge stopped early
+                if tx.send(entry).is_err() || failed {
+                    break;
+                }
+            }
+            (take_warnings(), take_unreadable())
+        });
+        Self {
+            entries: rx,
+            thread: Some(thread),
+        }
+    }
+
+    fn next(&mut self) -> Option<Result<(PathBuf, fs::Metadata)>> {
+        let entry = self.entries.recv().ok();
+        if entry.is_none() {
+            match self.thread.take()?.join() {
+                Ok((warnings, unreadable)) => {
+                    WARNINGS.with(|all| all.borrow_mut().extend(warnings));
+                    UNREADABLE.with(|all| all.borrow_mut().extend(unreadable));
+                }
+                Err(_) => return Some(Err(anyhow!("the scan stopped unexpectedly"))),
+            }
+        }
+        entry
+    }
+}
+
+/// Both sides' walks merged by path, each path once.
+struct MergedWalk<'scope> {
+    left: WalkThread<'scope>,
+    right: WalkThread<'scope>,
+    left_next: Option<(PathBuf, fs::Metadata)>,
+    right_next: Option<(PathBuf, fs::Metadata)>,
+}
+
+impl<'scope> MergedWalk<'scope> {
+    fn spawn<'env>(
+        scope: &'scope std::thread::Scope<'scope, 'env>,
+        left_dir: &'env Path,
+        right_dir: &'env Path,
+        options: &'env CompareOptions,
+        resume_after: Option<&'env Path>,
+    ) -> Self {
+        Self {
+            left: WalkThread::spawn(scope, left_dir, options, resume_after),
+            right: WalkThread::spawn(scope, right_dir, options, resume_after),
+            left_next: None,
+            right_next: None,
+        }
+    }
+}
+
+impl Iterator for MergedWalk<'_> {
+    type Item = Result<MetadataPair>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.left_next.is_none() {
+            match self.left.next().transpose() {
+                Ok(entry) => self.left_next = entry,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        if self.right_next.is_none() {
+            match self.right.next().transpose() {
+                Ok(entry) => self.right_next = entry,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        let order = match (&self.left_next, &self.right_next) {
+            (None, None) => return None,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some((left, _)), Some((right, _))) => left.cmp(right),
+        };
+        let pair = match order {
+            Ordering::Less => {
+                let (path, metadata) = self.left_next.take()?;
+                (path, Some(metadata), None)
+            }
+            Ordering::Greater => {
+                let (path, metadata) = self.right_next.take()?;
+                (path, None, Some(metadata))
+            }
+            Ordering::Equal => {
+                let (path, left) = self.left_next.take()?;
+                let (_, right) = self.right_next.take()?;
+                (path, Some(left), Some(right))
+            }
+        };
+        Some(Ok(pair))
+    }
+}
+
+thread_local! {
+    /// File contents read by comparisons on this thread so far.
+    static BYTES_READ: Cell<u64> = const { Cell::new(0) };
+}
+
+thread_local! {
+    /// Problems the scan on this thread ran into that don't stop it.
+    static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Report something the user should know about the scan, like a folder
+/// that was left out.
+fn warn(message: String) {
+    crate::utils::log_info(&message);
+    WARNINGS.with(|warnings| warnings.borrow_mut().push(message));
+}
+
+fn take_warnings() -> Vec<String> {
+    WARNINGS.with(|warnings| warnings.take())
+}
+
+thread_local! {
+    /// Folders and files the scan on this thread wasn't allowed to read.
+    static UNREADABLE: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Note a folder or file that couldn't be read for lack of permissions;
+/// the warning about them is made once the scan is done, see
+/// `needs_elevation`.
+fn unreadable(path: &Path) {
+    crate::utils::log_info(&format!("Permission denied: {}", path.display()));
+    UNREADABLE.with(|unreadable| unreadable.borrow_mut().push(path.to_path_buf()));
+}
+
+fn take_unreadable() -> Vec<PathBuf> {
+    UNREADABLE.with(|unreadable| unreadable.take())
+}
+
+/// The warning about the paths that couldn't be read, even with sudo when
+/// `sudo` is set.
+fn needs_elevation(unreadable: &[PathBuf], sudo: bool) -> String {
+    let how = if sudo {
+        "run as root to read them"
+    } else {
+        "use --unreadable sudo or run as root to read them"
+    };
+    match unreadable {
+        [path] => format!(
+            "Permission denied reading {}, shown without its contents; {}",
+            path.display(),
+            how
+        ),
+        paths => format!(
+            "Permission denied reading {} paths, shown without their contents; {}",
+            paths.len(),
+            how
+        ),
+    }
+}
+
+/// Count file contents read to compare them, for `CompareStats`, and hold
+/// the reading back to `--bwlimit` until `cancel` is set.
+pub fn count_read(bytes: usize, cancel: Option<&AtomicBool>) {
+    BYTES_READ.with(|read| read.set(read.get() + bytes as u64));
+    crate::throttle::consume(bytes as u64, cancel);
+}
+
+/// How long a comparison took and how much it read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompareStats {
+    /// Listing the trees and everything else that isn't reading file
+    /// contents; walked side by side, the trees are listed while comparing
+    pub scan_time: Duration,
+    /// Reading file contents to compare them
+    pub compare_time: Duration,
+    pub bytes_read: u64,
+    /// Paths on either side
+    pub paths: usize,
+    /// File pairs whose contents were read
+    pub files_compared: usize,
+}
+
+impl CompareStats {
+    pub fn total_time(&self) -> Duration {
+        self.scan_time + self.compare_time
+    }
+
+    pub fn paths_per_sec(&self) -> f64 {
+        let seconds = self.total_time().as_secs_f64();
+        if seconds > 0.0 {
+            self.paths as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// One line for `--progress json`.
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"event":"stats","paths":{},"files_compared":{},"bytes_read":{},"scan_ms":{},"compare_ms":{},"total_ms":{},"paths_per_sec":{:.1}}}"#,
+            self.paths,
+            self.files_compared,
+            self.bytes_read,
+            self.scan_time.as_millis(),
+            self.compare_time.as_millis(),
+            self.total_time().as_millis(),
+            self.paths_per_sec()
+        )
+    }
+}
+
+/// Started with a comparison to work out its `CompareStats` at the end.
+struct Stopwatch {
+    started: Instant,
+    read_before: u64,
+}
+
+impl Stopwatch {
+    fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            read_before: BYTES_READ.with(Cell::get),
+        }
+    }
+
+    /// Add the times and bytes read since the start to `stats`.
+    fn finish(&self, mut stats: CompareStats, progress: &mut Progress) -> CompareStats {
+        stats.scan_time = self.started.elapsed().saturating_sub(stats.compare_time);
+        stats.bytes_read = BYTES_READ.with(Cell::get) - self.read_before;
+        progress.stats(&stats);
+        stats
+    }
+}
+
+#[derive(Clone)]
+pub struct DirectoryComparison {
+    pub left_tree: FileNode,
+    pub right_tree: FileNode,
+    pub left_dir: PathBuf,
+    pub right_dir: PathBuf,
+    pub options: CompareOptions,
+    /// Listing hashes of every folder, empty when a side is a manifest.
+    pub digests: FolderDigests,
+    pub stats: CompareStats,
+    /// What the scan left out or had trouble with, for the user to see
+    pub warnings: Vec<String>,
+    /// Folders and files the scan wasn't allowed to read, which need
+    /// elevation to compare; a folder among them is shown without its
+    /// contents, and as different if that's all that differs
+    pub unreadable: Vec<PathBuf>,
+    /// The `--save-result` file this comparison was read from; its folders
+    /// may not exist here, so nothing is read from or changed in them
+    pub loaded_from: Option<PathBuf>,
+    /// `difference_count` as last counted; statuses only change through
+    /// `update_folder_statuses` and `update_ancestor_statuses`, which forget it
+    difference_count: Cell<Option<usize>>,
+    /// Which sides are a manifest file or S3 listing, decided once when the
+    /// comparison is first built so later checks don't touch the disk
+    manifest_sides: [bool; 2],
+}
+
+/// What a refresh needs from the comparison it replaces: its roots and
+/// options, and the folder hashes whose unchanged folders keep their
+/// results. Much less than the whole comparison, see
+/// `DirectoryComparison::previous_scan`.
+#[derive(Clone)]
+pub struct PreviousScan {
+    left_dir: PathBuf,
+    right_dir: PathBuf,
+    options: CompareOptions,
+    manifest_sides: [bool; 2],
+    digests: FolderDigests,
+}
+
+impl PreviousScan {
+    pub fn options(&self) -> &CompareOptions {
+        &self.options
+    }
+}
+
+impl DirectoryComparison {
+    pub fn new(left_dir: PathBuf, right_dir: PathBuf) -> Result<Self> {
+        Self::new_with_logging(left_dir, right_dir, CompareOptions::default(), true)
+    }
+
+    pub fn new_with_options(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        options: CompareOptions,
+    ) -> Result<Self> {
+        Self::new_with_logging(left_dir, right_dir, options, true)
+    }
+
+    #[allow(dead_code)]
+    pub fn new_silent(left_dir: PathBuf, right_dir: PathBuf) -> Result<Self> {
+        Self::new_with_logging(left_dir, right_dir, CompareOptions::default(), false)
+    }
+
+    pub fn new_silent_with_options(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        options: CompareOptions,
+    ) -> Result<Self> {
+        Self::new_with_logging(left_dir, right_dir, options, false)
+    }
+
+    /// Compare with status lines for a progress popup. Setting `cancel`
+    /// stops the comparison with an error at the next path.
+    pub fn new_with_progress<F>(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        options: CompareOptions,
+        cancel: &AtomicBool,
+        mut progress_callback: F,
+    ) -> Result<Self>
+    where
+        F: FnMut(&str),
+    {
+        Self::build(
+            left_dir,
+            right_dir,
+            options,
+            Progress::Callback(&mut progress_callback),
+            None,
+            Some(cancel),
+            None,
+        )
+    }
+
+    /// What `rescan_with_progress` needs to compare these roots again.
+    pub fn previous_scan(&self) -> PreviousScan {
+        PreviousScan {
+            left_dir: self.left_dir.clone(),
+            right_dir: self.right_dir.clone(),
+            options: self.options.clone(),
+            manifest_sides: self.manifest_sides,
+            digests: self.digests.clone(),
+        }
+    }
+
+    /// Compare the roots of `previous` again like `new_with_progress`. Files
+    /// in folders whose listing hashes haven't changed since keep their
+    /// previous results without being read.
+    pub fn rescan_with_progress<F>(
+        previous: &PreviousScan,
+        cancel: &AtomicBool,
+        mut progress_callback: F,
+    ) -> Result<Self>
+    where
+        F: FnMut(&str),
+    {
+        Self::build(
+            previous.left_dir.clone(),
+            previous.right_dir.clone(),
+            previous.options.clone(),
+            Progress::Callback(&mut progress_callback),
+            None,
+            Some(cancel),
+            Some(previous),
+        )
+    }
+
+    /// Compare silently, calling `on_entry` for every path as soon as its
+    /// status is known. Files arrive in path order; folders on both sides
+    /// follow their contents. The finished comparison is returned as usual.
+    pub fn run_streaming<F>(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        options: CompareOptions,
+        mut on_entry: F,
+    ) -> Result<Self>
+    where
+        F: FnMut(DiffEntry),
+    {
+        let mut stream = EntryStream {
+            callback: &mut on_entry,
+            open_dirs: Vec::new(),
+        };
+        Self::build(
+            left_dir,
+            right_dir,
+            options,
+            Progress::Silent,
+            Some(&mut stream),
+            None,
+            None,
+        )
+    }
+
+    fn new_with_logging(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        options: CompareOptions,
+        enable_logging: bool,
+    ) -> Result<Self> {
+        let progress = if enable_logging {
+            Progress::Stderr
+        } else {
+            Progress::Silent
+        };
+        Self::build(left_dir, right_dir, options, progress, None, None, None)
+    }
+
+    /// The comparison engine behind every constructor.
+    fn build(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        mut options: CompareOptions,
+        mut progress: Progress,
+        stream: Option<&mut EntryStream>,
+        cancel: Option<&AtomicBool>,
+        previous: Option<&PreviousScan>,
+    ) -> Result<Self> {
+        crate::utils::log_debug(&format!(
+            "Starting comparison: {} vs {}",
+            left_dir.display(),
+            right_dir.display()
+        ));
+
+        take_warnings();
+        take_unreadable();
+        let manifest_sides = match previous {
+            Some(previous) => previous.manifest_sides,
+            None => [is_manifest_path(&left_dir), is_manifest_path(&right_dir)],
+        };
+        for (root, is_manifest) in [&left_dir, &right_dir].into_iter().zip(manifest_sides) {
+            if !is_manifest {
+                Self::check_root(root, &options)?;
+            }
+        }
+
+        // A file or S3 URL on either side is a manifest to check the other
+        // side against
+        if manifest_sides.contains(&true) {
+            return Self::build_against_manifest(
+                left_dir,
+                right_dir,
+                manifest_sides,
+                options,
+                progress,
+                stream,
+                cancel,
+            );
+        }
+
+        let stopwatch = Stopwatch::start();
+        let mut stats = CompareStats::default();
+        progress.message("Starting directory scan...");
+        let mut limit = EntryLimit {
+            max: options.max_entries,
+            metadata_only: options.metadata_only,
+        };
+
+        // Mapped folders come out of the right walk in the wrong order to
+        // merge, so both sides are listed in full first
+        let result = if options.files_from.is_some() || !options.path_map.is_empty() {
+            progress.message("Scanning both directories...");
+            let (left_files, right_files) =
+                Self::collect_both(&left_dir, &right_dir, &options, &mut progress)?;
+
+            progress.message("Comparing files...");
+            let mut all_paths = std::collections::BTreeSet::new();
+            all_paths.extend(left_files.keys());
+            all_paths.extend(right_files.keys());
+            let total_paths = all_paths.len();
+            let pairs = || {
+                all_paths.iter().map(|path| {
+                    check_cancelled(cancel)?;
+                    Ok((
+                        (*path).clone(),
+                        left_files.get(*path).cloned(),
+                        right_files.get(*path).cloned(),
+                    ))
+                })
+            };
+            let reuse = Self::reuse_unchanged(pairs, previous, &options, &mut progress)?;
+            Self::compare_trees(
+                &left_dir,
+                &right_dir,
+                pairs(),
+                Some(total_paths),
+                &options,
+                &mut progress,
+                stream,
+                None,
+                reuse.as_ref(),
+                &mut stats,
+                &mut limit,
+                cancel,
+            )
+        } else {
+            // Both trees are walked side by side in path order, so entries are
+            // compared as they are found and only the open branches are held
+            let mut checkpoint = options
+                .checkpoint
+                .then(|| Checkpoint::open(&left_dir, &right_dir, &options));
+            let resume_after = checkpoint.as_ref().and_then(Checkpoint::resume_after);
+            // A resumed run only walks part of the trees, too little to hash
+            let previous = previous.filter(|_| resume_after.is_none());
+            let result = std::thread::scope(|scope| {
+                let pairs = || {
+                    MergedWalk::spawn(
+                        scope,
+                        &left_dir,
+                        &right_dir,
+                        &options,
+                        resume_after.as_deref(),
+                    )
+                    .map(|pair| check_cancelled(cancel).and(pair))
+                };
+                let reuse = Self::reuse_unchanged(pairs, previous, &options, &mut progress)?;
+                progress.message("Scanning and comparing...");
+                Self::compare_trees(
+                    &left_dir,
+                    &right_dir,
+                    pairs(),
+                    None,
+                    &options,
+                    &mut progress,
+                    stream,
+                    checkpoint.as_mut(),
+                    reuse.as_ref(),
+                    &mut stats,
+                    &mut limit,
+                    cancel,
+                )
+            });
+            // An interrupted run keeps its checkpoint for the next one
+            if let (Ok(_), Some(checkpoint)) = (&result, checkpoint) {
+                checkpoint.complete();
+            }
+            result
+        };
+        let (left_tree, right_tree, digests) = result
+            .inspect_err(|e| crate::utils::log_error(&format!("Failed to compare trees: {}", e)))?;
+        // Refreshes keep what was chosen at the limit, and have nobody to ask
+        options.max_entries = limit.max;
+        options.metadata_only = limit.metadata_only;
+        options.limit_prompt = None;
+
+        progress.message("Complete!");
+        crate::utils::log_debug("Comparison completed successfully");
+        let stats = stopwatch.finish(stats, &mut progress);
+
+        let mut comparison = Self {
+            left_tree,
+            right_tree,
+            left_dir,
+            right_dir,
+            options,
+            digests,
+            stats,
+            warnings: take_warnings(),
+            unreadable: take_unreadable(),
+            loaded_from: None,
+            difference_count: Cell::new(None),
+            manifest_sides: [false; 2],
+        };
+        if comparison.options.unreadable == UnreadablePolicy::Sudo {
+            comparison.reread_unreadable();
+        }
+        comparison.mark_unreadable();
+        Ok(comparison)
+    }
+
+    /// Compare a directory with a manifest given as the other side. Listed
+    /// files that are missing show up on the manifest side only, unlisted
+    /// ones on the directory side only, and files whose contents don't
+    /// match their hash as different.
+    fn build_against_manifest(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        manifest_sides: [bool; 2],
+        options: CompareOptions,
+        mut progress: Progress,
+        mut stream: Option<&mut EntryStream>,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<Self> {
+        let stopwatch = Stopwatch::start();
+        let mut stats = CompareStats::default();
+        let [manifest_on_left, manifest_on_right] = manifest_sides;
+        if manifest_on_left && manifest_on_right {
+            bail!("only one side can be a manifest");
+        }
+        let (dir, manifest_path) = if manifest_on_left {
+            (&right_dir, &left_dir)
+        } else {
+            (&left_dir, &right_dir)
+        };
+
+        progress.message("Reading manifest...");
+        let manifest = Manifest::open(manifest_path)?;
+
+        progress.message("Scanning directory...");
+        let files = Self::collect_files(dir, &options, &mut progress)?;
+
+        // Listed files (and the folders they imply), narrowed down the same
+        // way a scan of the directory is
+        let mut listed: BTreeMap<&Path, Option<&ManifestEntry>> = BTreeMap::new();
+        let recorded = manifest
+            .entries
+            .iter()
+            .map(|(path, entry)| (path, Some(entry)))
+            .chain(manifest.folders.iter().map(|path| (path, None)));
+        for (path, entry) in recorded {
+            let is_dir = entry.is_none();
+            let excluded = options.filter.is_excluded(path, is_dir)
+                || path
+                    .ancestors()
+                    .skip(1)
+                    .any(|folder| options.filter.is_excluded(folder, true));
+            let unlisted = options
+                .files_from
+                .as_ref()
+                .is_some_and(|paths| !paths.iter().any(|listed| path.starts_with(listed)));
+            if excluded || unlisted || !options.filter.is_included(path, is_dir) {
+                continue;
+            }
+            listed.insert(path, entry);
+            for folder in path.ancestors().skip(1) {
+                if !folder.as_os_str().is_empty() {
+                    listed.entry(folder).or_insert(None);
+                }
+            }
+        }
+
+        let root = |dir: &Path| {
+            let name = dir.file_name().unwrap_or_default().into();
+            let mut root = FileNode::new(name, true, true, FileStatus::Same);
+            root.expanded = true;
+            root
+        };
+        let mut left_builder = TreeBuilder::new(root(&left_dir));
+        let mut right_builder = TreeBuilder::new(root(&right_dir));
+
+        let mut all_paths = std::collections::BTreeSet::new();
+        all_paths.extend(files.keys().map(PathBuf::as_path));
+        all_paths.extend(listed.keys().copied());
+        all_paths.remove(Path::new(""));
+
+        let total_paths = all_paths.len();
+        progress.compare_started(Some(total_paths));
+
+        for (processed, path) in all_paths.into_iter().enumerate() {
+            check_cancelled(cancel)?;
+            let real = files.get(path);
+            let recorded = listed.get(path);
+            let is_dir = match (real, recorded) {
+                (Some(metadata), _) => metadata.is_dir(),
+                (None, recorded) => matches!(recorded, Some(None)),
+            };
+
+            let (real_only, manifest_only) = if manifest_on_left {
+                (FileStatus::RightOnly, FileStatus::LeftOnly)
+            } else {
+                (FileStatus::LeftOnly, FileStatus::RightOnly)
+            };
+            let status = match (real, recorded) {
+                (Some(metadata), Some(Some(entry)))
+                    if entry.size.is_some_and(|size| size != metadata.len()) =>
+                {
+                    FileStatus::Different
+                }
+                (Some(metadata), Some(Some(entry))) if !metadata.is_dir() => {
+                    progress.comparing(Some(path), processed, Some(total_paths));
+                    let file = dir.join(path);
+                    let reading = Instant::now();
+                    let verified = manifest.verify(path, entry, &file, options.s3_ranged_check);
+                    stats.compare_time += reading.elapsed();
+                    stats.files_compared += 1;
+                    match verified {
+                        Ok(true) => FileStatus::Same,
+                        Ok(false) => FileStatus::Different,
+                        Err(e) => {
+                            crate::utils::log_error(&format!(
+                                "Failed to verify {}: {}",
+                                file.display(),
+                                e
+                            ));
+                            FileStatus::Different
+                        }
+                    }
+                }
+                (Some(metadata), Some(None)) if metadata.is_dir() => FileStatus::Same,
+                // A folder where a file is listed or the other way round
+                (Some(_), Some(_)) => FileStatus::Different,
+                (Some(_), None) => real_only,
+                (None, _) => manifest_only,
+            };
+
+            let real_meta = real.map(Meta::from_metadata);
+            let recorded_meta = recorded.map(|entry| Meta {
+                is_dir,
+                size: entry.and_then(|entry| entry.size),
+                modified: entry.and_then(|entry| entry.modified),
+                mode: None,
+                owner: None,
+            });
+            let (left_meta, right_meta) = if manifest_on_left {
+                (recorded_meta, real_meta)
+            } else {
+                (real_meta, recorded_meta)
+            };
+            if let Some(stream) = stream.as_deref_mut() {
+                stream.push(DiffEntry {
+                    rel_path: path.to_path_buf(),
+                    status,
+                    left: left_meta,
+                    right: right_meta,
+                });
+            }
+
+            let name: Arc<OsStr> = path.file_name().unwrap_or_default().into();
+            let (real_builder, recorded_builder) = if manifest_on_left {
+                (&mut right_builder, &mut left_builder)
+            } else {
+                (&mut left_builder, &mut right_builder)
+            };
+            let (in_real, in_recorded) = (real.is_some(), recorded.is_some());
+            real_builder.insert(path, &name, in_real, is_dir, status, real_meta.as_ref());
+            recorded_builder.insert(path, &name, in_recorded, is_dir, status, None);
+
+            progress.compared(processed + 1, Some(total_paths));
+        }
+
+        if let Some(stream) = stream {
+            stream.finish();
+        }
+        progress.done(total_paths);
+        stats.paths = total_paths;
+        progress.message("Complete!");
+
+        let mut left_tree = left_builder.finish();
+        let mut right_tree = right_builder.finish();
+
+        // Snapshots know sizes and times, show them like a scan would
+        let recorded_tree = if manifest_on_left {
+            &mut left_tree
+        } else {
+            &mut right_tree
+        };
+        for (path, entry) in &listed {
+            if let (Some(entry), Some(node)) = (entry, recorded_tree.find_mut(path)) {
+                node.size = entry.size;
+                node.modified = entry.modified;
+            }
+        }
+
+        Self::sort_tree_recursive(&mut left_tree);
+        Self::sort_tree_recursive(&mut right_tree);
+        Self::update_folder_status(&mut left_tree);
+        Self::update_folder_status(&mut right_tree);
+
+        let mut comparison = Self {
+            left_tree,
+            right_tree,
+            left_dir,
+            right_dir,
+            options,
+            digests: FolderDigests::default(),
+            stats: stopwatch.finish(stats, &mut progress),
+            warnings: take_warnings(),
+            unreadable: take_unreadable(),
+            loaded_from: None,
+            difference_count: Cell::new(None),
+            manifest_sides,
+        };
+        comparison.mark_unreadable();
+        Ok(comparison)
+    }
+
+    /// The path of `path`, one of `unreadable`, below the roots.
+    fn relative_to_roots(&self, path: &Path) -> Option<PathBuf> {
+        self.relative_on(true, path)
+            .or_else(|| self.relative_on(false, path))
+    }
+
+    /// Where `path`, below the left (or right) root, is shown in the trees.
+    pub fn relative_on(&self, left: bool, path: &Path) -> Option<PathBuf> {
+        if left {
+            Some(path.strip_prefix(&self.left_dir).ok()?.to_path_buf())
+        } else {
+            let actual = path.strip_prefix(&self.right_dir).ok()?;
+            self.options.path_map.from_right(actual)
+        }
+    }
+
+    /// Where the entry at `relative` is on the left.
+    pub fn left_path(&self, relative: &Path) -> PathBuf {
+        self.left_dir.join(relative)
+    }
+
+    /// Where the entry at `relative` is on the right, which isn't the same
+    /// relative path below a folder mapped with `--map`; `None` where a
+    /// mapped folder has taken its name, see `PathMap::to_right`.
+    pub fn right_path(&self, relative: &Path) -> Option<PathBuf> {
+        let actual = self.options.path_map.to_right(relative)?;
+        Some(self.right_dir.join(actual))
+    }
+
+    /// `left_path` or `right_path`.
+    pub fn side_path(&self, left: bool, relative: &Path) -> Option<PathBuf> {
+        if left {
+            Some(self.left_path(relative))
+        } else {
+            self.right_path(relative)
+        }
+    }
+
+    /// Read the folders the scan wasn't allowed into again through sudo,
+    /// see `elevate::sudo_compare`, and put what was found in both trees.
+    /// The ones sudo can't read either stay in `unreadable`.
+    #[cfg(feature = "serde")]
+    fn reread_unreadable(&mut self) {
+        let mut folders: Vec<PathBuf> = self
+            .unreadable
+            .iter()
+            .filter(|path| path.is_dir())
+            .filter_map(|path| self.relative_to_roots(path))
+            .collect();
+        folders.sort();
+        folders.dedup_by(|folder, outer| folder.starts_with(outer));
+
+        let mut reread = Vec::new();
+        let mut found = Vec::new();
+        for folder in folders {
+            crate::utils::log_debug(&format!("Reading {} with sudo", folder.display()));
+            // Left out on the right in favour of a mapped folder
+            let Some(right) = self.right_path(&folder) else {
+                continue;
+            };
+            let result =
+                crate::elevate::sudo_compare(&self.left_path(&folder), &right, &self.options);
+            match result {
+                Ok(inner) => {
+                    // Patterns anchored at the roots only match here
+                    found.extend(inner.entries().filter_map(|mut entry| {
+                        entry.rel_path = folder.join(&entry.rel_path);
+                        let is_dir = entry.left.or(entry.right).is_some_and(|meta| meta.is_dir);
+                        (!self.options.filter.is_excluded(&entry.rel_path, is_dir)).then_some(entry)
+                    }));
+                    self.unreadable.extend(inner.unreadable);
+                    reread.push(folder);
+                }
+                Err(e) => {
+                    let message = format!("Couldn't read {} with sudo: {:#}", folder.display(), e);
+                    crate::utils::log_info(&message);
+                    self.warnings.push(message);
+                }
+            }
+        }
+        if reread.is_empty() {
+            return;
+        }
+
+        let below = |path: &Path| {
+            reread
+                .iter()
+                .any(|folder| path != folder && path.starts_with(folder))
+        };
+        let entries: Vec<DiffEntry> = self
+            .entries()
+            .filter(|entry| !below(&entry.rel_path))
+            .chain(found)
+            .collect();
+        let rebuilt = Self::from_entries(self.left_dir.clone(), self.right_dir.clone(), entries);
+        self.left_tree = rebuilt.left_tree;
+        self.right_tree = rebuilt.right_tree;
+        let unreadable = std::mem::take(&mut self.unreadable);
+        self.unreadable = unreadable
+            .into_iter()
+            .filter(|path| {
+                // What the sudo run couldn't read is outside the roots
+                self.relative_to_roots(path)
+                    .is_none_or(|path| !reread.iter().any(|folder| path.starts_with(folder)))
+            })
+            .collect();
+    }
+
+    #[cfg(not(feature = "serde"))]
+    fn reread_unreadable(&mut self) {
+        self.warnings
+            .push("Reading folders with sudo needs the `serde` feature".to_string());
+    }
+
+    /// Show the folders that couldn't be read as different rather than the
+    /// same, and warn about everything that couldn't be read.
+    fn mark_unreadable(&mut self) {
+        if self.unreadable.is_empty() {
+            return;
+        }
+        for path in &self.unreadable {
+            let Some(relative) = self.relative_to_roots(path) else {
+                continue;
+            };
+            for tree in [&mut self.left_tree, &mut self.right_tree] {
+                if let Some(node) = tree.find_mut(&relative) {
+                    if node.is_dir && node.status == FileStatus::Same {
+                        node.status = FileStatus::Different;
+                    }
+                }
+            }
+        }
+        Self::update_folder_status(&mut self.left_tree);
+        Self::update_folder_status(&mut self.right_tree);
+        let sudo = self.options.unreadable == UnreadablePolicy::Sudo;
+        self.warnings
+            .insert(0, needs_elevation(&self.unreadable, sudo));
+    }
+
+    /// Whether the given side is a manifest file or S3 listing rather than
+    /// a directory.
+    pub fn is_manifest(&self, left: bool) -> bool {
+        self.manifest_sides[usize::from(!left)]
+    }
+
+    /// Put the left side on the right and the other way round.
+    pub fn swap_sides(&mut self) {
+        std::mem::swap(&mut self.left_dir, &mut self.right_dir);
+        std::mem::swap(&mut self.left_tree, &mut self.right_tree);
+        self.manifest_sides.reverse();
+    }
+
+    pub fn has_manifest(&self) -> bool {
+        self.is_manifest(true) || self.is_manifest(false)
+    }
+
+    /// Rebuild a comparison from its entries, e.g. a saved result, without
+    /// looking at either folder.
+    pub fn from_entries(
+        left_dir: PathBuf,
+        right_dir: PathBuf,
+        entries: impl IntoIterator<Item = DiffEntry>,
+    ) -> Self {
+        let root = |dir: &Path| {
+            let name = dir.file_name().unwrap_or_default().into();
+            let mut root = FileNode::new(name, true, true, FileStatus::Same);
+            root.expanded = true;
+            TreeBuilder::new(root)
+        };
+        let manifest_sides = [is_manifest_path(&left_dir), is_manifest_path(&right_dir)];
+        let mut left_builder = root(&left_dir);
+        let mut right_builder = root(&right_dir);
+        for entry in entries {
+            Self::insert_entry(entry, &mut left_builder, &mut right_builder, None);
+        }
+
+        let mut left_tree = left_builder.finish();
+        let mut right_tree = right_builder.finish();
+        Self::sort_tree_recursive(&mut left_tree);
+        Self::sort_tree_recursive(&mut right_tree);
+        Self::update_folder_status(&mut left_tree);
+        Self::update_folder_status(&mut right_tree);
+
+        Self {
+            left_tree,
+            right_tree,
+            left_dir,
+            right_dir,
+            options: CompareOptions::default(),
+            digests: FolderDigests::default(),
+            stats: CompareStats::default(),
+            warnings: Vec::new(),
+            unreadable: Vec::new(),
+            loaded_from: None,
+            difference_count: Cell::new(None),
+            manifest_sides,
+        }
+    }
+
+    /// Leave paths matching `patterns` out from now on: they join the
+    /// excludes, so refreshes skip them as well, and are dropped from both
+    /// trees right away.
+    pub fn exclude(&mut self, patterns: &[String]) -> Result<()> {
+        let mut exclude = self.options.filter.exclude.patterns().to_vec();
+        exclude.extend_from_slice(patterns);
+        self.options.filter.exclude = PatternSet::new(&exclude)?;
+
+        Self::prune_excluded(
+            &mut self.left_tree,
+            &mut self.right_tree,
+            Path::new(""),
+            &self.options,
+        );
+        self.update_folder_statuses();
+        Ok(())
+    }
+
+    /// Compare `relative`, a path below both roots, again on its own, e.g.
+    /// after copying it, and return what was found on both sides for
+    /// `replace_path`. Nothing else is read, so it can run on a worker
+    /// while the comparison stays in use.
+    pub fn rescan_path<F>(
+        left_dir: &Path,
+        right_dir: &Path,
+        relative: &Path,
+        options: &CompareOptions,
+        cancel: &AtomicBool,
+        progress_callback: F,
+    ) -> Result<(FileNode, FileNode)>
+    where
+        F: FnMut(&str),
+    {
+        let options = CompareOptions {
+            files_from: Some(vec![relative.to_path_buf()]),
+            // Both would be replaced with what this one path holds
+            use_index: false,
+            checkpoint: false,
+            resume: false,
+            max_entries: None,
+            limit_prompt: None,
+            ..options.clone()
+        };
+        let inner = Self::new_with_progress(
+            left_dir.to_path_buf(),
+            right_dir.to_path_buf(),
+            options,
+            cancel,
+            progress_callback,
+        )?;
+        match (
+            inner.left_tree.find(relative),
+            inner.right_tree.find(relative),
+        ) {
+            (Some(left), Some(right)) => Ok((left.clone(), right.clone())),
+            _ => bail!("{} wasn't found again", relative.display()),
+        }
+    }
+
+    /// Put the nodes `rescan_path` found in place of the old ones at
+    /// `relative`; the folders above it get their statuses recounted.
+    pub fn replace_path(&mut self, relative: &Path, left: FileNode, right: FileNode) -> Result<()> {
+        match (
+            self.left_tree.find_mut(relative),
+            self.right_tree.find_mut(relative),
+        ) {
+            (Some(old_left), Some(old_right)) => {
+                *old_left = left;
+                *old_right = right;
+            }
+            _ => bail!("{} isn't in the comparison", relative.display()),
+        }
+        self.update_folder_statuses();
+        Ok(())
+    }
+
+    /// Drop the excluded children of a folder pair.
+    fn prune_excluded(
+        left: &mut FileNode,
+        right: &mut FileNode,
+        dir: &Path,
+        options: &CompareOptions,
+    ) {
+        let mut index = 0;
+        while index < left.children.len() {
+            let (left_child, right_child) = (&left.children[index], &right.children[index]);
+            let is_dir = left_child.is_dir || right_child.is_dir;
+            let path = dir.join(&*left_child.name);
+            if options.filter.is_excluded(&path, is_dir) {
+                left.children.remove(index);
+                right.children.remove(index);
+                continue;
+            }
+            if is_dir {
+                Self::prune_excluded(
+                    &mut left.children[index],
+                    &mut right.children[index],
+                    &path,
+                    options,
+                );
+            }
+            index += 1;
+        }
+    }
+
+    /// Work out every folder's status again from its contents, after file
+    /// statuses changed or entries were dropped.
+    pub fn update_folder_statuses(&mut self) {
+        self.difference_count.set(None);
+        Self::reset_folder_statuses(&mut self.left_tree, &mut self.right_tree, &self.options);
+        self.left_tree.status = FileStatus::Same;
+        self.right_tree.status = FileStatus::Same;
+        Self::update_folder_status(&mut self.left_tree);
+        Self::update_folder_status(&mut self.right_tree);
+    }
+
+    /// Put the folders below a folder pair back to the status they had
+    /// before their contents were counted, for `update_folder_status`.
+    fn reset_folder_statuses(left: &mut FileNode, right: &mut FileNode, options: &CompareOptions) {
+        for (left_child, right_child) in left.children.iter_mut().zip(&mut right.children) {
+            if !left_child.is_dir && !right_child.is_dir {
+                continue;
+            }
+            let status = Self::own_folder_status(left_child, right_child, options);
+            left_child.status = status;
+            right_child.status = status;
+            Self::reset_folder_statuses(left_child, right_child, options);
+        }
+    }
+
+    /// The status a folder pair has before its contents are counted: where
+    /// it exists, and whether its permissions differ.
+    fn own_folder_status(
+        left: &FileNode,
+        right: &FileNode,
+        options: &CompareOptions,
+    ) -> FileStatus {
+        match (left.exists, right.exists) {
+            (true, false) => FileStatus::LeftOnly,
+            (false, true) => FileStatus::RightOnly,
+            _ if options.compare_permissions && left.mode != right.mode => FileStatus::Different,
+            _ => FileStatus::Same,
+        }
+    }
+
+    /// Work out the status of the folders leading to `path` again, closest
+    /// first, after the status of the entry at `path` changed.
+    pub fn update_ancestor_statuses(&mut self, path: &Path) {
+        self.difference_count.set(None);
+        for folder in path.ancestors().skip(1) {
+            let (Some(left), Some(right)) =
+                (self.left_tree.find(folder), self.right_tree.find(folder))
+            else {
+                continue;
+            };
+            let own = Self::own_folder_status(left, right, &self.options);
+            let child_statuses: Vec<FileStatus> =
+                left.children.iter().map(|child| child.status).collect();
+            let status = Self::folder_status(own, &child_statuses);
+            for tree in [&mut self.left_tree, &mut self.right_tree] {
+                if let Some(node) = tree.find_mut(folder) {
+                    node.status = status;
+                }
+            }
+        }
+    }
+
+    /// Every compared path below the roots, parents before their children,
+    /// with placeholder nodes resolved to `None`.
+    pub fn entries(&self) -> Entries<'_> {
+        let mut entries = Entries { stack: Vec::new() };
+        entries.push_children(&self.left_tree, &self.right_tree, Path::new(""));
+        entries
+    }
+
+    /// Like `entries`, but only those below the folder at `dir`.
+    pub fn entries_below(&self, dir: &Path) -> Entries<'_> {
+        let mut entries = Entries { stack: Vec::new() };
+        if let (Some(left), Some(right)) = (self.left_tree.find(dir), self.right_tree.find(dir)) {
+            entries.push_children(left, right, dir);
+        }
+        entries
+    }
+
+    /// Number of files that differ or exist on one side only, counted once
+    /// until statuses change.
+    pub fn difference_count(&self) -> usize {
+        if let Some(count) = self.difference_count.get() {
+            return count;
+        }
+        let count = self
+            .entries()
+            .filter(|entry| {
+                entry.status != FileStatus::Same
+                    && !entry.left.or(entry.right).is_some_and(|meta| meta.is_dir)
+            })
+            .count();
+        self.difference_count.set(Some(count));
+        count
+    }
+
+    fn collect_files(
+        dir: &Path,
+        options: &CompareOptions,
+        progress: &mut Progress,
+    ) -> Result<HashMap<PathBuf, fs::Metadata>> {
+        if let Some(paths) = &options.files_from {
+            progress.message(&format!("Checking {} listed paths...", paths.len()));
+            return Self::collect_listed_files(dir, paths, options);
+        }
+
+        let mut files = HashMap::new();
+        let mut count = 0;
+
+        for entry in Self::walk(dir, options) {
+            let entry = entry?;
+            let relative_path = entry.path().strip_prefix(dir)?.to_path_buf();
+            let metadata = entry.metadata()?;
+            if !options.filter.is_included(&relative_path, metadata.is_dir()) {
+                continue;
+            }
+            files.insert(relative_path, metadata);
+
+            count += 1;
+            progress.scanned(dir, count, false);
+        }
+        progress.scanned(dir, count, true);
+
+        Self::prune_unmatched_dirs(&mut files, &options.filter);
+        Ok(files)
+    }
+
+    /// `collect_files` for both roots at once, the right one on another
+    /// thread: they're usually on different disks, or one is remote.
+    fn collect_both(
+        left_dir: &Path,
+        right_dir: &Path,
+        options: &CompareOptions,
+        progress: &mut Progress,
+    ) -> Result<(Listing, Listing)> {
+        // Status lines for the popup only come from this thread
+        let stderr = matches!(progress, Progress::Stderr);
+        // Listed paths are named as on the left
+        let right_options = CompareOptions {
+            files_from: options.files_from.as_ref().map(|paths| {
+                paths
+                    .iter()
+                    .filter_map(|path| options.path_map.to_right(path))
+                    .collect()
+            }),
+            ..options.clone()
+        };
+        let (left, right) = std::thread::scope(|scope| {
+            let right = scope.spawn(|| {
+                let mut progress = if stderr {
+                    Progress::Stderr
+                } else {
+                    Progress::Silent
+                };
+                (
+                    Self::collect_files(right_dir, &right_options, &mut progress),
+                    take_warnings(),
+                    take_unreadable(),
+                )
+            });
+            let left = Self::collect_files(left_dir, options, progress);
+            let right = match right.join() {
+                Ok((right, warnings, unreadable)) => {
+                    WARNINGS.with(|all| all.borrow_mut().extend(warnings));
+                    UNREADABLE.with(|all| all.borrow_mut().extend(unreadable));
+                    right
+                }
+                Err(_) => Err(anyhow!("the scan stopped unexpectedly")),
+            };
+            (left, right)
+        });
+        let log = |side: &str, dir: &Path, e: &anyhow::Error| {
+            crate::utils::log_error(&format!(
+                "Failed to collect {} files from {}: {}",
+                side,
+                dir.display(),
+                e
+            ))
+        };
+        let left = left.inspect_err(|e| log("left", left_dir, e))?;
+        let right = right.inspect_err(|e| log("right", right_dir, e))?;
+        let right = Self::remap_right(right, &options.path_map, right_dir);
+        Ok((left, right))
+    }
+
+    /// Key the right side's listing by the names its entries are shown at,
+    /// see `PathMap`.
+    fn remap_right(listing: Listing, path_map: &PathMap, right_dir: &Path) -> Listing {
+        if path_map.is_empty() {
+            return listing;
+        }
+        let mut remapped: Listing = listing
+            .into_iter()
+            .filter_map(|(path, metadata)| Some((path_map.from_right(&path)?, metadata)))
+            .collect();
+        // A mapped folder deeper than its counterpart (`lib/old=old`) needs
+        // folders the right doesn't have to sit in; the root stands in
+        if let Ok(root) = fs::metadata(right_dir) {
+            for (left, _) in path_map.pairs() {
+                if !remapped.contains_key(left) {
+                    continue;
+                }
+                for ancestor in left.ancestors().skip(1) {
+                    if !ancestor.as_os_str().is_empty() && !remapped.contains_key(ancestor) {
+                        remapped.insert(ancestor.to_path_buf(), root.clone());
+                    }
+                }
+            }
+        }
+        remapped
+    }
+
+    /// Gather metadata for an explicit list of relative paths (`--files-from`).
+    /// Their parent folders are added so the tree can be built, listed folders
+    /// are walked, and paths missing under `dir` are simply left out.
+    fn collect_listed_files(
+        dir: &Path,
+        paths: &[PathBuf],
+        options: &CompareOptions,
+    ) -> Result<HashMap<PathBuf, fs::Metadata>> {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::new(), fs::metadata(dir)?);
+
+        for relative_path in paths {
+            let path = dir.join(relative_path);
+            let metadata = if options.follow_links {
+                fs::metadata(&path)
+            } else {
+                fs::symlink_metadata(&path)
+            };
+            let Ok(metadata) = metadata else {
+                continue;
+            };
+            if options.filter.is_excluded(relative_path, metadata.is_dir()) {
+                continue;
+            }
+
+            for ancestor in relative_path.ancestors().skip(1) {
+                if ancestor.as_os_str().is_empty() || files.contains_key(ancestor) {
+                    continue;
+                }
+                if let Ok(ancestor_metadata) = fs::metadata(dir.join(ancestor)) {
+                    files.insert(ancestor.to_path_buf(), ancestor_metadata);
                 }
-                FileStatus::LeftOnly => {
-                    // Left side only
-                    Self::insert_into_tree(
-                        &mut left_root,
-                        &path,
-                        name.clone(),
-                        is_dir,
-                        status,
-                        true,
-                        left_meta,
-                    )?;
-                    Self::insert_into_tree(
-                        &mut right_root,
-                        &path,
-                        "".to_string(),
-                        is_dir,
-                        status,
-                        false,
-                        None,
-                    )?;
+            }
+
+            if metadata.is_dir() {
+                for entry in Self::walk_from(dir, &dir.join(relative_path), options, None) {
+                    let entry = entry?;
+                    let relative = entry.path().strip_prefix(dir)?.to_path_buf();
+                    let entry_metadata = entry.metadata()?;
+                    if options.filter.is_included(&relative, entry_metadata.is_dir()) {
+                        files.insert(relative, entry_metadata);
+                    }
                 }
-                FileStatus::RightOnly => {
-                    // Right side only
-                    Self::insert_into_tree(
-                        &mut left_root,
-                        &path,
-                        "".to_string(),
-                        is_dir,
-                        status,
-                        false,
-                        None,
-                    )?;
-                    Self::insert_into_tree(
-                        &mut right_root,
-                        &path,
-                        name,
-                        is_dir,
-                        status,
-                        true,
-                        right_meta,
-                    )?;
+            } else if options.filter.is_included(relative_path, false) {
+                files.insert(relative_path.clone(), metadata);
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Walk `dir`, skipping excluded entries together with their contents and,
+    /// with `one_file_system`, anything mounted below the root.
+    fn walk<'a>(
+        dir: &'a Path,
+        options: &'a CompareOptions,
+    ) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + 'a {
+        Self::walk_from(dir, dir, options, None)
+    }
+
+    /// Like `walk`, but starting at `start` below `root`; patterns still match
+    /// paths relative to `root`. Folders that lie entirely before
+    /// `resume_after` aren't read.
+    fn walk_from<'a>(
+        root: &'a Path,
+        start: &Path,
+        options: &'a CompareOptions,
+        resume_after: Option<&'a Path>,
+    ) -> impl Iterator<Item = walkdir::Result<walkdir::DirEntry>> + 'a {
+        let mut mounts = MountTracker::default();
+        // Sorted so both sides can be merged by path, see `MergedWalk`
+        WalkDir::new(start)
+            .same_file_system(options.one_file_system)
+            .follow_links(options.follow_links)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(move |entry| {
+                let relative_path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+                let done = resume_after
+                    .is_some_and(|last| relative_path < last && !last.starts_with(relative_path));
+                let is_dir = entry.file_type().is_dir();
+                if done || options.filter.is_excluded(relative_path, is_dir) {
+                    return false;
                 }
+                !is_dir || !Self::skip_pseudo_fs(entry, root, &mut mounts, options)
+            })
+            .filter(|entry| !Self::skip_bad_link(entry) && !Self::skip_unreadable(entry))
+    }
+
+    /// Whether a walk error is a folder the scan isn't allowed to read,
+    /// noting it to show without its contents instead of failing the scan.
+    fn skip_unreadable(entry: &walkdir::Result<walkdir::DirEntry>) -> bool {
+        let Err(e) = entry else {
+            return false;
+        };
+        let denied = e
+            .io_error()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied);
+        match e.path() {
+            Some(path) if denied => {
+                unreadable(path);
+                true
             }
+            _ => false,
+        }
+    }
 
-            processed += 1;
+    /// Whether a walk error is a followed link to leave out, with a warning
+    /// saying so: one back to a folder above it, which would be walked
+    /// forever, or one pointing nowhere. walkdir tells a loop by the device
+    /// and inode of the folders it is in.
+    fn skip_bad_link(entry: &walkdir::Result<walkdir::DirEntry>) -> bool {
+        let Err(e) = entry else {
+            return false;
+        };
+        let Some(path) = e.path() else {
+            return false;
+        };
+        if let Some(ancestor) = e.loop_ancestor() {
+            warn(format!(
+                "Skipped {}: the link loops back to {}",
+                path.display(),
+                ancestor.display()
+            ));
+            return true;
+        }
+        let dangling = e
+            .io_error()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+            && fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink());
+        if dangling {
+            warn(format!(
+                "Skipped {}: the link points to nothing",
+                path.display()
+            ));
         }
+        dangling
+    }
 
-        // Sort children at all levels after tree construction
-        Self::sort_tree_recursive(&mut left_root);
-        Self::sort_tree_recursive(&mut right_root);
+    /// Refuse a root on a pseudo filesystem unless asked to scan those.
+    fn check_root(root: &Path, options: &CompareOptions) -> Result<()> {
+        if options.pseudo_fs == PseudoFsPolicy::Scan {
+            return Ok(());
+        }
+        let Some(kind) = pseudo_filesystem(root) else {
+            return Ok(());
+        };
+        if options.pseudo_fs == PseudoFsPolicy::Skip {
+            bail!(
+                "{} is on a {} filesystem; pass --pseudo-fs warn to compare it anyway",
+                root.display(),
+                kind
+            );
+        }
+        warn(format!("{} is on a {} filesystem", root.display(), kind));
+        Ok(())
+    }
 
-        // Update folder status based on children's status
-        Self::update_folder_status(&mut left_root);
-        Self::update_folder_status(&mut right_root);
+    /// Whether to leave out a folder below a root because it's on a pseudo
+    /// filesystem. Where one is mounted, a warning says what was skipped or
+    /// scanned anyway.
+    fn skip_pseudo_fs(
+        entry: &walkdir::DirEntry,
+        root: &Path,
+        mounts: &mut MountTracker,
+        options: &CompareOptions,
+    ) -> bool {
+        if options.pseudo_fs == PseudoFsPolicy::Scan {
+            return false;
+        }
+        let dir = entry.path();
+        let metadata = entry.metadata().ok();
+        let (kind, parent_kind) = mounts.enter(dir, entry.depth(), metadata.as_ref());
+        let Some(kind) = kind.filter(|_| dir != root) else {
+            return false;
+        };
+        let skip = options.pseudo_fs == PseudoFsPolicy::Skip;
+        // Only the mount point itself is reported, not every folder in it
+        let mount_point = skip || parent_kind != Some(kind);
+        if mount_point {
+            warn(if skip {
+                format!("Skipped {}: {} filesystem", dir.display(), kind)
+            } else {
+                format!("Scanned {} on a {} filesystem", dir.display(), kind)
+            });
+        }
+        skip
+    }
+
+    /// With include patterns, drop folders that ended up without any matching file.
+    fn prune_unmatched_dirs(files: &mut HashMap<PathBuf, fs::Metadata>, filter: &PathFilter) {
+        if filter.include.is_empty() {
+            return;
+        }
+
+        let mut needed = std::collections::HashSet::new();
+        for (path, metadata) in files.iter() {
+            if !metadata.is_dir() {
+                needed.extend(path.ancestors().skip(1).map(Path::to_path_buf));
+            }
+        }
+
+        files.retain(|path, metadata| {
+            !metadata.is_dir() || path.as_os_str().is_empty() || needed.contains(path)
+        });
+    }
 
-        Ok((left_root, right_root))
+    /// Ask what to do now that the scan has seen `processed` paths, more
+    /// than the entry limit allows.
+    fn limit_reached(
+        processed: usize,
+        options: &CompareOptions,
+        limit: &mut EntryLimit,
+    ) -> Result<()> {
+        let choice = match &options.limit_prompt {
+            Some(prompt) => (prompt.0)(processed),
+            None => {
+                warn(format!(
+                    "The scan went past the limit of {} paths",
+                    processed
+                ));
+                LimitChoice::Continue
+            }
+        };
+        match choice {
+            LimitChoice::Continue => {}
+            LimitChoice::MetadataOnly => {
+                limit.metadata_only = true;
+                warn(format!(
+                    "Files after the first {} paths were compared by size and time only",
+                    processed
+                ));
+            }
+            LimitChoice::Abort => bail!("comparison stopped after {} paths", processed),
+        }
+        Ok(())
     }
 
-    fn compare_trees_with_progress<F>(
+    /// Build both trees from `pairs`, every path below the roots in sorted
+    /// order with the metadata of the sides it exists on. `total` is the
+    /// number of pairs, when known up front. The entries a `checkpoint`
+    /// resumes come first, and the pairs must follow them.
+    #[allow(clippy::too_many_arguments)]
+    fn compare_trees(
         left_dir: &Path,
         right_dir: &Path,
-        left_files: &HashMap<PathBuf, fs::Metadata>,
-        right_files: &HashMap<PathBuf, fs::Metadata>,
-        progress_callback: &mut F,
-    ) -> Result<(FileNode, FileNode)>
-    where
-        F: FnMut(&str),
-    {
-        let left_name = left_dir
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-        let right_name = right_dir
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
-
-        let mut left_root =
-            FileNode::new(left_name, left_dir.to_path_buf(), true, FileStatus::Same);
-        let mut right_root =
-            FileNode::new(right_name, right_dir.to_path_buf(), true, FileStatus::Same);
-
-        // Root folder always starts expanded
+        pairs: impl Iterator<Item = Result<MetadataPair>>,
+        total: Option<usize>,
+        options: &CompareOptions,
+        progress: &mut Progress,
+        mut stream: Option<&mut EntryStream>,
+        mut checkpoint: Option<&mut Checkpoint>,
+        reuse: Option<&Reuse>,
+        stats: &mut CompareStats,
+        limit: &mut EntryLimit,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<(FileNode, FileNode, FolderDigests)> {
+        let left_name = left_dir.file_name().unwrap_or_default().into();
+        let right_name = right_dir.file_name().unwrap_or_default().into();
+
+        let mut left_root = FileNode::new(left_name, true, true, FileStatus::Same);
+        let mut right_root = FileNode::new(right_name, true, true, FileStatus::Same);
+
+        // Root directory starts expanded
         left_root.expanded = true;
         right_root.expanded = true;
+        let mut left_builder = TreeBuilder::new(left_root);
+        let mut right_builder = TreeBuilder::new(right_root);
 
-        // Collect all unique paths
-        let mut all_paths = std::collections::BTreeSet::new();
-        all_paths.extend(left_files.keys().cloned());
-        all_paths.extend(right_files.keys().cloned());
+        progress.compare_started(total);
 
-        let total_paths = all_paths.len();
-        progress_callback(&format!("Files to compare: {}", total_paths));
-        progress_callback("Processing paths...");
+        let mut index = options
+            .use_index
+            .then(|| ComparisonIndex::load(left_dir, right_dir, options));
+        let mut reused = 0;
+        let mut digests = DigestBuilder::new(options);
+        let mut unchanged = 0;
 
         // Convert paths to tree structure
         let mut processed = 0;
-        for path in all_paths {
+        let resumed = checkpoint
+            .as_deref_mut()
+            .map(Checkpoint::take_resumed)
+            .unwrap_or_default();
+        if !resumed.is_empty() {
+            warn(format!(
+                "Resumed an interrupted comparison: {} paths taken from its checkpoint",
+                resumed.len()
+            ));
+        }
+        for entry in resumed {
+            digests.push_entry(&entry);
+            Self::insert_entry(
+                entry,
+                &mut left_builder,
+                &mut right_builder,
+                stream.as_deref_mut(),
+            );
+            processed += 1;
+        }
+
+        for pair in pairs {
+            let (path, left_meta, right_meta) = pair?;
             if path.as_os_str().is_empty() {
-                continue; // Skip empty paths
+                continue; // Skip root path
+            }
+            if limit.max.is_some_and(|max| processed >= max) {
+                limit.max = None;
+                Self::limit_reached(processed, options, limit)?;
             }
 
-            let name = path
-                .file_name()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
-            let left_meta = left_files.get(&path);
-            let right_meta = right_files.get(&path);
+            let path = &path;
+            let left_meta = left_meta.as_ref();
+            let right_meta = right_meta.as_ref();
             let is_dir = left_meta
                 .map(|m| m.is_dir())
-                .or_else(|| right_meta.map(|m| m.is_dir()))
+                .or(right_meta.map(|m| m.is_dir()))
                 .unwrap_or(false);
 
             crate::utils::log_debug(&format!(
@@ -475,147 +2413,147 @@ impl DirectoryComparison {
                 is_dir
             ));
 
-            if let Some(left) = left_meta {
-                crate::utils::log_debug(&format!(
-                    "Left metadata for {}: is_dir={}, is_file={}, len={}",
-                    path.display(),
-                    left.is_dir(),
-                    left.is_file(),
-                    left.len()
-                ));
-            }
-            if let Some(right) = right_meta {
-                crate::utils::log_debug(&format!(
-                    "Right metadata for {}: is_dir={}, is_file={}, len={}",
-                    path.display(),
-                    right.is_dir(),
-                    right.is_file(),
-                    right.len()
-                ));
-            }
-
-            let status = match (left_meta, right_meta) {
-                (Some(_), None) => FileStatus::LeftOnly,
-                (None, Some(_)) => FileStatus::RightOnly,
-                (Some(left), Some(right)) => {
-                    if is_dir {
-                        FileStatus::Same
-                    } else {
+            let previous = reuse.and_then(|reuse| reuse.status(path, left_meta, right_meta));
+            let status = match (left_meta, right_meta, previous) {
+                (_, _, Some(status)) => {
+                    unchanged += 1;
+                    status
+                }
+                (Some(left), Some(right), None) => {
+                    if left.is_file() && right.is_file() {
                         // Compare file contents
-                        let left_path = left_dir.join(&path);
-                        let right_path = right_dir.join(&path);
-
-                        if processed % 100 == 0 && processed > 0 {
-                            progress_callback(&format!(
-                                "Comparing... {}/{}",
-                                processed, total_paths
-                            ));
-                        }
-
-                        crate::utils::log_debug(&format!(
-                            "About to compare files: {} vs {}",
-                            left_path.display(),
-                            right_path.display()
-                        ));
-
-                        if match Self::files_are_same(&left_path, &right_path, left, right) {
-                            Ok(same) => {
-                                crate::utils::log_debug(&format!(
-                                    "File comparison completed: {} vs {} -> {}",
-                                    left_path.display(),
-                                    right_path.display(),
-                                    same
-                                ));
+                        let left_path = left_dir.join(path);
+                        let right_path = right_dir.join(
+                            options
+                                .path_map
+                                .to_right(path)
+                                .expect("found on the right under its mapped name"),
+                        );
+
+                        progress.comparing(Some(path), processed, total);
+
+                        let indexed = index
+                            .as_ref()
+                            .and_then(|index| index.lookup(path, left, right));
+                        let mut unread = false;
+                        let contents_same = match indexed {
+                            Some(same) => {
+                                reused += 1;
                                 same
                             }
-                            Err(e) => {
-                                crate::utils::log_error(&format!(
-                                    "CRITICAL ERROR in files_are_same: {} vs {} - {}",
-                                    left_path.display(),
-                                    right_path.display(),
-                                    e
-                                ));
-                                return Err(e);
+                            None if limit.metadata_only => {
+                                left.len() == right.len()
+                                    && mtimes_equal(
+                                        left.modified().ok(),
+                                        right.modified().ok(),
+                                        options.mtime_tolerance,
+                                    )
                             }
-                        } {
+                            None => {
+                                let reading = Instant::now();
+                                let same = Self::files_are_same(
+                                    &left_path,
+                                    &right_path,
+                                    left,
+                                    right,
+                                    options,
+                                    cancel,
+                                );
+                                stats.compare_time += reading.elapsed();
+                                stats.files_compared += 1;
+                                match same {
+                                    Err(e) if is_permission_denied(&e) => {
+                                        let same = Self::unreadable_files_same(
+                                            &left_path,
+                                            &right_path,
+                                            options,
+                                        );
+                                        unread = same.is_none();
+                                        same.unwrap_or(false)
+                                    }
+                                    // Stopped on purpose, which is no error
+                                    Err(e) if check_cancelled(cancel).is_err() => return Err(e),
+                                    same => same.inspect_err(|e| {
+                                        crate::utils::log_error(&format!(
+                                            "CRITICAL ERROR in files_are_same: {} vs {} - {}",
+                                            left_path.display(),
+                                            right_path.display(),
+                                            e
+                                        ))
+                                    })?,
+                                }
+                            }
+                        };
+                        // A guess from the metadata isn't worth remembering,
+                        // and neither is a pair that couldn't be read
+                        if let Some(index) = index
+                            .as_mut()
+                            .filter(|_| indexed.is_some() || !(limit.metadata_only || unread))
+                        {
+                            index.record(path, left, right, contents_same);
+                        }
+                        let same = !Self::permissions_differ(left, right, options) && contents_same;
+                        if same {
                             FileStatus::Same
                         } else {
                             FileStatus::Different
                         }
+                    } else if Self::permissions_differ(left, right, options) {
+                        FileStatus::Different
+                    } else {
+                        FileStatus::Same // Assume directories are same for now
                     }
                 }
-                (None, None) => FileStatus::Same, // This shouldn't actually happen
+                (Some(_), None, _) => FileStatus::LeftOnly,
+                (None, Some(_), _) => FileStatus::RightOnly,
+                (None, None, _) => unreachable!(),
             };
 
-            // Insert node into tree
-            match status {
-                FileStatus::LeftOnly => {
-                    Self::insert_into_tree(
-                        &mut left_root,
-                        &path,
-                        name.clone(),
-                        is_dir,
-                        status,
-                        false,
-                        left_meta,
-                    )?;
-                    Self::insert_into_tree(
-                        &mut right_root,
-                        &path,
-                        String::new(),
-                        is_dir,
-                        status,
-                        true,
-                        None,
-                    )?;
-                }
-                FileStatus::RightOnly => {
-                    Self::insert_into_tree(
-                        &mut left_root,
-                        &path,
-                        String::new(),
-                        is_dir,
-                        status,
-                        true,
-                        None,
-                    )?;
-                    Self::insert_into_tree(
-                        &mut right_root,
-                        &path,
-                        name.clone(),
-                        is_dir,
-                        status,
-                        false,
-                        right_meta,
-                    )?;
-                }
-                _ => {
-                    Self::insert_into_tree(
-                        &mut left_root,
-                        &path,
-                        name.clone(),
-                        is_dir,
-                        status,
-                        false,
-                        left_meta,
-                    )?;
-                    Self::insert_into_tree(
-                        &mut right_root,
-                        &path,
-                        name,
-                        is_dir,
-                        status,
-                        false,
-                        right_meta,
-                    )?;
-                }
+            let entry = DiffEntry {
+                rel_path: path.clone(),
+                status,
+                left: left_meta.map(Meta::from_metadata),
+                right: right_meta.map(Meta::from_metadata),
+            };
+            if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                checkpoint.record(&entry);
             }
+            digests.push_entry(&entry);
+            Self::insert_entry(
+                entry,
+                &mut left_builder,
+                &mut right_builder,
+                stream.as_deref_mut(),
+            );
 
             processed += 1;
-            if processed % 10 == 0 || processed == total_paths {
-                progress_callback(&format!("Progress: {}/{}", processed, total_paths));
+            progress.compared(processed, total);
+        }
+
+        if let Some(stream) = stream {
+            stream.finish();
+        }
+        progress.done(processed);
+        stats.paths = processed;
+
+        if let Some(index) = index {
+            crate::utils::log_debug(&format!(
+                "Comparison index: reused {} results without reading the files",
+                reused
+            ));
+            if let Err(e) = index.save() {
+                crate::utils::log_error(&format!("Failed to save the comparison index: {:#}", e));
             }
         }
+        if reuse.is_some() {
+            crate::utils::log_debug(&format!(
+                "Folder digests: reused {} results from unchanged folders",
+                unchanged
+            ));
+        }
+
+        let mut left_root = left_builder.finish();
+        let mut right_root = right_builder.finish();
 
         // Sort children at all levels after tree construction
         Self::sort_tree_recursive(&mut left_root);
@@ -625,40 +2563,91 @@ impl DirectoryComparison {
         Self::update_folder_status(&mut left_root);
         Self::update_folder_status(&mut right_root);
 
-        Ok((left_root, right_root))
+        Ok((left_root, right_root, digests.finish()))
     }
 
-    fn sort_tree_recursive(node: &mut FileNode) {
-        // Sort children: folders first, then case-insensitive alphabetical
-        node.children.sort_by(|a, b| {
-            let a_name = if a.name.is_empty() {
-                a.path
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or("")
-            } else {
-                &a.name
-            };
-            let b_name = if b.name.is_empty() {
-                b.path
-                    .file_name()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap_or("")
-            } else {
-                &b.name
-            };
-
-            // Folders first, then files
-            match (a.is_dir, b.is_dir) {
-                (true, false) => std::cmp::Ordering::Less, // folder < file
-                (false, true) => std::cmp::Ordering::Greater, // file > folder
-                _ => {
-                    // Same type (both folders or both files) - case insensitive alphabetical
-                    a_name.to_lowercase().cmp(&b_name.to_lowercase())
-                }
+    /// With the previous scan of the same roots at hand, walk both sides
+    /// once to hash their folders, keeping only the hashes, and reuse the
+    /// previous results of the folders whose hashes didn't change. `pairs`
+    /// starts a fresh walk each time it's called.
+    fn reuse_unchanged<'a, I>(
+        pairs: impl Fn() -> I,
+        previous: Option<&'a PreviousScan>,
+        options: &CompareOptions,
+        progress: &mut Progress,
+    ) -> Result<Option<Reuse<'a>>>
+    where
+        I: Iterator<Item = Result<MetadataPair>>,
+    {
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
+        progress.message("Hashing folder listings...");
+        let mut digests = DigestBuilder::new(options);
+        for pair in pairs() {
+            let (path, left, right) = pair?;
+            if !path.as_os_str().is_empty() {
+                digests.push(
+                    &path,
+                    left.as_ref().map(Meta::from_metadata),
+                    right.as_ref().map(Meta::from_metadata),
+                );
             }
+        }
+        Ok(Some(Reuse {
+            previous: &previous.digests,
+            folders: digests.finish().unchanged_since(&previous.digests),
+        }))
+    }
+
+    /// Add a compared path to both trees and hand it to the stream.
+    fn insert_entry(
+        entry: DiffEntry,
+        left_builder: &mut TreeBuilder,
+        right_builder: &mut TreeBuilder,
+        stream: Option<&mut EntryStream>,
+    ) {
+        let is_dir = entry.left.or(entry.right).is_some_and(|meta| meta.is_dir);
+        let name: Arc<OsStr> = entry.rel_path.file_name().unwrap_or_default().into();
+
+        // Insert only items that exist in each panel
+        // For LeftOnly/RightOnly, insert empty nodes on opposite side for alignment
+        let (in_left, in_right) = match entry.status {
+            FileStatus::LeftOnly => (true, false),
+            FileStatus::RightOnly => (false, true),
+            FileStatus::Same | FileStatus::Different => (true, true),
+        };
+        left_builder.insert(
+            &entry.rel_path,
+            &name,
+            in_left,
+            is_dir,
+            entry.status,
+            entry.left.as_ref(),
+        );
+        right_builder.insert(
+            &entry.rel_path,
+            &name,
+            in_right,
+            is_dir,
+            entry.status,
+            entry.right.as_ref(),
+        );
+
+        if let Some(stream) = stream {
+            stream.push(entry);
+        }
+    }
+
+    fn sort_tree_recursive(node: &mut FileNode) {
+        // Sort children: folders first, then case-insensitive alphabetical.
+        // Placeholders carry the name they stand in for, so both panels keep
+        // the same order
+        node.children.sort_by_cached_key(|child| {
+            (
+                !child.is_dir,
+                crate::utils::escape_name(&*child.name).to_lowercase(),
+            )
         });
 
         // Recursively sort all child nodes
@@ -680,52 +2669,58 @@ impl DirectoryComparison {
         }
 
         // Folder status is determined by children's status
-        let new_status = if child_statuses.is_empty() {
-            // Empty folders maintain original status
-            node.status
-        } else {
-            // Analyze children's status
-            let has_different = child_statuses.iter().any(|&s| s == FileStatus::Different);
-            let has_left_only = child_statuses.iter().any(|&s| s == FileStatus::LeftOnly);
-            let has_right_only = child_statuses.iter().any(|&s| s == FileStatus::RightOnly);
-            let has_same = child_statuses.iter().any(|&s| s == FileStatus::Same);
-
-            if has_different {
-                // If any child is Different, folder is Different
-                FileStatus::Different
-            } else if has_left_only && has_right_only {
-                // If has both LeftOnly and RightOnly children, folder is Different
-                FileStatus::Different
-            } else if has_left_only && has_same {
-                // If has both LeftOnly and Same children, folder is Different
-                FileStatus::Different
-            } else if has_right_only && has_same {
-                // If has both RightOnly and Same children, folder is Different
-                FileStatus::Different
-            } else if has_left_only {
-                // If all children are LeftOnly, folder is LeftOnly
-                FileStatus::LeftOnly
-            } else if has_right_only {
-                // If all children are RightOnly, folder is RightOnly
-                FileStatus::RightOnly
-            } else {
-                // If all children are Same, folder is Same
-                FileStatus::Same
-            }
-        };
+        let new_status = Self::folder_status(node.status, &child_statuses);
 
         node.status = new_status;
         new_status
     }
 
+    /// Status of a folder given its own status and its children's statuses.
+    fn folder_status(own: FileStatus, child_statuses: &[FileStatus]) -> FileStatus {
+        if child_statuses.is_empty() || own == FileStatus::Different {
+            // Empty folders, and folders that differ themselves (permissions),
+            // keep their own status
+            return own;
+        }
+
+        // Analyze children's status
+        let has_different = child_statuses.contains(&FileStatus::Different);
+        let has_left_only = child_statuses.contains(&FileStatus::LeftOnly);
+        let has_right_only = child_statuses.contains(&FileStatus::RightOnly);
+        let has_same = child_statuses.contains(&FileStatus::Same);
+
+        if has_different {
+            // If any child is Different, folder is Different
+            FileStatus::Different
+        } else if has_left_only && has_right_only {
+            // If has both LeftOnly and RightOnly children, folder is Different
+            FileStatus::Different
+        } else if has_left_only && has_same {
+            // If has both LeftOnly and Same children, folder is Different
+            FileStatus::Different
+        } else if has_right_only && has_same {
+            // If has both RightOnly and Same children, folder is Different
+            FileStatus::Different
+        } else if has_left_only {
+            // If all children are LeftOnly, folder is LeftOnly
+            FileStatus::LeftOnly
+        } else if has_right_only {
+            // If all children are RightOnly, folder is RightOnly
+            FileStatus::RightOnly
+        } else {
+            // If all children are Same, folder is Same
+            FileStatus::Same
+        }
+    }
+
     #[allow(dead_code)]
     fn debug_print_tree(node: &FileNode, depth: usize) {
         let indent = "  ".repeat(depth);
         eprintln!(
             "{}🔍 '{}' (empty: {}, status: {:?}, is_dir: {})",
             indent,
-            node.name,
-            node.name.is_empty(),
+            node.display_name(),
+            !node.exists,
             node.status,
             node.is_dir
         );
@@ -734,13 +2729,171 @@ impl DirectoryComparison {
         }
     }
 
+    fn permissions_differ(
+        left: &fs::Metadata,
+        right: &fs::Metadata,
+        options: &CompareOptions,
+    ) -> bool {
+        options.compare_permissions && permission_bits(left) != permission_bits(right)
+    }
+
     pub fn files_are_same_public(
         left: &Path,
         right: &Path,
         left_meta: &fs::Metadata,
         right_meta: &fs::Metadata,
+        options: &CompareOptions,
+    ) -> Result<bool> {
+        Self::files_are_same(left, right, left_meta, right_meta, options, None)
+    }
+
+    /// Compare the files at `paths` (relative to the roots) again, reading
+    /// each pair in full. Returns their statuses, and the paths that weren't
+    /// read: missing on a side, not regular files on both, or unreadable.
+    pub fn verify<F>(
+        left_dir: &Path,
+        right_dir: &Path,
+        paths: &[PathBuf],
+        options: &CompareOptions,
+        cancel: &AtomicBool,
+        progress: F,
+    ) -> Result<Verification>
+    where
+        F: Fn(&Path, usize),
+    {
+        let mut statuses = Vec::new();
+        let mut skipped = Vec::new();
+        for (done, path) in paths.iter().enumerate() {
+            check_cancelled(Some(cancel))?;
+            let Some(right) = options.path_map.to_right(path) else {
+                skipped.push(path.clone());
+                continue;
+            };
+            let (left, right) = (left_dir.join(path), right_dir.join(right));
+            let (Ok(left_meta), Ok(right_meta)) = (fs::metadata(&left), fs::metadata(&right))
+            else {
+                skipped.push(path.clone());
+                continue;
+            };
+            if !left_meta.is_file() || !right_meta.is_file() {
+                skipped.push(path.clone());
+                continue;
+            }
+
+            progress(path, done);
+            match Self::verify_files(&left, &right, &left_meta, &right_meta, options, cancel) {
+                Ok(same) => {
+                    let same = same && !Self::permissions_differ(&left_meta, &right_meta, options);
+                    let status = if same {
+                        FileStatus::Same
+                    } else {
+                        FileStatus::Different
+                    };
+                    statuses.push((path.clone(), status));
+                }
+                Err(e) => {
+                    crate::utils::log_error(&format!(
+                        "Failed to verify {}: {:#}",
+                        path.display(),
+                        e
+                    ));
+                    skipped.push(path.clone());
+                }
+            }
+        }
+        Ok((statuses, skipped))
+    }
+
+    /// Whether two files are the same, reading both in full: none of the
+    /// size, time and sampling shortcuts `files_are_same` takes for speed.
+    /// Only a size mismatch is taken on trust.
+    fn verify_files(
+        left: &Path,
+        right: &Path,
+        left_meta: &fs::Metadata,
+        right_meta: &fs::Metadata,
+        options: &CompareOptions,
+        cancel: &AtomicBool,
     ) -> Result<bool> {
-        Self::files_are_same(left, right, left_meta, right_meta)
+        if let Some(same) = Self::normalized_same(left, right, options) {
+            return Ok(same);
+        }
+        if left_meta.len() != right_meta.len() {
+            return Ok(false);
+        }
+
+        let (mut left_file, mut right_file) = (fs::File::open(left)?, fs::File::open(right)?);
+        let mut left_buffer = vec![0; VERIFY_CHUNK_SIZE];
+        let mut right_buffer = vec![0; VERIFY_CHUNK_SIZE];
+        loop {
+            check_cancelled(Some(cancel))?;
+            let read = Self::read_chunk(&mut left_file, &mut left_buffer, cancel)?;
+            if Self::read_chunk(&mut right_file, &mut right_buffer, cancel)? != read
+                || left_buffer[..read] != right_buffer[..read]
+            {
+                return Ok(false);
+            }
+            if read == 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Fill `buffer` unless the file ends first; returns how much was read.
+    fn read_chunk(file: &mut fs::File, buffer: &mut [u8], cancel: &AtomicBool) -> Result<usize> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match file.read(&mut buffer[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        count_read(filled, Some(cancel));
+        Ok(filled)
+    }
+
+    /// The verdict of the file's normalization command, or of comparing the
+    /// files without comments, when one applies.
+    fn normalized_same(left: &Path, right: &Path, options: &CompareOptions) -> Option<bool> {
+        if let Some(command) = options.normalizers.command_for(left) {
+            match (
+                crate::normalize::digest(command, left),
+                crate::normalize::digest(command, right),
+            ) {
+                (Ok(left_digest), Ok(right_digest)) => return Some(left_digest == right_digest),
+                (Err(e), _) | (_, Err(e)) => {
+                    crate::utils::log_error(&format!("Normalization skipped: {:#}", e));
+                }
+            }
+        }
+
+        if options.ignore_comments {
+            return crate::normalize::same_ignoring_comments(left, right);
+        }
+        None
+    }
+
+    /// Whether two files the scan isn't allowed to read are the same, asking
+    /// sudo when allowed to. `None` when they couldn't be read, after noting
+    /// the one that couldn't.
+    fn unreadable_files_same(left: &Path, right: &Path, options: &CompareOptions) -> Option<bool> {
+        if options.unreadable == UnreadablePolicy::Sudo {
+            match crate::elevate::sudo_files_same(left, right) {
+                Ok(same) => return Some(same),
+                Err(e) => warn(format!(
+                    "Couldn't read {} with sudo: {:#}",
+                    left.display(),
+                    e
+                )),
+            }
+        }
+        let denied = if fs::File::open(left).is_err() {
+            left
+        } else {
+            right
+        };
+        unreadable(denied);
+        None
     }
 
     fn files_are_same(
@@ -748,6 +2901,8 @@ impl DirectoryComparison {
         right: &Path,
         left_meta: &fs::Metadata,
         right_meta: &fs::Metadata,
+        options: &CompareOptions,
+        cancel: Option<&AtomicBool>,
     ) -> Result<bool> {
         crate::utils::log_debug(&format!(
             "files_are_same: Starting comparison - {} vs {}",
@@ -801,14 +2956,14 @@ impl DirectoryComparison {
         };
 
         // If either is actually a directory, return false immediately
-        if left_real_meta.as_ref().map_or(false, |m| m.is_dir())
-            || right_real_meta.as_ref().map_or(false, |m| m.is_dir())
+        if left_real_meta.as_ref().is_some_and(|m| m.is_dir())
+            || right_real_meta.as_ref().is_some_and(|m| m.is_dir())
         {
             crate::utils::log_debug(&format!("files_are_same: At least one path is actually a directory - {} (is_dir: {}) vs {} (is_dir: {})",
                                    left.display(),
-                                   left_real_meta.as_ref().map_or(false, |m| m.is_dir()),
+                                   left_real_meta.as_ref().is_some_and(|m| m.is_dir()),
                                    right.display(),
-                                   right_real_meta.as_ref().map_or(false, |m| m.is_dir())));
+                                   right_real_meta.as_ref().is_some_and(|m| m.is_dir())));
             return Ok(false);
         }
 
@@ -823,6 +2978,12 @@ impl DirectoryComparison {
             return Ok(false);
         }
 
+        // Files with a normalization command are equal when its output is,
+        // whatever their sizes; if it fails the raw contents are compared
+        if let Some(same) = Self::normalized_same(left, right, options) {
+            return Ok(same);
+        }
+
         // Stage 1: File size comparison (fastest)
         if left_meta.len() != right_meta.len() {
             crate::utils::log_debug(&format!(
@@ -835,7 +2996,8 @@ impl DirectoryComparison {
             return Ok(false);
         }
 
-        // Stage 2: Skip time comparison (removed for reliability)
+        // Stage 2: Modification times are only consulted for large files
+        // (see stage 6), after the size check has passed
 
         // Stage 3: Zero-size files are considered same
         if left_meta.len() == 0 {
@@ -891,6 +3053,7 @@ impl DirectoryComparison {
                     return Err(e.into());
                 }
             };
+            count_read(left_content.len() + right_content.len(), cancel);
             let result = left_content == right_content;
             crate::utils::log_debug(&format!(
                 "files_are_same: Small file content comparison result: {} - {} vs {}",
@@ -909,27 +3072,44 @@ impl DirectoryComparison {
                 left.display(),
                 right.display()
             ));
-            return Self::compare_file_crc32(left, right);
+            return Self::compare_file_crc32(left, right, cancel);
         }
 
-        // Stage 6: Large files (≥1MB) - compare first 4KB only (quick check)
+        // Stage 6: Large files (≥1MB) of equal size - compare the first and
+        // last 4KB. Modification times only decide whether that sample is
+        // trusted: when they disagree beyond the tolerance the whole file is
+        // checksummed, which is the only full read left for large files.
+        let mtimes_match = mtimes_equal(
+            left_meta.modified().ok(),
+            right_meta.modified().ok(),
+            options.mtime_tolerance,
+        );
         crate::utils::log_debug(&format!(
-            "files_are_same: Using head comparison for large files ({} bytes) - {} vs {}",
+            "files_are_same: Using head and tail comparison for large files ({} bytes, mtimes match: {}) - {} vs {}",
             left_meta.len(),
+            mtimes_match,
             left.display(),
             right.display()
         ));
-        Self::compare_file_heads(left, right, 4096)
+        if !Self::compare_file_heads(left, right, 4096)?
+            || !Self::compare_file_tails(left, right, 4096)?
+        {
+            return Ok(false);
+        }
+        if mtimes_match {
+            return Ok(true);
+        }
+        Self::compare_file_crc32(left, right, cancel)
     }
 
-    fn compare_file_crc32(left: &Path, right: &Path) -> Result<bool> {
+    fn compare_file_crc32(left: &Path, right: &Path, cancel: Option<&AtomicBool>) -> Result<bool> {
         crate::utils::log_debug(&format!(
             "Starting CRC32 comparison: {} vs {}",
             left.display(),
             right.display()
         ));
 
-        let left_crc = match Self::calculate_file_crc32(left) {
+        let left_crc = match Self::calculate_file_crc32(left, cancel) {
             Ok(crc) => {
                 crate::utils::log_debug(&format!(
                     "Left CRC32 calculated successfully: {} (0x{:08x})",
@@ -948,7 +3128,7 @@ impl DirectoryComparison {
             }
         };
 
-        let right_crc = match Self::calculate_file_crc32(right) {
+        let right_crc = match Self::calculate_file_crc32(right, cancel) {
             Ok(crc) => {
                 crate::utils::log_debug(&format!(
                     "Right CRC32 calculated successfully: {} (0x{:08x})",
@@ -978,7 +3158,9 @@ impl DirectoryComparison {
     }
 
 
-    fn calculate_file_crc32(path: &Path) -> Result<u32> {
+    /// Checksum the contents of `path`, stopping early once `cancel` is set,
+    /// so cancelling doesn't wait for a large file to be read to its end.
+    fn calculate_file_crc32(path: &Path, cancel: Option<&AtomicBool>) -> Result<u32> {
         crate::utils::log_debug(&format!("Calculating CRC32 for: {}", path.display()));
 
         // Check if path is a directory first
@@ -1055,6 +3237,8 @@ impl DirectoryComparison {
             }
             hasher.update(&buffer[..bytes_read]);
             total_bytes += bytes_read;
+            count_read(bytes_read, cancel);
+            check_cancelled(cancel)?;
         }
 
         let crc = hasher.finalize();
@@ -1188,6 +3372,7 @@ impl DirectoryComparison {
 
         let left_bytes = left_file.read(&mut left_buffer)?;
         let right_bytes = right_file.read(&mut right_buffer)?;
+        count_read(left_bytes + right_bytes, None);
 
         if left_bytes != right_bytes {
             return Ok(false);
@@ -1196,72 +3381,22 @@ impl DirectoryComparison {
         Ok(left_buffer[..left_bytes] == right_buffer[..right_bytes])
     }
 
-    fn insert_into_tree(
-        root: &mut FileNode,
-        path: &Path,
-        name: String,
-        is_dir: bool,
-        status: FileStatus,
-        _exists: bool,
-        metadata: Option<&fs::Metadata>,
-    ) -> Result<()> {
-        let components: Vec<_> = path.components().collect();
-        let mut current = root;
-
-        for (i, component) in components.iter().enumerate() {
-            let component_name = component.as_os_str().to_string_lossy().to_string();
-            let is_last = i == components.len() - 1;
-
-            // Find or create child with matching name at current level (path-based matching)
-            let child_index = current.children.iter().position(|child| {
-                // Match based on path (same path = same node even with empty name)
-                child.path.file_name().unwrap_or_default() == component.as_os_str()
-            });
-
-            if let Some(index) = child_index {
-                if is_last {
-                    // Update status if this is the last component
-                    current.children[index].status = status;
-                }
-                current = &mut current.children[index];
-            } else {
-                // Create new node
-                let child_path = if i == 0 {
-                    PathBuf::from(&component_name)
-                } else {
-                    components.iter().take(i + 1).collect()
-                };
-
-                let child_status = if is_last { status } else { FileStatus::Same };
-                let child_is_dir = if is_last { is_dir } else { true };
-
-                // Handle names for non-existent files
-                let actual_name = if is_last {
-                    // Always use the actual name instead of empty string
-                    name.clone()
-                } else {
-                    component_name.clone()
-                };
-
-                let new_child = if is_last {
-                    FileNode::new_with_metadata(
-                        actual_name,
-                        child_path,
-                        child_is_dir,
-                        child_status,
-                        metadata,
-                    )
-                } else {
-                    FileNode::new(actual_name, child_path, child_is_dir, child_status)
-                };
-
-                current.children.push(new_child);
-                let new_index = current.children.len() - 1;
-                current = &mut current.children[new_index];
-            }
-        }
-
-        Ok(())
+    /// Whether the last `bytes_to_read` bytes of two files of the same size
+    /// are equal.
+    fn compare_file_tails(left: &Path, right: &Path, bytes_to_read: usize) -> Result<bool> {
+        let mut left_file = fs::File::open(left)?;
+        let mut right_file = fs::File::open(right)?;
+        let offset = -(bytes_to_read as i64);
+        left_file.seek(SeekFrom::End(offset))?;
+        right_file.seek(SeekFrom::End(offset))?;
+
+        let mut left_buffer = Vec::with_capacity(bytes_to_read);
+        let mut right_buffer = Vec::with_capacity(bytes_to_read);
+        left_file.read_to_end(&mut left_buffer)?;
+        right_file.read_to_end(&mut right_buffer)?;
+        count_read(left_buffer.len() + right_buffer.len(), None);
+
+        Ok(left_buffer == right_buffer)
     }
 
     #[allow(dead_code)]
@@ -1296,4 +3431,105 @@ impl DirectoryComparison {
 
         Ok(output)
     }
+
+    /// Unified diff of two files with `a/` and `b/` headers, as printed by `--simple`.
+    /// With a `deadline`, a large diff may come out rougher than the
+    /// smallest one rather than take long.
+    pub fn unified_diff(
+        label: &Path,
+        left_path: &Path,
+        right_path: &Path,
+        deadline: Option<Instant>,
+    ) -> Result<String> {
+        let (Ok(left_content), Ok(right_content)) = (
+            fs::read_to_string(left_path),
+            fs::read_to_string(right_path),
+        ) else {
+            return Ok(format!(
+                "Binary files a/{} and b/{} differ\n",
+                crate::utils::escape_name(label),
+                crate::utils::escape_name(label)
+            ));
+        };
+
+        let mut config = similar::TextDiff::configure();
+        if let Some(deadline) = deadline {
+            config.deadline(deadline);
+        }
+        let diff = config.diff_lines(&left_content, &right_content);
+        Ok(diff
+            .unified_diff()
+            .header(
+                &format!("a/{}", crate::utils::escape_name(label)),
+                &format!("b/{}", crate::utils::escape_name(label)),
+            )
+            .to_string())
+    }
+
+    /// How alike two text files are, as the share of matching lines from 0
+    /// to 1. `None` for binary files and files too large to diff quickly.
+    pub fn similarity(left_path: &Path, right_path: &Path) -> Option<f32> {
+        let (left, right) = (Self::read_text(left_path)?, Self::read_text(right_path)?);
+        let diff = similar::TextDiff::configure()
+            .deadline(Instant::now() + SIMILARITY_DEADLINE)
+            .diff_lines(&left, &right);
+        Some(diff.ratio())
+    }
+
+    /// Lines removed from the left file and added in the right one, a
+    /// missing side counting as empty. `None` for binary files and files
+    /// too large to diff quickly; a diff that takes long is cut short and
+    /// may count more lines than the smallest one would.
+    pub fn line_changes(
+        left_path: Option<&Path>,
+        right_path: Option<&Path>,
+    ) -> Option<(usize, usize)> {
+        let read = |path: Option<&Path>| path.map_or(Some(String::new()), Self::read_text);
+        let (left, right) = (read(left_path)?, read(right_path)?);
+        let diff = similar::TextDiff::configure()
+            .deadline(Instant::now() + SIMILARITY_DEADLINE)
+            .diff_lines(&left, &right);
+        let (mut removed, mut added) = (0, 0);
+        for change in diff.iter_all_changes() {
+            match change.tag() {
+                similar::ChangeTag::Delete => removed += 1,
+                similar::ChangeTag::Insert => added += 1,
+                similar::ChangeTag::Equal => {}
+            }
+        }
+        Some((removed, added))
+    }
+
+    /// The differing files below `dir` (relative to the roots, empty for
+    /// the roots themselves) with their paths on the sides they're on, to
+    /// count their `line_changes` for a diffstat.
+    pub fn diffstat_files(&self, dir: &Path) -> Vec<(PathBuf, [Option<PathBuf>; 2])> {
+        let entries = if dir.as_os_str().is_empty() {
+            self.entries()
+        } else {
+            self.entries_below(dir)
+        };
+        entries
+            .filter(|entry| {
+                entry.status != FileStatus::Same
+                    && !entry.left.or(entry.right).is_some_and(|meta| meta.is_dir)
+            })
+            .map(|entry| {
+                let left = entry.left.map(|_| self.left_path(&entry.rel_path));
+                let right = entry.right.and_then(|_| self.right_path(&entry.rel_path));
+                (entry.rel_path, [left, right])
+            })
+            .collect()
+    }
+
+    /// The contents of a text file small enough to diff quickly.
+    fn read_text(path: &Path) -> Option<String> {
+        let size = fs::metadata(path).ok()?.len();
+        if size > SIMILARITY_MAX_BYTES {
+            return None;
+        }
+        fs::read_to_string(path)
+            .ok()
+            .filter(|content| !content.contains('\0'))
+    }
 }
@@ -0,0 +1,259 @@
+use anyhow::{bail, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::copy::CopyBackend;
+use crate::elevate::UnreadablePolicy;
+use crate::manifest::HashAlgorithm;
+use crate::normalize::Normalizers;
+use crate::pathmap::PathMap;
+use crate::theme::{parse_style, Highlights};
+
+/// Settings read from the config file. Command line flags take precedence.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Draw plain ASCII markers instead of emoji icons.
+    pub ascii: bool,
+    /// Show statuses with markers and bold/underline instead of colors.
+    pub no_color: bool,
+    /// Status color palette, see `theme::PALETTES`.
+    pub theme: Option<String>,
+    /// Styles of the selected row, set as `selected` and `inactive` in the
+    /// `[highlight]` section, e.g. `selected = on blue bold`.
+    pub highlight: Highlights,
+    /// Algorithm of the hashes shown with `H`.
+    pub hash: HashAlgorithm,
+    /// What scans do with what they aren't allowed to read, `report` or
+    /// `sudo`; `--unreadable` overrides it.
+    pub unreadable: UnreadablePolicy,
+    /// Bytes per second file contents are read and copied at, e.g.
+    /// `bwlimit = 20M`; `--bwlimit` overrides it.
+    pub bwlimit: Option<u64>,
+    /// `left=right` folder mappings, set in the `[map]` section, e.g.
+    /// `src = source`; `--map` overrides them.
+    pub map: Vec<String>,
+    /// Remappable keys, set in the `[keys]` section.
+    pub keys: Keymap,
+    /// `builtin` or `rsync`, set as `backend` in the `[copy]` section.
+    pub copy_backend: CopyBackend,
+    /// Commands that normalize files before comparing, set per extension in
+    /// the `[normalize]` section, e.g. `json = jq -S .`.
+    pub normalize: Normalizers,
+}
+
+/// `line` without its trailing comment. A `#` inside a word, like in a
+/// normalize command's `sed 's/#.*//'`, or starting a `#rrggbb` color is
+/// part of the value.
+fn strip_comment(line: &str) -> &str {
+    let comment = line.match_indices('#').find(|&(at, _)| {
+        at == 0 || line[..at].ends_with(char::is_whitespace) && !is_hex_color(&line[at + 1..])
+    });
+    match comment {
+        Some((at, _)) => line[..at].trim_end(),
+        None => line,
+    }
+}
+
+/// Whether `text` starts with six hex digits ending the word.
+fn is_hex_color(text: &str) -> bool {
+    text.len() >= 6
+        && text.as_bytes()[..6].iter().all(u8::is_ascii_hexdigit)
+        && text[6..].chars().next().is_none_or(char::is_whitespace)
+}
+
+/// A single key with its modifiers, e.g. `ctrl+r` or `>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub const fn char(c: char) -> Self {
+        Self {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    /// Shift is ignored because terminals disagree on whether they report it
+    /// for shifted characters like `>`.
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        let relevant = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        key.code == self.code && key.modifiers & relevant == self.modifiers & relevant
+    }
+
+    /// Parse `>`, `ctrl+r`, `alt+x`, `f5`, `enter`, `tab`, `backspace`...
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = spec;
+        // A lone `+` is a key, not a separator
+        while let Some((modifier, key)) = rest.split_once('+').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" | "meta" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => bail!("unknown modifier `{}` in `{}`", modifier, spec),
+            };
+            rest = key;
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "enter" | "return" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" | "ins" => KeyCode::Insert,
+                "esc" | "escape" => KeyCode::Esc,
+                "space" => KeyCode::Char(' '),
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => bail!("unknown key `{}`", spec),
+                },
+            },
+        };
+        Ok(Self { code, modifiers })
+    }
+}
+
+/// Keys that can be rebound from the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    /// Copy the selected row from the left to the right panel
+    pub copy_right: KeyBinding,
+    /// Copy the selected row from the right to the left panel
+    pub copy_left: KeyBinding,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            copy_right: KeyBinding::char('>'),
+            copy_left: KeyBinding::char('<'),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/tudiff/config`, falling back to `~/.config/tudiff/config`.
+pub fn config_file() -> Option<PathBuf> {
+    crate::utils::config_dir().map(|dir| dir.join("config"))
+}
+
+impl Config {
+    /// Load the config file. A missing file yields the defaults; a broken one
+    /// is logged and otherwise ignored so a typo never stops the program.
+    pub fn load() -> Self {
+        let Some(path) = config_file() else {
+            return Self::default();
+        };
+        if !path.exists() {
+            return Self::default();
+        }
+        match Self::from_file(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Warning: ignoring {}: {:#}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        Self::parse(&content)
+    }
+
+    /// Parse `key = value` lines. `#` starts a comment at the start of a line
+    /// or after whitespace, except for `#rrggbb` colors, and `[section]`
+    /// headers prefix the following keys as `section.key`.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut config = Self::default();
+        let mut section = String::new();
+
+        for (number, line) in content.lines().enumerate() {
+            let line = strip_comment(line.trim());
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_string();
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("line {}: expected `key = value`", number + 1);
+            };
+            let key = key.trim();
+            let key = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", section, key)
+            };
+            config
+                .set(&key, value.trim())
+                .with_context(|| format!("line {}", number + 1))?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "ascii" => self.ascii = parse_bool(value)?,
+            "no_color" => self.no_color = parse_bool(value)?,
+            "theme" => {
+                if !crate::theme::PALETTES
+                    .iter()
+                    .any(|palette| palette.name.eq_ignore_ascii_case(value))
+                {
+                    bail!(
+                        "expected one of {}, got `{}`",
+                        crate::theme::palette_names(),
+                        value
+                    );
+                }
+                self.theme = Some(value.to_string());
+            }
+            "hash" => self.hash = HashAlgorithm::parse(value)?,
+            "unreadable" => self.unreadable = UnreadablePolicy::parse(value)?,
+            "bwlimit" => self.bwlimit = crate::throttle::parse_rate(value)?,
+            "highlight.selected" => self.highlight.selected = parse_style(value)?,
+            "highlight.inactive" => self.highlight.inactive = parse_style(value)?,
+            "highlight.hover" => self.highlight.hover = parse_style(value)?,
+            "keys.copy_right" => self.keys.copy_right = KeyBinding::parse(value)?,
+            "keys.copy_left" => self.keys.copy_left = KeyBinding::parse(value)?,
+            "copy.backend" => {
+                self.copy_backend = match value.to_ascii_lowercase().as_str() {
+                    "builtin" => CopyBackend::Builtin,
+                    "rsync" => CopyBackend::Rsync,
+                    _ => bail!("expected `builtin` or `rsync`, got `{}`", value),
+                }
+            }
+            _ if key.starts_with("map.") => {
+                let spec = format!("{}={}", &key["map.".len()..], value);
+                PathMap::new(std::slice::from_ref(&spec))?;
+                self.map.push(spec);
+            }
+            _ => match key.strip_prefix("normalize.") {
+                Some(_) if value.is_empty() => bail!("expected a command for `{}`", key),
+                Some(extension) if !extension.is_empty() => self.normalize.add(extension, value),
+                _ => bail!("unknown setting `{}`", key),
+            },
+        }
+        Ok(())
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => bail!("expected a boolean, got `{}`", value),
+    }
+}
@@ -0,0 +1,1168 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::compare::mtimes_equal;
+use crate::utils::Worker;
+
+const BUFFER_SIZE: usize = 256 * 1024;
+/// Per copy_file_range call; small enough for the per-file gauge to move
+#[cfg(target_os = "linux")]
+const KERNEL_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+/// Keep the channel from flooding on fast disks; the UI polls every 50ms anyway
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Snapshot of a running copy for the progress popup.
+#[derive(Debug, Clone, Default)]
+pub struct CopyProgress {
+    /// File being copied, relative to the copied entry's parent
+    pub current_file: PathBuf,
+    pub file_bytes: u64,
+    pub file_total: u64,
+    pub copied_bytes: u64,
+    /// Bytes of files left alone by the overwrite policy
+    pub skipped_bytes: u64,
+    pub total_bytes: u64,
+    pub files_done: usize,
+    pub files_skipped: usize,
+    pub file_count: usize,
+    /// Average transfer rate since the copy started
+    pub bytes_per_sec: f64,
+}
+
+impl CopyProgress {
+    pub fn file_ratio(&self) -> f64 {
+        ratio(self.file_bytes, self.file_total)
+    }
+
+    pub fn total_ratio(&self) -> f64 {
+        ratio(self.copied_bytes + self.skipped_bytes, self.total_bytes)
+    }
+}
+
+fn ratio(done: u64, total: u64) -> f64 {
+    if total == 0 {
+        1.0
+    } else {
+        (done as f64 / total as f64).min(1.0)
+    }
+}
+
+/// What to do with files that already exist at the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    #[default]
+    Overwrite,
+    SkipExisting,
+    /// Replace existing files only when the source is newer
+    OnlyNewer,
+}
+
+impl OverwritePolicy {
+    pub const ALL: [OverwritePolicy; 3] = [
+        OverwritePolicy::Overwrite,
+        OverwritePolicy::SkipExisting,
+        OverwritePolicy::OnlyNewer,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OverwritePolicy::Overwrite => "Overwrite",
+            OverwritePolicy::SkipExisting => "Skip existing",
+            OverwritePolicy::OnlyNewer => "Only newer",
+        }
+    }
+
+    /// Whether the policy allows writing `dst`, given the source's metadata.
+    pub fn allows(self, metadata: &fs::Metadata, dst: &Path, mtime_tolerance: Duration) -> bool {
+        let Ok(existing) = fs::symlink_metadata(dst) else {
+            return true;
+        };
+        match self {
+            OverwritePolicy::Overwrite => true,
+            OverwritePolicy::SkipExisting => false,
+            OverwritePolicy::OnlyNewer => {
+                let (src_time, dst_time) = (metadata.modified().ok(), existing.modified().ok());
+                match (src_time, dst_time) {
+                    (Some(src_time), Some(dst_time)) => {
+                        src_time > dst_time
+                            && !mtimes_equal(src_time.into(), dst_time.into(), mtime_tolerance)
+                    }
+                    _ => true,
+                }
+            }
+        }
+    }
+
+    /// The next choice, wrapping around; `forward = false` goes back.
+    pub fn cycle(self, forward: bool) -> Self {
+        let index = Self::ALL.iter().position(|p| *p == self).unwrap_or(0);
+        let len = Self::ALL.len();
+        let next = if forward { index + 1 } else { index + len - 1 };
+        Self::ALL[next % len]
+    }
+}
+
+/// What performs the copy, chosen with `backend` in the config's `[copy]`
+/// section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CopyBackend {
+    #[default]
+    Builtin,
+    /// Run `rsync -a`, which handles sparse files, resumes interrupted
+    /// transfers and skips files that already match. Its changes aren't
+    /// journaled, so a failed or cancelled rsync copy isn't rolled back
+    Rsync,
+}
+
+impl CopyBackend {
+    /// The backend to copy with, and why it isn't this one. rsync before
+    /// 3.1 (macOS ships 2.6.9, openrsync claims to be it) has neither
+    /// `--info=progress2` nor `--outbuf`, so the builtin copy stands in.
+    pub fn usable(self) -> (Self, Option<String>) {
+        match (self, rsync_version()) {
+            (CopyBackend::Rsync, Some(version)) if version < (3, 1) => (
+                CopyBackend::Builtin,
+                Some(format!(
+                    "rsync {}.{} is older than 3.1, copying with the builtin backend",
+                    version.0, version.1
+                )),
+            ),
+            _ => (self, None),
+        }
+    }
+}
+
+/// The installed rsync's major and minor version, asked once; `None` when
+/// it doesn't run, which the copy itself then reports.
+fn rsync_version() -> Option<(u32, u32)> {
+    static VERSION: OnceLock<Option<(u32, u32)>> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let output = std::process::Command::new("rsync")
+            .arg("--version")
+            .output()
+            .ok()?;
+        parse_rsync_version(&String::from_utf8_lossy(&output.stdout))
+    })
+}
+
+/// Read `rsync  version 3.2.7  protocol version 31`, or the second line of
+/// openrsync's `openrsync: protocol version 29` / `rsync version 2.6.9
+/// compatible`.
+fn parse_rsync_version(output: &str) -> Option<(u32, u32)> {
+    let mut words = output
+        .lines()
+        .map(|line| line.split_whitespace())
+        .find(|words| words.clone().take(2).eq(["rsync", "version"]))?;
+    words.nth(1)?;
+    let mut numbers = words.next()?.trim_start_matches('v').split('.');
+    let major = numbers.next()?.parse().ok()?;
+    let minor = numbers.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    Some((major, minor))
+}
+
+/// Everything the copy worker needs to know.
+#[derive(Debug, Clone)]
+pub struct CopyJob {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub file_count: usize,
+    pub total_bytes: u64,
+    pub policy: OverwritePolicy,
+    /// For `OnlyNewer`, times closer than this count as equal
+    pub mtime_tolerance: Duration,
+    /// Copy only these entries of the source folder (relative to it)
+    /// instead of all of it
+    pub entries: Option<Vec<PathBuf>>,
+    pub backend: CopyBackend,
+}
+
+/// Bytes the job would actually write: files its policy lets through, less
+/// the ones rsync would find up to date (same size and time), so a copy over
+/// a slow link can be put off when it's big. It reads every folder of the
+/// source, so it belongs on a worker; once `cancel` is set the count stops
+/// short and is meaningless.
+pub fn transfer_bytes(job: &CopyJob, cancel: &AtomicBool) -> u64 {
+    match &job.entries {
+        Some(entries) => entries
+            .iter()
+            .map(|entry| {
+                pending_bytes(
+                    job,
+                    &job.source.join(entry),
+                    &job.target.join(entry),
+                    cancel,
+                )
+            })
+            .sum(),
+        None => pending_bytes(job, &job.source, &job.target, cancel),
+    }
+}
+
+fn pending_bytes(job: &CopyJob, source: &Path, target: &Path, cancel: &AtomicBool) -> u64 {
+    if cancel.load(Ordering::Relaxed) {
+        return 0;
+    }
+    let Ok(metadata) = fs::symlink_metadata(source) else {
+        return 0;
+    };
+    if metadata.is_dir() {
+        let Ok(entries) = fs::read_dir(source) else {
+            return 0;
+        };
+        return entries
+            .flatten()
+            .filter(|entry| !is_leftover(&entry.file_name()))
+            .map(|entry| pending_bytes(job, &entry.path(), &target.join(entry.file_name()), cancel))
+            .sum();
+    }
+    // Symlinks are recreated, not transferred
+    if !metadata.is_file() || !job.policy.allows(&metadata, target, job.mtime_tolerance) {
+        return 0;
+    }
+    if job.backend == CopyBackend::Rsync {
+        let up_to_date = fs::symlink_metadata(target).is_ok_and(|existing| {
+            existing.len() == metadata.len()
+                && mtimes_equal(
+                    metadata.modified().ok(),
+                    existing.modified().ok(),
+                    job.mtime_tolerance,
+                )
+        });
+        if up_to_date {
+            return 0;
+        }
+    }
+    metadata.len()
+}
+
+pub enum CopyMessage {
+    Progress(CopyProgress),
+    Complete,
+    Error(String),
+    /// A backup a killed copy made of a file it was replacing, found in a
+    /// folder this copy writes into. It's left alone: after the original
+    /// was moved aside, it may be the only copy there is.
+    StaleBackup(PathBuf),
+}
+
+/// Copy the job's source (a file or a whole folder) to its target on a
+/// worker thread. Progress, then `Complete` or `Error`, arrive on the
+/// returned channel. A builtin copy that fails or is cancelled through
+/// the returned worker is rolled back, and the error says what could and
+/// couldn't be undone.
+pub fn spawn(job: CopyJob) -> (mpsc::Receiver<CopyMessage>, Worker) {
+    let (tx, rx) = mpsc::channel();
+
+    let worker = Worker::spawn(move |cancel| {
+        let root = job.source.parent().unwrap_or(Path::new("")).to_path_buf();
+        let mut copier = Copier {
+            tx,
+            root,
+            progress: CopyProgress {
+                total_bytes: job.total_bytes,
+                file_count: job.file_count,
+                ..CopyProgress::default()
+            },
+            policy: job.policy,
+            mtime_tolerance: job.mtime_tolerance,
+            started: Instant::now(),
+            last_sent: None,
+            journal: Journal::default(),
+            cancel,
+            swept: HashSet::new(),
+        };
+
+        let result = match (job.backend, &job.entries) {
+            (CopyBackend::Rsync, _) => copier.rsync(&job),
+            (CopyBackend::Builtin, Some(entries)) => {
+                copier.copy_entries(&job.source, &job.target, entries)
+            }
+            (CopyBackend::Builtin, None) => copier.copy_entry(&job.source, &job.target),
+        };
+        let message = match result {
+            Ok(()) => {
+                copier.journal.commit();
+                CopyMessage::Complete
+            }
+            Err(e) if job.backend == CopyBackend::Builtin => {
+                CopyMessage::Error(format!("{:#}\n\n{}", e, copier.journal.roll_back()))
+            }
+            Err(e) => CopyMessage::Error(format!(
+                "{:#}\n\nrsync copies aren't rolled back: what it transferred stays.",
+                e
+            )),
+        };
+        let _ = copier.tx.send(message);
+    });
+
+    (rx, worker)
+}
+
+struct Copier {
+    tx: mpsc::Sender<CopyMessage>,
+    root: PathBuf,
+    progress: CopyProgress,
+    policy: OverwritePolicy,
+    mtime_tolerance: Duration,
+    started: Instant,
+    last_sent: Option<Instant>,
+    journal: Journal,
+    cancel: Arc<AtomicBool>,
+    /// Folders already cleared of leftovers, see `sweep_leftovers`
+    swept: HashSet<PathBuf>,
+}
+
+/// Number of paths listed when a rollback leaves something behind.
+const MAX_LISTED_FAILURES: usize = 5;
+
+/// What a copy changed at the target, so a failed copy can be undone.
+#[derive(Default)]
+struct Journal {
+    /// Files, links and folders that didn't exist before, in creation order
+    created: Vec<PathBuf>,
+    /// Files that were replaced, and where the original was kept
+    replaced: Vec<(PathBuf, PathBuf)>,
+    /// Files written over in place, with a copy of their old contents and
+    /// their old modification time
+    overwritten: Vec<(PathBuf, PathBuf, Option<SystemTime>)>,
+    /// The file being written, under its temporary name
+    writing: Option<PathBuf>,
+}
+
+impl Journal {
+    /// Create `dir` and its missing parents, noting each one.
+    fn create_dir_all(&mut self, dir: &Path) -> Result<()> {
+        let missing: Vec<&Path> = dir.ancestors().take_while(|a| !a.exists()).collect();
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        self.created
+            .extend(missing.into_iter().rev().map(Path::to_path_buf));
+        Ok(())
+    }
+
+    /// Where to write `dst` before it's complete: a hidden name next to it,
+    /// so an interrupted copy never leaves a half-written file under the
+    /// real name for the next comparison to take for a changed one.
+    fn temp_file(&mut self, dst: &Path) -> PathBuf {
+        let temp = hidden_sibling(dst, "part");
+        self.writing = Some(temp.clone());
+        temp
+    }
+
+    /// Where to write the contents of `dst`: usually a temporary name next
+    /// to it (see `temp_file`), but `dst` itself when it has other hard
+    /// links, which a rename would leave with the old contents, or when its
+    /// folder takes no new names. Its contents are copied aside first then,
+    /// to be put back on a rollback.
+    fn file_target(&mut self, dst: &Path) -> Result<PathBuf> {
+        let existing = fs::symlink_metadata(dst).ok().filter(fs::Metadata::is_file);
+        #[cfg(unix)]
+        let linked = {
+            use std::os::unix::fs::MetadataExt;
+            existing
+                .as_ref()
+                .is_some_and(|metadata| metadata.nlink() > 1)
+        };
+        #[cfg(not(unix))]
+        let linked = false;
+
+        if !linked {
+            let temp = self.temp_file(dst);
+            match fs::File::create(&temp) {
+                Ok(_) => return Ok(temp),
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::PermissionDenied && existing.is_some() =>
+                {
+                    self.writing = None;
+                }
+                Err(e) => return Err(e).with_context(|| format!("creating {}", temp.display())),
+            }
+        }
+        self.back_up(dst)?;
+        Ok(dst.to_path_buf())
+    }
+
+    /// Copy the contents of `dst` aside before it's written over in place:
+    /// next to it if the folder allows, in the temporary directory if not.
+    fn back_up(&mut self, dst: &Path) -> Result<()> {
+        static BACKUPS: AtomicUsize = AtomicUsize::new(0);
+
+        let modified = fs::metadata(dst)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let sibling = hidden_sibling(dst, "old");
+        let backup = match fs::copy(dst, &sibling) {
+            Ok(_) => sibling,
+            Err(_) => {
+                let backup = std::env::temp_dir().join(format!(
+                    "tudiff-{}-{}.old",
+                    std::process::id(),
+                    BACKUPS.fetch_add(1, Ordering::Relaxed)
+                ));
+                fs::copy(dst, &backup).with_context(|| format!("backing up {}", dst.display()))?;
+                backup
+            }
+        };
+        self.overwritten.push((dst.to_path_buf(), backup, modified));
+        Ok(())
+    }
+
+    /// Move the finished `temp` to `dst` in one rename. An existing file
+    /// is kept under another name first, so it can be put back: linked
+    /// where possible, so `dst` never goes missing, moved aside otherwise.
+    fn put_in_place(&mut self, temp: &Path, dst: &Path) -> Result<()> {
+        // Written in place, see `file_target`
+        if temp == dst {
+            return Ok(());
+        }
+        match fs::symlink_metadata(dst) {
+            // The rename will fail, and the folder isn't ours to remove
+            Ok(existing) if existing.is_dir() => {}
+            Ok(_) => {
+                let backup = hidden_sibling(dst, "old");
+                if fs::hard_link(dst, &backup).is_err() {
+                    fs::rename(dst, &backup)
+                        .with_context(|| format!("moving {} aside", dst.display()))?;
+                }
+                self.replaced.push((dst.to_path_buf(), backup));
+            }
+            Err(_) => {}
+        }
+        fs::rename(temp, dst)
+            .with_context(|| format!("renaming {} to {}", temp.display(), dst.display()))?;
+        self.writing = None;
+        self.created.push(dst.to_path_buf());
+        Ok(())
+    }
+
+    /// The copy went through: drop the replaced originals.
+    fn commit(&mut self) {
+        let overwritten = self.overwritten.drain(..).map(|(_, backup, _)| backup);
+        for backup in self
+            .replaced
+            .drain(..)
+            .map(|(_, backup)| backup)
+            .chain(overwritten)
+        {
+            if let Err(e) = fs::remove_file(&backup) {
+                crate::utils::log_error(&format!("Failed to remove {}: {}", backup.display(), e));
+            }
+        }
+        self.created.clear();
+    }
+
+    /// Remove everything the copy created and put the replaced files back,
+    /// and describe how that went.
+    fn roll_back(&mut self) -> String {
+        let mut failures = Vec::new();
+        let (mut removed, mut restored) = (0, 0);
+
+        if let Some(temp) = self.writing.take() {
+            if let Err(e) = fs::remove_file(&temp) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    failures.push(format!("{} ({})", temp.display(), e));
+                }
+            }
+        }
+
+        for path in self.created.drain(..).rev() {
+            let result = match fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir(&path),
+                Ok(_) => fs::remove_file(&path),
+                // Never got written
+                Err(_) => continue,
+            };
+            match result {
+                Ok(()) => removed += 1,
+                Err(e) => failures.push(format!("{} ({})", path.display(), e)),
+            }
+        }
+        for (path, backup) in self.replaced.drain(..).rev() {
+            match fs::rename(&backup, &path) {
+                Ok(()) => restored += 1,
+                Err(e) => failures.push(format!(
+                    "{} (original left at {}: {})",
+                    path.display(),
+                    backup.display(),
+                    e
+                )),
+            }
+        }
+
+        for (path, backup, modified) in self.overwritten.drain(..).rev() {
+            match restore_contents(&backup, &path, modified) {
+                Ok(()) => {
+                    restored += 1;
+                    let _ = fs::remove_file(&backup);
+                }
+                Err(e) => failures.push(format!(
+                    "{} (original left at {}: {})",
+                    path.display(),
+                    backup.display(),
+                    e
+                )),
+            }
+        }
+
+        let mut report = if removed + restored == 0 && failures.is_empty() {
+            "Nothing had been written yet.".to_string()
+        } else {
+            format!(
+                "Rolled back: removed {} new entries, restored {} replaced files.",
+                removed, restored
+            )
+        };
+        if !failures.is_empty() {
+            report.push_str(&format!("\nCould not undo {}:", failures.len()));
+            for failure in failures.iter().take(MAX_LISTED_FAILURES) {
+                report.push_str(&format!("\n{}", failure));
+            }
+            if failures.len() > MAX_LISTED_FAILURES {
+                report.push_str(&format!(
+                    "\n...and {} more",
+                    failures.len() - MAX_LISTED_FAILURES
+                ));
+            }
+        }
+        report
+    }
+}
+
+impl Copier {
+    fn copy_entry(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        if let Some(parent) = dst.parent() {
+            self.journal.create_dir_all(parent)?;
+        }
+        self.copy_path(src, dst, fs::symlink_metadata(src)?.file_type())
+    }
+
+    fn copy_entries(&mut self, src: &Path, dst: &Path, entries: &[PathBuf]) -> Result<()> {
+        for entry in entries {
+            let (from, to) = (src.join(entry), dst.join(entry));
+            if let Some(parent) = to.parent() {
+                self.journal.create_dir_all(parent)?;
+            }
+            self.copy_path(&from, &to, fs::symlink_metadata(&from)?.file_type())?;
+        }
+
+        // The folders on the way were written into, so restore their times;
+        // sorted in reverse, every folder comes before its parent
+        let mut folders: Vec<&Path> = entries
+            .iter()
+            .flat_map(|entry| entry.ancestors().skip(1))
+            .collect();
+        folders.sort_unstable();
+        folders.dedup();
+        for folder in folders.into_iter().rev() {
+            let target = dst.join(folder);
+            preserve_dir_attributes(&fs::metadata(src.join(folder))?, &target)
+                .with_context(|| format!("setting attributes of {}", target.display()))?;
+        }
+        Ok(())
+    }
+
+    fn copy_path(&mut self, src: &Path, dst: &Path, file_type: fs::FileType) -> Result<()> {
+        self.check_cancelled()?;
+        if file_type.is_dir() {
+            return self.copy_dir(src, dst);
+        }
+
+        if let Some(parent) = dst.parent() {
+            self.sweep_leftovers(parent);
+        }
+        if self.policy != OverwritePolicy::Overwrite {
+            let metadata = fs::symlink_metadata(src)?;
+            if !self.should_replace(&metadata, dst) {
+                self.skip_file(src, &metadata);
+                return Ok(());
+            }
+        }
+
+        if file_type.is_symlink() {
+            self.copy_symlink(src, dst)
+                .with_context(|| format!("copying link {}", src.display()))
+        } else {
+            self.copy_file(src, dst)
+                .with_context(|| format!("copying {}", src.display()))
+        }
+    }
+
+    /// Remove what killed copies left in `dir`: files of ours whose writer
+    /// is gone, and rsync's partial files, which only rsync could resume.
+    /// Their backups of replaced files are reported instead, to be
+    /// restored or kept. Once per folder and copy; failures are only logged.
+    fn sweep_leftovers(&mut self, dir: &Path) {
+        if !self.swept.insert(dir.to_path_buf()) {
+            return;
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let path = entry.path();
+            let removed = if name == RSYNC_PARTIAL_DIR {
+                fs::remove_dir_all(&path)
+            } else {
+                match parse_leftover(&name) {
+                    Some((_, pid, suffix)) if !process_running(pid) => {
+                        if suffix == "old" {
+                            let _ = self.tx.send(CopyMessage::StaleBackup(path));
+                            continue;
+                        }
+                        fs::remove_file(&path)
+                    }
+                    _ => continue,
+                }
+            };
+            match removed {
+                Ok(()) => crate::utils::log_info(&format!("Removed leftover {}", path.display())),
+                Err(e) => crate::utils::log_error(&format!(
+                    "Failed to remove leftover {}: {}",
+                    path.display(),
+                    e
+                )),
+            }
+        }
+    }
+
+    fn copy_dir(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.journal.create_dir_all(dst)?;
+
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            if is_leftover(&entry.file_name()) {
+                continue;
+            }
+            self.copy_path(
+                &entry.path(),
+                &dst.join(entry.file_name()),
+                entry.file_type()?,
+            )?;
+        }
+
+        // Only now, or copying the contents would bump the mtime again and a
+        // read-only folder would refuse them
+        preserve_dir_attributes(&fs::metadata(src)?, dst)
+            .with_context(|| format!("setting attributes of {}", dst.display()))
+    }
+
+    fn copy_file(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let mut reader = fs::File::open(src)?;
+        let metadata = reader.metadata()?;
+
+        self.start_file(src, metadata.len());
+        let temp = self.journal.file_target(dst)?;
+        let writer = self.copy_contents(src, &temp, &mut reader, metadata.len())?;
+
+        // Through the open handle, which works even for read-only sources
+        preserve_file_attributes(&writer, &metadata)?;
+        drop(writer);
+        self.journal.put_in_place(&temp, dst)?;
+        self.finish_file();
+        Ok(())
+    }
+
+    /// Write `dst` with the contents of `reader` and return it opened.
+    /// std's copy clones on APFS and falls back to copyfile(3), which can't
+    /// be held to `--bwlimit`.
+    #[cfg(target_os = "macos")]
+    fn copy_contents(
+        &mut self,
+        src: &Path,
+        dst: &Path,
+        reader: &mut fs::File,
+        len: u64,
+    ) -> Result<fs::File> {
+        if crate::throttle::limit().is_some() {
+            let mut writer = fs::File::create(dst)?;
+            self.copy_buffered(reader, &mut writer)?;
+            return Ok(writer);
+        }
+        fs::copy(src, dst)?;
+        self.advance(len);
+        Ok(fs::File::open(dst)?)
+    }
+
+    /// Write `dst` with the contents of `reader` and return it opened.
+    /// Clones or copies inside the kernel where the filesystem allows and
+    /// streams through a buffer otherwise.
+    #[cfg(not(target_os = "macos"))]
+    fn copy_contents(
+        &mut self,
+        _src: &Path,
+        dst: &Path,
+        reader: &mut fs::File,
+        len: u64,
+    ) -> Result<fs::File> {
+        // Overwrite like fs::copy
+        let mut writer = fs::File::create(dst)?;
+
+        #[cfg(target_os = "linux")]
+        {
+            // Shares the extents on btrfs and XFS: instant and no extra space,
+            // and nothing to hold to `--bwlimit` since no data moves
+            if rustix::fs::ioctl_ficlone(&writer, &*reader).is_ok() {
+                self.advance(len);
+                return Ok(writer);
+            }
+            if self.copy_file_range(reader, &writer, len)? {
+                return Ok(writer);
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = len;
+
+        self.copy_buffered(reader, &mut writer)?;
+        Ok(writer)
+    }
+
+    /// Let the kernel copy without a round trip through user space. Returns
+    /// `false`, before anything was written, when the filesystems involved
+    /// don't support it.
+    #[cfg(target_os = "linux")]
+    fn copy_file_range(&mut self, reader: &fs::File, writer: &fs::File, len: u64) -> Result<bool> {
+        use rustix::io::Errno;
+
+        // Throttled copies go in steps small enough to pace evenly
+        let chunk = match crate::throttle::limit() {
+            Some(_) => BUFFER_SIZE,
+            None => KERNEL_CHUNK_SIZE,
+        };
+        let mut copied = 0;
+        loop {
+            match rustix::fs::copy_file_range(reader, None, writer, None, chunk) {
+                // Some pseudo filesystems report 0 for files that have contents
+                Ok(0) if copied == 0 && len > 0 => return Ok(false),
+                Ok(0) => return Ok(true),
+                Ok(n) => {
+                    copied += n as u64;
+                    crate::throttle::consume(n as u64, Some(&self.cancel));
+                    self.advance(n as u64);
+                    self.check_cancelled()?;
+                }
+                Err(Errno::INTR) => continue,
+                Err(Errno::XDEV | Errno::NOSYS | Errno::INVAL | Errno::OPNOTSUPP | Errno::PERM)
+                    if copied == 0 =>
+                {
+                    return Ok(false)
+                }
+                Err(e) => return Err(std::io::Error::from(e).into()),
+            }
+        }
+    }
+
+    fn copy_buffered(&mut self, reader: &mut fs::File, writer: &mut fs::File) -> Result<()> {
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            writer.write_all(&buffer[..read])?;
+            crate::throttle::consume(read as u64, Some(&self.cancel));
+            self.advance(read as u64);
+            self.check_cancelled()?;
+        }
+    }
+
+    /// Recreate the link itself rather than copying what it points to.
+    #[cfg(unix)]
+    fn copy_symlink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let metadata = fs::symlink_metadata(src)?;
+        let link_target = fs::read_link(src)?;
+        self.start_file(src, 0);
+
+        let temp = self.journal.temp_file(dst);
+        std::os::unix::fs::symlink(&link_target, &temp)?;
+        // Links have no permissions of their own and std can't set their times
+        if running_as_root() {
+            std::os::unix::fs::lchown(&temp, Some(metadata.uid()), Some(metadata.gid()))?;
+        }
+        self.journal.put_in_place(&temp, dst)?;
+
+        self.finish_file();
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn copy_symlink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.copy_file(src, dst)
+    }
+
+    fn should_replace(&self, metadata: &fs::Metadata, dst: &Path) -> bool {
+        self.policy.allows(metadata, dst, self.mtime_tolerance)
+    }
+
+    fn skip_file(&mut self, src: &Path, metadata: &fs::Metadata) {
+        self.progress.current_file = src.strip_prefix(&self.root).unwrap_or(src).to_path_buf();
+        if metadata.is_file() {
+            self.progress.skipped_bytes += metadata.len();
+        }
+        self.progress.files_skipped += 1;
+        self.send(false);
+    }
+
+    /// Hand the whole job to rsync and follow its `--info=progress2` output.
+    fn rsync(&mut self, job: &CopyJob) -> Result<()> {
+        use std::process::{Command, Stdio};
+
+        let is_dir = fs::symlink_metadata(&job.source)?.is_dir();
+        if let Some(parent) = job.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut command = Command::new("rsync");
+        // Unbuffered, since progress lines end in \r rather than \n
+        command.args([
+            "--archive",
+            "--sparse",
+            // Kept out of sight, not under the real name, and resumed from
+            "--partial-dir=.rsync-partial",
+            // Our own leftovers, see `is_leftover`
+            "--exclude=.*.tudiff-*.part",
+            "--exclude=.*.tudiff-*.old",
+            "--info=name1,progress2",
+            "--outbuf=N",
+        ]);
+        match self.policy {
+            OverwritePolicy::Overwrite => {}
+            OverwritePolicy::SkipExisting => {
+                command.arg("--ignore-existing");
+            }
+            OverwritePolicy::OnlyNewer => {
+                command.arg("--update");
+            }
+        }
+        if let Some(limit) = crate::throttle::limit() {
+            // In whole KiB per second
+            command.arg(format!("--bwlimit={}", limit.div_ceil(1024)));
+        }
+        if !self.mtime_tolerance.is_zero() {
+            // rsync only takes whole seconds
+            let seconds = self.mtime_tolerance.as_secs_f64().ceil() as u64;
+            command.arg(format!("--modify-window={}", seconds));
+        }
+        if job.entries.is_some() {
+            // --files-from turns off the recursion of --archive, but the list
+            // names whole folders
+            command.args(["--files-from=-", "--from0", "--recursive"]);
+        }
+        command.arg("--");
+        if is_dir {
+            // With trailing slashes rsync copies the folder's contents into
+            // the target instead of nesting the folder inside it
+            command.arg(with_trailing_slash(&job.source));
+            command.arg(with_trailing_slash(&job.target));
+        } else {
+            command.arg(&job.source).arg(&job.target);
+        }
+
+        let mut child = command
+            .stdin(if job.entries.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("starting rsync, is it installed?")?;
+
+        // On threads, so neither pipe can fill up and stall rsync
+        let stdin = child.stdin.take();
+        let entries = job.entries.clone().unwrap_or_default();
+        let writer = thread::spawn(move || -> std::io::Result<()> {
+            if let Some(mut stdin) = stdin {
+                for entry in entries {
+                    stdin.write_all(entry.as_os_str().as_encoded_bytes())?;
+                    stdin.write_all(b"\0")?;
+                }
+            }
+            Ok(())
+        });
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let errors = thread::spawn(move || {
+            let mut errors = String::new();
+            let _ = stderr.read_to_string(&mut errors);
+            errors
+        });
+
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let mut buffer = vec![0; BUFFER_SIZE];
+        let mut line = Vec::new();
+        // Bytes copied before the current file, for the per-file gauge
+        let mut file_start = 0;
+        loop {
+            if self.cancel.load(Ordering::Relaxed) {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("copy cancelled");
+            }
+            let read = stdout.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            for &byte in &buffer[..read] {
+                if byte != b'\r' && byte != b'\n' {
+                    line.push(byte);
+                    continue;
+                }
+                let text = String::from_utf8_lossy(&line);
+                if let Some((copied, transferred)) = parse_progress2(&text) {
+                    self.progress.copied_bytes = copied;
+                    self.progress.file_bytes = copied
+                        .saturating_sub(file_start)
+                        .min(self.progress.file_total);
+                    if let Some(transferred) = transferred {
+                        self.progress.files_done = transferred;
+                    }
+                    self.send(false);
+                } else if !text.trim().is_empty() {
+                    // A name, relative to the source folder
+                    let src = if is_dir {
+                        job.source.join(text.as_ref())
+                    } else {
+                        job.source.clone()
+                    };
+                    if let Ok(metadata) = fs::symlink_metadata(&src) {
+                        if metadata.is_file() {
+                            file_start = self.progress.copied_bytes;
+                            self.start_file(&src, metadata.len());
+                        }
+                    }
+                }
+                line.clear();
+            }
+        }
+
+        let status = child.wait()?;
+        let errors = errors.join().unwrap_or_default();
+        // rsync exiting early breaks the pipe, which its status explains better
+        let written = writer.join();
+        if !status.success() {
+            match errors.trim() {
+                "" => bail!("rsync failed ({})", status),
+                errors => bail!("rsync failed ({}): {}", status, errors),
+            }
+        }
+        if let Ok(Err(e)) = written {
+            return Err(e).context("passing the file list to rsync");
+        }
+        self.send(true);
+        Ok(())
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancel.load(Ordering::Relaxed) {
+            bail!("copy cancelled");
+        }
+        Ok(())
+    }
+
+    fn start_file(&mut self, src: &Path, file_total: u64) {
+        self.progress.current_file = src.strip_prefix(&self.root).unwrap_or(src).to_path_buf();
+        self.progress.file_bytes = 0;
+        self.progress.file_total = file_total;
+        self.send(true);
+    }
+
+    fn advance(&mut self, bytes: u64) {
+        self.progress.file_bytes += bytes;
+        self.progress.copied_bytes += bytes;
+        self.send(false);
+    }
+
+    fn finish_file(&mut self) {
+        self.progress.files_done += 1;
+        self.send(true);
+    }
+
+    fn send(&mut self, force: bool) {
+        let now = Instant::now();
+        if !force
+            && self
+                .last_sent
+                .is_some_and(|last| now - last < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        self.last_sent = Some(now);
+
+        let elapsed = (now - self.started).as_secs_f64();
+        self.progress.bytes_per_sec = if elapsed > 0.0 {
+            self.progress.copied_bytes as f64 / elapsed
+        } else {
+            0.0
+        };
+        let _ = self.tx.send(CopyMessage::Progress(self.progress.clone()));
+    }
+}
+
+fn with_trailing_slash(path: &Path) -> std::ffi::OsString {
+    let mut path = path.as_os_str().to_owned();
+    path.push("/");
+    path
+}
+
+/// Parse a progress line like `1,234,567  45%  12.34MB/s  0:00:01 (xfr#3,
+/// to-chk=5/10)` into the bytes copied so far and, once known, the number
+/// of files transferred.
+fn parse_progress2(line: &str) -> Option<(u64, Option<usize>)> {
+    let mut fields = line.split_whitespace();
+    let bytes = fields.next()?;
+    if !bytes.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    // Thousands separators depend on the locale
+    let bytes = bytes
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()
+        .ok()?;
+    fields.next()?.strip_suffix('%')?.parse::<u32>().ok()?;
+
+    let transferred = line.split_once("xfr#").and_then(|(_, rest)| {
+        rest.split(|c: char| !c.is_ascii_digit())
+            .next()?
+            .parse()
+            .ok()
+    });
+    Some((bytes, transferred))
+}
+
+/// Give `dst` the source's mtime and permissions and, when running as root,
+/// its owner and group.
+fn preserve_file_attributes(dst: &fs::File, metadata: &fs::Metadata) -> Result<()> {
+    dst.set_modified(metadata.modified()?)?;
+    // Before the permissions: changing the owner clears setuid and setgid
+    #[cfg(unix)]
+    if running_as_root() {
+        use std::os::unix::fs::MetadataExt;
+        std::os::unix::fs::fchown(dst, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    dst.set_permissions(metadata.permissions())?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn preserve_dir_attributes(metadata: &fs::Metadata, dst: &Path) -> Result<()> {
+    preserve_file_attributes(&fs::File::open(dst)?, metadata)
+}
+
+/// Folders can't be opened as files everywhere, so only carry over what
+/// works by path.
+#[cfg(not(unix))]
+fn preserve_dir_attributes(metadata: &fs::Metadata, dst: &Path) -> Result<()> {
+    fs::set_permissions(dst, metadata.permissions())?;
+    Ok(())
+}
+
+/// Write the contents of `backup` back into `path`, keeping its inode and
+/// so its hard links, and give it its old modification time again.
+fn restore_contents(backup: &Path, path: &Path, modified: Option<SystemTime>) -> Result<()> {
+    fs::copy(backup, path)?;
+    if let Some(modified) = modified {
+        fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)?;
+    }
+    Ok(())
+}
+
+/// `.name.tudiff-<pid>.<suffix>` next to `path`, from the raw name: a lossy
+/// one could collide.
+fn hidden_sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".tudiff-{}.{}", std::process::id(), suffix));
+    path.with_file_name(name)
+}
+
+/// rsync's `--partial-dir`, relative to each folder it writes into.
+const RSYNC_PARTIAL_DIR: &str = ".rsync-partial";
+
+/// Whether `name` was left behind by a killed copy: a file written under
+/// its temporary name (see `Journal::temp_file`), the backup of a file it
+/// replaced (see `Journal::back_up` and `Journal::put_in_place`) or
+/// rsync's partial folder. Comparisons and copies leave these out.
+pub fn is_leftover(name: &OsStr) -> bool {
+    name == RSYNC_PARTIAL_DIR || parse_leftover(name).is_some()
+}
+
+/// The parts of a `.name.tudiff-<pid>.<suffix>` file name from
+/// `hidden_sibling`: the raw name it stands for, the process that wrote it
+/// and `part` or `old`.
+fn parse_leftover(name: &OsStr) -> Option<(&[u8], u32, &'static str)> {
+    const MARK: &[u8] = b".tudiff-";
+    let rest = name.as_encoded_bytes().strip_prefix(b".")?;
+    let (rest, suffix) = match rest.strip_suffix(b".part") {
+        Some(rest) => (rest, "part"),
+        None => (rest.strip_suffix(b".old")?, "old"),
+    };
+    let at = rest
+        .windows(MARK.len())
+        .rposition(|window| window == MARK)?;
+    let pid = std::str::from_utf8(&rest[at + MARK.len()..])
+        .ok()?
+        .parse()
+        .ok()?;
+    Some((&rest[..at], pid, suffix))
+}
+
+/// Put a backup reported by `CopyMessage::StaleBackup` back under the name
+/// it was taken of, replacing whatever a killed copy left there. Returns
+/// that path.
+pub fn restore_backup(backup: &Path) -> Result<PathBuf> {
+    let name = backup.file_name().unwrap_or_default();
+    let Some((original, _, "old")) = parse_leftover(name) else {
+        bail!("{} isn't a backup", backup.display());
+    };
+    let original = backup.with_file_name(crate::utils::path_from_bytes(original));
+    fs::rename(backup, &original)
+        .with_context(|| format!("renaming {} to {}", backup.display(), original.display()))?;
+    // Renaming a hard link over another link to the same file does nothing,
+    // and a backup linked to the original is still there
+    if fs::symlink_metadata(backup).is_ok() {
+        fs::remove_file(backup).with_context(|| format!("removing {}", backup.display()))?;
+    }
+    Ok(original)
+}
+
+/// Whether `pid` may still be writing. Without a way to tell, it is
+/// assumed to be, so nothing in use is removed.
+fn process_running(pid: u32) -> bool {
+    if pid == std::process::id() {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    return Path::new("/proc").join(pid.to_string()).exists();
+    #[cfg(all(unix, not(target_os = "linux")))]
+    return std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(true, |status| status.success());
+    #[cfg(not(unix))]
+    true
+}
+
+/// Only root may hand files to other users, so ownership is preserved only
+/// then; everyone else ends up owning their copies.
+#[cfg(unix)]
+fn running_as_root() -> bool {
+    users::get_effective_uid() == 0
+}
@@ -0,0 +1,217 @@
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::compare::{CompareOptions, DiffEntry, FileStatus, Meta};
+use crate::index::SETTLE_TIME;
+
+/// SHA-256 of one side of a folder's listing.
+pub type FolderHash = [u8; 32];
+
+/// Merkle-style hashes of every folder on both sides of a comparison. A
+/// folder's hash covers the name, size, modification time and mode of each
+/// file in it and the hash of each subfolder, so an equal hash means nothing
+/// below the folder changed, without looking at any of it. The files found
+/// different are kept alongside, so the next scan can take the results of
+/// unchanged folders from here.
+#[derive(Debug, Clone, Default)]
+pub struct FolderDigests {
+    fingerprint: String,
+    folders: HashMap<PathBuf, (Option<FolderHash>, Option<FolderHash>)>,
+    unsettled: HashSet<PathBuf>,
+    different: HashSet<PathBuf>,
+}
+
+/// The options that change how files are compared; the results of a scan
+/// with other ones can't be carried over.
+fn fingerprint(options: &CompareOptions) -> String {
+    format!(
+        "{:?} {} {} {:?} {}",
+        options.mtime_tolerance,
+        options.compare_permissions,
+        options.ignore_comments,
+        options.normalizers,
+        options.metadata_only
+    )
+}
+
+/// The first bytes of a hash in hex, enough to tell listings apart by eye.
+pub fn short_hex(hash: &FolderHash) -> String {
+    hash[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl FolderDigests {
+    /// The left and right hash of a folder, `None` on a side without it.
+    pub fn get(&self, dir: &Path) -> Option<(Option<FolderHash>, Option<FolderHash>)> {
+        self.folders.get(dir).copied()
+    }
+
+    /// The status a file on both sides had when these were taken.
+    pub fn file_status(&self, path: &Path) -> FileStatus {
+        if self.different.contains(path) {
+            FileStatus::Different
+        } else {
+            FileStatus::Same
+        }
+    }
+
+    /// Keep a file's status in step when it was compared again since.
+    pub fn set_file_status(&mut self, path: &Path, status: FileStatus) {
+        if status == FileStatus::Different {
+            self.different.insert(path.to_path_buf());
+        } else {
+            self.different.remove(path);
+        }
+    }
+
+    /// The folders present on both sides whose hashes are the same as in
+    /// `previous`. Folders holding a file modified within the last moments
+    /// are left out: a change in the same mtime tick wouldn't show.
+    pub fn unchanged_since(&self, previous: &FolderDigests) -> HashSet<PathBuf> {
+        if self.fingerprint != previous.fingerprint {
+            return HashSet::new();
+        }
+        self.folders
+            .iter()
+            .filter(|(dir, hashes)| {
+                matches!(hashes, (Some(_), Some(_)))
+                    && previous.folders.get(*dir) == Some(*hashes)
+                    && !self.unsettled.contains(*dir)
+            })
+            .map(|(dir, _)| dir.clone())
+            .collect()
+    }
+}
+
+/// A folder whose entries are still arriving.
+struct OpenFolder {
+    path: PathBuf,
+    left: Option<Sha256>,
+    right: Option<Sha256>,
+    settled: bool,
+}
+
+/// Builds the folder hashes from the compared paths, which must arrive in
+/// path order so a folder is finished once a path outside it shows up.
+pub struct DigestBuilder {
+    fingerprint: String,
+    settled_before: SystemTime,
+    open: Vec<OpenFolder>,
+    digests: FolderDigests,
+}
+
+impl DigestBuilder {
+    pub fn new(options: &CompareOptions) -> Self {
+        Self {
+            fingerprint: fingerprint(options),
+            settled_before: SystemTime::now() - SETTLE_TIME,
+            open: vec![OpenFolder {
+                path: PathBuf::new(),
+                left: Some(Sha256::new()),
+                right: Some(Sha256::new()),
+                settled: true,
+            }],
+            digests: FolderDigests::default(),
+        }
+    }
+
+    /// Add a path below the roots with the metadata of each side.
+    pub fn push(&mut self, path: &Path, left: Option<Meta>, right: Option<Meta>) {
+        while self.open.len() > 1 && !path.starts_with(&self.open[self.open.len() - 1].path) {
+            self.close_last();
+        }
+
+        let name = path.file_name().unwrap_or_default().as_encoded_bytes();
+        let settled_before = self.settled_before;
+        let Some(parent) = self.open.last_mut() else {
+            return;
+        };
+        for (meta, hasher) in [(left, &mut parent.left), (right, &mut parent.right)] {
+            let (Some(meta), Some(hasher)) = (meta, hasher.as_mut()) else {
+                continue;
+            };
+            if meta.is_dir {
+                continue; // Added with its hash once it's complete
+            }
+            let modified = meta
+                .modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_nanos());
+            hasher.update(name);
+            hasher.update(b"\0f");
+            hasher.update(meta.size.unwrap_or(0).to_le_bytes());
+            hasher.update(modified.to_le_bytes());
+            hasher.update(meta.mode.unwrap_or(0).to_le_bytes());
+            if meta
+                .modified
+                .is_none_or(|modified| modified > settled_before)
+            {
+                parent.settled = false;
+            }
+        }
+
+        let is_dir = |meta: Option<Meta>| meta.is_some_and(|meta| meta.is_dir);
+        if is_dir(left) || is_dir(right) {
+            self.open.push(OpenFolder {
+                path: path.to_path_buf(),
+                left: is_dir(left).then(Sha256::new),
+                right: is_dir(right).then(Sha256::new),
+                settled: true,
+            });
+        }
+    }
+
+    /// Add a compared path, noting the files that differ.
+    pub fn push_entry(&mut self, entry: &DiffEntry) {
+        self.push(&entry.rel_path, entry.left, entry.right);
+        let is_dir = entry.left.or(entry.right).is_some_and(|meta| meta.is_dir);
+        if entry.status == FileStatus::Different && !is_dir {
+            self.digests.different.insert(entry.rel_path.clone());
+        }
+    }
+
+    /// Finish the innermost open folder and add it to its parent.
+    fn close_last(&mut self) {
+        let Some(folder) = self.open.pop() else {
+            return;
+        };
+        let left = folder
+            .left
+            .map(|hasher| FolderHash::from(hasher.finalize()));
+        let right = folder
+            .right
+            .map(|hasher| FolderHash::from(hasher.finalize()));
+        if let Some(parent) = self.open.last_mut() {
+            let name = folder
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .as_encoded_bytes();
+            for (hash, hasher) in [(left, &mut parent.left), (right, &mut parent.right)] {
+                if let (Some(hash), Some(hasher)) = (hash, hasher.as_mut()) {
+                    hasher.update(name);
+                    hasher.update(b"\0d");
+                    hasher.update(hash);
+                }
+            }
+            parent.settled &= folder.settled;
+        }
+        if !folder.settled {
+            self.digests.unsettled.insert(folder.path.clone());
+        }
+        self.digests.folders.insert(folder.path, (left, right));
+    }
+
+    /// The hashes of every folder, the roots included under an empty path.
+    pub fn finish(mut self) -> FolderDigests {
+        while !self.open.is_empty() {
+            self.close_last();
+        }
+        self.digests.fingerprint = self.fingerprint;
+        self.digests
+    }
+}
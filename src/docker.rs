@@ -0,0 +1,106 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::utils::ExitCleanup;
+
+static EXPORTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether `path` is a `docker://container:/path` spec rather than a local path.
+pub fn is_docker(path: &Path) -> bool {
+    path.to_string_lossy().starts_with("docker://")
+}
+
+/// A directory copied out of a container or image into a temporary
+/// directory, which is removed again when this is dropped or tudiff is
+/// interrupted.
+pub struct Export {
+    /// The copy of the requested directory
+    pub dir: PathBuf,
+    _cleanup: ExitCleanup,
+}
+
+fn remove_temp(temp: &Path) {
+    if let Err(e) = fs::remove_dir_all(temp) {
+        crate::utils::log_error(&format!("Failed to remove {}: {}", temp.display(), e));
+    }
+}
+
+fn remove_container(container: &str) {
+    if let Err(e) = docker(&["rm", container]) {
+        crate::utils::log_error(&format!("Failed to remove container {}: {}", container, e));
+    }
+}
+
+/// Copy the directory named by `docker://container:/path` (or
+/// `docker://image:tag:/path`) out with `docker cp`. Images are copied from
+/// a container created for the purpose and removed right after.
+pub fn export(spec: &Path) -> Result<Export> {
+    let spec = spec.to_string_lossy();
+    let Some((target, path)) = spec
+        .strip_prefix("docker://")
+        .and_then(|rest| rest.rsplit_once(":/"))
+    else {
+        bail!("expected docker://container:/path, got `{}`", spec);
+    };
+    if target.is_empty() {
+        bail!("`{}` names no container or image", spec);
+    }
+    let path = format!("/{}", path.trim_end_matches('/'));
+
+    let temp = std::env::temp_dir().join(format!(
+        "tudiff-docker-{}-{}",
+        std::process::id(),
+        EXPORTS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&temp).with_context(|| format!("creating {}", temp.display()))?;
+    let name = Path::new(&path)
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(|| target.replace(['/', ':'], "_").into());
+    let export = Export {
+        dir: temp.join(name),
+        _cleanup: ExitCleanup::new(move || remove_temp(&temp)),
+    };
+
+    let is_container = docker(&["container", "inspect", target]).is_ok();
+    let (container, created) = if is_container {
+        (target.to_string(), None)
+    } else {
+        // The command is never run, but images without one can't be created
+        let container = docker(&["create", target, "true"])
+            .with_context(|| format!("`{}` is neither a container nor an image", target))?;
+        let id = container.clone();
+        let removal = ExitCleanup::new(move || remove_container(&id));
+        (container, Some(removal))
+    };
+
+    let source = format!("{}:{}", container, path);
+    let copied = docker(&["cp", &source, &export.dir.to_string_lossy()]);
+    drop(created);
+    copied?;
+
+    if !export.dir.is_dir() {
+        bail!("`{}` is not a directory in {}", path, target);
+    }
+    Ok(export)
+}
+
+/// Run `docker` with the arguments and return its trimmed output.
+fn docker(args: &[&str]) -> Result<String> {
+    let output = Command::new("docker")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .context("running docker, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "docker {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,154 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+#[cfg(feature = "serde")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "serde")]
+use crate::compare::{CompareOptions, DirectoryComparison};
+
+/// What a scan does with folders and files it isn't allowed to read, like
+/// another user's home or a 0600 config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnreadablePolicy {
+    /// Show them without their contents and list them as needing elevation
+    #[default]
+    Report,
+    /// Read them again through `sudo -n`; the ones it can't read either are
+    /// reported
+    Sudo,
+}
+
+impl UnreadablePolicy {
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "report" => Ok(Self::Report),
+            "sudo" => Ok(Self::Sudo),
+            _ => bail!("expected `report` or `sudo`, got `{}`", value),
+        }
+    }
+}
+
+/// Whether `error`, or anything it was caused by, is a refused permission.
+pub fn is_permission_denied(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == std::io::ErrorKind::PermissionDenied)
+    })
+}
+
+/// Run `command` without a terminal to ask for a password on; sudo fails
+/// right away with `-n` when it would need one. A quiet exit status of 1
+/// passes when `differ_ok`: that's how `cmp` says the files differ.
+fn run(command: &mut Command, differ_ok: bool) -> Result<std::process::Output> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .context("running sudo")?;
+    let differ = differ_ok && output.status.code() == Some(1) && output.stderr.is_empty();
+    if !output.status.success() && !differ {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().last().unwrap_or("failed").trim();
+        bail!("{} (run `sudo -v` first if it needs a password)", reason);
+    }
+    Ok(output)
+}
+
+/// Whether two files have the same bytes, read by `cmp` under `sudo -n`.
+/// Normalizers and `--ignore-comments` don't apply.
+pub fn sudo_files_same(left: &Path, right: &Path) -> Result<bool> {
+    let output = run(
+        Command::new("sudo")
+            .args(["-n", "cmp", "-s", "--"])
+            .arg(left)
+            .arg(right),
+        true,
+    )?;
+    Ok(output.status.success())
+}
+
+#[cfg(feature = "serde")]
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Compare `left` with `right` in a `tudiff --save-result` run under
+/// `sudo -n`, passing on the options that decide what is compared. A side
+/// that doesn't exist is compared as an empty folder. The run reads root's
+/// config file, so its normalizers may differ.
+#[cfg(feature = "serde")]
+pub fn sudo_compare(
+    left: &Path,
+    right: &Path,
+    options: &CompareOptions,
+) -> Result<DirectoryComparison> {
+    // Only we may read the listing of folders we weren't allowed into
+    let temp = std::env::temp_dir().join(format!(
+        "tudiff-sudo-{}-{}",
+        std::process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder
+        .create(&temp)
+        .with_context(|| format!("creating {}", temp.display()))?;
+    let empty = temp.join("empty");
+    let result = std::fs::create_dir(&empty)
+        .with_context(|| format!("creating {}", empty.display()))
+        .and_then(|_| {
+            let side = |dir: &Path| {
+                if dir.is_dir() {
+                    dir.to_path_buf()
+                } else {
+                    empty.clone()
+                }
+            };
+            let output = temp.join("result.json");
+            run(
+                helper_command(&output, options)
+                    .arg("--")
+                    .arg(side(left))
+                    .arg(side(right)),
+                false,
+            )?;
+            crate::saved::load(&output)
+        });
+    let _ = std::fs::remove_dir_all(&temp);
+    result
+}
+
+/// `sudo -n tudiff --save-result output` with `options` as flags.
+#[cfg(feature = "serde")]
+fn helper_command(output: &Path, options: &CompareOptions) -> Command {
+    let exe = std::env::current_exe().unwrap_or_else(|_| "tudiff".into());
+    let mut command = Command::new("sudo");
+    command
+        .arg("-n")
+        .arg(exe)
+        .arg("--save-result")
+        .arg(output)
+        .args(["--quiet", "--max-entries", "0", "--mtime-tolerance"])
+        .arg(options.mtime_tolerance.as_secs_f64().to_string());
+    if let Some(limit) = crate::throttle::limit() {
+        command.arg(format!("--bwlimit={}B", limit));
+    }
+    for pattern in options.filter.exclude.patterns() {
+        command.arg(format!("--exclude={}", pattern));
+    }
+    for pattern in options.filter.include.patterns() {
+        command.arg(format!("--include={}", pattern));
+    }
+    for (set, flag) in [
+        (options.one_file_system, "--one-file-system"),
+        (options.follow_links, "--follow-links"),
+        (options.compare_permissions, "--compare-permissions"),
+        (options.ignore_comments, "--ignore-comments"),
+    ] {
+        if set {
+            command.arg(flag);
+        }
+    }
+    command
+}
@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// How much of a file is read to tell its type; enough for the tar header.
+const HEAD_BYTES: usize = 4096;
+
+/// Leading bytes of common formats, with the short name shown for them.
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x7fELF", "elf"),
+    (b"MZ", "pe"),
+    (b"\xcf\xfa\xed\xfe", "mach-o"),
+    (b"\xce\xfa\xed\xfe", "mach-o"),
+    (b"\x00asm", "wasm"),
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpeg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"II*\x00", "tiff"),
+    (b"MM\x00*", "tiff"),
+    (b"\x00\x00\x01\x00", "ico"),
+    (b"%PDF-", "pdf"),
+    (b"%!PS", "postscript"),
+    (b"{\\rtf", "rtf"),
+    (b"PK\x03\x04", "zip"),
+    (b"PK\x05\x06", "zip"),
+    (b"\x1f\x8b", "gzip"),
+    (b"BZh", "bzip2"),
+    (b"\xfd7zXZ\x00", "xz"),
+    (b"\x28\xb5\x2f\xfd", "zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+    (b"Rar!\x1a\x07", "rar"),
+    (b"SQLite format 3\x00", "sqlite"),
+    (b"OggS", "ogg"),
+    (b"fLaC", "flac"),
+    (b"ID3", "mp3"),
+    (b"\x1aE\xdf\xa3", "matroska"),
+    (b"\xef\xbb\xbf", "utf8 text"),
+    (b"\xff\xfe", "utf16 text"),
+    (b"\xfe\xff", "utf16 text"),
+];
+
+/// Extensions of formats without a reliable signature, for binary files
+/// the leading bytes didn't tell apart.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("bmp", "bmp"),
+    ("class", "java class"),
+    ("o", "object"),
+    ("obj", "object"),
+    ("a", "archive"),
+    ("lib", "archive"),
+    ("so", "elf"),
+    ("dll", "pe"),
+    ("exe", "pe"),
+    ("pyc", "pyc"),
+    ("ttf", "font"),
+    ("otf", "font"),
+    ("woff", "font"),
+    ("woff2", "font"),
+    ("mo", "gettext"),
+    ("iso", "iso image"),
+    ("img", "disk image"),
+];
+
+/// A short name for what kind of file `path` is, like `elf`, `png`,
+/// `utf8 text` or `gzip` (10 characters at most), from its first bytes
+/// and, failing that, its extension; `None` when it can't be read.
+pub fn detect(path: &Path) -> Option<&'static str> {
+    let mut head = Vec::with_capacity(HEAD_BYTES);
+    File::open(path)
+        .ok()?
+        .take(HEAD_BYTES as u64)
+        .read_to_end(&mut head)
+        .ok()?;
+    Some(from_head(&head).unwrap_or_else(|| from_extension(path)))
+}
+
+fn from_head(head: &[u8]) -> Option<&'static str> {
+    if head.is_empty() {
+        return Some("empty");
+    }
+    if let Some((_, name)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(name);
+    }
+    match (head.get(0..4), head.get(4..8), head.get(8..12)) {
+        (Some(b"RIFF"), _, Some(b"WAVE")) => return Some("wav"),
+        (Some(b"RIFF"), _, Some(b"WEBP")) => return Some("webp"),
+        (Some(b"RIFF"), _, Some(b"AVI ")) => return Some("avi"),
+        (_, Some(b"ftyp"), _) => return Some("mp4"),
+        _ => {}
+    }
+    if head.get(257..262) == Some(b"ustar") {
+        return Some("tar");
+    }
+    if head.contains(&0) {
+        return None;
+    }
+    // The read may have stopped in the middle of a character
+    match std::str::from_utf8(head) {
+        Ok(text) if text.is_ascii() => Some("ascii text"),
+        Ok(_) => Some("utf8 text"),
+        Err(e) if e.error_len().is_none() => Some("utf8 text"),
+        Err(_) => None,
+    }
+}
+
+fn from_extension(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    extension
+        .and_then(|extension| {
+            EXTENSIONS
+                .iter()
+                .find(|(known, _)| *known == extension)
+                .map(|(_, name)| *name)
+        })
+        .unwrap_or("data")
+}
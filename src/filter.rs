@@ -0,0 +1,414 @@
+use anyhow::{anyhow, bail, Context, Result};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::fmt;
+use std::path::Path;
+
+use crate::compare::{DiffEntry, FileStatus};
+
+/// A list of rsync-style patterns compiled into glob sets.
+///
+/// - `name` or `*.log` (no slash) matches the final component at any depth
+/// - `dir/name` matches those trailing components at any depth
+/// - `/dir/name` is anchored at the comparison root
+/// - a trailing `/` restricts the pattern to directories
+#[derive(Debug, Clone)]
+pub struct PatternSet {
+    patterns: Vec<String>,
+    any: GlobSet,
+    dirs_only: GlobSet,
+}
+
+impl Default for PatternSet {
+    fn default() -> Self {
+        Self {
+            patterns: Vec::new(),
+            any: GlobSet::empty(),
+            dirs_only: GlobSet::empty(),
+        }
+    }
+}
+
+impl PatternSet {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let mut any = GlobSetBuilder::new();
+        let mut dirs_only = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let (glob, dir_only) = compile_pattern(pattern)?;
+            if dir_only {
+                dirs_only.add(glob);
+            } else {
+                any.add(glob);
+            }
+        }
+
+        Ok(Self {
+            patterns: patterns.to_vec(),
+            any: any.build()?,
+            dirs_only: dirs_only.build()?,
+        })
+    }
+
+    /// Files the OS and editors leave behind that say nothing about the
+    /// folders, hidden with `i`.
+    pub fn noise() -> Self {
+        let patterns: Vec<String> = ["Thumbs.db", ".DS_Store", "*.swp", "*~"]
+            .map(String::from)
+            .to_vec();
+        Self::new(&patterns).expect("noise patterns are valid")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub fn matches(&self, relative_path: &Path, is_dir: bool) -> bool {
+        self.any.is_match(relative_path) || (is_dir && self.dirs_only.is_match(relative_path))
+    }
+}
+
+fn compile_pattern(pattern: &str) -> Result<(Glob, bool)> {
+    let dir_only = pattern.len() > 1 && pattern.ends_with('/');
+    let trimmed = pattern.trim_end_matches('/');
+
+    let glob = match trimmed.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None => format!("**/{}", trimmed),
+    };
+
+    let glob = GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("Invalid pattern '{}'", pattern))?;
+    Ok((glob, dir_only))
+}
+
+/// Include/exclude rules applied while scanning. Excludes always win; when any
+/// include pattern is given, only matching files (and the folders leading to
+/// them) are kept.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    pub include: PatternSet,
+    pub exclude: PatternSet,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        Ok(Self {
+            include: PatternSet::new(include)?,
+            exclude: PatternSet::new(exclude)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Excluded entries are skipped together with everything beneath them,
+    /// and so are the leftovers of killed copies.
+    pub fn is_excluded(&self, relative_path: &Path, is_dir: bool) -> bool {
+        let Some(name) = relative_path.file_name() else {
+            return false;
+        };
+        crate::copy::is_leftover(name) || self.exclude.matches(relative_path, is_dir)
+    }
+
+    /// Whether a (non-excluded) entry should be kept. Directories are always
+    /// walked so includes can match files inside them.
+    pub fn is_included(&self, relative_path: &Path, is_dir: bool) -> bool {
+        is_dir || self.include.is_empty() || self.include.matches(relative_path, is_dir)
+    }
+}
+
+/// What `:filter` shows: predicates combined with `AND`, `OR`, `NOT` and
+/// parentheses, like `status:different AND size>1M AND path:*.log`.
+///
+/// - `status:same`, `status:different`, `status:left`, `status:right` or
+///   `status:orphan` (on one side only)
+/// - `type:file` or `type:dir`
+/// - `size>1M`, also `>=`, `<`, `<=` and `=`, in bytes or with a `K`, `M` or
+///   `G` suffix, against the larger side
+/// - `path:PATTERN`, an rsync-style pattern as in [`PatternSet`] that also
+///   matches everything inside a matching folder
+///
+/// Terms next to each other are ANDed, and a bare pattern means
+/// `path:PATTERN`, except that bare patterns next to each other match any
+/// of them, so `*.rs src/` keeps meaning what it always did.
+#[derive(Debug, Clone)]
+pub struct FilterExpr {
+    source: String,
+    root: Term,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    And(Box<Term>, Box<Term>),
+    Or(Box<Term>, Box<Term>),
+    Not(Box<Term>),
+    Status(&'static [FileStatus]),
+    Dir(bool),
+    Size(SizeOp, u64),
+    Path(PatternSet),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum SizeOp {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl FilterExpr {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source);
+        if tokens.is_empty() {
+            bail!("Empty filter");
+        }
+        let mut parser = Parser { tokens, next: 0 };
+        let root = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected `{}` in filter", token);
+        }
+        Ok(Self {
+            source: source.split_whitespace().collect::<Vec<_>>().join(" "),
+            root,
+        })
+    }
+
+    /// Whether an entry is shown (the folders leading to it aside).
+    pub fn matches(&self, entry: &DiffEntry) -> bool {
+        self.root.matches(entry)
+    }
+}
+
+impl fmt::Display for FilterExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Term {
+    fn matches(&self, entry: &DiffEntry) -> bool {
+        let meta = entry.left.as_ref().or(entry.right.as_ref());
+        match self {
+            Term::And(a, b) => a.matches(entry) && b.matches(entry),
+            Term::Or(a, b) => a.matches(entry) || b.matches(entry),
+            Term::Not(term) => !term.matches(entry),
+            Term::Status(statuses) => statuses.contains(&entry.status),
+            Term::Dir(is_dir) => meta.is_some_and(|meta| meta.is_dir == *is_dir),
+            Term::Size(op, limit) => {
+                let size = [&entry.left, &entry.right]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|meta| meta.size)
+                    .max();
+                size.is_some_and(|size| match op {
+                    SizeOp::Less => size < *limit,
+                    SizeOp::LessOrEqual => size <= *limit,
+                    SizeOp::Equal => size == *limit,
+                    SizeOp::GreaterOrEqual => size >= *limit,
+                    SizeOp::Greater => size > *limit,
+                })
+            }
+            Term::Path(patterns) => {
+                let is_dir = meta.is_some_and(|meta| meta.is_dir);
+                patterns.matches(&entry.rel_path, is_dir)
+                    || entry
+                        .rel_path
+                        .ancestors()
+                        .skip(1)
+                        .any(|folder| patterns.matches(folder, true))
+            }
+        }
+    }
+}
+
+/// Words, with parentheses split off their ends where they group terms:
+/// unmatched ones, and a pair around the whole word. Others belong to the
+/// pattern, like those in `report(1).pdf`.
+fn tokenize(source: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    for word in source.split_whitespace() {
+        let mut core = word;
+        let (mut opening, mut closing) = (0, 0);
+        loop {
+            let excess = core.matches('(').count() as isize - core.matches(')').count() as isize;
+            if excess > 0 && core.starts_with('(') {
+                core = &core[1..];
+                opening += 1;
+            } else if excess < 0 && core.ends_with(')') {
+                core = &core[..core.len() - 1];
+                closing += 1;
+            } else if wraps_whole(core) {
+                core = &core[1..core.len() - 1];
+                opening += 1;
+                closing += 1;
+            } else {
+                break;
+            }
+        }
+        tokens.extend(std::iter::repeat_n("(".to_string(), opening));
+        if !core.is_empty() {
+            tokens.push(core.to_string());
+        }
+        tokens.extend(std::iter::repeat_n(")".to_string(), closing));
+    }
+    tokens
+}
+
+/// Whether `word` is in parentheses that match each other, as in `(a)` but
+/// not `(a)(b)`.
+fn wraps_whole(word: &str) -> bool {
+    if word.len() < 2 || !word.starts_with('(') || !word.ends_with(')') {
+        return false;
+    }
+    let mut depth = 0;
+    for (at, c) in word.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return at == word.len() - 1;
+        }
+    }
+    false
+}
+
+fn is_keyword(token: &str, keyword: &str) -> bool {
+    token.eq_ignore_ascii_case(keyword)
+}
+
+/// Bare words are patterns, anything with a predicate's prefix isn't:
+/// `size` only counts with a comparison right after it, so `sizes.txt`
+/// stays a pattern.
+fn is_bare_pattern(token: &str) -> bool {
+    !["(", ")"].contains(&token)
+        && !["AND", "OR", "NOT"].iter().any(|k| is_keyword(token, k))
+        && !["status:", "type:", "path:"]
+            .iter()
+            .any(|prefix| token.starts_with(prefix))
+        && !token
+            .strip_prefix("size")
+            .is_some_and(|rest| rest.starts_with(['<', '>', '=']))
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    next: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.next).map(String::as_str)
+    }
+
+    fn advance(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Term> {
+        let mut term = self.and()?;
+        while self.peek().is_some_and(|token| is_keyword(token, "OR")) {
+            self.advance();
+            term = Term::Or(Box::new(term), Box::new(self.and()?));
+        }
+        Ok(term)
+    }
+
+    fn and(&mut self) -> Result<Term> {
+        let mut term = self.not()?;
+        loop {
+            match self.peek() {
+                Some(token) if is_keyword(token, "AND") => {
+                    self.advance();
+                }
+                Some(token) if token != ")" && !is_keyword(token, "OR") => {}
+                _ => return Ok(term),
+            }
+            term = Term::And(Box::new(term), Box::new(self.not()?));
+        }
+    }
+
+    fn not(&mut self) -> Result<Term> {
+        if self.peek().is_some_and(|token| is_keyword(token, "NOT")) {
+            self.advance();
+            return Ok(Term::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Term> {
+        let token = self
+            .advance()
+            .ok_or_else(|| anyhow!("Filter ends where a term was expected"))?;
+        if token == "(" {
+            let term = self.or()?;
+            if self.advance().as_deref() != Some(")") {
+                bail!("Missing `)` in filter");
+            }
+            return Ok(term);
+        }
+        if is_bare_pattern(&token) {
+            let mut patterns = vec![token];
+            while let Some(next) = self.peek().filter(|token| is_bare_pattern(token)) {
+                patterns.push(next.to_string());
+                self.advance();
+            }
+            return Ok(Term::Path(PatternSet::new(&patterns)?));
+        }
+        parse_predicate(&token)
+    }
+}
+
+fn parse_predicate(token: &str) -> Result<Term> {
+    if let Some(status) = token.strip_prefix("status:") {
+        let statuses: &'static [FileStatus] = match status {
+            "same" => &[FileStatus::Same],
+            "different" => &[FileStatus::Different],
+            "left" => &[FileStatus::LeftOnly],
+            "right" => &[FileStatus::RightOnly],
+            "orphan" => &[FileStatus::LeftOnly, FileStatus::RightOnly],
+            _ => bail!(
+                "Unknown status `{}` (same, different, left, right or orphan)",
+                status
+            ),
+        };
+        return Ok(Term::Status(statuses));
+    }
+    if let Some(kind) = token.strip_prefix("type:") {
+        return match kind {
+            "file" => Ok(Term::Dir(false)),
+            "dir" => Ok(Term::Dir(true)),
+            _ => bail!("Unknown type `{}` (file or dir)", kind),
+        };
+    }
+    if let Some(pattern) = token.strip_prefix("path:") {
+        return Ok(Term::Path(PatternSet::new(&[pattern.to_string()])?));
+    }
+    if let Some(comparison) = token.strip_prefix("size") {
+        let (op, value) = [
+            (">=", SizeOp::GreaterOrEqual),
+            ("<=", SizeOp::LessOrEqual),
+            (">", SizeOp::Greater),
+            ("<", SizeOp::Less),
+            ("=", SizeOp::Equal),
+        ]
+        .into_iter()
+        .find_map(|(prefix, op)| Some((op, comparison.strip_prefix(prefix)?)))
+        .ok_or_else(|| anyhow!("Expected a comparison like `size>1M`, got `{}`", token))?;
+        let size = crate::utils::parse_size(value, 1)
+            .ok_or_else(|| anyhow!("Expected a size like `500K` or `1M`, got `{}`", value))?;
+        return Ok(Term::Size(op, size));
+    }
+    bail!("Unknown filter term `{}`", token)
+}
@@ -0,0 +1,102 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::utils::ExitCleanup;
+
+static WORKTREES: AtomicUsize = AtomicUsize::new(0);
+
+/// A commit checked out into a temporary worktree, which is removed again
+/// (along with its bookkeeping in the repository) when this is dropped or
+/// tudiff is interrupted.
+pub struct Worktree {
+    /// The checked out tree
+    pub dir: PathBuf,
+    _cleanup: ExitCleanup,
+}
+
+fn remove_worktree(repo: &Path, dir: &Path, temp: &Path) {
+    let path = dir.to_string_lossy();
+    if let Err(e) = git(repo, &["worktree", "remove", "--force", &path]) {
+        crate::utils::log_error(&format!("Failed to remove worktree {}: {}", path, e));
+        let _ = fs::remove_dir_all(dir);
+    }
+    // Also forgets worktrees of earlier runs that were killed outright
+    let _ = git(repo, &["worktree", "prune"]);
+    let _ = fs::remove_dir(temp);
+}
+
+/// Split `A..B` into its two revisions, like `git diff` reads them: a
+/// missing side means `HEAD`, and `A...B` starts from the merge base.
+pub fn split_range(repo: &Path, range: &str) -> Result<(String, String)> {
+    let (from, to, merge_base) = match range.split_once("...") {
+        Some((from, to)) => (from, to, true),
+        None => match range.split_once("..") {
+            Some((from, to)) => (from, to, false),
+            None => bail!("expected a range like A..B, got `{}`", range),
+        },
+    };
+    let or_head = |rev: &str| if rev.is_empty() { "HEAD" } else { rev }.to_string();
+    let (from, to) = (or_head(from), or_head(to));
+    if merge_base {
+        let base = git(repo, &["merge-base", &from, &to])
+            .with_context(|| format!("finding the merge base of {} and {}", from, to))?;
+        // Short, since the worktree (and so the panel) is named after it
+        let base = git(repo, &["rev-parse", "--short", &base])?;
+        return Ok((base, to));
+    }
+    Ok((from, to))
+}
+
+/// Check `rev` of the repository at `repo` out into a detached temporary
+/// worktree named after the revision.
+pub fn checkout(repo: &Path, rev: &str) -> Result<Worktree> {
+    let spec = format!("{}^{{commit}}", rev);
+    let commit = git(repo, &["rev-parse", "--verify", "--quiet", &spec])
+        .with_context(|| format!("`{}` is not a commit in {}", rev, repo.display()))?;
+
+    let temp = std::env::temp_dir().join(format!(
+        "tudiff-git-{}-{}",
+        std::process::id(),
+        WORKTREES.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&temp).with_context(|| format!("creating {}", temp.display()))?;
+    let dir = temp.join(rev.replace(['/', '\\', ':'], "_"));
+
+    // Registered first, so a checkout that is interrupted goes as well
+    let cleanup = {
+        let (repo, dir) = (repo.to_path_buf(), dir.clone());
+        ExitCleanup::new(move || remove_worktree(&repo, &dir, &temp))
+    };
+    let path = dir.to_string_lossy();
+    git(
+        repo,
+        &["worktree", "add", "--detach", "--quiet", &path, &commit],
+    )
+    .with_context(|| format!("checking out {}", rev))?;
+    Ok(Worktree {
+        dir,
+        _cleanup: cleanup,
+    })
+}
+
+/// Run `git -C repo` with the arguments and return its trimmed output.
+fn git(repo: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .context("running git, is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
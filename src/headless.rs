@@ -0,0 +1,160 @@
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::{backend::TestBackend, Terminal};
+use std::time::{Duration, Instant};
+
+use crate::app::{App, FileItem};
+use crate::compare::DirectoryComparison;
+use crate::ui::draw_ui;
+
+/// Drives an [`App`] without a terminal: synthetic key and mouse events go
+/// through the same handlers as in the TUI, and the screen is drawn into an
+/// in-memory buffer after each one, so tests and scripts can check the
+/// resulting lists, state and rendering deterministically.
+///
+/// Enter on a file doesn't launch the external diff tool here.
+pub struct Headless {
+    pub app: App,
+    terminal: Terminal<TestBackend>,
+}
+
+impl Headless {
+    /// Start on `comparison` with a screen of the given size.
+    pub fn new(comparison: DirectoryComparison, width: u16, height: u16) -> Result<Self> {
+        let mut app = App::new(comparison);
+        app.headless = true;
+        let mut headless = Self {
+            app,
+            terminal: Terminal::new(TestBackend::new(width, height))?,
+        };
+        headless.draw()?;
+        Ok(headless)
+    }
+
+    /// Handle a key event like the TUI does. Returns true when the key
+    /// asked the app to quit.
+    pub fn key(&mut self, key: KeyEvent) -> Result<bool> {
+        let quit = self.app.handle_key_event(key)?;
+        self.draw()?;
+        Ok(quit)
+    }
+
+    /// Press a key without modifiers.
+    pub fn press(&mut self, code: KeyCode) -> Result<bool> {
+        self.key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    /// Press each character of `text` in turn, e.g. to fill in a name prompt.
+    pub fn type_text(&mut self, text: &str) -> Result<()> {
+        for c in text.chars() {
+            self.press(KeyCode::Char(c))?;
+        }
+        Ok(())
+    }
+
+    /// Handle a mouse event like the TUI does. Positions refer to the last
+    /// drawn screen.
+    pub fn mouse(&mut self, mouse: MouseEvent) -> Result<()> {
+        if mouse.kind == MouseEventKind::Moved {
+            if self.app.move_mouse(mouse.column, mouse.row) {
+                self.draw()?;
+            }
+        } else {
+            self.app.handle_mouse_event(mouse);
+            self.draw()?;
+        }
+        Ok(())
+    }
+
+    /// Move the mouse pointer to the given cell.
+    pub fn hover(&mut self, column: u16, row: u16) -> Result<()> {
+        self.mouse(MouseEvent {
+            kind: MouseEventKind::Moved,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        })
+    }
+
+    /// Left click at the given cell.
+    pub fn click(&mut self, column: u16, row: u16) -> Result<()> {
+        self.mouse(MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        })
+    }
+
+    /// Wait for a running rescan or copy to finish, at most `timeout`.
+    /// Returns false if it's still running.
+    pub fn settle(&mut self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let refreshed = self.app.check_refresh_progress();
+            let copied = self.app.check_copy_progress();
+            let compared = self.app.check_similarity_progress();
+            let counted = self.app.check_transfer_progress()
+                | self.app.check_hash_progress()
+                | self.app.check_diffstat_progress();
+            if refreshed || copied || compared || counted {
+                self.draw()?;
+            }
+            if !self.app.is_busy() {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    pub fn resize(&mut self, width: u16, height: u16) -> Result<()> {
+        self.terminal.backend_mut().resize(width, height);
+        self.app.resize(width, height);
+        self.draw()
+    }
+
+    /// The last drawn screen as text, one line per row with trailing
+    /// spaces removed.
+    pub fn screen(&self) -> String {
+        let buffer = self.terminal.backend().buffer();
+        let mut screen = String::new();
+        for y in 0..buffer.area.height {
+            let mut line = String::new();
+            let mut skip = 0;
+            for x in 0..buffer.area.width {
+                // Wide characters fill the following cells too
+                if skip > 0 {
+                    skip -= 1;
+                    continue;
+                }
+                let symbol = buffer[(x, y)].symbol();
+                skip = ratatui::text::Span::raw(symbol).width().saturating_sub(1);
+                line.push_str(symbol);
+            }
+            screen.push_str(line.trim_end());
+            screen.push('\n');
+        }
+        screen
+    }
+
+    /// The rows of the left or right panel, placeholders included.
+    pub fn items(&self, left: bool) -> &[FileItem] {
+        if left {
+            &self.app.left_items
+        } else {
+            &self.app.right_items
+        }
+    }
+
+    /// The row selected in the active panel.
+    pub fn selected(&self) -> Option<&FileItem> {
+        self.app.get_selected_item()
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        draw_ui(&mut self.terminal, &mut self.app)
+    }
+}
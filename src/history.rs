@@ -0,0 +1,257 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::app::{FilterMode, ListLayout, PlaceholderRows, SortOrder};
+use crate::utils::path_from_bytes;
+
+/// Number of directory pairs kept in the history file.
+const MAX_ENTRIES: usize = 20;
+
+/// `$XDG_STATE_HOME/tudiff/history`, falling back to `~/.local/state/tudiff/history`.
+pub fn history_file() -> Option<PathBuf> {
+    crate::utils::state_dir().map(|dir| dir.join("history"))
+}
+
+/// Recently compared directory pairs, most recent first.
+pub fn load_recent() -> Vec<(PathBuf, PathBuf)> {
+    let Some(path) = history_file() else {
+        return Vec::new();
+    };
+
+    read_records(&path, 2)
+        .into_iter()
+        .filter_map(|record| match record.as_slice() {
+            [left, right] => Some((path_from_bytes(left), path_from_bytes(right))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Move (or add) the pair to the top of the history file.
+pub fn record(left: &Path, right: &Path) -> Result<()> {
+    let Some(path) = history_file() else {
+        return Ok(());
+    };
+
+    let left = left.canonicalize().unwrap_or_else(|_| left.to_path_buf());
+    let right = right.canonicalize().unwrap_or_else(|_| right.to_path_buf());
+
+    let mut entries = load_recent();
+    entries.retain(|(l, r)| !(l == &left && r == &right));
+    entries.insert(0, (left, right));
+    entries.truncate(MAX_ENTRIES);
+
+    write_records(
+        &path,
+        entries.iter().map(|(l, r)| {
+            vec![
+                l.as_os_str().as_encoded_bytes(),
+                r.as_os_str().as_encoded_bytes(),
+            ]
+        }),
+    )
+}
+
+/// The records of a state file, each `fields` long. Every field ends with
+/// a NUL, the one byte a path can't hold. Files written before that put a
+/// record on each line with tabs between fields, and are still read.
+fn read_records(path: &Path, fields: usize) -> Vec<Vec<Vec<u8>>> {
+    let Ok(content) = fs::read(path) else {
+        return Vec::new();
+    };
+
+    if !content.contains(&0) {
+        return content
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                line.split(|&byte| byte == b'\t')
+                    .map(<[u8]>::to_vec)
+                    .collect()
+            })
+            .collect();
+    }
+
+    let values: Vec<Vec<u8>> = content
+        .split(|&byte| byte == 0)
+        .map(<[u8]>::to_vec)
+        .collect();
+    // The last NUL leaves an empty piece after it
+    values[..values.len() - 1]
+        .chunks_exact(fields)
+        .map(<[Vec<u8>]>::to_vec)
+        .collect()
+}
+
+fn write_records<'a>(path: &Path, records: impl Iterator<Item = Vec<&'a [u8]>>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut content = Vec::new();
+    for field in records.flatten() {
+        content.extend_from_slice(field);
+        content.push(0);
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Number of directory pairs whose display settings are remembered.
+const MAX_VIEWS: usize = 100;
+
+/// Display settings last used on a directory pair.
+#[derive(Clone, Copy, PartialEq)]
+pub struct SavedView {
+    pub filter_mode: FilterMode,
+    pub raw_sizes: bool,
+    pub show_permissions: bool,
+    pub show_owner: bool,
+    pub vertical_layout: bool,
+    pub show_preview: bool,
+    pub show_similarity: bool,
+    pub show_file_type: bool,
+    pub layout: ListLayout,
+    pub sort_order: SortOrder,
+    pub placeholder_rows: PlaceholderRows,
+}
+
+/// `$XDG_STATE_HOME/tudiff/views`, next to the history file.
+pub fn views_file() -> Option<PathBuf> {
+    crate::utils::state_dir().map(|dir| dir.join("views"))
+}
+
+fn load_views() -> Vec<(PathBuf, PathBuf, SavedView)> {
+    let Some(path) = views_file() else {
+        return Vec::new();
+    };
+
+    // Older files may leave out the flags
+    read_records(&path, 4)
+        .into_iter()
+        .filter_map(|record| {
+            let [left, right, filter, rest @ ..] = record.as_slice() else {
+                return None;
+            };
+            let (left, right) = (path_from_bytes(left), path_from_bytes(right));
+            let filter_mode = match filter.as_slice() {
+                b"all" => FilterMode::All,
+                b"different" => FilterMode::Different,
+                b"diff-only" => FilterMode::DifferentNotOrphans,
+                _ => return None,
+            };
+            let flags = String::from_utf8_lossy(rest.first().map_or(&[][..], Vec::as_slice));
+            // Flags that are on, by name; unknown ones are ignored
+            let flags: Vec<&str> = flags.split(',').collect();
+            let view = SavedView {
+                filter_mode,
+                raw_sizes: flags.contains(&"raw-sizes"),
+                show_permissions: flags.contains(&"permissions"),
+                show_owner: flags.contains(&"owner"),
+                vertical_layout: flags.contains(&"vertical"),
+                show_preview: flags.contains(&"preview"),
+                show_similarity: flags.contains(&"similarity"),
+                show_file_type: flags.contains(&"file-type"),
+                layout: if flags.contains(&"flat") {
+                    ListLayout::Flat
+                } else if flags.contains(&"grouped") {
+                    ListLayout::Grouped
+                } else {
+                    ListLayout::Tree
+                },
+                sort_order: if flags.contains(&"by-status") {
+                    SortOrder::Status
+                } else if flags.contains(&"by-size") {
+                    SortOrder::Size
+                } else {
+                    SortOrder::Path
+                },
+                placeholder_rows: if flags.contains(&"ghost-rows") {
+                    PlaceholderRows::Ghost
+                } else if flags.contains(&"hide-placeholders") {
+                    PlaceholderRows::Hidden
+                } else {
+                    PlaceholderRows::Blank
+                },
+            };
+            Some((left, right, view))
+        })
+        .collect()
+}
+
+/// The display settings last saved for the pair, if any.
+pub fn load_view(left: &Path, right: &Path) -> Option<SavedView> {
+    let left = left.canonicalize().unwrap_or_else(|_| left.to_path_buf());
+    let right = right.canonicalize().unwrap_or_else(|_| right.to_path_buf());
+    load_views()
+        .into_iter()
+        .find(|(l, r, _)| l == &left && r == &right)
+        .map(|(_, _, view)| view)
+}
+
+/// Remember the display settings of the pair, replacing earlier ones.
+pub fn save_view(left: &Path, right: &Path, view: &SavedView) -> Result<()> {
+    let Some(path) = views_file() else {
+        return Ok(());
+    };
+
+    let left = left.canonicalize().unwrap_or_else(|_| left.to_path_buf());
+    let right = right.canonicalize().unwrap_or_else(|_| right.to_path_buf());
+
+    let mut views = load_views();
+    views.retain(|(l, r, _)| !(l == &left && r == &right));
+    views.insert(0, (left, right, *view));
+    views.truncate(MAX_VIEWS);
+
+    // The filter and the flags that are on, by name
+    let settings: Vec<(&str, String)> = views
+        .iter()
+        .map(|(_, _, view)| {
+            let filter = match view.filter_mode {
+                FilterMode::All => "all",
+                FilterMode::Different => "different",
+                FilterMode::DifferentNotOrphans => "diff-only",
+            };
+            let flags: Vec<&str> = [
+                (view.raw_sizes, "raw-sizes"),
+                (view.show_permissions, "permissions"),
+                (view.show_owner, "owner"),
+                (view.vertical_layout, "vertical"),
+                (view.show_preview, "preview"),
+                (view.show_similarity, "similarity"),
+                (view.show_file_type, "file-type"),
+                (view.layout == ListLayout::Flat, "flat"),
+                (view.layout == ListLayout::Grouped, "grouped"),
+                (view.sort_order == SortOrder::Status, "by-status"),
+                (view.sort_order == SortOrder::Size, "by-size"),
+                (
+                    view.placeholder_rows == PlaceholderRows::Ghost,
+                    "ghost-rows",
+                ),
+                (
+                    view.placeholder_rows == PlaceholderRows::Hidden,
+                    "hide-placeholders",
+                ),
+            ]
+            .into_iter()
+            .filter_map(|(on, name)| on.then_some(name))
+            .collect();
+            (filter, flags.join(","))
+        })
+        .collect();
+    write_records(
+        &path,
+        views
+            .iter()
+            .zip(&settings)
+            .map(|((l, r, _), (filter, flags))| {
+                vec![
+                    l.as_os_str().as_encoded_bytes(),
+                    r.as_os_str().as_encoded_bytes(),
+                    filter.as_bytes(),
+                    flags.as_bytes(),
+                ]
+            }),
+    )
+}
@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Glyphs drawn by the TUI and the simple text output.
+#[derive(Debug)]
+pub struct Icons {
+    pub folder_closed: &'static str,
+    pub folder_open: &'static str,
+    pub file: &'static str,
+    /// Marks the newer side of a changed file
+    pub newer_left: &'static str,
+    pub newer_right: &'static str,
+    pub different: &'static str,
+    /// Follows the name of an entry compared again in full
+    pub verified: &'static str,
+    pub show_all: &'static str,
+    pub show_different: &'static str,
+    pub show_diff_only: &'static str,
+    pub expand_all: &'static str,
+    pub collapse_all: &'static str,
+    pub refresh: &'static str,
+    pub swap: &'static str,
+    pub copy_right: &'static str,
+    pub copy_left: &'static str,
+    pub delete: &'static str,
+    pub tools: &'static str,
+    pub warning: &'static str,
+    pub picker: &'static str,
+    pub new_paths: &'static str,
+    pub pair_separator: &'static str,
+    pub cursor: &'static str,
+    pub scroll_up: &'static str,
+    pub scroll_down: &'static str,
+    /// Marks a name cut short to fit its panel
+    pub ellipsis: &'static str,
+    pub processing: &'static str,
+    pub comparing: &'static str,
+}
+
+pub const UNICODE: Icons = Icons {
+    folder_closed: "📁",
+    folder_open: "📂",
+    file: "📄",
+    newer_left: "◀",
+    newer_right: "▶",
+    different: "≠",
+    verified: "✓",
+    show_all: "📁",
+    show_different: "🔍",
+    show_diff_only: "⚡",
+    expand_all: "📂",
+    collapse_all: "📁",
+    refresh: "🔄",
+    swap: "🔃",
+    copy_right: "▶️",
+    copy_left: "◀️",
+    delete: "🗑️",
+    tools: "🛠️ ",
+    warning: "⚠️ ",
+    picker: "🔀",
+    new_paths: "➕",
+    pair_separator: "⇄",
+    cursor: "▏",
+    scroll_up: "↑",
+    scroll_down: "↓",
+    ellipsis: "…",
+    processing: "🔀",
+    comparing: "🔍",
+};
+
+/// Plain ASCII for terminals without emoji fonts. Folder and file markers
+/// share one width so names stay aligned.
+pub const ASCII: Icons = Icons {
+    folder_closed: "[+]",
+    folder_open: "[-]",
+    file: " - ",
+    newer_left: "<",
+    newer_right: ">",
+    different: "!",
+    verified: "ok",
+    show_all: "[*]",
+    show_different: "[?]",
+    show_diff_only: "[!]",
+    expand_all: "[+]",
+    collapse_all: "[-]",
+    refresh: "[~]",
+    swap: "[=]",
+    copy_right: ">>",
+    copy_left: "<<",
+    delete: "[x]",
+    tools: "",
+    warning: "!",
+    picker: "",
+    new_paths: "+",
+    pair_separator: "<->",
+    cursor: "|",
+    scroll_up: "^",
+    scroll_down: "v",
+    ellipsis: "~",
+    processing: "*",
+    comparing: "-",
+};
+
+static ASCII_MODE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_ascii(enabled: bool) {
+    ASCII_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_ascii() -> bool {
+    ASCII_MODE.load(Ordering::Relaxed)
+}
+
+pub fn icons() -> &'static Icons {
+    if is_ascii() {
+        &ASCII
+    } else {
+        &UNICODE
+    }
+}
+
+/// `icon text`, or just `text` when the icon is empty.
+pub fn labeled(icon: &str, text: &str) -> String {
+    if icon.is_empty() {
+        text.to_string()
+    } else {
+        format!("{} {}", icon, text)
+    }
+}
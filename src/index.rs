@@ -0,0 +1,134 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::compare::CompareOptions;
+
+/// First line of an index file; bump the version when the format changes.
+const HEADER: &str = "tudiff-index 1";
+
+/// Files modified this recently aren't recorded: a change within the same
+/// mtime tick would go unnoticed by the next scan.
+pub const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Size and modification time (nanoseconds since the epoch) of one side.
+type Stamp = (u64, u128);
+
+/// Results of the last content comparison of each file pair under two
+/// roots, kept on disk so a rescan of huge trees only reads the files
+/// whose size or modification time changed since, like rsync's quick check.
+pub struct ComparisonIndex {
+    file: Option<PathBuf>,
+    fingerprint: String,
+    previous: HashMap<PathBuf, (Stamp, Stamp, bool)>,
+    current: HashMap<PathBuf, (Stamp, Stamp, bool)>,
+}
+
+/// The options that change whether two files count as the same; an index
+/// written with other ones is thrown away.
+fn fingerprint(options: &CompareOptions) -> String {
+    format!(
+        "{:?} {} {:?}",
+        options.mtime_tolerance, options.ignore_comments, options.normalizers
+    )
+}
+
+fn stamp(metadata: &fs::Metadata) -> Option<Stamp> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_nanos()))
+}
+
+impl ComparisonIndex {
+    /// Load the index of the two roots. A missing or outdated one starts
+    /// out empty.
+    pub fn load(left: &Path, right: &Path, options: &CompareOptions) -> Self {
+        let file = crate::utils::pair_state_file("index", left, right);
+        let fingerprint = fingerprint(options);
+        let previous = file
+            .as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|content| Self::parse(&content, &fingerprint))
+            .unwrap_or_default();
+        Self {
+            file,
+            fingerprint,
+            previous,
+            current: HashMap::new(),
+        }
+    }
+
+    /// `lsize lmtime rsize rmtime same path` lines, tab separated, after
+    /// the header and fingerprint.
+    fn parse(content: &str, fingerprint: &str) -> HashMap<PathBuf, (Stamp, Stamp, bool)> {
+        let mut lines = content.lines();
+        if lines.next() != Some(HEADER) || lines.next() != Some(fingerprint) {
+            return HashMap::new();
+        }
+        lines
+            .filter_map(|line| {
+                let mut fields = line.splitn(6, '\t');
+                let left = (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?);
+                let right = (fields.next()?.parse().ok()?, fields.next()?.parse().ok()?);
+                let same = fields.next()? == "1";
+                Some((PathBuf::from(fields.next()?), (left, right, same)))
+            })
+            .collect()
+    }
+
+    /// The recorded result for `path` if neither side changed since.
+    pub fn lookup(&self, path: &Path, left: &fs::Metadata, right: &fs::Metadata) -> Option<bool> {
+        let (left_stamp, right_stamp, same) = self.previous.get(path)?;
+        (Some(*left_stamp) == stamp(left) && Some(*right_stamp) == stamp(right)).then_some(*same)
+    }
+
+    /// Remember the result for `path` for the next scan.
+    pub fn record(&mut self, path: &Path, left: &fs::Metadata, right: &fs::Metadata, same: bool) {
+        let (Some(left_stamp), Some(right_stamp)) = (stamp(left), stamp(right)) else {
+            return;
+        };
+        let settled = |metadata: &fs::Metadata| {
+            metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= SETTLE_TIME)
+        };
+        let storable = path.to_str().is_some_and(|path| !path.contains('\n'));
+        if storable && settled(left) && settled(right) {
+            self.current
+                .insert(path.to_path_buf(), (left_stamp, right_stamp, same));
+        }
+    }
+
+    /// Replace the index file with the results recorded during this scan,
+    /// which drops entries that no longer exist on both sides.
+    pub fn save(&self) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+
+        let mut content = format!("{}\n{}\n", HEADER, self.fingerprint);
+        for (path, ((left_size, left_mtime), (right_size, right_mtime), same)) in &self.current {
+            content.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\n",
+                left_size,
+                left_mtime,
+                right_size,
+                right_mtime,
+                if *same { 1 } else { 0 },
+                path.display()
+            ));
+        }
+
+        // Written aside and renamed, so an interrupted save leaves the old one
+        let temp = file.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&temp, content).with_context(|| format!("writing {}", temp.display()))?;
+        fs::rename(&temp, file).with_context(|| format!("writing {}", file.display()))?;
+        Ok(())
+    }
+}
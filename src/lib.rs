@@ -1,10 +1,38 @@
 pub mod compare;
+pub mod filter;
 pub mod utils;
 pub mod ui;
 pub mod app;
 pub mod terminal;
+pub mod history;
+pub mod picker;
+pub mod config;
+pub mod copy;
+pub mod digest;
+pub mod checkpoint;
+pub mod docker;
+pub mod git;
+pub mod headless;
+pub mod index;
+pub mod manifest;
+pub mod normalize;
+pub mod oplog;
+pub mod pathmap;
+pub mod filetype;
+pub mod elevate;
+pub mod pseudofs;
+pub mod throttle;
+#[cfg(feature = "serde")]
+pub mod saved;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod icons;
+pub mod theme;
 
-pub use compare::{DirectoryComparison, FileNode, FileStatus};
-pub use app::{App, AppMode, FilterMode, CopyInfo};
+pub use compare::{
+    CompareOptions, DiffEntry, DirectoryComparison, FileNode, FileStatus, Meta,
+};
+pub use app::{App, AppMode, FilterMode, CopyInfo, ViewOptions};
+pub use headless::Headless;
 pub use terminal::{TerminalManager, TerminalState};
 pub use ui::{draw_ui, centered_rect, panel_centered_rect};
\ No newline at end of file
@@ -1,24 +1,408 @@
 use anyhow::Result;
-use clap::Parser;
-use std::path::PathBuf;
+use std::io::IsTerminal;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use tudiff::terminal::{run_tui, simple_compare, ensure_cursor_visible};
+use tudiff::app::{FilterMode, PlaceholderRows, ViewOptions};
+use tudiff::compare::{CompareOptions, LimitPrompt};
+use tudiff::config::Config;
+use tudiff::docker::is_docker;
+use tudiff::elevate::UnreadablePolicy;
+use tudiff::filter::PathFilter;
+use tudiff::manifest::is_remote;
+use tudiff::pathmap::PathMap;
+use tudiff::pseudofs::PseudoFsPolicy;
+use tudiff::terminal::{
+    ask_limit_on_stdin, confirm_on_stdin, ensure_cursor_visible, run_picker, run_tui,
+    simple_compare, watch_compare, WatchAlert,
+};
+use tudiff::utils::ProgressMode;
 
 #[derive(Parser)]
 #[command(name = "tudiff")]
 #[command(about = "TUI-based directory and file comparison tool")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    #[arg(help = "First directory to compare")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(help = "First directory (or a file to find in the second directory)")]
     dir1: Option<PathBuf>,
 
-    #[arg(help = "Second directory to compare")]
+    #[arg(help = "Second directory (or a file to find in the first directory)")]
     dir2: Option<PathBuf>,
 
+    #[arg(help = "Additional target directories compared against the first one")]
+    more_targets: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        visible_alias = "against",
+        value_name = "FILE",
+        conflicts_with_all = ["dir2", "more_targets"],
+        help = "Check the directory against a snapshot or a sha256sum/sha512sum manifest instead of a second directory"
+    )]
+    manifest: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "A..B",
+        conflicts_with_all = ["dir2", "more_targets", "manifest"],
+        help = "Compare two revisions of the git repository given as the first directory (default: the current one)"
+    )]
+    git_refs: Option<String>,
+
     #[arg(long, help = "Use simple text output instead of TUI")]
     simple: bool,
 
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["more_targets", "watch"],
+        help = "Compare without the TUI and write the result to FILE, to browse later with --load-result"
+    )]
+    save_result: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["dir1", "dir2", "more_targets", "manifest", "git_refs", "watch", "save_result"],
+        help = "Browse a result written by --save-result instead of comparing"
+    )]
+    load_result: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["more_targets", "git_refs"],
+        help = "Keep comparing and print a line whenever an entry's status changes (no TUI)"
+    )]
+    watch: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "HOW",
+        requires = "watch",
+        help = "With --watch, call attention to paths that were the same and became different"
+    )]
+    notify: Option<NotifyArg>,
+
     #[arg(short, long, help = "Enable verbose logging")]
     verbose: bool,
+
+    #[arg(long, help = "Draw plain ASCII markers instead of emoji icons")]
+    ascii: bool,
+
+    #[arg(
+        long,
+        help = "Show statuses with markers and bold/underline instead of colors (also set by NO_COLOR)"
+    )]
+    no_color: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Status colors: default, deuteranopia or protanopia (colorblind-friendly)"
+    )]
+    theme: Option<String>,
+
+    #[arg(
+        short,
+        long,
+        conflicts_with = "progress",
+        help = "Don't print scan progress"
+    )]
+    quiet: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "dots",
+        help = "How scan progress is printed to stderr"
+    )]
+    progress: ProgressArg,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 2.0,
+        help = "Modification time difference still treated as equal"
+    )]
+    mtime_tolerance: f64,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Skip matching files and folders (repeatable, rsync-style patterns)"
+    )]
+    exclude: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Only compare matching files (repeatable, excludes take precedence)"
+    )]
+    include: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "LEFT=RIGHT",
+        help = "Compare the right's folder RIGHT with the left's LEFT, for folders renamed between the sides (repeatable, e.g. src=source)"
+    )]
+    map: Vec<String>,
+
+    #[arg(
+        short = 'x',
+        long,
+        help = "Don't cross filesystem boundaries (skip /proc, mounted shares, ...)"
+    )]
+    one_file_system: bool,
+
+    #[arg(
+        short = 'L',
+        long,
+        help = "Compare what symlinks point to and descend into linked folders"
+    )]
+    follow_links: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "skip",
+        help = "What to do with folders on /proc, /sys and other kernel pseudo filesystems"
+    )]
+    pseudo_fs: PseudoFsArg,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "What to do with folders and files you aren't allowed to read (default: report, or the config setting)"
+    )]
+    unreadable: Option<UnreadableArg>,
+
+    #[arg(
+        long,
+        help = "Compare even when one directory is the other or lies inside it"
+    )]
+    allow_overlap: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1_000_000,
+        help = "Ask before going on once a scan passes N paths (0: no limit)"
+    )]
+    max_entries: usize,
+
+    #[arg(
+        long,
+        value_name = "RATE",
+        help = "Read and copy file contents at most this fast, in KiB/s or with a B, K, M or G suffix (0: no limit)"
+    )]
+    bwlimit: Option<String>,
+
+    #[arg(long, help = "Treat entries whose permission bits differ as different")]
+    compare_permissions: bool,
+
+    #[arg(
+        long,
+        help = "Compare source and config files without their comments and blank lines"
+    )]
+    ignore_comments: bool,
+
+    #[arg(
+        long,
+        help = "Keep an on-disk index of file comparisons and only re-read files whose size or mtime changed"
+    )]
+    index: bool,
+
+    #[arg(
+        long,
+        help = "Continue an interrupted comparison of the same folders from its checkpoint"
+    )]
+    resume: bool,
+
+    #[arg(
+        long,
+        help = "For S3 objects without an MD5 ETag, compare the first and last 64 KiB too"
+    )]
+    s3_ranged_check: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Compare only the relative paths listed in FILE, one per line ('-' for stdin)"
+    )]
+    files_from: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Color diffs in --simple output (uses delta, bat or colordiff when available)"
+    )]
+    color: ColorArg,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Initial filter mode (default: the last one used on these directories, or all)"
+    )]
+    filter: Option<FilterArg>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "How to show the row across from an entry that exists on one side only (default: the last setting used on these directories, or blank)"
+    )]
+    placeholders: Option<PlaceholderArg>,
+
+    #[arg(long, help = "Start with all folders expanded")]
+    expand_all: bool,
+
+    #[arg(long, value_name = "PATH", help = "Start with this entry selected")]
+    select: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Disable copying, deleting, renaming and everything else that changes the folders, and open files read-only"
+    )]
+    read_only: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Append every copy, delete, rename and other change made in the TUI to FILE (J shows them too)"
+    )]
+    session_log: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Record paths, sizes, times and hashes of a directory to check it
+    /// against later with --against
+    Snapshot {
+        dir: PathBuf,
+
+        #[arg(
+            short,
+            long,
+            value_name = "FILE",
+            help = "Where to write the snapshot (JSON)"
+        )]
+        output: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FilterArg {
+    /// Show all files and folders
+    All,
+    /// Show changed and one-sided entries
+    Different,
+    /// Show entries that exist on both sides but differ
+    DiffOnly,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PlaceholderArg {
+    /// An empty row, keeping the panels aligned
+    Blank,
+    /// The missing entry's name, dimmed
+    Ghost,
+    /// No row; each panel lists only its own entries
+    Hide,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PseudoFsArg {
+    /// Leave them out with a warning, and refuse a root on one
+    Skip,
+    /// Scan them, with a warning
+    Warn,
+    /// Scan them like any other folder
+    Scan,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum UnreadableArg {
+    /// Show them without their contents and list them as needing elevation
+    Report,
+    /// Read them again with `sudo -n`, reporting the ones it can't read
+    Sudo,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum NotifyArg {
+    /// Ring the terminal bell
+    Bell,
+    /// Show a desktop notification (needs the desktop-notify feature)
+    Desktop,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ProgressArg {
+    /// Dots and status lines
+    Dots,
+    /// One JSON event per line
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<FilterArg> for FilterMode {
+    fn from(arg: FilterArg) -> Self {
+        match arg {
+            FilterArg::All => FilterMode::All,
+            FilterArg::Different => FilterMode::Different,
+            FilterArg::DiffOnly => FilterMode::DifferentNotOrphans,
+        }
+    }
+}
+
+impl From<PlaceholderArg> for PlaceholderRows {
+    fn from(arg: PlaceholderArg) -> Self {
+        match arg {
+            PlaceholderArg::Blank => PlaceholderRows::Blank,
+            PlaceholderArg::Ghost => PlaceholderRows::Ghost,
+            PlaceholderArg::Hide => PlaceholderRows::Hidden,
+        }
+    }
+}
+
+impl From<NotifyArg> for WatchAlert {
+    fn from(arg: NotifyArg) -> Self {
+        match arg {
+            NotifyArg::Bell => WatchAlert::Bell,
+            NotifyArg::Desktop => WatchAlert::Desktop,
+        }
+    }
+}
+
+impl From<PseudoFsArg> for PseudoFsPolicy {
+    fn from(arg: PseudoFsArg) -> Self {
+        match arg {
+            PseudoFsArg::Skip => PseudoFsPolicy::Skip,
+            PseudoFsArg::Warn => PseudoFsPolicy::Warn,
+            PseudoFsArg::Scan => PseudoFsPolicy::Scan,
+        }
+    }
+}
+
+impl From<UnreadableArg> for UnreadablePolicy {
+    fn from(arg: UnreadableArg) -> Self {
+        match arg {
+            UnreadableArg::Report => UnreadablePolicy::Report,
+            UnreadableArg::Sudo => UnreadablePolicy::Sudo,
+        }
+    }
 }
 
 fn main() -> Result<()> {
@@ -26,11 +410,59 @@ fn main() -> Result<()> {
 
     // Initialize logging based on verbose flag
     tudiff::utils::init_logging(args.verbose);
+    tudiff::utils::set_progress_mode(match (args.quiet, args.progress) {
+        (true, _) => ProgressMode::Quiet,
+        (false, ProgressArg::Dots) => ProgressMode::Dots,
+        (false, ProgressArg::Json) => ProgressMode::Json,
+    });
+
+    if let Some(Command::Snapshot { dir, output }) = &args.command {
+        return snapshot(dir, output);
+    }
+
+    let config = Config::load();
+    tudiff::icons::set_ascii(args.ascii || config.ascii);
+    if let Some(theme) = args.theme.as_ref().or(config.theme.as_ref()) {
+        if !tudiff::theme::set_palette(theme) {
+            eprintln!(
+                "Error: unknown theme `{}` (one of {})",
+                theme,
+                tudiff::theme::palette_names()
+            );
+            std::process::exit(1);
+        }
+    }
+    tudiff::theme::set_highlights(config.highlight);
+    tudiff::theme::set_no_color(
+        args.no_color || config.no_color || tudiff::theme::no_color_requested(),
+    );
+
+    if let Some(input) = &args.load_result {
+        let result = load_result(input, &args, config);
+        ensure_cursor_visible();
+        return result;
+    }
 
-    let (dir1, dir2) = match (args.dir1, args.dir2) {
+    let can_pick =
+        !args.simple && std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+
+    let (dir1, dir2) = match (args.dir1, args.dir2.or(args.manifest.clone())) {
+        // Both sides are checked out from this repository further down
+        (repo, None) if args.git_refs.is_some() => {
+            let repo = repo.unwrap_or_else(|| PathBuf::from("."));
+            (repo.clone(), repo)
+        }
         (Some(d1), Some(d2)) => (d1, d2),
+        (None, None) if can_pick => match run_picker()? {
+            Some(pair) => pair,
+            None => {
+                ensure_cursor_visible();
+                return Ok(());
+            }
+        },
         _ => {
             eprintln!("Usage: tudiff <dir1> <dir2>");
+            eprintln!("       tudiff            (pick from recent comparisons)");
             eprintln!("\nCompares two directories in a TUI interface");
             eprintln!("\nNavigation:");
             eprintln!("  Up/Down     - Navigate files");
@@ -42,20 +474,258 @@ fn main() -> Result<()> {
         }
     };
 
-    if !dir1.exists() || !dir1.is_dir() {
+    // An s3:// URL on either side is listed and checked like a manifest
+    let against = args.manifest.is_some() || is_remote(&dir1) || is_remote(&dir2);
+    let from_docker =
+        is_docker(&dir1) || is_docker(&dir2) || args.more_targets.iter().any(|dir| is_docker(dir));
+
+    // Like diff, a file paired with a directory is compared against the
+    // same-named file inside that directory.
+    let mut single_file = None;
+    let (dir1, dir2) = match (dir1.is_file(), dir2.is_dir()) {
+        _ if against || from_docker || args.git_refs.is_some() => (dir1, dir2),
+        (true, true) => {
+            let (parent, name) = split_file_arg(&dir1);
+            single_file = Some(name);
+            (parent, dir2)
+        }
+        (false, false) if dir1.is_dir() && dir2.is_file() => {
+            let (parent, name) = split_file_arg(&dir2);
+            single_file = Some(name);
+            (dir1, parent)
+        }
+        _ => (dir1, dir2),
+    };
+
+    if single_file.is_some() && args.files_from.is_some() {
+        eprintln!("Error: --files-from can't be combined with a file argument");
+        std::process::exit(1);
+    }
+
+    if !is_remote(&dir1) && !is_docker(&dir1) && !dir1.is_dir() {
         eprintln!("Error: '{}' is not a valid directory", dir1.display());
         std::process::exit(1);
     }
 
-    if !dir2.exists() || !dir2.is_dir() {
-        eprintln!("Error: '{}' is not a valid directory", dir2.display());
+    if args.manifest.is_some() && !dir2.is_file() && !is_remote(&dir2) {
+        eprintln!("Error: '{}' is not a manifest file", dir2.display());
         std::process::exit(1);
     }
 
-    let result = if args.simple {
-        simple_compare(dir1, dir2)
+    let second_dir = Some(&dir2).filter(|dir| args.manifest.is_none() && !is_remote(dir));
+    for dir in second_dir.into_iter().chain(&args.more_targets) {
+        if !is_docker(dir) && !dir.is_dir() {
+            eprintln!("Error: '{}' is not a valid directory", dir.display());
+            std::process::exit(1);
+        }
+    }
+
+    // Better to find out now than after the first copy
+    if let Some(file) = &args.session_log {
+        let opened = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file);
+        if let Err(e) = opened {
+            eprintln!(
+                "Error: can't write the session log {}: {}",
+                file.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    }
+
+    // Comparing a folder with itself or its own subfolder is almost always
+    // a slip, and reads the inner one twice
+    if !args.allow_overlap && !against && !from_docker && args.git_refs.is_none() {
+        let overlaps = std::iter::once(&dir2)
+            .chain(&args.more_targets)
+            .filter_map(|dir| tudiff::compare::root_overlap(&dir1, dir));
+        for overlap in overlaps {
+            let go_on = std::io::stdin().is_terminal()
+                && confirm_on_stdin(&format!("Warning: {}. Compare anyway?", overlap));
+            if !go_on {
+                eprintln!(
+                    "Error: {}; pass --allow-overlap to compare them anyway",
+                    overlap
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // A copy out of a container never changes
+    if args.watch && from_docker {
+        eprintln!("Error: --watch needs local directories, not docker:// paths");
+        std::process::exit(1);
+    }
+
+    if matches!(args.notify, Some(NotifyArg::Desktop)) && !cfg!(feature = "desktop-notify") {
+        eprintln!("Error: --notify desktop needs tudiff built with --features desktop-notify");
+        std::process::exit(1);
+    }
+
+    if !args.mtime_tolerance.is_finite() || args.mtime_tolerance < 0.0 {
+        eprintln!("Error: --mtime-tolerance must be a non-negative number of seconds");
+        std::process::exit(1);
+    }
+
+    match args.bwlimit.as_deref().map(tudiff::throttle::parse_rate) {
+        Some(Ok(limit)) => tudiff::throttle::set_limit(limit),
+        Some(Err(e)) => {
+            eprintln!("Error: --bwlimit: {}", e);
+            std::process::exit(1);
+        }
+        None => tudiff::throttle::set_limit(config.bwlimit),
+    }
+
+    // The picker only reopens pairs of directories that are still there, and
+    // display settings are only kept for them too
+    let remember = !against && !from_docker && args.git_refs.is_none();
+    if remember {
+        if let Err(e) = tudiff::history::record(&dir1, &dir2) {
+            tudiff::utils::log_error(&format!("Failed to update history: {}", e));
+        }
+    }
+
+    // Worktrees only differ in where their .git file points
+    let mut exclude = args.exclude.clone();
+    if args.git_refs.is_some() {
+        exclude.push("/.git".to_string());
+    }
+
+    let filter = match PathFilter::new(&args.include, &exclude) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if !args.map.is_empty() && (against || args.git_refs.is_some()) {
+        eprintln!("Error: --map needs two directories");
+        std::process::exit(1);
+    }
+    let path_map = if against || args.git_refs.is_some() {
+        PathMap::default()
+    } else {
+        let specs = if args.map.is_empty() {
+            &config.map
+        } else {
+            &args.map
+        };
+        match PathMap::new(specs) {
+            Ok(path_map) => path_map,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    let files_from = match &args.files_from {
+        _ if single_file.is_some() => single_file.clone().map(|name| vec![name]),
+        Some(source) => match read_path_list(source) {
+            Ok(paths) => Some(paths),
+            Err(e) => {
+                eprintln!("Error: failed to read path list '{}': {}", source.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Directories in containers and images are copied out for the
+    // comparison, and the copies removed once it's done
+    let mut exports = Vec::new();
+    let mut local = |dir: PathBuf| -> Result<PathBuf> {
+        if !is_docker(&dir) {
+            return Ok(dir);
+        }
+        eprintln!("Copying {} out of docker...", dir.display());
+        let export = tudiff::docker::export(&dir)?;
+        let local = export.dir.clone();
+        exports.push(export);
+        Ok(local)
+    };
+    let dir1 = local(dir1)?;
+    let dir2 = local(dir2)?;
+    let more_targets = args
+        .more_targets
+        .iter()
+        .cloned()
+        .map(local)
+        .collect::<Result<Vec<_>>>()?;
+
+    // Revisions are checked out into temporary worktrees, removed on exit
+    let mut worktrees = Vec::new();
+    let (dir1, dir2) = match &args.git_refs {
+        Some(range) => {
+            let (from, to) = tudiff::git::split_range(&dir1, range)?;
+            eprintln!("Checking out {} and {}...", from, to);
+            worktrees.push(tudiff::git::checkout(&dir1, &from)?);
+            worktrees.push(tudiff::git::checkout(&dir1, &to)?);
+            (worktrees[0].dir.clone(), worktrees[1].dir.clone())
+        }
+        None => (dir1, dir2),
+    };
+
+    let options = CompareOptions {
+        mtime_tolerance: Duration::from_secs_f64(args.mtime_tolerance),
+        filter,
+        one_file_system: args.one_file_system,
+        follow_links: args.follow_links,
+        pseudo_fs: args.pseudo_fs.into(),
+        unreadable: args.unreadable.map_or(config.unreadable, Into::into),
+        max_entries: (args.max_entries > 0).then_some(args.max_entries),
+        // The TUI asks in a popup
+        limit_prompt: (args.simple && std::io::stdin().is_terminal())
+            .then(|| LimitPrompt(Arc::new(ask_limit_on_stdin))),
+        metadata_only: false,
+        files_from,
+        compare_permissions: args.compare_permissions,
+        s3_ranged_check: args.s3_ranged_check,
+        normalizers: config.normalize.clone(),
+        ignore_comments: args.ignore_comments,
+        use_index: args.index,
+        // Worktrees are new temporary folders every run, and watching
+        // rescans far too often to be worth resuming. `--save-result` runs
+        // include the sudo helper, which mustn't leave root's files behind
+        checkpoint: worktrees.is_empty() && !args.watch && args.save_result.is_none(),
+        resume: args.resume,
+        path_map,
+    };
+
+    let view = ViewOptions {
+        filter_mode: args.filter.map(Into::into),
+        expand_all: args.expand_all,
+        select: args.select.clone().or(single_file),
+        placeholder_rows: args.placeholders.map(Into::into),
+        remember,
+        hash: config.hash,
+        read_only: args.read_only,
+        session_log: args.session_log.clone(),
+    };
+
+    let color = use_color(args.color);
+
+    let result = if let Some(output) = &args.save_result {
+        save_result(&dir1, &dir2, options, output, args.simple.then_some(color))
+    } else if args.watch {
+        watch_compare(dir1, dir2, options, args.notify.map(Into::into))
+    } else if args.simple {
+        simple_compare_all(&dir1, &dir2, &more_targets, &options, color)
     } else {
-        match run_tui(dir1.clone(), dir2.clone()) {
+        match run_tui(
+            dir1.clone(),
+            dir2.clone(),
+            more_targets.clone(),
+            options.clone(),
+            view,
+            config.keys,
+            config.copy_backend,
+        ) {
             Ok(_) => Ok(()),
             Err(e) => {
                 eprintln!("TUI Error: {}", e);
@@ -70,7 +740,7 @@ fn main() -> Result<()> {
                     }
                     Err(_) => eprintln!("Cannot detect terminal. Falling back..."),
                 }
-                simple_compare(dir1, dir2)
+                simple_compare_all(&dir1, &dir2, &more_targets, &options, color)
             }
         }
     };
@@ -80,3 +750,144 @@ fn main() -> Result<()> {
     result
 }
 
+
+fn simple_compare_all(
+    source: &Path,
+    first_target: &Path,
+    more_targets: &[PathBuf],
+    options: &CompareOptions,
+    color: bool,
+) -> Result<()> {
+    for target in std::iter::once(first_target).chain(more_targets.iter().map(PathBuf::as_path)) {
+        simple_compare(source.to_path_buf(), target.to_path_buf(), options.clone(), color)?;
+        if more_targets.is_empty() {
+            break;
+        }
+        println!();
+    }
+    Ok(())
+}
+
+fn use_color(arg: ColorArg) -> bool {
+    match arg {
+        ColorArg::Auto => std::io::stdout().is_terminal() && !tudiff::theme::is_no_color(),
+        ColorArg::Always => true,
+        ColorArg::Never => false,
+    }
+}
+
+/// Compare and write the result for `--load-result`, printing it as well
+/// when `print` holds the `--simple` color setting.
+#[cfg(feature = "serde")]
+fn save_result(
+    dir1: &Path,
+    dir2: &Path,
+    options: CompareOptions,
+    output: &Path,
+    print: Option<bool>,
+) -> Result<()> {
+    let comparison = tudiff::compare::DirectoryComparison::new_with_options(
+        dir1.to_path_buf(),
+        dir2.to_path_buf(),
+        options,
+    )?;
+    match print {
+        Some(color) => tudiff::terminal::print_comparison(&comparison, color)?,
+        None => {
+            for warning in &comparison.warnings {
+                eprintln!("Warning: {}", warning);
+            }
+        }
+    }
+    let count = tudiff::saved::save(&comparison, output)?;
+    eprintln!("Saved {} entries to {}", count, output.display());
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn save_result(
+    _dir1: &Path,
+    _dir2: &Path,
+    _options: CompareOptions,
+    _output: &Path,
+    _print: Option<bool>,
+) -> Result<()> {
+    anyhow::bail!("saved results need the `serde` feature")
+}
+
+/// Show a result written by `--save-result`, in the TUI or as `--simple`
+/// text.
+#[cfg(feature = "serde")]
+fn load_result(input: &Path, args: &Args, config: Config) -> Result<()> {
+    let comparison = tudiff::saved::load(input)?;
+    if args.simple {
+        return tudiff::terminal::print_comparison(&comparison, use_color(args.color));
+    }
+    let view = ViewOptions {
+        filter_mode: args.filter.map(Into::into),
+        expand_all: args.expand_all,
+        select: args.select.clone(),
+        placeholder_rows: args.placeholders.map(Into::into),
+        remember: false,
+        hash: config.hash,
+        read_only: args.read_only,
+        // Nothing can be changed in a saved result
+        session_log: None,
+    };
+    tudiff::terminal::run_tui_with(comparison, view, config.keys, config.copy_backend)
+}
+
+#[cfg(not(feature = "serde"))]
+fn load_result(_input: &Path, _args: &Args, _config: Config) -> Result<()> {
+    anyhow::bail!("saved results need the `serde` feature")
+}
+
+#[cfg(feature = "serde")]
+fn snapshot(dir: &Path, output: &Path) -> Result<()> {
+    if !dir.is_dir() {
+        eprintln!("Error: '{}' is not a valid directory", dir.display());
+        std::process::exit(1);
+    }
+    let (count, errors) = tudiff::manifest::write_snapshot(dir, output)?;
+    eprintln!("Recorded {} entries in {}", count, output.display());
+    if errors > 0 {
+        eprintln!("Warning: {} of them couldn't be read, see the log", errors);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn snapshot(_dir: &Path, _output: &Path) -> Result<()> {
+    anyhow::bail!("snapshots need the `serde` feature")
+}
+
+/// Split a file argument into its directory and file name.
+fn split_file_arg(file: &Path) -> (PathBuf, PathBuf) {
+    let parent = match file.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(parent) => parent.to_path_buf(),
+        None => std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+    };
+    let name = file.file_name().map(PathBuf::from).unwrap_or_default();
+    (parent, name)
+}
+
+/// Read relative paths for `--files-from`, one per line. Blank lines, leading
+/// `./` and entries escaping the roots (absolute or containing `..`) are skipped.
+fn read_path_list(source: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let content = if source == Path::new("-") {
+        std::io::read_to_string(std::io::stdin())?
+    } else {
+        std::fs::read_to_string(source)?
+    };
+
+    Ok(content
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty())
+        .map(|line| PathBuf::from(line.strip_prefix("./").unwrap_or(line)))
+        .filter(|path| {
+            path.components()
+                .all(|c| matches!(c, std::path::Component::Normal(_)))
+        })
+        .collect())
+}
@@ -0,0 +1,443 @@
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+/// What a manifest records about one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// Lowercase hex digest of the contents; its length tells the algorithm.
+    /// S3 objects uploaded in parts have none and are compared by size, as
+    /// are those whose ETag turns out not to be an MD5;
+    /// links and unreadable files in a snapshot have neither.
+    pub hash: Option<String>,
+    /// Only snapshots and S3 listings record these
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+}
+
+/// Relative paths with the hashes their contents should have, compared
+/// against a real directory in place of the other side.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub entries: BTreeMap<PathBuf, ManifestEntry>,
+    /// Folders recorded explicitly, so empty ones count too
+    pub folders: BTreeSet<PathBuf>,
+    /// Where the objects of an S3 listing live, for ranged reads
+    #[cfg(feature = "s3")]
+    pub location: Option<crate::s3::Location>,
+}
+
+/// Whether `path` is an `s3://bucket/prefix` URL rather than a local path.
+pub fn is_remote(path: &Path) -> bool {
+    path.to_string_lossy().starts_with("s3://")
+}
+
+/// Whether `path` stands for a manifest (a file or an S3 listing) rather
+/// than a directory.
+pub fn is_manifest_path(path: &Path) -> bool {
+    path.is_file() || is_remote(path)
+}
+
+impl Manifest {
+    /// Read a manifest file, or list the objects under an S3 URL.
+    pub fn open(path: &Path) -> Result<Self> {
+        if !is_remote(path) {
+            return Self::load(path);
+        }
+        #[cfg(feature = "s3")]
+        {
+            crate::s3::list(path).with_context(|| format!("listing {}", path.display()))
+        }
+        #[cfg(not(feature = "s3"))]
+        {
+            bail!("s3:// paths need the `s3` feature")
+        }
+    }
+
+    /// Read a snapshot (JSON, see [`write_snapshot`]) or a checksum list.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let manifest = if content.trim_start().starts_with('{') {
+            Self::parse_snapshot(&content)
+        } else {
+            Self::parse(&content)
+        };
+        manifest.with_context(|| format!("reading manifest {}", path.display()))
+    }
+
+    /// Parse `sha256sum` or `sha512sum` output: `<hash>  <path>` per line, or
+    /// `<hash> *<path>` for binary mode. Paths are relative to the compared
+    /// directory; a leading `./` is ignored.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // Names with a newline or backslash are escaped and flagged
+            let (escaped, line) = match line.strip_prefix('\\') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let Some((hash, rest)) = line.split_once(' ') else {
+                bail!("line {}: expected `<hash>  <path>`", number + 1);
+            };
+            if !matches!(hash.len(), 64 | 128) || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                bail!("line {}: expected a SHA-256 or SHA-512 hash", number + 1);
+            }
+            let name = rest.strip_prefix([' ', '*']).unwrap_or(rest);
+            let name = if escaped {
+                unescape(name)
+            } else {
+                name.to_string()
+            };
+
+            let path: PathBuf = Path::new(&name)
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .collect();
+            if path.as_os_str().is_empty()
+                || !path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+            {
+                bail!("line {}: `{}` is not a relative path", number + 1, name);
+            }
+
+            entries.insert(
+                path,
+                ManifestEntry {
+                    hash: Some(hash.to_ascii_lowercase()),
+                    size: None,
+                    modified: None,
+                },
+            );
+        }
+
+        Ok(Self {
+            entries,
+            ..Self::default()
+        })
+    }
+
+    #[cfg(feature = "serde")]
+    fn parse_snapshot(content: &str) -> Result<Self> {
+        let snapshot: Snapshot = serde_json::from_str(content)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            bail!("unsupported snapshot version {}", snapshot.version);
+        }
+
+        let mut manifest = Self::default();
+        for entry in snapshot.entries {
+            if !entry
+                .path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+            {
+                bail!("`{}` is not a relative path", entry.path.display());
+            }
+            match entry.sha256 {
+                Some(hash) if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                    bail!("`{}` has no valid SHA-256 hash", entry.path.display());
+                }
+                Some(hash) => {
+                    manifest.entries.insert(
+                        entry.path,
+                        ManifestEntry {
+                            hash: Some(hash),
+                            size: entry.size,
+                            modified: entry.modified,
+                        },
+                    );
+                }
+                // Links and unreadable files: only their presence can be checked
+                None if entry.link.is_some() || entry.error.is_some() => {
+                    manifest.entries.insert(
+                        entry.path,
+                        ManifestEntry {
+                            hash: None,
+                            size: None,
+                            modified: None,
+                        },
+                    );
+                }
+                None => {
+                    manifest.folders.insert(entry.path);
+                }
+            }
+        }
+        Ok(manifest)
+    }
+
+    #[cfg(not(feature = "serde"))]
+    fn parse_snapshot(_content: &str) -> Result<Self> {
+        bail!("reading snapshots needs the `serde` feature")
+    }
+
+    /// Whether `file`, already known to have the recorded size, matches the
+    /// entry at `relative`. Without a hash only an S3 listing can tell, and
+    /// only with `ranged` reads of the object; otherwise the size decides.
+    pub fn verify(
+        &self,
+        relative: &Path,
+        entry: &ManifestEntry,
+        file: &Path,
+        ranged: bool,
+    ) -> Result<bool> {
+        #[cfg(feature = "s3")]
+        let hash = match (&entry.hash, &self.location) {
+            (Some(_), Some(location)) if !crate::s3::etag_is_md5(location, relative)? => None,
+            (hash, _) => hash.as_ref(),
+        };
+        #[cfg(not(feature = "s3"))]
+        let hash = entry.hash.as_ref();
+        if let Some(hash) = hash {
+            return Ok(file_digest(file, hash)? == *hash);
+        }
+        #[cfg(feature = "s3")]
+        if let (true, Some(location)) = (ranged, &self.location) {
+            return crate::s3::ranged_same(location, relative, file);
+        }
+        let _ = (relative, ranged);
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "serde")]
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The JSON written by `tudiff snapshot`.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Snapshot {
+    version: u32,
+    /// The directory the snapshot was taken of, for reference only
+    root: PathBuf,
+    created: SystemTime,
+    entries: Vec<SnapshotEntry>,
+}
+
+/// A file (with a hash), a link (with its target), a file that couldn't be
+/// read (with the error) or a folder (with none of them).
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SnapshotEntry {
+    path: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    link: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+impl SnapshotEntry {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            size: None,
+            modified: None,
+            sha256: None,
+            link: None,
+            error: None,
+        }
+    }
+
+    fn failed(path: PathBuf, error: &dyn std::fmt::Display) -> Self {
+        Self {
+            error: Some(error.to_string()),
+            ..Self::new(path)
+        }
+    }
+}
+
+/// Record every file under `dir` with its size, modification time and
+/// SHA-256 hash, and every folder, as JSON in `output`. Symbolic links to
+/// files are recorded like the files they point to; other links (dangling
+/// or to folders) with their target, and files that can't be read with the
+/// error. Returns the number of entries and how many of them are errors.
+#[cfg(feature = "serde")]
+pub fn write_snapshot(dir: &Path, output: &Path) -> Result<(usize, usize)> {
+    let mut entries = Vec::new();
+    let mut errors = 0;
+    for entry in walkdir::WalkDir::new(dir).sort_by_file_name().min_depth(1) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                // A folder that can't be listed; the walk goes on without it
+                let Some(path) = e.path().and_then(|path| path.strip_prefix(dir).ok()) else {
+                    return Err(e.into());
+                };
+                crate::utils::log_error(&format!("Snapshot skipped {}: {}", path.display(), e));
+                entries.push(SnapshotEntry::failed(path.to_path_buf(), &e));
+                errors += 1;
+                continue;
+            }
+        };
+        let path = entry.path().strip_prefix(dir)?.to_path_buf();
+        if entry.file_type().is_dir() {
+            entries.push(SnapshotEntry::new(path));
+            continue;
+        }
+        let metadata = match fs::metadata(entry.path()) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            // Dangling, or pointing at a folder the walk doesn't enter
+            _ if entry.path_is_symlink() => {
+                let target = fs::read_link(entry.path()).unwrap_or_default();
+                entries.push(SnapshotEntry {
+                    link: Some(target),
+                    ..SnapshotEntry::new(path)
+                });
+                continue;
+            }
+            Ok(metadata) => metadata,
+            Err(e) => {
+                crate::utils::log_error(&format!("Snapshot skipped {}: {}", path.display(), e));
+                entries.push(SnapshotEntry::failed(path, &e));
+                errors += 1;
+                continue;
+            }
+        };
+        match digest::<Sha256>(entry.path()) {
+            Ok(hash) => entries.push(SnapshotEntry {
+                size: Some(metadata.len()),
+                modified: metadata.modified().ok(),
+                sha256: Some(hash),
+                ..SnapshotEntry::new(path)
+            }),
+            Err(e) => {
+                crate::utils::log_error(&format!("Snapshot skipped {}: {}", path.display(), e));
+                entries.push(SnapshotEntry::failed(path, &e));
+                errors += 1;
+            }
+        }
+    }
+
+    let count = entries.len();
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        root: fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf()),
+        created: SystemTime::now(),
+        entries,
+    };
+    fs::write(output, serde_json::to_string_pretty(&snapshot)?)
+        .with_context(|| format!("writing {}", output.display()))?;
+    Ok((count, errors))
+}
+
+fn unescape(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                result.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                result.push('\\');
+                chars.next();
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Hex digest of a file's contents, made with the algorithm that produced
+/// `expected` so the two can be compared.
+pub fn file_digest(path: &Path, expected: &str) -> Result<String> {
+    match expected.len() {
+        // The ETag of an S3 object uploaded in one piece
+        #[cfg(feature = "s3")]
+        32 => digest::<md5::Md5>(path),
+        64 => digest::<Sha256>(path),
+        128 => digest::<Sha512>(path),
+        len => bail!("no hash algorithm with {} hex digits", len),
+    }
+}
+
+/// Algorithm of the hashes `H` shows, set as `hash` in the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 2] = [HashAlgorithm::Sha256, HashAlgorithm::Sha512];
+
+    /// The name used in the config file, like `sha256sum` without `sum`.
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+            .with_context(|| format!("expected `sha256` or `sha512`, got `{}`", name))
+    }
+}
+
+/// Hex digest of a file's contents, calling `progress` with the bytes read
+/// so far as it goes. Stops with an error once `cancel` is set.
+pub fn file_hash(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    cancel: &AtomicBool,
+    progress: impl FnMut(u64),
+) -> Result<String> {
+    match algorithm {
+        HashAlgorithm::Sha256 => digest_with::<Sha256>(path, Some(cancel), progress),
+        HashAlgorithm::Sha512 => digest_with::<Sha512>(path, Some(cancel), progress),
+    }
+}
+
+fn digest<D: Digest>(path: &Path) -> Result<String> {
+    digest_with::<D>(path, None, |_| {})
+}
+
+fn digest_with<D: Digest>(
+    path: &Path,
+    cancel: Option<&AtomicBool>,
+    mut progress: impl FnMut(u64),
+) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = D::new();
+    let mut buffer = vec![0; 256 * 1024];
+    let mut done = 0;
+    loop {
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            bail!("hashing cancelled");
+        }
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        crate::compare::count_read(read, cancel);
+        hasher.update(&buffer[..read]);
+        done += read as u64;
+        progress(done);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Commands that put files of an extension into a canonical form before
+/// they are compared, e.g. `jq -S .` for JSON or `sort` for lockfiles, set
+/// in the `[normalize]` section of the config file.
+#[derive(Debug, Clone, Default)]
+pub struct Normalizers {
+    /// Extension without the dot, and the shell command
+    commands: Vec<(String, String)>,
+}
+
+impl Normalizers {
+    pub fn add(&mut self, extension: &str, command: &str) {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.commands.retain(|(known, _)| *known != extension);
+        self.commands.push((extension, command.to_string()));
+    }
+
+    /// The command for files named like `path`.
+    pub fn command_for(&self, path: &Path) -> Option<&str> {
+        let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+        self.commands
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, command)| command.as_str())
+    }
+}
+
+/// Run `command` through the shell with `file` on its standard input, and
+/// return the SHA-256 of what it prints.
+pub fn digest(command: &str, file: &Path) -> Result<Vec<u8>> {
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let input = fs::File::open(file).with_context(|| format!("opening {}", file.display()))?;
+    let output = Command::new(shell)
+        .args([flag, command])
+        .stdin(input)
+        .output()
+        .with_context(|| format!("running `{}`", command))?;
+    if !output.status.success() {
+        bail!(
+            "`{}` failed on {}: {}",
+            command,
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(Sha256::digest(&output.stdout).to_vec())
+}
+
+/// How comments are written in a language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CommentStyle {
+    /// `# ...` to the end of the line (shell, Python, YAML, TOML...)
+    Hash,
+    /// `// ...` and `/* ... */` (C, Rust, Java, JavaScript...)
+    Slash,
+}
+
+fn comment_style(path: &Path) -> Option<CommentStyle> {
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    if matches!(name.as_str(), "makefile" | "dockerfile" | "containerfile") {
+        return Some(CommentStyle::Hash);
+    }
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    match extension.as_str() {
+        "sh" | "bash" | "zsh" | "fish" | "py" | "rb" | "pl" | "r" | "yaml" | "yml" | "toml"
+        | "conf" | "cfg" | "ini" | "env" | "properties" | "mk" | "cmake" | "tf" | "nix" => {
+            Some(CommentStyle::Hash)
+        }
+        "c" | "h" | "cc" | "cpp" | "cxx" | "hpp" | "cs" | "java" | "kt" | "scala" | "swift"
+        | "go" | "rs" | "js" | "jsx" | "mjs" | "ts" | "tsx" | "css" | "scss" | "less" | "jsonc"
+        | "json5" | "proto" | "dart" | "php" => Some(CommentStyle::Slash),
+        _ => None,
+    }
+}
+
+/// Whether two source files are the same apart from comments, blank lines
+/// and trailing whitespace. None when their comment syntax isn't known or
+/// they aren't UTF-8 text, so the caller compares the raw bytes.
+pub fn same_ignoring_comments(left: &Path, right: &Path) -> Option<bool> {
+    let style = comment_style(left)?;
+    let left = fs::read_to_string(left).ok()?;
+    let right = fs::read_to_string(right).ok()?;
+    crate::compare::count_read(left.len() + right.len(), None);
+    Some(strip_comments(&left, style) == strip_comments(&right, style))
+}
+
+/// The non-blank lines of `text` with comments removed. Quoted strings
+/// (`"..."`, `'...'` and, with `//` comments, JavaScript's multi-line
+/// `` `...` ``) are skipped so `"http://..."` survives; `#` only starts a
+/// comment at the start of a line or after whitespace, unlike in `${#x}`.
+fn strip_comments(text: &str, style: CommentStyle) -> Vec<String> {
+    let mut code = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    // The quote of the string being read
+    let mut quote = None;
+    let mut in_block = false;
+
+    while let Some(c) = chars.next() {
+        if in_block {
+            if c == '*' && chars.peek() == Some(&'/') {
+                chars.next();
+                in_block = false;
+            } else if c == '\n' {
+                code.push(c);
+            }
+            continue;
+        }
+        if let Some(open) = quote {
+            code.push(c);
+            if c == '\\' {
+                if let Some(escaped) = chars.next() {
+                    code.push(escaped);
+                }
+            } else if c == open || (c == '\n' && open != '`') {
+                quote = None;
+            }
+            continue;
+        }
+        let line_comment = match style {
+            CommentStyle::Hash => c == '#' && code.chars().last().is_none_or(char::is_whitespace),
+            CommentStyle::Slash => c == '/' && chars.peek() == Some(&'/'),
+        };
+        if line_comment {
+            while chars.peek().is_some_and(|&next| next != '\n') {
+                chars.next();
+            }
+        } else if style == CommentStyle::Slash && c == '/' && chars.peek() == Some(&'*') {
+            chars.next();
+            in_block = true;
+        } else {
+            let template = style == CommentStyle::Slash && c == '`';
+            quote = (c == '"' || c == '\'' || template).then_some(c);
+            code.push(c);
+        }
+    }
+
+    code.lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect()
+}
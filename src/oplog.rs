@@ -0,0 +1,95 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::utils::format_file_size;
+
+/// One change made to the folders during a session.
+#[derive(Debug, Clone)]
+pub struct Operation {
+    /// Local time, `2024-05-01 14:03:22`
+    pub time: String,
+    /// `copy`, `delete`, `rename`...
+    pub action: &'static str,
+    /// What it was done to, e.g. `/src/a -> /dst/a`
+    pub detail: String,
+    /// Bytes written or removed, where that means something
+    pub bytes: Option<u64>,
+    /// Why it failed, for operations that did
+    pub error: Option<String>,
+}
+
+impl Operation {
+    /// One line for the popup and the log file.
+    pub fn line(&self) -> String {
+        let mut line = format!("{}  {:<11} {}", self.time, self.action, self.detail);
+        if let Some(bytes) = self.bytes {
+            line.push_str(&format!(" ({})", format_file_size(Some(bytes)).trim()));
+        }
+        if let Some(error) = &self.error {
+            line.push_str(&format!(" FAILED: {}", error));
+        }
+        line
+    }
+}
+
+/// Every copy, delete, rename and other change made to the folders since
+/// tudiff started, to tell afterwards what was done to a tree. Also appended
+/// to `file` as it happens, with `--session-log`, so a crash loses nothing.
+#[derive(Debug, Default)]
+pub struct SessionLog {
+    pub operations: Vec<Operation>,
+    file: Option<PathBuf>,
+}
+
+impl SessionLog {
+    pub fn new(file: Option<PathBuf>) -> Self {
+        Self {
+            operations: Vec::new(),
+            file,
+        }
+    }
+
+    /// Note a change that was made.
+    pub fn record(&mut self, action: &'static str, detail: String, bytes: Option<u64>) {
+        self.push(action, detail, bytes, None);
+    }
+
+    /// Note a change that failed, possibly halfway.
+    pub fn record_failure(&mut self, action: &'static str, detail: String, error: &str) {
+        self.push(action, detail, None, Some(error.to_string()));
+    }
+
+    fn push(
+        &mut self,
+        action: &'static str,
+        detail: String,
+        bytes: Option<u64>,
+        error: Option<String>,
+    ) {
+        let operation = Operation {
+            time: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            action,
+            // One line each, whatever the names hold
+            detail: detail.replace('\n', " "),
+            bytes,
+            error: error.map(|error| error.replace('\n', " ")),
+        };
+        crate::utils::log_info(&format!("Session: {}", operation.line()));
+        if let Some(file) = &self.file {
+            let written = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file)
+                .and_then(|mut out| writeln!(out, "{}", operation.line()));
+            if let Err(e) = written {
+                crate::utils::log_error(&format!(
+                    "Failed to write the session log {}: {}",
+                    file.display(),
+                    e
+                ));
+            }
+        }
+        self.operations.push(operation);
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{bail, Result};
+use std::path::{Component, Path, PathBuf};
+
+/// Folders that are named differently on the right, like `--map src=source`:
+/// the right's `source/` is compared with the left's `src/`, file to file,
+/// and shown under the left's name. The right's own `src/`, if it has one,
+/// is left out while mapped over.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathMap {
+    /// (left, right) relative paths
+    pairs: Vec<(PathBuf, PathBuf)>,
+}
+
+impl PathMap {
+    /// Parse `left=right` pairs of paths relative to the roots.
+    pub fn new(specs: &[String]) -> Result<Self> {
+        let mut pairs: Vec<(PathBuf, PathBuf)> = Vec::new();
+        for spec in specs {
+            let Some((left, right)) = spec.split_once('=') else {
+                bail!("expected `left=right`, got `{}`", spec);
+            };
+            let (left, right) = (relative(left, spec)?, relative(right, spec)?);
+            if pairs.iter().any(|(l, _)| *l == left) {
+                bail!("`{}` is mapped more than once", left.display());
+            }
+            if pairs.iter().any(|(_, r)| *r == right) {
+                bail!("`{}` is mapped to more than once", right.display());
+            }
+            pairs.push((left, right));
+        }
+        Ok(Self { pairs })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn pairs(&self) -> &[(PathBuf, PathBuf)] {
+        &self.pairs
+    }
+
+    /// Where the entry shown at `relative` lives below the right root, or
+    /// `None` if a mapped folder has taken its place there: with
+    /// `src=source`, the left's own `source/` has no counterpart.
+    pub fn to_right(&self, relative: &Path) -> Option<PathBuf> {
+        if let Some(actual) = Self::replace(&self.pairs, relative, |(left, right)| (left, right)) {
+            return Some(actual);
+        }
+        let covered = self
+            .pairs
+            .iter()
+            .any(|(_, right)| relative.starts_with(right));
+        (!covered).then(|| relative.to_path_buf())
+    }
+
+    /// Where an entry found at `actual` below the right root is shown, or
+    /// `None` if a mapped folder has taken its place.
+    pub fn from_right(&self, actual: &Path) -> Option<PathBuf> {
+        if let Some(shown) = Self::replace(&self.pairs, actual, |(left, right)| (right, left)) {
+            return Some(shown);
+        }
+        let covered = self.pairs.iter().any(|(left, _)| actual.starts_with(left));
+        (!covered).then(|| actual.to_path_buf())
+    }
+
+    /// `path` with the longest matching `from` prefix swapped for its `to`.
+    fn replace<'a>(
+        pairs: &'a [(PathBuf, PathBuf)],
+        path: &Path,
+        direction: impl Fn(&'a (PathBuf, PathBuf)) -> (&'a PathBuf, &'a PathBuf),
+    ) -> Option<PathBuf> {
+        pairs
+            .iter()
+            .map(direction)
+            .filter(|(from, _)| path.starts_with(from))
+            .max_by_key(|(from, _)| from.components().count())
+            .map(|(from, to)| to.join(path.strip_prefix(from).unwrap_or(path)))
+    }
+}
+
+/// A folder below the root, `src` or `lib/old`, without `..` or `/` at the
+/// start.
+fn relative(path: &str, spec: &str) -> Result<PathBuf> {
+    let path = Path::new(path.trim().trim_end_matches('/'));
+    let normal = path.components().all(|c| matches!(c, Component::Normal(_)));
+    if path.as_os_str().is_empty() || !normal {
+        bail!(
+            "`{}` in `{}` must be a folder below the root",
+            path.display(),
+            spec
+        );
+    }
+    Ok(path.components().collect())
+}
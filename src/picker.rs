@@ -0,0 +1,154 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::widgets::ListState;
+use std::path::PathBuf;
+
+#[derive(PartialEq)]
+pub enum PickerMode {
+    Recent,
+    Input,
+}
+
+pub enum PickerOutcome {
+    Pending,
+    Selected(PathBuf, PathBuf),
+    Quit,
+}
+
+/// Startup screen shown when tudiff is launched without directories.
+pub struct Picker {
+    pub recent: Vec<(PathBuf, PathBuf)>,
+    pub list_state: ListState,
+    pub mode: PickerMode,
+    pub inputs: [String; 2],
+    pub active_input: usize,
+    pub error: Option<String>,
+}
+
+impl Picker {
+    pub fn new(recent: Vec<(PathBuf, PathBuf)>) -> Self {
+        let mut list_state = ListState::default();
+        list_state.select(Some(0));
+
+        let mode = if recent.is_empty() {
+            PickerMode::Input
+        } else {
+            PickerMode::Recent
+        };
+
+        Self {
+            recent,
+            list_state,
+            mode,
+            inputs: [String::new(), String::new()],
+            active_input: 0,
+            error: None,
+        }
+    }
+
+    /// Number of rows in the list: every recent pair plus the "new paths" entry.
+    pub fn row_count(&self) -> usize {
+        self.recent.len() + 1
+    }
+
+    pub fn handle_key_event(&mut self, key: KeyEvent) -> PickerOutcome {
+        if key.kind != KeyEventKind::Press {
+            return PickerOutcome::Pending;
+        }
+
+        match self.mode {
+            PickerMode::Recent => self.handle_recent_key(key.code),
+            PickerMode::Input => self.handle_input_key(key.code),
+        }
+    }
+
+    fn handle_recent_key(&mut self, code: KeyCode) -> PickerOutcome {
+        let selected = self.list_state.selected().unwrap_or(0);
+
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => return PickerOutcome::Quit,
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.list_state.select(Some(selected.saturating_sub(1)));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.list_state
+                    .select(Some((selected + 1).min(self.row_count() - 1)));
+            }
+            KeyCode::Char('n') => self.start_input(None),
+            KeyCode::Char('e') => {
+                if let Some(pair) = self.recent.get(selected).cloned() {
+                    self.start_input(Some(pair));
+                }
+            }
+            KeyCode::Enter => match self.recent.get(selected).cloned() {
+                Some((left, right)) => return self.try_select(left, right),
+                None => self.start_input(None),
+            },
+            _ => {}
+        }
+
+        PickerOutcome::Pending
+    }
+
+    fn handle_input_key(&mut self, code: KeyCode) -> PickerOutcome {
+        match code {
+            KeyCode::Esc => {
+                if self.recent.is_empty() {
+                    return PickerOutcome::Quit;
+                }
+                self.mode = PickerMode::Recent;
+                self.error = None;
+            }
+            KeyCode::Tab | KeyCode::Down | KeyCode::Up | KeyCode::BackTab => {
+                self.active_input = 1 - self.active_input;
+            }
+            KeyCode::Backspace => {
+                self.inputs[self.active_input].pop();
+            }
+            KeyCode::Char(c) => {
+                self.inputs[self.active_input].push(c);
+            }
+            KeyCode::Enter => {
+                if self.active_input == 0 {
+                    self.active_input = 1;
+                } else {
+                    let left = PathBuf::from(expand_home(self.inputs[0].trim()));
+                    let right = PathBuf::from(expand_home(self.inputs[1].trim()));
+                    return self.try_select(left, right);
+                }
+            }
+            _ => {}
+        }
+
+        PickerOutcome::Pending
+    }
+
+    fn start_input(&mut self, prefill: Option<(PathBuf, PathBuf)>) {
+        self.inputs = match prefill {
+            Some((left, right)) => [left.display().to_string(), right.display().to_string()],
+            None => [String::new(), String::new()],
+        };
+        self.active_input = 0;
+        self.error = None;
+        self.mode = PickerMode::Input;
+    }
+
+    fn try_select(&mut self, left: PathBuf, right: PathBuf) -> PickerOutcome {
+        for dir in [&left, &right] {
+            if !dir.is_dir() {
+                self.error = Some(format!("'{}' is not a valid directory", dir.display()));
+                return PickerOutcome::Pending;
+            }
+        }
+        PickerOutcome::Selected(left, right)
+    }
+}
+
+/// `path` with a leading `~` replaced by the home folder.
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home, rest)
+        }
+        _ => path.to_string(),
+    }
+}
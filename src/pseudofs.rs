@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::Path;
+
+/// What a scan does with folders on kernel pseudo filesystems like /proc
+/// and /sys. Their files change while being read, some never end, and
+/// comparing them is almost always a mistake, like pointing tudiff at `/`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PseudoFsPolicy {
+    /// Leave them out with a warning; a root on one is refused
+    #[default]
+    Skip,
+    /// Scan them, with a warning
+    Warn,
+    /// Scan them like any other folder
+    Scan,
+}
+
+/// Filesystem magic numbers from `linux/magic.h`, with the names `mount`
+/// shows for them. tmpfs is missing: /dev and /run can't be told apart
+/// from an ordinary RAM disk that way.
+#[cfg(target_os = "linux")]
+const PSEUDO_FILESYSTEMS: &[(u32, &str)] = &[
+    (0x9fa0, "proc"),
+    (0x6265_6572, "sysfs"),
+    (0x1cd1, "devpts"),
+    (0x6462_6720, "debugfs"),
+    (0x7472_6163, "tracefs"),
+    (0x7363_6673, "securityfs"),
+    (0x0027_e0eb, "cgroup"),
+    (0x6367_7270, "cgroup2"),
+    (0xcafe_4a11, "bpf"),
+    (0x6165_676c, "pstore"),
+    (0x6265_6570, "configfs"),
+    (0x6573_5543, "fusectl"),
+    (0xde5e_81e4, "efivarfs"),
+    (0x1980_0202, "mqueue"),
+];
+
+/// The name of the pseudo filesystem `path` is on, if it is on one.
+#[cfg(target_os = "linux")]
+pub fn pseudo_filesystem(path: &Path) -> Option<&'static str> {
+    let magic = rustix::fs::statfs(path).ok()?.f_type as u32;
+    PSEUDO_FILESYSTEMS
+        .iter()
+        .find(|(known, _)| *known == magic)
+        .map(|(_, name)| *name)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pseudo_filesystem(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+/// The pseudo filesystems of the folders a walk goes through, parents
+/// before children. A folder on its parent's device is on its filesystem
+/// too, so `statfs` only runs where the device changes.
+#[derive(Default)]
+pub struct MountTracker {
+    /// Device and pseudo filesystem of each folder down to the last one
+    parents: Vec<(Option<u64>, Option<&'static str>)>,
+}
+
+impl MountTracker {
+    /// The pseudo filesystem of the folder at `depth` below the start of the
+    /// walk, and that of its parent, `None` for the start. Call it for every
+    /// folder walked.
+    pub fn enter(
+        &mut self,
+        dir: &Path,
+        depth: usize,
+        metadata: Option<&fs::Metadata>,
+    ) -> (Option<&'static str>, Option<&'static str>) {
+        self.parents.truncate(depth);
+        let device = metadata.and_then(device);
+        let parent = self.parents.last().copied().unwrap_or_default();
+        let kind = match (device, parent) {
+            (Some(device), (Some(parent_device), parent_kind)) if device == parent_device => {
+                parent_kind
+            }
+            _ => pseudo_filesystem(dir),
+        };
+        self.parents.push((device, kind));
+        (kind, parent.1)
+    }
+}
+
+#[cfg(unix)]
+fn device(metadata: &fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &fs::Metadata) -> Option<u64> {
+    None
+}
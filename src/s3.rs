@@ -0,0 +1,397 @@
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::manifest::{Manifest, ManifestEntry};
+
+/// Bytes read from each end of an object for ranged content checks.
+const RANGE_BYTES: u64 = 64 * 1024;
+
+/// A bucket and key prefix, from `s3://bucket/prefix`, together with where
+/// and how to reach it.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub bucket: String,
+    /// Empty, or ending in `/`
+    pub prefix: String,
+    region: String,
+    /// Custom endpoint (MinIO, R2, ...) addressed path-style
+    endpoint: Option<String>,
+    credentials: Option<Credentials>,
+}
+
+#[derive(Debug, Clone)]
+struct Credentials {
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl Location {
+    /// Parse the URL and pick up the standard `AWS_*` environment variables.
+    /// Without credentials, requests are sent unsigned (public buckets).
+    pub fn from_url(url: &Path) -> Result<Self> {
+        let url = url.to_string_lossy();
+        let Some(rest) = url.strip_prefix("s3://") else {
+            bail!("`{}` is not an s3:// URL", url);
+        };
+        let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+        if bucket.is_empty() {
+            bail!("`{}` names no bucket", url);
+        }
+        let prefix = match prefix.trim_end_matches('/') {
+            "" => String::new(),
+            prefix => format!("{}/", prefix),
+        };
+
+        let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let credentials = match (env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key), Some(secret_key)) => Some(Credentials {
+                access_key,
+                secret_key,
+                session_token: env("AWS_SESSION_TOKEN"),
+            }),
+            _ => None,
+        };
+
+        Ok(Self {
+            bucket: bucket.to_string(),
+            prefix,
+            region: env("AWS_REGION")
+                .or_else(|| env("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            endpoint: env("AWS_ENDPOINT_URL_S3").or_else(|| env("AWS_ENDPOINT_URL")),
+            credentials,
+        })
+    }
+
+    /// Host and path of a key (empty for the bucket itself).
+    fn url_parts(&self, key: &str) -> (String, String, String) {
+        match &self.endpoint {
+            Some(endpoint) => {
+                let endpoint = endpoint.trim_end_matches('/');
+                let (scheme, host) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+                let path = format!("/{}/{}", self.bucket, uri_encode(key, false));
+                (scheme.to_string(), host.to_string(), path)
+            }
+            // Dots in the bucket name break the wildcard certificate of
+            // virtual-hosted addresses
+            None if self.bucket.contains('.') => (
+                "https".to_string(),
+                format!("s3.{}.amazonaws.com", self.region),
+                format!("/{}/{}", self.bucket, uri_encode(key, false)),
+            ),
+            None => (
+                "https".to_string(),
+                format!("{}.s3.{}.amazonaws.com", self.bucket, self.region),
+                format!("/{}", uri_encode(key, false)),
+            ),
+        }
+    }
+
+    /// Send a signed GET request for `key` with the given query parameters
+    /// and extra headers.
+    fn get(
+        &self,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+    ) -> Result<ureq::Response> {
+        self.send("GET", key, query, headers)
+    }
+
+    /// Send a signed request without a body.
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+    ) -> Result<ureq::Response> {
+        let (scheme, host, path) = self.url_parts(key);
+        let mut query: Vec<(String, String)> = query
+            .iter()
+            .map(|(name, value)| (uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        query.sort();
+        let query = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let url = if query.is_empty() {
+            format!("{}://{}{}", scheme, host, path)
+        } else {
+            format!("{}://{}{}?{}", scheme, host, path, query)
+        };
+        let mut request = ureq::request(method, &url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        if let Some(credentials) = &self.credentials {
+            for (name, value) in self.sign(credentials, method, &host, &path, &query, headers) {
+                request = request.set(&name, &value);
+            }
+        }
+
+        match request.call() {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(code, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let message = xml_value(&body, "Message").unwrap_or(body);
+                bail!("S3 answered {}: {}", code, message.trim())
+            }
+            Err(e) => Err(e).context("contacting S3"),
+        }
+    }
+
+    /// AWS Signature Version 4 headers for a request without a body.
+    fn sign(
+        &self,
+        credentials: &Credentials,
+        method: &str,
+        host: &str,
+        path: &str,
+        query: &str,
+        extra: &[(&str, String)],
+    ) -> Vec<(String, String)> {
+        let now = chrono::Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(b""));
+
+        let mut headers: Vec<(String, String)> = vec![
+            ("host".to_string(), host.to_string()),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), timestamp.clone()),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        headers.extend(
+            extra
+                .iter()
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string())),
+        );
+        headers.sort();
+
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, path, query, canonical_headers, signed_headers, payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            timestamp,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = [date.as_str(), self.region.as_str(), "s3", "aws4_request"]
+            .iter()
+            .fold(
+                format!("AWS4{}", credentials.secret_key).into_bytes(),
+                |key, part| hmac(&key, part.as_bytes()),
+            );
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        let mut result: Vec<(String, String)> = headers
+            .into_iter()
+            .filter(|(name, _)| name.starts_with("x-amz-"))
+            .collect();
+        result.push((
+            "authorization".to_string(),
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                credentials.access_key, scope, signed_headers, signature
+            ),
+        ));
+        result
+    }
+}
+
+/// List the objects under the location as a manifest. ETags that look like
+/// an MD5 are kept as the hash, to be trusted only after [`etag_is_md5`];
+/// other objects can only be compared by size (and by ranged reads, see
+/// [`ranged_same`]).
+pub fn list(url: &Path) -> Result<Manifest> {
+    let location = Location::from_url(url)?;
+    let mut manifest = Manifest::default();
+    let mut token: Option<String> = None;
+
+    loop {
+        let mut query = vec![("list-type", "2"), ("prefix", location.prefix.as_str())];
+        if let Some(token) = &token {
+            query.push(("continuation-token", token));
+        }
+        let body = location
+            .get("", &query, &[])?
+            .into_string()
+            .context("reading the object listing")?;
+
+        for object in xml_elements(&body, "Contents") {
+            let Some(key) = xml_value(&object, "Key") else {
+                continue;
+            };
+            let Some(relative) = key.strip_prefix(&location.prefix) else {
+                continue;
+            };
+            // Zero-byte keys ending in a slash are how consoles create folders
+            if let Some(folder) = relative.strip_suffix('/') {
+                if !folder.is_empty() {
+                    manifest.folders.insert(PathBuf::from(folder));
+                }
+                continue;
+            }
+
+            let etag = xml_value(&object, "ETag").unwrap_or_default();
+            let etag = etag.trim_matches('"').to_ascii_lowercase();
+            let hash =
+                (etag.len() == 32 && etag.bytes().all(|b| b.is_ascii_hexdigit())).then_some(etag);
+            let modified = xml_value(&object, "LastModified")
+                .and_then(|time| chrono::DateTime::parse_from_rfc3339(&time).ok())
+                .map(SystemTime::from);
+            manifest.entries.insert(
+                PathBuf::from(relative),
+                ManifestEntry {
+                    hash,
+                    size: xml_value(&object, "Size").and_then(|size| size.parse().ok()),
+                    modified,
+                },
+            );
+        }
+
+        token = xml_value(&body, "NextContinuationToken");
+        if xml_value(&body, "IsTruncated").as_deref() != Some("true") || token.is_none() {
+            break;
+        }
+    }
+
+    manifest.location = Some(location);
+    Ok(manifest)
+}
+
+/// Whether the ETag of the object at `relative` is the MD5 of its contents:
+/// only for single-part uploads stored unencrypted or with S3-managed keys,
+/// not with KMS or customer-provided keys. The listing doesn't tell, so the
+/// object's headers are asked for.
+pub fn etag_is_md5(location: &Location, relative: &Path) -> Result<bool> {
+    let response = location.send("HEAD", &object_key(location, relative), &[], &[])?;
+    let single_part = response
+        .header("ETag")
+        .is_some_and(|etag| !etag.contains('-'));
+    let encryption = response.header("x-amz-server-side-encryption");
+    let customer_key = response.header("x-amz-server-side-encryption-customer-algorithm");
+    Ok(single_part && matches!(encryption, None | Some("AES256")) && customer_key.is_none())
+}
+
+/// The key of the object at `relative` below the location's prefix.
+fn object_key(location: &Location, relative: &Path) -> String {
+    format!(
+        "{}{}",
+        location.prefix,
+        relative.to_string_lossy().replace('\\', "/")
+    )
+}
+
+/// Compare the first and last bytes of the object at `relative` with `file`
+/// of the same size, which catches most changes without a full download.
+pub fn ranged_same(location: &Location, relative: &Path, file: &Path) -> Result<bool> {
+    let key = object_key(location, relative);
+    let mut local = fs::File::open(file)?;
+    let size = local.metadata()?.len();
+
+    let mut ranges = vec![(0, size.min(RANGE_BYTES))];
+    if size > RANGE_BYTES {
+        let start = size.saturating_sub(RANGE_BYTES).max(RANGE_BYTES);
+        ranges.push((start, size - start));
+    }
+    for (start, len) in ranges {
+        if len == 0 {
+            continue;
+        }
+        let range = format!("bytes={}-{}", start, start + len - 1);
+        let mut remote = Vec::new();
+        location
+            .get(&key, &[], &[("Range", range)])?
+            .into_reader()
+            .take(len)
+            .read_to_end(&mut remote)?;
+
+        let mut expected = vec![0; len as usize];
+        local.seek(SeekFrom::Start(start))?;
+        local.read_exact(&mut expected)?;
+        if remote != expected {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Percent-encode everything but unreserved characters, as SigV4 wants it.
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bodies of all `<tag>` elements, for the flat XML S3 answers with.
+fn xml_elements(xml: &str, tag: &str) -> Vec<String> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut elements = Vec::new();
+    loop {
+        match reader.read_event() {
+            Ok(quick_xml::events::Event::Start(start))
+                if start.name().as_ref() == tag.as_bytes() =>
+            {
+                match reader.read_text(start.name()) {
+                    Ok(text) => elements.push(text.into_owned()),
+                    Err(_) => break,
+                }
+            }
+            Ok(quick_xml::events::Event::Eof) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+    elements
+}
+
+/// The unescaped text of the first `<tag>` element.
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let raw = xml_elements(xml, tag).into_iter().next()?;
+    quick_xml::escape::unescape(&raw)
+        .ok()
+        .map(|text| text.into_owned())
+}
@@ -0,0 +1,64 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::compare::{DiffEntry, DirectoryComparison};
+
+const RESULT_VERSION: u32 = 1;
+
+/// The JSON written by `--save-result`: everything the panels show, so a
+/// comparison made on a server can be browsed elsewhere with
+/// `--load-result`, without the folders.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedResult {
+    version: u32,
+    /// The compared folders, on the machine that compared them
+    left_dir: PathBuf,
+    right_dir: PathBuf,
+    created: SystemTime,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unreadable: Vec<PathBuf>,
+    entries: Vec<DiffEntry>,
+}
+
+/// Write every entry of `comparison` with its status and metadata to
+/// `output`. Returns the number of entries.
+pub fn save(comparison: &DirectoryComparison, output: &Path) -> Result<usize> {
+    let result = SavedResult {
+        version: RESULT_VERSION,
+        left_dir: comparison.left_dir.clone(),
+        right_dir: comparison.right_dir.clone(),
+        created: SystemTime::now(),
+        warnings: comparison.warnings.clone(),
+        unreadable: comparison.unreadable.clone(),
+        entries: comparison.entries().collect(),
+    };
+    fs::write(output, serde_json::to_string(&result)?)
+        .with_context(|| format!("writing {}", output.display()))?;
+    Ok(result.entries.len())
+}
+
+/// Read a comparison written by `save`.
+pub fn load(input: &Path) -> Result<DirectoryComparison> {
+    let content =
+        fs::read_to_string(input).with_context(|| format!("reading {}", input.display()))?;
+    let result: SavedResult = serde_json::from_str(&content)
+        .with_context(|| format!("{} is not a saved tudiff result", input.display()))?;
+    if result.version != RESULT_VERSION {
+        bail!(
+            "{} was saved by another version of tudiff (format {})",
+            input.display(),
+            result.version
+        );
+    }
+
+    let mut comparison =
+        DirectoryComparison::from_entries(result.left_dir, result.right_dir, result.entries);
+    comparison.warnings = result.warnings;
+    comparison.unreadable = result.unreadable;
+    comparison.loaded_from = Some(input.to_path_buf());
+    Ok(comparison)
+}
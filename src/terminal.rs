@@ -1,12 +1,17 @@
-use anyhow::Result;
-use crossterm::event::{self, Event};
+use anyhow::{Context, Result};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
 use ratatui::{backend::Backend, Terminal};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
 use std::time::Duration;
 
-use crate::app::App;
-use crate::compare::{DirectoryComparison, FileStatus};
-use crate::ui::draw_ui;
+use crate::app::{progress_percentage, App, ViewOptions};
+use crate::compare::{CompareOptions, DirectoryComparison, FileStatus, LimitChoice, LimitPrompt};
+use crate::config::Keymap;
+use crate::copy::CopyBackend;
+use crate::picker::{Picker, PickerOutcome};
+use crate::ui::{draw_limit_prompt, draw_picker, draw_scan_progress, draw_ui};
+use crate::utils::Worker;
 
 #[derive(Clone)]
 pub struct TerminalState;
@@ -28,6 +33,8 @@ impl TerminalState {
 
         print!("\x1b[?25h");
         print!("\x1b[?12h");
+        // Pop the window title pushed on startup
+        print!("\x1b[23;0t");
         use std::io::Write;
         std::io::stdout().flush().ok();
 
@@ -68,6 +75,8 @@ impl TerminalManager {
         )?;
 
         print!("\x1b[?12l");
+        // Push the window title so restore() can bring it back
+        print!("\x1b[22;0t");
         use std::io::Write;
         stdout.flush()?;
 
@@ -91,8 +100,16 @@ impl TerminalManager {
     }
 }
 
-pub fn run_tui(dir1: std::path::PathBuf, dir2: std::path::PathBuf) -> Result<()> {
-    let comparison = DirectoryComparison::new(dir1, dir2)?;
+pub fn run_tui(
+    dir1: std::path::PathBuf,
+    dir2: std::path::PathBuf,
+    more_targets: Vec<std::path::PathBuf>,
+    options: CompareOptions,
+    view: ViewOptions,
+    keymap: Keymap,
+    copy_backend: CopyBackend,
+) -> Result<()> {
+    let resumable = options.checkpoint;
     let _terminal_manager = TerminalManager::new()?;
 
     let backend = ratatui::backend::CrosstermBackend::new(std::io::stdout());
@@ -100,34 +117,290 @@ pub fn run_tui(dir1: std::path::PathBuf, dir2: std::path::PathBuf) -> Result<()>
 
     terminal.clear()?;
 
-    let result = run_app(&mut terminal, comparison);
+    let mut stopped = false;
+    let result = match compare_with_progress(&mut terminal, dir1, dir2, more_targets, options) {
+        Ok(Some((comparison, target_comparisons))) => run_app(
+            &mut terminal,
+            comparison,
+            target_comparisons,
+            &view,
+            keymap,
+            copy_backend,
+        ),
+        Ok(None) => {
+            stopped = true;
+            Ok(())
+        }
+        Err(e) => Err(e),
+    };
 
     _terminal_manager.restore()?;
     ensure_cursor_visible();
 
+    if stopped && resumable {
+        eprintln!(
+            "Comparison stopped. Run tudiff again with --resume to continue where it left off."
+        );
+    } else if stopped {
+        eprintln!("Comparison stopped.");
+    }
+    result
+}
+
+/// Browse a comparison made earlier, e.g. one read with `--load-result`.
+pub fn run_tui_with(
+    comparison: DirectoryComparison,
+    view: ViewOptions,
+    keymap: Keymap,
+    copy_backend: CopyBackend,
+) -> Result<()> {
+    let _terminal_manager = TerminalManager::new()?;
+
+    let backend = ratatui::backend::CrosstermBackend::new(std::io::stdout());
+    let mut terminal = ratatui::Terminal::new(backend)?;
+
+    terminal.clear()?;
+    let result = run_app(
+        &mut terminal,
+        comparison,
+        Vec::new(),
+        &view,
+        keymap,
+        copy_backend,
+    );
+
+    _terminal_manager.restore()?;
+    ensure_cursor_visible();
     result
 }
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, comparison: DirectoryComparison) -> Result<()> {
+enum ScanMessage {
+    Progress(String),
+    /// The scan passed the entry limit and waits for the answer
+    Limit(usize, mpsc::Sender<LimitChoice>),
+    Done(Result<Vec<DirectoryComparison>>),
+}
+
+/// Compare the source with every target behind a progress popup. Esc or
+/// Ctrl+C stops the scan and returns `None`; paths finished by then are
+/// kept in the checkpoint for the next run. A scan passing the entry limit
+/// asks whether to go on.
+fn compare_with_progress<B: Backend>(
+    terminal: &mut Terminal<B>,
+    source: PathBuf,
+    first_target: PathBuf,
+    more_targets: Vec<PathBuf>,
+    mut options: CompareOptions,
+) -> Result<Option<(DirectoryComparison, Vec<DirectoryComparison>)>> {
+    let (tx, rx) = mpsc::channel();
+    let ask = tx.clone();
+    options.limit_prompt = Some(LimitPrompt(Arc::new(move |processed| {
+        let (reply, answer) = mpsc::channel();
+        let _ = ask.send(ScanMessage::Limit(processed, reply));
+        answer.recv().unwrap_or(LimitChoice::Abort)
+    })));
+    let worker = Worker::spawn(move |cancel| {
+        let result = std::iter::once(first_target)
+            .chain(more_targets)
+            .map(|target| {
+                let tx = tx.clone();
+                DirectoryComparison::new_with_progress(
+                    source.clone(),
+                    target,
+                    options.clone(),
+                    &cancel,
+                    move |message| {
+                        let _ = tx.send(ScanMessage::Progress(message.to_string()));
+                    },
+                )
+            })
+            .collect();
+        let _ = tx.send(ScanMessage::Done(result));
+    });
+
+    let mut message = "Starting directory scan...".to_string();
+    let mut limit: Option<(usize, mpsc::Sender<LimitChoice>)> = None;
+    let mut dirty = true;
+    loop {
+        while let Ok(update) = rx.try_recv() {
+            match update {
+                ScanMessage::Progress(text) => {
+                    message = text;
+                    dirty = true;
+                }
+                ScanMessage::Limit(processed, reply) => {
+                    limit = Some((processed, reply));
+                    dirty = true;
+                }
+                ScanMessage::Done(result) => {
+                    let mut comparisons = result?.into_iter();
+                    let comparison = comparisons.next().context("nothing was compared")?;
+                    return Ok(Some((comparison, comparisons.collect())));
+                }
+            }
+        }
+
+        if dirty {
+            terminal.draw(|f| match &limit {
+                Some((processed, _)) => draw_limit_prompt(f, *processed),
+                None => draw_scan_progress(f, &message, progress_percentage(&message)),
+            })?;
+            dirty = false;
+        }
+
+        if event::poll(Duration::from_millis(50))? {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press && limit.is_some() => {
+                    let choice = match key.code {
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            LimitChoice::Abort
+                        }
+                        KeyCode::Char('c') | KeyCode::Enter => LimitChoice::Continue,
+                        KeyCode::Char('m') => LimitChoice::MetadataOnly,
+                        KeyCode::Char('a') | KeyCode::Esc => LimitChoice::Abort,
+                        _ => continue,
+                    };
+                    if let Some((_, reply)) = limit.take() {
+                        let _ = reply.send(choice);
+                    }
+                    if choice == LimitChoice::Abort {
+                        stop_scan(worker, rx);
+                        return Ok(None);
+                    }
+                    dirty = true;
+                }
+                Event::Key(key)
+                    if key.kind == KeyEventKind::Press
+                        && (key.code == KeyCode::Esc
+                            || (key.code == KeyCode::Char('c')
+                                && key.modifiers.contains(KeyModifiers::CONTROL))) =>
+                {
+                    stop_scan(worker, rx);
+                    return Ok(None);
+                }
+                Event::Resize(_, _) => dirty = true,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Stop a scan of `compare_with_progress`. The channel goes first: a limit
+/// question still in it, or asked after, is answered with `Abort` once its
+/// reply channel is dropped, where waiting for the thread would hang.
+fn stop_scan(worker: Worker, rx: mpsc::Receiver<ScanMessage>) {
+    drop(rx);
+    worker.cancel();
+}
+
+/// Let the user choose a recent directory pair or type new paths.
+/// Returns `None` when the picker was dismissed.
+pub fn run_picker() -> Result<Option<(std::path::PathBuf, std::path::PathBuf)>> {
+    let terminal_manager = TerminalManager::new()?;
+
+    let backend = ratatui::backend::CrosstermBackend::new(std::io::stdout());
+    let mut terminal = ratatui::Terminal::new(backend)?;
+    terminal.clear()?;
+
+    let mut picker = Picker::new(crate::history::load_recent());
+    let mut dirty = true;
+    let result = loop {
+        if dirty {
+            draw_picker(&mut terminal, &mut picker)?;
+            dirty = false;
+        }
+
+        if event::poll(Duration::from_millis(50))? {
+            match event::read()? {
+                Event::Key(key) => {
+                    dirty = true;
+                    match picker.handle_key_event(key) {
+                        PickerOutcome::Pending => {}
+                        PickerOutcome::Selected(left, right) => break Some((left, right)),
+                        PickerOutcome::Quit => break None,
+                    }
+                }
+                Event::Resize(_, _) => dirty = true,
+                _ => {}
+            }
+        }
+    };
+
+    terminal_manager.restore()?;
+    Ok(result)
+}
+
+fn run_app<B: Backend>(
+    terminal: &mut Terminal<B>,
+    comparison: DirectoryComparison,
+    target_comparisons: Vec<DirectoryComparison>,
+    view: &ViewOptions,
+    keymap: Keymap,
+    copy_backend: CopyBackend,
+) -> Result<()> {
     let mut app = App::new(comparison);
+    app.keymap = keymap;
+    app.copy_backend = copy_backend;
+    if !target_comparisons.is_empty() {
+        app.add_targets(target_comparisons);
+    }
+    app.apply_view_options(view);
     let mut need_redraw = true;
+    // Only draw after input, resizes and refresh or copy progress; idle sessions stay idle
+    let mut dirty = true;
+    let mut window_title = String::new();
 
     loop {
-        app.check_refresh_progress();
+        if app.check_refresh_progress() {
+            dirty = true;
+        }
+        if app.check_copy_progress() {
+            dirty = true;
+        }
+        if app.check_similarity_progress() {
+            dirty = true;
+        }
+        if app.check_transfer_progress() {
+            dirty = true;
+        }
+        if app.check_hash_progress() {
+            dirty = true;
+        }
+        if app.check_diffstat_progress() {
+            dirty = true;
+        }
+        if app.expire_flash() {
+            dirty = true;
+        }
 
         if need_redraw {
             terminal.clear()?;
             need_redraw = false;
+            dirty = true;
         }
 
-        draw_ui(terminal, &mut app)?;
+        if dirty {
+            draw_ui(terminal, &mut app)?;
+            dirty = false;
+
+            let title = app.window_title();
+            if title != window_title {
+                crossterm::execute!(std::io::stdout(), crossterm::terminal::SetTitle(&title))?;
+                window_title = title;
+            }
+        }
 
         if event::poll(Duration::from_millis(50))? {
             match event::read()? {
                 Event::Key(key) => {
                     if app.handle_key_event(key)? {
+                        app.save_view();
                         return Ok(());
                     }
+                    dirty = true;
+                    if app.take_screen_replaced() {
+                        need_redraw = true;
+                    }
 
                     if let crossterm::event::KeyCode::Enter = key.code {
                         if key.kind == crossterm::event::KeyEventKind::Press {
@@ -136,7 +409,19 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, comparison: DirectoryComparis
                     }
                 }
                 Event::Mouse(mouse) => {
-                    app.handle_mouse_event(mouse);
+                    // Moving only redraws when the pointer gets onto another row
+                    if mouse.kind == crossterm::event::MouseEventKind::Moved {
+                        if app.move_mouse(mouse.column, mouse.row) {
+                            dirty = true;
+                        }
+                    } else {
+                        app.handle_mouse_event(mouse);
+                        dirty = true;
+                    }
+                }
+                Event::Resize(width, height) => {
+                    app.resize(width, height);
+                    need_redraw = true;
                 }
                 _ => {}
             }
@@ -144,16 +429,138 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, comparison: DirectoryComparis
     }
 }
 
-pub fn launch_external_editor(status: &FileStatus, left_path: &Path, right_path: &Path) -> Result<()> {
-    let _ = crossterm::terminal::disable_raw_mode();
-    let _ = crossterm::execute!(
-        std::io::stdout(),
-        crossterm::terminal::LeaveAlternateScreen
-    );
-
-    print!("\x1b[2J\x1b[H");
+/// Put `text` on the clipboard with an OSC 52 escape sequence, which the
+/// terminal handles, so it works over SSH too. Inside tmux this needs
+/// `set-clipboard on`.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
     use std::io::Write;
-    let _ = std::io::stdout().flush();
+
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let triple = chunk.iter().enumerate().fold(0u32, |triple, (i, byte)| {
+            triple | (*byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// File managers that run inside the terminal, so tudiff steps aside for
+/// them like for the editor instead of starting them in the background.
+const TERMINAL_FILE_MANAGERS: &[&str] = &[
+    "ranger", "lf", "nnn", "vifm", "mc", "yazi", "broot", "joshuto", "xplr", "fff", "clifm",
+];
+
+/// Open two folders outside tudiff, for work the panels can't do: each in
+/// `$FILEMANAGER` when that is set, otherwise in a new tmux window split
+/// into a shell per folder when running inside tmux, and otherwise in the
+/// desktop's default file manager. A terminal file manager runs in the
+/// foreground, one folder after the other; returns whether one did, so
+/// the screen has to be drawn again.
+pub fn open_both_sides(left: &Path, right: &Path) -> Result<bool> {
+    use std::process::Command;
+
+    if let Some(manager) = std::env::var("FILEMANAGER")
+        .ok()
+        .filter(|manager| !manager.trim().is_empty())
+    {
+        let mut words = manager.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+        let name = Path::new(program).file_name().unwrap_or_default();
+        if TERMINAL_FILE_MANAGERS.iter().any(|known| name == *known) {
+            leave_tui();
+            let result = [left, right].into_iter().try_for_each(|dir| {
+                let status = Command::new(program)
+                    .args(&args)
+                    .arg(dir)
+                    .status()
+                    .with_context(|| format!("failed to run {}", manager))?;
+                if !status.success() {
+                    anyhow::bail!("{} failed with {}", manager, status);
+                }
+                Ok(())
+            });
+            return_to_tui();
+            return result.map(|()| true);
+        }
+        for dir in [left, right] {
+            spawn_detached(Command::new(program).args(&args).arg(dir))
+                .with_context(|| format!("failed to run {}", manager))?;
+        }
+        return Ok(false);
+    }
+
+    if std::env::var_os("TMUX").is_some() {
+        let status = Command::new("tmux")
+            .arg("new-window")
+            .arg("-c")
+            .arg(left)
+            .arg(";")
+            .arg("split-window")
+            .arg("-h")
+            .arg("-c")
+            .arg(right)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .context("failed to run tmux")?;
+        if !status.success() {
+            anyhow::bail!("tmux failed with {}", status);
+        }
+        return Ok(false);
+    }
+
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+    for dir in [left, right] {
+        spawn_detached(Command::new(opener).arg(dir))
+            .with_context(|| format!("failed to run {}", opener))?;
+    }
+    Ok(false)
+}
+
+/// Start a program that must not touch the terminal, reaping it once it
+/// exits.
+fn spawn_detached(command: &mut std::process::Command) -> std::io::Result<()> {
+    let mut child = command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Open a one-sided file in vim (or vi, nano) and a pair in vimdiff, in
+/// view mode when `read_only`.
+pub fn launch_external_editor(
+    status: &FileStatus,
+    left_path: &Path,
+    right_path: &Path,
+    read_only: bool,
+) -> Result<()> {
+    leave_tui();
 
     match status {
         FileStatus::LeftOnly => {
@@ -163,6 +570,7 @@ pub fn launch_external_editor(status: &FileStatus, left_path: &Path, right_path:
 
                 for editor in &editors {
                     let result = std::process::Command::new(editor)
+                        .args(view_mode(editor, read_only))
                         .arg(left_path)
                         .status();
                     if result.is_ok() {
@@ -188,6 +596,7 @@ pub fn launch_external_editor(status: &FileStatus, left_path: &Path, right_path:
 
                 for editor in &editors {
                     let result = std::process::Command::new(editor)
+                        .args(view_mode(editor, read_only))
                         .arg(right_path)
                         .status();
                     if result.is_ok() {
@@ -210,6 +619,7 @@ pub fn launch_external_editor(status: &FileStatus, left_path: &Path, right_path:
             let mut success = false;
 
             let result = std::process::Command::new("vimdiff")
+                .args(view_mode("vimdiff", read_only))
                 .arg(left_path)
                 .arg(right_path)
                 .status();
@@ -218,6 +628,7 @@ pub fn launch_external_editor(status: &FileStatus, left_path: &Path, right_path:
                 success = true;
             } else {
                 let result = std::process::Command::new("vim")
+                    .args(view_mode("vim", read_only))
                     .arg("-d")
                     .arg(left_path)
                     .arg(right_path)
@@ -242,6 +653,25 @@ pub fn launch_external_editor(status: &FileStatus, left_path: &Path, right_path:
         }
     }
 
+    return_to_tui();
+
+    Ok(())
+}
+
+/// Hand the terminal to a program that draws on it, like an editor.
+fn leave_tui() {
+    let _ = crossterm::terminal::disable_raw_mode();
+    let _ = crossterm::execute!(std::io::stdout(), crossterm::terminal::LeaveAlternateScreen);
+
+    print!("\x1b[2J\x1b[H");
+    use std::io::Write;
+    let _ = std::io::stdout().flush();
+}
+
+/// Take the terminal back after `leave_tui`.
+fn return_to_tui() {
+    use std::io::Write;
+
     std::thread::sleep(Duration::from_millis(200));
 
     if let Err(e) = crossterm::terminal::enable_raw_mode() {
@@ -266,8 +696,19 @@ pub fn launch_external_editor(status: &FileStatus, left_path: &Path, right_path:
 
     print!("\x1b[?12l");
     let _ = std::io::stdout().flush();
+}
 
-    Ok(())
+/// The flag that opens a file without allowing changes in `editor`.
+fn view_mode(editor: &str, read_only: bool) -> Option<&'static str> {
+    if !read_only {
+        return None;
+    }
+    // vim's -R only warns before writing, -M refuses to change the buffer
+    Some(match editor {
+        "nano" => "-v",
+        "vi" => "-R",
+        _ => "-M",
+    })
 }
 
 pub fn ensure_cursor_visible() {
@@ -279,41 +720,105 @@ pub fn ensure_cursor_visible() {
     let _ = std::io::stdout().flush();
 }
 
-pub fn simple_compare(dir1: std::path::PathBuf, dir2: std::path::PathBuf) -> Result<()> {
-    let comparison = DirectoryComparison::new(dir1, dir2)?;
+/// Ask on the terminal whether a scan that passed the entry limit should
+/// go on, for `--simple` runs.
+pub fn ask_limit_on_stdin(processed: usize) -> LimitChoice {
+    eprint!(
+        "The scan has passed {} paths. [c]ontinue, compare by [m]etadata only, or [a]bort? ",
+        processed
+    );
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return LimitChoice::Abort;
+    }
+    match answer.trim().to_lowercase().as_str() {
+        "c" | "continue" => LimitChoice::Continue,
+        "m" | "metadata" => LimitChoice::MetadataOnly,
+        _ => LimitChoice::Abort,
+    }
+}
+
+/// Ask a yes/no `question` on the terminal; anything but yes is no.
+pub fn confirm_on_stdin(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+pub fn simple_compare(
+    dir1: std::path::PathBuf,
+    dir2: std::path::PathBuf,
+    options: CompareOptions,
+    color: bool,
+) -> Result<()> {
+    let comparison = DirectoryComparison::new_with_options(dir1, dir2, options)?;
+    print_comparison(&comparison, color)
+}
+
+/// Print both trees of a finished comparison, followed by the diffs of the
+/// changed files when the folders are at hand.
+pub fn print_comparison(comparison: &DirectoryComparison, color: bool) -> Result<()> {
+    for warning in &comparison.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if !comparison.unreadable.is_empty() {
+        eprintln!("Needs elevation:");
+        for path in &comparison.unreadable {
+            eprintln!("  {}", crate::utils::escape_name(path));
+        }
+    }
 
     crossterm::execute!(std::io::stdout(), crossterm::cursor::Show).ok();
 
     println!("Directory Comparison Results:");
-    println!("Left:  {}", comparison.left_dir.display());
-    println!("Right: {}", comparison.right_dir.display());
+    println!("Left:  {}", crate::utils::escape_name(&comparison.left_dir));
+    println!("Right: {}", crate::utils::escape_name(&comparison.right_dir));
+    for (left, right) in comparison.options.path_map.pairs() {
+        println!(
+            "Map:   {} = {}",
+            crate::utils::escape_name(left),
+            crate::utils::escape_name(right)
+        );
+    }
     println!();
 
     fn print_tree(node: &crate::compare::FileNode, depth: usize) {
         let indent = "  ".repeat(depth);
 
-        if node.name.is_empty() {
+        if !node.exists {
             println!("{}", indent);
         } else {
-            let icon = if node.is_dir { "📁" } else { "📄" };
+            let icons = crate::icons::icons();
+            let icon = if node.is_dir {
+                icons.folder_closed
+            } else {
+                icons.file
+            };
             let status_char = match node.status {
                 FileStatus::Same => "=",
-                FileStatus::Different => "≠",
+                FileStatus::Different => icons.different,
                 FileStatus::LeftOnly => "L",
                 FileStatus::RightOnly => "R",
             };
 
-            println!("{}{} {} [{}]", indent, icon, node.name, status_char);
+            let name = node.display_name();
+            println!("{}{} {} [{}]", indent, icon, name, status_char);
         }
 
-        if node.is_dir && !node.name.is_empty() {
+        if node.is_dir && node.exists {
             for child in &node.children {
                 print_tree(child, depth + 1);
             }
         }
     }
 
-    println!("Legend: [=] Same, [≠] Different, [L] Left only, [R] Right only");
+    println!(
+        "Legend: [=] Same, [{}] Different, [L] Left only, [R] Right only",
+        crate::icons::icons().different
+    );
     println!();
 
     println!("=== LEFT PANEL ===");
@@ -323,5 +828,281 @@ pub fn simple_compare(dir1: std::path::PathBuf, dir2: std::path::PathBuf) -> Res
     println!("=== RIGHT PANEL ===");
     print_tree(&comparison.right_tree, 0);
 
+    let changed: Vec<_> = comparison
+        .entries()
+        .filter(|entry| {
+            entry.status == FileStatus::Different
+                && entry.left.is_some_and(|meta| !meta.is_dir)
+                && entry.right.is_some_and(|meta| !meta.is_dir)
+        })
+        .map(|entry| entry.rel_path)
+        .collect();
+    // A manifest only has hashes to show for the other side, and a saved
+    // result no files at all
+    if !changed.is_empty() && !comparison.has_manifest() && comparison.loaded_from.is_none() {
+        let mut diffs = String::new();
+        for path in &changed {
+            let Some(right) = comparison.right_path(path) else {
+                continue;
+            };
+            diffs.push_str(&DirectoryComparison::unified_diff(
+                path,
+                &comparison.left_path(path),
+                &right,
+                None,
+            )?);
+        }
+
+        println!();
+        println!("=== DIFFERENCES ===");
+        print_diff(&diffs, color);
+    }
+
+    Ok(())
+}
+
+/// How `watch_compare` calls attention to paths that stopped matching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAlert {
+    /// Ring the terminal bell
+    Bell,
+    /// Show a desktop notification, with the `desktop-notify` feature
+    Desktop,
+}
+
+/// How long filesystem events must pause before `--watch` rescans.
+const WATCH_SETTLE: Duration = Duration::from_millis(500);
+/// The longest a rescan waits for events to settle, so a folder that never
+/// stops changing (a log being written) is still compared now and then.
+const WATCH_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Keep comparing the two roots and print a line whenever an entry's status
+/// changes, until interrupted. Filesystem events trigger a rescan once they
+/// have settled for half a second, or after five seconds at the latest.
+pub fn watch_compare(
+    dir1: std::path::PathBuf,
+    dir2: std::path::PathBuf,
+    options: CompareOptions,
+    alert: Option<WatchAlert>,
+) -> Result<()> {
+    use notify::{EventKind, RecursiveMode, Watcher};
+
+    let comparison =
+        DirectoryComparison::new_with_options(dir1.clone(), dir2.clone(), options.clone())?;
+    let mut states = watch_states(&comparison);
+    // Progress dots on every rescan would bury the changes
+    crate::utils::set_progress_mode(crate::utils::ProgressMode::Quiet);
+
+    let (sender, events) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    for root in [&dir1, &dir2] {
+        if crate::manifest::is_remote(root) {
+            continue;
+        }
+        let mode = if root.is_dir() {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher
+            .watch(root, mode)
+            .with_context(|| format!("watching {}", root.display()))?;
+    }
+
+    let differing = states
+        .values()
+        .filter(|status| **status != FileStatus::Same)
+        .count();
+    println!(
+        "Watching {} and {}: {} entries, {} not the same",
+        dir1.display(),
+        dir2.display(),
+        states.len(),
+        differing
+    );
+
+    while let Ok(event) = events.recv() {
+        match event {
+            // Rescans read files, which must not trigger another one
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => continue,
+            Ok(_) => {}
+            Err(e) => {
+                crate::utils::log_error(&format!("Watch error: {}", e));
+                continue;
+            }
+        }
+        let deadline = std::time::Instant::now() + WATCH_MAX_DELAY;
+        loop {
+            let left = deadline.saturating_duration_since(std::time::Instant::now());
+            if left.is_zero() || events.recv_timeout(WATCH_SETTLE.min(left)).is_err() {
+                break;
+            }
+        }
+
+        let comparison = match DirectoryComparison::new_with_options(
+            dir1.clone(),
+            dir2.clone(),
+            options.clone(),
+        ) {
+            Ok(comparison) => comparison,
+            Err(e) => {
+                eprintln!("Rescan failed: {:#}", e);
+                continue;
+            }
+        };
+        let new_states = watch_states(&comparison);
+
+        let time = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        let paths: std::collections::BTreeSet<_> = states.keys().chain(new_states.keys()).collect();
+        let mut broken = Vec::new();
+        for path in paths {
+            let (old, new) = (states.get(path), new_states.get(path));
+            if old != new {
+                println!(
+                    "{}  {:<10}  {}  (was {})",
+                    time,
+                    status_label(new),
+                    crate::utils::escape_name(path),
+                    status_label(old)
+                );
+            }
+            if old == Some(&FileStatus::Same) && new == Some(&FileStatus::Different) {
+                broken.push(path.as_path());
+            }
+        }
+        if let (Some(alert), false) = (alert, broken.is_empty()) {
+            raise_alert(alert, &broken);
+        }
+        states = new_states;
+    }
+
     Ok(())
+}
+
+/// Tell the user that `paths`, the same until now, became different. A
+/// desktop notification that can't be shown falls back to the bell.
+fn raise_alert(alert: WatchAlert, paths: &[&Path]) {
+    if alert == WatchAlert::Desktop {
+        match notify_desktop(paths) {
+            Ok(()) => return,
+            Err(e) => crate::utils::log_error(&format!("Desktop notification failed: {:#}", e)),
+        }
+    }
+    use std::io::Write;
+    eprint!("\x07");
+    let _ = std::io::stderr().flush();
+}
+
+#[cfg(feature = "desktop-notify")]
+fn notify_desktop(paths: &[&Path]) -> Result<()> {
+    const LISTED: usize = 5;
+    let summary = match paths.len() {
+        1 => "1 path became different".to_string(),
+        count => format!("{} paths became different", count),
+    };
+    let mut body: Vec<String> = paths
+        .iter()
+        .take(LISTED)
+        .map(crate::utils::escape_name)
+        .collect();
+    if paths.len() > LISTED {
+        body.push(format!("and {} more", paths.len() - LISTED));
+    }
+    notify_rust::Notification::new()
+        .appname("tudiff")
+        .summary(&summary)
+        .body(&body.join("\n"))
+        .show()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "desktop-notify"))]
+fn notify_desktop(_paths: &[&Path]) -> Result<()> {
+    anyhow::bail!("tudiff was built without the desktop-notify feature")
+}
+
+/// Status per compared path, with folders on both sides counting as the
+/// same so that only their files report changes.
+fn watch_states(
+    comparison: &DirectoryComparison,
+) -> std::collections::BTreeMap<std::path::PathBuf, FileStatus> {
+    comparison
+        .entries()
+        .map(|entry| {
+            let both_dirs = entry.left.is_some_and(|meta| meta.is_dir)
+                && entry.right.is_some_and(|meta| meta.is_dir);
+            let status = if both_dirs {
+                FileStatus::Same
+            } else {
+                entry.status
+            };
+            (entry.rel_path, status)
+        })
+        .collect()
+}
+
+fn status_label(status: Option<&FileStatus>) -> &'static str {
+    match status {
+        Some(FileStatus::Same) => "same",
+        Some(FileStatus::Different) => "different",
+        Some(FileStatus::LeftOnly) => "left only",
+        Some(FileStatus::RightOnly) => "right only",
+        None => "gone",
+    }
+}
+
+/// Print a unified diff, through delta, bat or colordiff when writing colored
+/// output to a terminal, with plain ANSI colors otherwise.
+fn print_diff(diff: &str, color: bool) {
+    use std::io::{IsTerminal, Write};
+
+    if color && std::io::stdout().is_terminal() {
+        let printers: [(&str, &[&str]); 3] = [
+            ("delta", &["--paging=never"]),
+            ("bat", &["--language=diff", "--paging=never", "--style=plain", "--color=always"]),
+            ("colordiff", &[]),
+        ];
+        // A printer's output is only shown once it has succeeded, so one that
+        // fails partway doesn't leave half a diff in front of the fallback
+        for (program, args) in printers {
+            let child = std::process::Command::new(program)
+                .args(args)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn();
+            if let Ok(mut child) = child {
+                let writer = child.stdin.take().map(|mut stdin| {
+                    let diff = diff.to_string();
+                    std::thread::spawn(move || {
+                        let _ = stdin.write_all(diff.as_bytes());
+                    })
+                });
+                let output = child.wait_with_output();
+                if let Some(writer) = writer {
+                    let _ = writer.join();
+                }
+                if let Some(output) = output.ok().filter(|output| output.status.success()) {
+                    let _ = std::io::stdout().write_all(&output.stdout);
+                    return;
+                }
+            }
+        }
+    }
+
+    for line in diff.lines() {
+        let code = match line.as_bytes().first() {
+            _ if !color => None,
+            Some(b'+') | Some(b'-') if line.starts_with("+++") || line.starts_with("---") => {
+                Some("1")
+            }
+            Some(b'+') => Some("32"),
+            Some(b'-') => Some("31"),
+            Some(b'@') => Some("36"),
+            _ => None,
+        };
+        match code {
+            Some(code) => println!("\x1b[{}m{}\x1b[0m", code, line),
+            None => println!("{}", line),
+        }
+    }
 }
\ No newline at end of file
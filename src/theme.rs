@@ -0,0 +1,228 @@
+use anyhow::{bail, Result};
+use ratatui::buffer::Buffer;
+use ratatui::style::{Color, Modifier, Style};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use crate::compare::FileStatus;
+use crate::icons::icons;
+
+/// Colors of entry statuses and diff lines.
+#[derive(Debug)]
+pub struct Palette {
+    pub name: &'static str,
+    pub same: Color,
+    pub different: Color,
+    /// Entries on one side only
+    pub one_sided: Color,
+    /// Folder names use stronger variants of the same hues
+    pub folder_same: Color,
+    pub folder_different: Color,
+    pub folder_one_sided: Color,
+    /// Lines added and removed in the preview diff
+    pub added: Color,
+    pub removed: Color,
+}
+
+pub const DEFAULT: Palette = Palette {
+    name: "default",
+    same: Color::Gray,
+    different: Color::LightRed,
+    one_sided: Color::LightBlue,
+    folder_same: Color::White,
+    folder_different: Color::Red,
+    folder_one_sided: Color::Blue,
+    added: Color::Green,
+    removed: Color::Red,
+};
+
+/// Orange against blue from the Okabe-Ito set, which stays apart for
+/// red-green color blindness, where red and green look alike.
+pub const DEUTERANOPIA: Palette = Palette {
+    name: "deuteranopia",
+    same: Color::Gray,
+    different: Color::Rgb(230, 159, 0),
+    one_sided: Color::Rgb(86, 180, 233),
+    folder_same: Color::White,
+    folder_different: Color::Rgb(213, 94, 0),
+    folder_one_sided: Color::Rgb(0, 114, 178),
+    added: Color::Rgb(86, 180, 233),
+    removed: Color::Rgb(230, 159, 0),
+};
+
+/// Like deuteranopia, but with lighter oranges since reds look dark
+/// without red cones.
+pub const PROTANOPIA: Palette = Palette {
+    name: "protanopia",
+    same: Color::Gray,
+    different: Color::Rgb(255, 191, 64),
+    one_sided: Color::Rgb(86, 180, 233),
+    folder_same: Color::White,
+    folder_different: Color::Rgb(230, 159, 0),
+    folder_one_sided: Color::Rgb(0, 114, 178),
+    added: Color::Rgb(86, 180, 233),
+    removed: Color::Rgb(255, 191, 64),
+};
+
+pub const PALETTES: [&Palette; 3] = [&DEFAULT, &DEUTERANOPIA, &PROTANOPIA];
+
+static PALETTE: AtomicUsize = AtomicUsize::new(0);
+
+/// Switch to the palette called `name`. Returns false for unknown names.
+pub fn set_palette(name: &str) -> bool {
+    match PALETTES
+        .iter()
+        .position(|palette| palette.name.eq_ignore_ascii_case(name))
+    {
+        Some(index) => {
+            PALETTE.store(index, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn palette() -> &'static Palette {
+    PALETTES[PALETTE.load(Ordering::Relaxed)]
+}
+
+/// Names of all palettes, for error messages.
+pub fn palette_names() -> String {
+    PALETTES
+        .iter()
+        .map(|palette| palette.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The color an entry's name is drawn in.
+pub fn status_color(status: FileStatus, is_dir: bool) -> Color {
+    let palette = palette();
+    match (status, is_dir) {
+        (FileStatus::Same, false) => palette.same,
+        (FileStatus::Different, false) => palette.different,
+        (FileStatus::LeftOnly | FileStatus::RightOnly, false) => palette.one_sided,
+        (FileStatus::Same, true) => palette.folder_same,
+        (FileStatus::Different, true) => palette.folder_different,
+        (FileStatus::LeftOnly | FileStatus::RightOnly, true) => palette.folder_one_sided,
+    }
+}
+
+/// How the selected row and the one under the mouse pointer are drawn, set
+/// in the `[highlight]` section of the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Highlights {
+    /// The selected row of the active panel
+    pub selected: Style,
+    /// The row across from it in the other panel
+    pub inactive: Style,
+    /// The row under the mouse pointer
+    pub hover: Style,
+}
+
+impl Default for Highlights {
+    fn default() -> Self {
+        Self {
+            selected: Style::default().bg(Color::Yellow).fg(Color::Black),
+            inactive: Style::default().bg(Color::Rgb(60, 60, 80)).fg(Color::White),
+            hover: Style::default().bg(Color::Rgb(40, 40, 55)),
+        }
+    }
+}
+
+static HIGHLIGHTS: OnceLock<Highlights> = OnceLock::new();
+
+pub fn set_highlights(highlights: Highlights) {
+    let _ = HIGHLIGHTS.set(highlights);
+}
+
+pub fn highlights() -> Highlights {
+    HIGHLIGHTS.get().copied().unwrap_or_default()
+}
+
+/// Parse a style like `black on yellow`, `on #3c3c50 bold` or `reversed`:
+/// an optional foreground color, `on` and a background color, and any of
+/// `bold`, `dim`, `italic`, `underlined` and `reversed`. Without a
+/// foreground the row keeps its status color.
+pub fn parse_style(spec: &str) -> Result<Style> {
+    let mut style = Style::default();
+    let mut words = spec.split_whitespace();
+    while let Some(word) = words.next() {
+        let modifier = match word.to_ascii_lowercase().as_str() {
+            "bold" => Modifier::BOLD,
+            "dim" => Modifier::DIM,
+            "italic" => Modifier::ITALIC,
+            "underlined" => Modifier::UNDERLINED,
+            "reversed" => Modifier::REVERSED,
+            "on" => {
+                let Some(color) = words.next() else {
+                    bail!("expected a color after `on` in `{}`", spec);
+                };
+                style = style.bg(parse_color(color)?);
+                continue;
+            }
+            _ if style.fg.is_none() && style.bg.is_none() => {
+                style = style.fg(parse_color(word)?);
+                continue;
+            }
+            _ => bail!("unexpected `{}` in `{}`", word, spec),
+        };
+        style = style.add_modifier(modifier);
+    }
+    Ok(style)
+}
+
+/// A color name such as `yellow` or `light-blue`, `#rrggbb` or a 0-255 index.
+fn parse_color(name: &str) -> Result<Color> {
+    Color::from_str(name).map_err(|_| anyhow::anyhow!("unknown color `{}`", name))
+}
+
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Draw without colors: statuses get a marker in front of the name and
+/// bold or underlined names, for monochrome terminals and colorblind users.
+pub fn set_no_color(enabled: bool) {
+    NO_COLOR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_no_color() -> bool {
+    NO_COLOR.load(Ordering::Relaxed)
+}
+
+/// Whether the `NO_COLOR` environment variable asks for no colors, see
+/// <https://no-color.org>.
+pub fn no_color_requested() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// The marker shown in front of an entry's name in no-color mode.
+pub fn status_marker(status: FileStatus) -> &'static str {
+    match status {
+        FileStatus::Same => "=",
+        FileStatus::Different => icons().different,
+        FileStatus::LeftOnly => "L",
+        FileStatus::RightOnly => "R",
+    }
+}
+
+/// How an entry's name is emphasized in no-color mode.
+pub fn status_modifier(status: FileStatus) -> Modifier {
+    match status {
+        FileStatus::Same => Modifier::empty(),
+        FileStatus::Different => Modifier::BOLD,
+        FileStatus::LeftOnly | FileStatus::RightOnly => Modifier::UNDERLINED,
+    }
+}
+
+/// Remove all colors from a drawn frame. Cells with a background (the
+/// selection, active buttons, filled gauges) are shown reversed instead.
+pub fn strip_colors(buffer: &mut Buffer) {
+    for cell in buffer.content.iter_mut() {
+        if !matches!(cell.bg, Color::Reset | Color::Black) {
+            cell.modifier.insert(Modifier::REVERSED);
+        }
+        cell.fg = Color::Reset;
+        cell.bg = Color::Reset;
+    }
+}
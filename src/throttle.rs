@@ -0,0 +1,69 @@
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes per second file contents may be read or copied at; 0 for no limit.
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+/// When the bytes let through so far are paid for, shared by every thread
+/// so the limit holds for the whole process.
+static DUE: Mutex<Option<Instant>> = Mutex::new(None);
+/// Longest single sleep, so a cancelled transfer stops promptly even at a
+/// rate where one chunk takes seconds.
+const SLEEP_SLICE: Duration = Duration::from_millis(50);
+
+/// Cap reading and copying file contents at `bytes_per_sec`, or lift the
+/// cap with `None`.
+pub fn set_limit(bytes_per_sec: Option<u64>) {
+    LIMIT.store(bytes_per_sec.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn limit() -> Option<u64> {
+    Some(LIMIT.load(Ordering::Relaxed)).filter(|&limit| limit > 0)
+}
+
+/// Account for `bytes` just read or copied, sleeping as long as it takes to
+/// stay under the limit. Idle time isn't saved up for a burst later. Once
+/// `cancel` is set it returns early, for the caller to notice.
+pub fn consume(bytes: u64, cancel: Option<&AtomicBool>) {
+    let Some(limit) = limit() else {
+        return;
+    };
+    if bytes == 0 {
+        return;
+    }
+    let end = {
+        let mut due = DUE.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let start = due.map_or(now, |due| due.max(now));
+        let next = start + Duration::from_secs_f64(bytes as f64 / limit as f64);
+        *due = Some(next);
+        next
+    };
+    loop {
+        let left = end.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return;
+        }
+        if cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            // What wasn't waited out isn't charged to the next transfer
+            let mut due = DUE.lock().unwrap_or_else(|e| e.into_inner());
+            *due = due.map(|due| due.checked_sub(left).unwrap_or(due));
+            return;
+        }
+        thread::sleep(left.min(SLEEP_SLICE));
+    }
+}
+
+/// Parse a rate the way rsync's `--bwlimit` takes it: a number of KiB per
+/// second, or with a `B`, `K`, `M` or `G` suffix. 0 means no limit.
+pub fn parse_rate(value: &str) -> Result<Option<u64>> {
+    let rate = crate::utils::parse_size(value, 1 << 10).ok_or_else(|| {
+        anyhow!(
+            "expected a rate like `500K` or `10M`, got `{}`",
+            value.trim()
+        )
+    })?;
+    Ok((rate > 0).then_some(rate))
+}
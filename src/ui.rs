@@ -4,196 +4,466 @@ use ratatui::{
     style::{Color, Modifier, Style},
     text::{Line, Span},
     widgets::{
-        Block, Borders, Clear, Gauge, List, ListItem, Paragraph, Scrollbar, ScrollbarOrientation,
+        Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph, Scrollbar,
+        ScrollbarOrientation, Wrap,
     },
     Frame, Terminal,
 };
 
-use crate::app::{App, AppMode, CopyInfo, DeleteInfo, FilterMode};
-use crate::compare::FileStatus;
-use crate::utils::{format_file_size, format_modified_time, truncate_path};
+use crate::app::{
+    App, AppMode, CopyInfo, DeleteInfo, FileItem, FilterMode, HashSide, ListLayout, NameAction,
+    PermissionsInfo, PlaceholderRows, SortOrder, TimestampSync,
+};
+use crate::compare::{mtimes_equal, FileStatus};
+use crate::copy::{CopyBackend, OverwritePolicy};
+use crate::icons::{icons, labeled};
+use crate::picker::{Picker, PickerMode};
+use crate::theme::{
+    highlights, is_no_color, palette, status_color, status_marker, status_modifier, strip_colors,
+};
+use crate::utils::{
+    escape_name, format_modified_time, format_owner, format_permissions, truncate_path,
+};
 
 pub fn draw_ui<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> anyhow::Result<()> {
-    terminal.draw(|f| match app.mode {
-        AppMode::DirectoryView => draw_directory_view(f, app),
-        AppMode::FileView => draw_file_view(f, app),
-        AppMode::CopyConfirm => {
-            draw_directory_view(f, app);
-            draw_copy_confirm_popup(f, app);
+    terminal.draw(|f| {
+        match app.mode {
+            AppMode::DirectoryView => {
+                draw_directory_view(f, app);
+                draw_flash(f, app);
+            }
+            AppMode::FileView => draw_file_view(f, app),
+            AppMode::CopyConfirm => {
+                draw_directory_view(f, app);
+                draw_copy_confirm_popup(f, app);
+            }
+            AppMode::Copying => {
+                draw_directory_view(f, app);
+                draw_copy_progress_popup(f, app);
+            }
+            AppMode::DeleteConfirm => {
+                draw_directory_view(f, app);
+                draw_delete_confirm_popup(f, app);
+            }
+            AppMode::TimestampConfirm => {
+                draw_directory_view(f, app);
+                draw_timestamp_confirm_popup(f, app);
+            }
+            AppMode::PermissionsConfirm => {
+                draw_directory_view(f, app);
+                draw_permissions_confirm_popup(f, app);
+            }
+            AppMode::NameInput => {
+                draw_directory_view(f, app);
+                draw_name_input_popup(f, app);
+            }
+            AppMode::CommandLine => {
+                draw_directory_view(f, app);
+                draw_command_line(f, app);
+            }
+            AppMode::HashView => {
+                draw_directory_view(f, app);
+                draw_hash_popup(f, app);
+            }
+            AppMode::SessionLog => {
+                draw_directory_view(f, app);
+                draw_session_log_popup(f, app);
+            }
+            AppMode::DiffStat => {
+                draw_directory_view(f, app);
+                draw_diffstat_popup(f, app);
+            }
+            AppMode::QuitConfirm => {
+                draw_directory_view(f, app);
+                if app.mode_before_quit == AppMode::Copying {
+                    draw_copy_progress_popup(f, app);
+                }
+                draw_quit_confirm_popup(f, app);
+            }
+            AppMode::RestoreConfirm => {
+                draw_directory_view(f, app);
+                draw_restore_confirm_popup(f, app);
+            }
         }
-        AppMode::DeleteConfirm => {
-            draw_directory_view(f, app);
-            draw_delete_confirm_popup(f, app);
+        if is_no_color() {
+            strip_colors(f.buffer_mut());
         }
     })?;
     Ok(())
 }
 
-fn draw_directory_view(f: &mut Frame, app: &mut App) {
-    app.viewport_height = f.area().height;
+/// Where the directory view puts the toolbar, the two panels and the
+/// preview (empty when hidden) in `area`.
+pub fn directory_layout(app: &App, area: Rect) -> (Rect, [Rect; 2], Rect) {
+    let preview_height = if app.show_preview { area.height / 3 } else { 0 };
 
     let main_chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Length(3), Constraint::Min(0)])
-        .split(f.area());
-
-    app.toolbar_area = main_chunks[0];
-
-    draw_toolbar(f, app, main_chunks[0]);
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(preview_height),
+        ])
+        .split(area);
 
+    let direction = if app.vertical_layout {
+        Direction::Vertical
+    } else {
+        Direction::Horizontal
+    };
     let chunks = Layout::default()
-        .direction(Direction::Horizontal)
+        .direction(direction)
         .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
         .split(main_chunks[1]);
 
+    (main_chunks[0], [chunks[0], chunks[1]], main_chunks[2])
+}
+
+fn draw_directory_view(f: &mut Frame, app: &mut App) {
+    let (toolbar, chunks, preview) = directory_layout(app, f.area());
+
+    // Paging works on one panel's height (plus the toolbar, see calculate_half_page)
+    app.viewport_height = toolbar.height + chunks[0].height;
     let panel_width = chunks[0].width.saturating_sub(2) as usize;
 
+    // Similarities are only worked out for rows that can be on screen
+    let selected = if app.active_panel == 0 {
+        app.left_list_state.selected()
+    } else {
+        app.right_list_state.selected()
+    }
+    .unwrap_or(0);
+    if app.show_similarity {
+        let height = chunks[0].height as usize;
+        app.update_similarities(selected.saturating_sub(height)..selected + height);
+    } else if app.show_preview {
+        app.update_similarities(selected..selected + 1);
+    }
+    if app.show_file_type {
+        let height = chunks[0].height as usize;
+        app.update_file_types(selected.saturating_sub(height)..selected + height);
+    }
+
+    app.panel_areas = chunks;
+    // After the panels are laid out, since it shows the root the focused
+    // panel's title had to shorten
+    draw_toolbar(f, app, toolbar);
+    if app.recenter {
+        app.center_selection();
+    }
     draw_left_panel(f, app, chunks[0], panel_width);
     draw_right_panel(f, app, chunks[1], panel_width);
+    draw_hover(f, app);
+
+    if app.show_preview {
+        app.update_preview();
+        draw_preview(f, app, preview, app.similarity(selected));
+    }
 
     if app.is_refreshing {
         draw_progress_popup(f, app);
     }
 }
 
-fn draw_toolbar(f: &mut Frame, app: &App, area: Rect) {
-    let toolbar_items = vec![Line::from(vec![
-        Span::styled("📁", Style::default().fg(Color::Yellow)),
-        Span::raw(" All Files"),
-        Span::raw("("),
-        Span::styled("1", Style::default().fg(Color::Red)),
-        Span::raw(")"),
-        Span::raw(" │ "),
-        Span::styled("🔍", Style::default().fg(Color::Cyan)),
-        Span::raw(" Different"),
-        Span::raw("("),
-        Span::styled("2", Style::default().fg(Color::Red)),
-        Span::raw(")"),
-        Span::raw(" │ "),
-        Span::styled("⚡", Style::default().fg(Color::Magenta)),
-        Span::raw(" Diff Only"),
-        Span::raw("("),
-        Span::styled("3", Style::default().fg(Color::Red)),
-        Span::raw(")"),
-        Span::raw(" │ "),
-        Span::styled("📂", Style::default().fg(Color::Green)),
-        Span::raw(" Expand All"),
-        Span::raw("("),
-        Span::styled("+", Style::default().fg(Color::Red)),
-        Span::raw(")"),
-        Span::raw(" │ "),
-        Span::styled("📁", Style::default().fg(Color::Blue)),
-        Span::raw(" Collapse All"),
-        Span::raw("("),
-        Span::styled("-", Style::default().fg(Color::Red)),
-        Span::raw(")"),
-        Span::raw(" │ "),
-        Span::styled("🔄", Style::default().fg(Color::Magenta)),
-        Span::raw(" Refresh"),
-        Span::raw("("),
-        Span::styled("F5", Style::default().fg(Color::Red)),
-        Span::raw(")"),
-        Span::raw(" │ "),
-        Span::styled("🔃", Style::default().fg(Color::Red)),
-        Span::raw(" Swap Panels"),
-        Span::raw("("),
-        Span::styled("s", Style::default().fg(Color::Red)),
-        Span::raw(")"),
-        Span::raw(" │ "),
-        if app.can_copy() {
-            if app.active_panel == 0 {
-                Span::styled("▶️", Style::default().fg(Color::Green))
-            } else {
-                Span::styled("◀️", Style::default().fg(Color::Green))
-            }
-        } else {
-            if app.active_panel == 0 {
-                Span::styled("▶️", Style::default().fg(Color::DarkGray))
-            } else {
-                Span::styled("◀️", Style::default().fg(Color::DarkGray))
-            }
-        },
-        if app.can_copy() {
-            Span::styled("Copy", Style::default().fg(Color::White))
-        } else {
-            Span::styled("Copy", Style::default().fg(Color::DarkGray))
-        },
-        Span::raw("("),
-        if app.can_copy() {
-            if app.active_panel == 0 {
-                Span::styled("Ctrl+R", Style::default().fg(Color::Red))
-            } else {
-                Span::styled("Ctrl+L", Style::default().fg(Color::Red))
-            }
-        } else {
-            if app.active_panel == 0 {
-                Span::styled("Ctrl+R", Style::default().fg(Color::DarkGray))
-            } else {
-                Span::styled("Ctrl+L", Style::default().fg(Color::DarkGray))
-            }
-        },
-        Span::raw(")"),
-        Span::raw(" │ "),
-        if app.can_delete() {
-            Span::styled("🗑️", Style::default().fg(Color::Red))
-        } else {
-            Span::styled("🗑️", Style::default().fg(Color::DarkGray))
-        },
-        if app.can_delete() {
-            Span::styled(" Delete", Style::default().fg(Color::White))
-        } else {
-            Span::styled(" Delete", Style::default().fg(Color::DarkGray))
-        },
-        Span::raw("("),
-        if app.can_delete() {
-            Span::styled("Del", Style::default().fg(Color::Red))
+/// What a toolbar button does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolbarAction {
+    ShowAll,
+    ShowDifferent,
+    ShowDiffOnly,
+    ExpandAll,
+    CollapseAll,
+    Refresh,
+    Swap,
+    Copy,
+    Delete,
+}
+
+const TOOLBAR_SEPARATOR: &str = " │ ";
+
+/// A toolbar button. The toolbar is drawn from a list of these, and the
+/// drawn rects are kept on the app for mouse hit-testing.
+struct Button {
+    action: ToolbarAction,
+    icon: &'static str,
+    icon_color: Color,
+    label: &'static str,
+    key: &'static str,
+    /// Greyed out when the action can't apply to the selection
+    enabled: bool,
+    /// The current filter mode
+    active: bool,
+}
+
+impl Button {
+    fn new(
+        action: ToolbarAction,
+        icon: &'static str,
+        icon_color: Color,
+        label: &'static str,
+        key: &'static str,
+    ) -> Self {
+        Self {
+            action,
+            icon,
+            icon_color,
+            label,
+            key,
+            enabled: true,
+            active: false,
+        }
+    }
+
+    fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    fn active(mut self, active: bool) -> Self {
+        self.active = active;
+        self
+    }
+
+    fn spans(&self) -> Vec<Span<'static>> {
+        let dimmed = |color: Color| if self.enabled { color } else { Color::DarkGray };
+        let label_style = if self.active {
+            Style::default()
+                .fg(Color::Black)
+                .bg(Color::Yellow)
+                .add_modifier(Modifier::BOLD)
+        } else if self.enabled {
+            Style::default()
         } else {
-            Span::styled("Del", Style::default().fg(Color::DarkGray))
-        },
-        Span::raw(")"),
-        Span::raw(" │ "),
-        Span::styled(
-            "Filter: ",
+            Style::default().fg(Color::DarkGray)
+        };
+
+        vec![
+            Span::styled(self.icon, Style::default().fg(dimmed(self.icon_color))),
+            Span::styled(format!(" {}", self.label), label_style),
+            Span::raw("("),
+            Span::styled(self.key, Style::default().fg(dimmed(Color::Red))),
+            Span::raw(")"),
+        ]
+    }
+}
+
+fn toolbar_buttons(app: &App) -> Vec<Button> {
+    let icons = icons();
+    let (copy_icon, copy_key) = if app.active_panel == 0 {
+        (icons.copy_right, "Ctrl+R")
+    } else {
+        (icons.copy_left, "Ctrl+L")
+    };
+    let filter = app.filter_mode;
+
+    vec![
+        Button::new(
+            ToolbarAction::ShowAll,
+            icons.show_all,
+            Color::Yellow,
+            "All Files",
+            "1",
+        )
+        .active(filter == FilterMode::All),
+        Button::new(
+            ToolbarAction::ShowDifferent,
+            icons.show_different,
+            Color::Cyan,
+            "Different",
+            "2",
+        )
+        .active(filter == FilterMode::Different),
+        Button::new(
+            ToolbarAction::ShowDiffOnly,
+            icons.show_diff_only,
+            Color::Magenta,
+            "Diff Only",
+            "3",
+        )
+        .active(filter == FilterMode::DifferentNotOrphans),
+        Button::new(
+            ToolbarAction::ExpandAll,
+            icons.expand_all,
+            Color::Green,
+            "Expand All",
+            "+",
+        ),
+        Button::new(
+            ToolbarAction::CollapseAll,
+            icons.collapse_all,
+            Color::Blue,
+            "Collapse All",
+            "-",
+        ),
+        Button::new(
+            ToolbarAction::Refresh,
+            icons.refresh,
+            Color::Magenta,
+            "Refresh",
+            "F5",
+        ),
+        Button::new(
+            ToolbarAction::Swap,
+            icons.swap,
+            Color::Red,
+            "Swap Panels",
+            "s",
+        ),
+        Button::new(
+            ToolbarAction::Copy,
+            copy_icon,
+            Color::Green,
+            "Copy",
+            copy_key,
+        )
+        .enabled(app.can_copy() && !app.read_only),
+        Button::new(
+            ToolbarAction::Delete,
+            icons.delete,
+            Color::Red,
+            "Delete",
+            "Del",
+        )
+        .enabled(app.can_delete() && !app.read_only),
+    ]
+}
+
+fn draw_toolbar(f: &mut Frame, app: &mut App, area: Rect) {
+    let inner = Block::default().borders(Borders::ALL).inner(area);
+    let separator_width = Span::raw(TOOLBAR_SEPARATOR).width() as u16;
+
+    let mut spans = Vec::new();
+    let mut x = inner.x;
+    app.toolbar_buttons.clear();
+    for button in toolbar_buttons(app) {
+        let button_spans = button.spans();
+        let width = button_spans.iter().map(Span::width).sum::<usize>() as u16;
+        // Clip to the toolbar so hidden buttons can't be clicked
+        let visible = width.min(inner.right().saturating_sub(x));
+        if visible > 0 {
+            app.toolbar_buttons
+                .push((button.action, Rect::new(x, inner.y, visible, 1)));
+        }
+        x = x.saturating_add(width + separator_width);
+
+        spans.extend(button_spans);
+        spans.push(Span::raw(TOOLBAR_SEPARATOR));
+    }
+    spans.push(Span::styled(
+        "Filter: ",
+        Style::default()
+            .fg(Color::White)
+            .add_modifier(Modifier::BOLD),
+    ));
+    spans.push(Span::styled(
+        app.filter_mode.label(),
+        Style::default()
+            .fg(Color::Yellow)
+            .add_modifier(Modifier::BOLD),
+    ));
+    if let Some(filter) = &app.name_filter {
+        spans.push(Span::styled(
+            format!(", {}", filter),
             Style::default()
-                .fg(Color::White)
+                .fg(Color::Yellow)
                 .add_modifier(Modifier::BOLD),
-        ),
-        Span::styled(
-            match app.filter_mode {
-                FilterMode::All => "All Files",
-                FilterMode::Different => "Different Only",
-                FilterMode::DifferentNotOrphans => "Diff Only (No Orphans)",
-            },
+        ));
+    }
+    if app.hide_noise {
+        spans.push(Span::styled(
+            ", noise hidden",
             Style::default()
                 .fg(Color::Yellow)
                 .add_modifier(Modifier::BOLD),
-        ),
-    ])];
-
+        ));
+    }
+    let layout = match app.layout {
+        ListLayout::Tree => None,
+        ListLayout::Flat => Some("flat list"),
+        ListLayout::Grouped => Some("grouped by status"),
+    };
+    if let Some(layout) = layout {
+        let order = match app.sort_order {
+            SortOrder::Path => "",
+            SortOrder::Status => ", by status",
+            SortOrder::Size => ", by size",
+        };
+        spans.push(Span::styled(
+            format!(", {}{}", layout, order),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ));
+    }
+    let toolbar_items = vec![Line::from(spans)];
+
+    let title = Line::from(format!(
+        " {} - {}{} ",
+        labeled(icons().tools, "Tools"),
+        app.difference_summary(),
+        if app.read_only { " - read-only" } else { "" }
+    ));
+    let title_width = title.width();
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan))
+        .title(title)
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        );
+    // The timing of the last scan goes on the right while there's room
+    let stats = app.stats_summary();
+    if !stats.is_empty() {
+        let stats = Line::from(format!(" {} ", stats)).right_aligned();
+        if title_width + stats.width() + 4 <= area.width as usize {
+            block = block.title(stats);
+        }
+    }
+    let mut warning_width = 0;
+    if let Some(warning) = app.warning_summary() {
+        let warning = Line::from(format!(" {} {} ", icons().warning, warning));
+        warning_width = warning.width();
+        block = block.title_bottom(warning.style(Style::default().fg(Color::Yellow)));
+    }
+    // The focused panel's root in full when its title had to shorten it
+    let left = app.active_panel == 0;
+    let room = (area.width as usize).saturating_sub(warning_width + 6);
+    if room >= 10 && panel_title(app, left, app.panel_areas[app.active_panel].width).1 {
+        let dir = if left {
+            &app.comparison.left_dir
+        } else {
+            &app.comparison.right_dir
+        };
+        let path = truncate_path(&escape_name(dir), room);
+        block = block.title_bottom(Line::from(format!(" {} ", path)).right_aligned());
+    }
     let toolbar = Paragraph::new(toolbar_items)
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(Color::Cyan))
-                .title(" 🛠️  Tools ")
-                .title_style(
-                    Style::default()
-                        .fg(Color::Cyan)
-                        .add_modifier(Modifier::BOLD),
-                ),
-        )
+        .block(block)
         .alignment(Alignment::Left);
     f.render_widget(toolbar, area);
 }
 
 fn draw_left_panel(f: &mut Frame, app: &mut App, area: Rect, panel_width: usize) {
-    let left_items: Vec<ListItem> = create_list_items(&app.left_items, panel_width);
+    let extras = row_extras(app, true, &app.left_items, &app.right_items, icons().newer_left);
+    let left_items: Vec<ListItem> = create_list_items(
+        app,
+        &app.left_items,
+        &app.target_markers,
+        &extras,
+        panel_width,
+    );
+
+    let (left_title, _) = panel_title(app, true, area.width);
 
+    let shown_rows = shown_rows(app, true);
+    let left_items = keep_rows(left_items, shown_rows.as_deref());
     let left_list = List::new(left_items)
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(format!("Left: {}", app.comparison.left_dir.display()))
+                .title(left_title)
                 .border_style(if app.active_panel == 0 {
                     Style::default().fg(Color::Yellow)
                 } else {
@@ -201,18 +471,24 @@ fn draw_left_panel(f: &mut Frame, app: &mut App, area: Rect, panel_width: usize)
                 }),
         )
         .highlight_style(if app.active_panel == 0 {
-            Style::default().bg(Color::Yellow).fg(Color::Black)
+            highlights().selected
         } else {
-            Style::default().bg(Color::Rgb(60, 60, 80)).fg(Color::White)
+            highlights().inactive
         });
 
-    f.render_stateful_widget(left_list, area, &mut app.left_list_state);
+    render_rows(
+        f,
+        left_list,
+        area,
+        &mut app.left_list_state,
+        shown_rows.as_deref(),
+    );
 
     f.render_stateful_widget(
         Scrollbar::default()
             .orientation(ScrollbarOrientation::VerticalRight)
-            .begin_symbol(Some("↑"))
-            .end_symbol(Some("↓")),
+            .begin_symbol(Some(icons().scroll_up))
+            .end_symbol(Some(icons().scroll_down)),
         area.inner(Margin {
             vertical: 1,
             horizontal: 0,
@@ -222,13 +498,25 @@ fn draw_left_panel(f: &mut Frame, app: &mut App, area: Rect, panel_width: usize)
 }
 
 fn draw_right_panel(f: &mut Frame, app: &mut App, area: Rect, panel_width: usize) {
-    let right_items: Vec<ListItem> = create_list_items(&app.right_items, panel_width);
+    let extras = row_extras(
+        app,
+        false,
+        &app.right_items,
+        &app.left_items,
+        icons().newer_right,
+    );
+    let right_items: Vec<ListItem> =
+        create_list_items(app, &app.right_items, &[], &extras, panel_width);
 
+    let (right_title, _) = panel_title(app, false, area.width);
+
+    let shown_rows = shown_rows(app, false);
+    let right_items = keep_rows(right_items, shown_rows.as_deref());
     let right_list = List::new(right_items)
         .block(
             Block::default()
                 .borders(Borders::ALL)
-                .title(format!("Right: {}", app.comparison.right_dir.display()))
+                .title(right_title)
                 .border_style(if app.active_panel == 1 {
                     Style::default().fg(Color::Yellow)
                 } else {
@@ -236,18 +524,24 @@ fn draw_right_panel(f: &mut Frame, app: &mut App, area: Rect, panel_width: usize
                 }),
         )
         .highlight_style(if app.active_panel == 1 {
-            Style::default().bg(Color::Yellow).fg(Color::Black)
+            highlights().selected
         } else {
-            Style::default().bg(Color::Rgb(60, 60, 80)).fg(Color::White)
+            highlights().inactive
         });
 
-    f.render_stateful_widget(right_list, area, &mut app.right_list_state);
+    render_rows(
+        f,
+        right_list,
+        area,
+        &mut app.right_list_state,
+        shown_rows.as_deref(),
+    );
 
     f.render_stateful_widget(
         Scrollbar::default()
             .orientation(ScrollbarOrientation::VerticalRight)
-            .begin_symbol(Some("↑"))
-            .end_symbol(Some("↓")),
+            .begin_symbol(Some(icons().scroll_up))
+            .end_symbol(Some(icons().scroll_down)),
         area.inner(Margin {
             vertical: 1,
             horizontal: 0,
@@ -256,153 +550,836 @@ fn draw_right_panel(f: &mut Frame, app: &mut App, area: Rect, panel_width: usize
     );
 }
 
-fn create_list_items(
-    items: &[(
-        String,
-        FileStatus,
-        std::path::PathBuf,
-        bool,
-        Option<u64>,
-        Option<std::time::SystemTime>,
-    )],
-    panel_width: usize,
-) -> Vec<ListItem<'_>> {
+/// The title of the left or right panel, `width` columns wide, with the
+/// root shortened in the middle when it doesn't fit; and whether it was.
+fn panel_title(app: &App, left: bool, width: u16) -> (String, bool) {
+    let (label, dir, suffix) = match (left, app.is_multi_target()) {
+        (true, true) => (
+            "Source: ".to_string(),
+            &app.comparison.left_dir,
+            format!(
+                "{} [{} targets]{}",
+                zoom_breadcrumb(app),
+                app.target_count(),
+                lock_marker(app)
+            ),
+        ),
+        (true, false) => (
+            "Left: ".to_string(),
+            &app.comparison.left_dir,
+            format!("{}{}", zoom_breadcrumb(app), lock_marker(app)),
+        ),
+        (false, true) => (
+            format!("Target {}/{} (t): ", app.active_target + 1, app.target_count()),
+            &app.comparison.right_dir,
+            format!("{}{}", zoom_breadcrumb(app), lock_marker(app)),
+        ),
+        (false, false) => (
+            "Right: ".to_string(),
+            &app.comparison.right_dir,
+            format!("{}{}", zoom_breadcrumb(app), lock_marker(app)),
+        ),
+    };
+    // Inside the corners of the border
+    let room =
+        (width as usize).saturating_sub(2 + Span::raw(&label).width() + Span::raw(&suffix).width());
+    let path = escape_name(dir);
+    let shortened = Span::raw(&path).width() > room;
+    (
+        format!("{}{}{}", label, truncate_path(&path, room), suffix),
+        shortened,
+    )
+}
+
+/// Shade the row under the mouse pointer in both panels, unless it is the
+/// selected one, so it's clear which pair a click selects.
+fn draw_hover(f: &mut Frame, app: &App) {
+    let Some(row) = app.hovered_row() else {
+        return;
+    };
+    for (panel, state) in [(0, &app.left_list_state), (1, &app.right_list_state)] {
+        if state.selected() == Some(row) {
+            continue;
+        }
+        let Some(line) = app.row_line(panel, row) else {
+            continue;
+        };
+        let list = app.panel_areas[panel].inner(Margin::new(1, 1));
+        f.buffer_mut().set_style(
+            Rect::new(list.x, list.y + line, list.width, 1),
+            highlights().hover,
+        );
+    }
+}
+
+/// The rows a panel shows when placeholder rows are hidden; `None` when all
+/// of them are.
+fn shown_rows(app: &App, left: bool) -> Option<Vec<usize>> {
+    if app.placeholder_rows != PlaceholderRows::Hidden {
+        return None;
+    }
+    let count = if left {
+        app.left_items.len()
+    } else {
+        app.right_items.len()
+    };
+    Some(
+        (0..count)
+            .filter(|row| !app.hides_row(left, *row))
+            .collect(),
+    )
+}
+
+fn keep_rows<'a>(items: Vec<ListItem<'a>>, rows: Option<&[usize]>) -> Vec<ListItem<'a>> {
+    let Some(rows) = rows else {
+        return items;
+    };
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(row, _)| rows.binary_search(row).is_ok())
+        .map(|(_, item)| item)
+        .collect()
+}
+
+/// Draw a panel's list. With placeholder rows hidden, the selection and
+/// scroll position of `state` (which count every row) are translated to
+/// the rows that are drawn and back.
+fn render_rows(
+    f: &mut Frame,
+    list: List,
+    area: Rect,
+    state: &mut ListState,
+    rows: Option<&[usize]>,
+) {
+    let Some(rows) = rows else {
+        f.render_stateful_widget(list, area, state);
+        return;
+    };
+    let position = |row: usize| rows.partition_point(|shown| *shown < row);
+    let selected = state.selected().unwrap_or(0);
+    let mut offset = position(state.offset());
+    // The other panel's entry is selected: keep the spot it would take in view
+    let height = area.height.saturating_sub(2) as usize;
+    let target = position(selected);
+    if target < offset {
+        offset = target;
+    } else if height > 0 && target >= offset + height {
+        offset = target + 1 - height;
+    }
+
+    let mut shown = ListState::default()
+        .with_offset(offset)
+        .with_selected(rows.binary_search(&selected).ok());
+    f.render_stateful_widget(list, area, &mut shown);
+    *state.offset_mut() = rows.get(shown.offset()).copied().unwrap_or(0);
+}
+
+/// For each row, `symbol` if it is the more recently modified side of a
+/// changed file. Mtimes within the comparison's tolerance count as equal.
+fn newer_rows(
+    app: &App,
+    items: &[FileItem],
+    counterparts: &[FileItem],
+    symbol: &'static str,
+) -> Vec<Option<&'static str>> {
+    let tolerance = app.comparison.options.mtime_tolerance;
     items
         .iter()
-        .map(|(display_name, status, _, is_dir, size, modified)| {
-            if *is_dir && !display_name.trim().is_empty() {
-                let trimmed = display_name.trim_start();
-                let indent_len = display_name.len() - trimmed.len();
-                let indent = &display_name[..indent_len];
-
-                let parts: Vec<&str> = trimmed.splitn(2, ' ').collect();
-                if parts.len() == 2 {
-                    let icon = parts[0];
-                    let folder_name = parts[1];
-
-                    let text_color = match status {
-                        FileStatus::Same => Color::White,
-                        FileStatus::Different => Color::Red,
-                        FileStatus::LeftOnly => Color::Blue,
-                        FileStatus::RightOnly => Color::Blue,
-                    };
-
-                    let line = Line::from(vec![
-                        Span::raw(indent),
-                        Span::raw(icon),
-                        Span::raw(" "),
-                        Span::styled(folder_name, Style::default().fg(text_color)),
-                    ]);
-                    return ListItem::new(line);
-                }
+        .zip(counterparts)
+        .map(|(item, other)| match (item.modified, other.modified) {
+            (Some(modified), Some(other_modified))
+                if item.status == FileStatus::Different
+                    && !item.is_dir
+                    && modified > other_modified
+                    && !mtimes_equal(Some(modified), Some(other_modified), tolerance) =>
+            {
+                Some(symbol)
             }
+            _ => None,
+        })
+        .collect()
+}
 
-            let color = match status {
-                FileStatus::Same => Color::Gray,
-                FileStatus::Different => Color::LightRed,
-                FileStatus::LeftOnly => Color::LightBlue,
-                FileStatus::RightOnly => Color::LightBlue,
-            };
+/// For each row, whether its permission bits differ from the other side's.
+/// Only reported when permission comparison is enabled.
+fn mode_mismatch_rows(app: &App, items: &[FileItem], counterparts: &[FileItem]) -> Vec<bool> {
+    if !app.comparison.options.compare_permissions {
+        return Vec::new();
+    }
+    items
+        .iter()
+        .zip(counterparts)
+        .map(|(item, other)| {
+            !item.display_name.trim().is_empty()
+                && !other.display_name.trim().is_empty()
+                && item.mode != other.mode
+        })
+        .collect()
+}
 
-            if !*is_dir && !display_name.trim().is_empty() {
-                let size_str = format_file_size(*size);
-                let modified_str = format_modified_time(*modified);
-
-                let total_width = panel_width;
-                let name_width = display_name.len();
-                let info_width = size_str.len() + 1 + modified_str.len();
-
-                if name_width + info_width + 2 <= total_width {
-                    let padding_width = total_width - name_width - info_width;
-                    let padding = " ".repeat(padding_width);
-
-                    let line = Line::from(vec![
-                        Span::styled(display_name, Style::default().fg(color)),
-                        Span::raw(padding),
-                        Span::styled(size_str, Style::default().fg(Color::DarkGray)),
-                        Span::raw(" "),
-                        Span::styled(modified_str, Style::default().fg(Color::DarkGray)),
-                    ]);
-                    ListItem::new(line)
-                } else {
-                    ListItem::new(Line::from(Span::styled(
-                        display_name,
-                        Style::default().fg(color),
-                    )))
-                }
-            } else {
-                ListItem::new(Line::from(Span::styled(
-                    display_name,
-                    Style::default().fg(color),
-                )))
-            }
+/// For each row, `owner:group` padded to the widest entry of both panels and
+/// whether it differs from the other side. Empty unless the column is shown.
+fn owner_cells(
+    app: &App,
+    items: &[FileItem],
+    counterparts: &[FileItem],
+) -> Vec<Option<(String, bool)>> {
+    if !app.show_owner {
+        return Vec::new();
+    }
+    let owners: Vec<String> = items.iter().map(|item| format_owner(item.owner)).collect();
+    let width = owners
+        .iter()
+        .map(String::len)
+        .chain(
+            counterparts
+                .iter()
+                .map(|item| format_owner(item.owner).len()),
+        )
+        .max()
+        .unwrap_or(0);
+
+    items
+        .iter()
+        .zip(counterparts)
+        .zip(owners)
+        .map(|((item, other), owner)| {
+            let differs = !item.display_name.trim().is_empty()
+                && !other.display_name.trim().is_empty()
+                && item.owner != other.owner;
+            Some((format!("{:<width$}", owner), differs))
         })
         .collect()
 }
 
-fn draw_progress_popup(f: &mut Frame, app: &App) {
-    let popup_area = centered_rect(50, 20, f.area());
+/// For each row, how much bigger or smaller a changed file is than the other
+/// side (`+1.2M`), padded to the widest one. Empty when no changed file
+/// differs in size.
+fn size_delta_cells(
+    app: &App,
+    items: &[FileItem],
+    counterparts: &[FileItem],
+) -> Vec<Option<String>> {
+    let deltas: Vec<Option<String>> = items
+        .iter()
+        .zip(counterparts)
+        .map(|(item, other)| {
+            let (Some(size), Some(other_size)) = (item.size, other.size) else {
+                return None;
+            };
+            if item.status != FileStatus::Different || item.is_dir || size == other_size {
+                return None;
+            }
+            let sign = if size > other_size { '+' } else { '-' };
+            let delta = app.format_size(Some(size.abs_diff(other_size)));
+            Some(format!("{}{}", sign, delta.trim_start()))
+        })
+        .collect();
+    let width = deltas.iter().flatten().map(String::len).max().unwrap_or(0);
+    if width == 0 {
+        return Vec::new();
+    }
 
-    f.render_widget(Clear, popup_area);
+    deltas
+        .into_iter()
+        .map(|delta| Some(format!("{:>width$}", delta.unwrap_or_default())))
+        .collect()
+}
 
-    let popup_block = Block::default()
-        .title(" 🔄 새로고침 진행 중... ")
-        .title_style(
-            Style::default()
-                .fg(Color::Cyan)
-                .add_modifier(Modifier::BOLD),
-        )
-        .borders(Borders::ALL)
-        .border_style(Style::default().fg(Color::Cyan));
+/// Cells of a row that depend on the other panel.
+#[derive(Default)]
+struct RowExtras {
+    newer: Option<&'static str>,
+    mode_mismatch: bool,
+    owner: Option<(String, bool)>,
+    similarity: Option<u8>,
+    file_type: Option<&'static str>,
+    size_delta: Option<String>,
+    /// Name of the missing entry, for a placeholder row shown as a ghost
+    ghost: Option<String>,
+}
 
-    let popup_inner = popup_block.inner(popup_area);
-    f.render_widget(popup_block, popup_area);
+fn row_extras(
+    app: &App,
+    left: bool,
+    items: &[FileItem],
+    counterparts: &[FileItem],
+    newer_symbol: &'static str,
+) -> Vec<RowExtras> {
+    let mut newer = newer_rows(app, items, counterparts, newer_symbol).into_iter();
+    let mut mode_mismatch = mode_mismatch_rows(app, items, counterparts).into_iter();
+    let mut owner = owner_cells(app, items, counterparts).into_iter();
+    let mut size_delta = size_delta_cells(app, items, counterparts).into_iter();
+    let ghost = |index: usize| {
+        let name = counterparts.get(index)?.display_name.trim_start();
+        let shown = app.placeholder_rows == PlaceholderRows::Ghost
+            && items[index].display_name.trim().is_empty()
+            && !name.is_empty();
+        shown.then(|| name.to_string())
+    };
 
-    let popup_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(2),
-            Constraint::Length(3),
-            Constraint::Length(1),
-        ])
-        .split(popup_inner);
+    (0..items.len())
+        .map(|index| RowExtras {
+            newer: newer.next().flatten(),
+            mode_mismatch: mode_mismatch.next().unwrap_or(false),
+            owner: owner.next().flatten(),
+            similarity: app.similarity(index),
+            file_type: app.file_type(left, index),
+            size_delta: size_delta.next().flatten(),
+            ghost: ghost(index),
+        })
+        .collect()
+}
 
-    let message = Paragraph::new(app.refresh_progress.clone())
-        .alignment(Alignment::Center)
-        .style(Style::default().fg(Color::White));
-    f.render_widget(message, popup_chunks[0]);
+fn create_list_items<'a>(
+    app: &App,
+    items: &'a [FileItem],
+    markers: &[Vec<Option<FileStatus>>],
+    extras: &[RowExtras],
+    panel_width: usize,
+) -> Vec<ListItem<'a>> {
+    let no_extras = RowExtras::default();
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            if let Some(header) = group_header_line(app, index) {
+                return ListItem::new(header);
+            }
+            let extras = extras.get(index).unwrap_or(&no_extras);
+            let line = match markers.get(index) {
+                Some(row_markers) => {
+                    let mut spans = target_marker_spans(row_markers);
+                    let marker_width = row_markers.len() + 1;
+                    spans.extend(
+                        create_item_line(
+                            app,
+                            item,
+                            extras,
+                            panel_width.saturating_sub(marker_width),
+                        )
+                        .spans,
+                    );
+                    Line::from(spans)
+                }
+                None => create_item_line(app, item, extras, panel_width),
+            };
+            ListItem::new(line)
+        })
+        .collect()
+}
 
-    let progress = Gauge::default()
-        .block(Block::default().borders(Borders::ALL))
-        .gauge_style(Style::default().fg(Color::Cyan).bg(Color::Black))
-        .percent((app.refresh_percentage * 100.0) as u16)
-        .label(format!("{:.1}%", app.refresh_percentage * 100.0));
-    f.render_widget(progress, popup_chunks[1]);
+/// The title of a section in the grouped layout, if the row is one.
+fn group_header_line(app: &App, index: usize) -> Option<Line<'static>> {
+    let (_, status, count) = app.group_headers.iter().find(|(row, ..)| *row == index)?;
+    let label = match status {
+        FileStatus::Different => "Different",
+        FileStatus::LeftOnly => "Left only",
+        FileStatus::RightOnly => "Right only",
+        FileStatus::Same => "Same",
+    };
+    let folder = if app.collapsed_groups.contains(status) {
+        icons().folder_closed
+    } else {
+        icons().folder_open
+    };
+    Some(Line::from(Span::styled(
+        format!("{} {} {} ({})", folder, status_marker(*status), label, count),
+        Style::default()
+            .fg(status_color(*status, true))
+            .add_modifier(Modifier::BOLD),
+    )))
+}
 
-    let help = Paragraph::new("Press ESC to cancel")
-        .alignment(Alignment::Center)
-        .style(Style::default().fg(Color::Gray));
-    f.render_widget(help, popup_chunks[2]);
+/// One character per target describing the row's status against that target.
+fn target_marker_spans(row_markers: &[Option<FileStatus>]) -> Vec<Span<'static>> {
+    let mut spans: Vec<Span> = row_markers
+        .iter()
+        .map(|status| match status {
+            Some(status) => Span::styled(
+                status_marker(*status),
+                Style::default().fg(status_color(*status, false)),
+            ),
+            None => Span::raw(" "),
+        })
+        .collect();
+    spans.push(Span::raw(" "));
+    spans
 }
 
-fn draw_copy_confirm_popup(f: &mut Frame, app: &App) {
-    if let Some(copy_info) = &app.copy_info {
-        let popup_area = if copy_info.from_left_to_right {
-            panel_centered_rect(50, 25, f.area(), true)
+/// `newer` is the ◀/▶ shown after the mtime when this side is the newer one.
+fn create_item_line<'a>(
+    app: &App,
+    item: &'a FileItem,
+    extras: &RowExtras,
+    panel_width: usize,
+) -> Line<'a> {
+    let FileItem {
+        display_name,
+        status,
+        path,
+        is_dir,
+        size,
+        modified,
+        mode,
+        ..
+    } = item;
+    let newer = extras.newer;
+    let placeholder = display_name.trim().is_empty();
+    let verified = (!placeholder && app.verified.contains(path)).then(|| {
+        Span::styled(
+            format!(" {}", icons().verified),
+            Style::default().fg(Color::Green),
+        )
+    });
+
+    // Without colors, the status is spelled out in front of the name and
+    // the name is emphasized
+    let mut name = Vec::new();
+    let mut emphasis = Modifier::empty();
+    if is_no_color() {
+        let marker = if placeholder {
+            " "
         } else {
-            panel_centered_rect(50, 25, f.area(), false)
+            status_marker(*status)
         };
+        name.push(Span::raw(format!("{} ", marker)));
+        emphasis = status_modifier(*status);
+    }
 
-        f.render_widget(Clear, popup_area);
+    if let (true, Some(ghost)) = (placeholder, &extras.ghost) {
+        name.push(Span::raw(display_name.as_str()));
+        name.push(Span::styled(
+            ghost.clone(),
+            Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::DIM | Modifier::ITALIC),
+        ));
+        return layout_row(name, RowColumns::default(), panel_width);
+    }
 
-        let title = if copy_info.from_left_to_right {
-            " ▶️ Copy to RIGHT panel "
-        } else {
-            " ◀️ Copy to LEFT panel "
+    // Permission and owner cells, shared by files and folders
+    let mut attributes = Vec::new();
+    if !placeholder {
+        let highlight = |differs: bool| {
+            let style = Style::default().fg(if differs {
+                Color::Yellow
+            } else {
+                Color::DarkGray
+            });
+            if differs && is_no_color() {
+                style.add_modifier(Modifier::BOLD)
+            } else {
+                style
+            }
         };
-
-        let popup_block = Block::default()
-            .title(title)
+        if app.show_permissions {
+            attributes.push(Span::styled(
+                format!("{} ", format_permissions(*mode)),
+                highlight(extras.mode_mismatch),
+            ));
+        }
+        if let Some((owner, differs)) = &extras.owner {
+            attributes.push(Span::styled(format!("{} ", owner), highlight(*differs)));
+        }
+        if app.show_similarity {
+            let percent = extras
+                .similarity
+                .map(|percent| format!("{}%", percent))
+                .unwrap_or_default();
+            attributes.push(Span::styled(
+                format!("{:>4} ", percent),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        if app.show_file_type {
+            attributes.push(Span::styled(
+                format!("{:<10} ", extras.file_type.unwrap_or_default()),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        if let Some(delta) = &extras.size_delta {
+            let color = if delta.contains('+') {
+                palette().added
+            } else {
+                palette().removed
+            };
+            attributes.push(Span::styled(
+                format!("{} ", delta),
+                Style::default().fg(color),
+            ));
+        }
+    }
+
+    if *is_dir && !placeholder {
+        let trimmed = display_name.trim_start();
+        let indent_len = display_name.len() - trimmed.len();
+        let indent = &display_name[..indent_len];
+
+        let parts: Vec<&str> = trimmed.splitn(2, ' ').collect();
+        if parts.len() == 2 {
+            let icon = parts[0];
+            let folder_name = parts[1];
+
+            let text_color = status_color(*status, true);
+
+            name.extend([
+                Span::raw(indent),
+                Span::raw(icon),
+                Span::raw(" "),
+                Span::styled(
+                    folder_name,
+                    Style::default().fg(text_color).add_modifier(emphasis),
+                ),
+            ]);
+            name.extend(verified);
+            // Folders have no size or time; keep their attributes in the same columns
+            let columns = if attributes.is_empty() {
+                RowColumns::default()
+            } else {
+                RowColumns {
+                    attributes,
+                    size: Some(Span::raw(" ".repeat(app.format_size(None).len()))),
+                    date: vec![Span::raw(" ".repeat(format_modified_time(None).len() + 2))],
+                }
+            };
+            return layout_row(name, columns, panel_width);
+        }
+    }
+
+    let color = status_color(*status, false);
+    // Keep the underline off the indentation
+    let trimmed = display_name.trim_start();
+    let indent = &display_name[..display_name.len() - trimmed.len()];
+    name.push(Span::raw(indent));
+    name.push(Span::styled(
+        trimmed,
+        Style::default().fg(color).add_modifier(emphasis),
+    ));
+    name.extend(verified);
+
+    if !*is_dir && !placeholder {
+        let size_str = app.format_size(*size);
+        let modified_str = format_modified_time(*modified);
+
+        let modified_color = if newer.is_some() {
+            Color::Yellow
+        } else {
+            Color::DarkGray
+        };
+
+        let columns = RowColumns {
+            attributes,
+            size: Some(Span::styled(size_str, Style::default().fg(Color::DarkGray))),
+            date: vec![
+                Span::raw(" "),
+                Span::styled(modified_str, Style::default().fg(modified_color)),
+                // One extra column for the newer-side indicator
+                Span::styled(newer.unwrap_or(" "), Style::default().fg(Color::Yellow)),
+            ],
+        };
+        layout_row(name, columns, panel_width)
+    } else {
+        layout_row(name, RowColumns::default(), panel_width)
+    }
+}
+
+/// Narrowest name column kept before columns start to be dropped.
+const MIN_NAME_WIDTH: usize = 16;
+
+/// Right-hand columns of a row, in the order they are given up when the
+/// panel narrows: date first, then size, then the attribute columns.
+#[derive(Default)]
+struct RowColumns<'a> {
+    attributes: Vec<Span<'a>>,
+    size: Option<Span<'a>>,
+    date: Vec<Span<'a>>,
+}
+
+impl<'a> RowColumns<'a> {
+    fn width(&self) -> usize {
+        self.attributes
+            .iter()
+            .chain(&self.size)
+            .chain(&self.date)
+            .map(Span::width)
+            .sum()
+    }
+
+    /// Drop the next column; false when there is nothing left to drop.
+    fn drop_one(&mut self) -> bool {
+        if !self.date.is_empty() {
+            self.date.clear();
+        } else if self.size.is_some() {
+            self.size = None;
+        } else if !self.attributes.is_empty() {
+            self.attributes.clear();
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn into_spans(self) -> impl Iterator<Item = Span<'a>> {
+        self.attributes
+            .into_iter()
+            .chain(self.size)
+            .chain(self.date)
+    }
+}
+
+/// Right-align the columns after `name`. Columns are dropped while they would
+/// leave the name less than `MIN_NAME_WIDTH`; names that still don't fit are
+/// cut with an ellipsis.
+fn layout_row<'a>(
+    name: Vec<Span<'a>>,
+    mut columns: RowColumns<'a>,
+    panel_width: usize,
+) -> Line<'a> {
+    // The choice depends only on the panel width, so columns stay aligned
+    while columns.width() > 0 && columns.width() + 1 + MIN_NAME_WIDTH > panel_width {
+        if !columns.drop_one() {
+            break;
+        }
+    }
+
+    let columns_width = columns.width();
+    let name_space = if columns_width == 0 {
+        panel_width
+    } else {
+        panel_width - columns_width - 1
+    };
+    let mut spans = truncate_spans(name, name_space);
+
+    if columns_width > 0 {
+        let padding = panel_width - columns_width - spans.iter().map(Span::width).sum::<usize>();
+        spans.push(Span::raw(" ".repeat(padding)));
+        spans.extend(columns.into_spans());
+    }
+    Line::from(spans)
+}
+
+/// Cut `spans` to `max_width` columns, ending in an ellipsis when anything was cut.
+fn truncate_spans(spans: Vec<Span<'_>>, max_width: usize) -> Vec<Span<'_>> {
+    if spans.iter().map(Span::width).sum::<usize>() <= max_width {
+        return spans;
+    }
+
+    let ellipsis = icons().ellipsis;
+    let budget = max_width.saturating_sub(Span::raw(ellipsis).width());
+    let mut used = 0;
+    let mut truncated = Vec::new();
+    for span in spans {
+        let width = span.width();
+        if used + width <= budget {
+            used += width;
+            truncated.push(span);
+            continue;
+        }
+
+        let mut text = String::new();
+        for grapheme in span.styled_graphemes(Style::default()) {
+            let width = Span::raw(grapheme.symbol).width();
+            if used + width > budget {
+                break;
+            }
+            used += width;
+            text.push_str(grapheme.symbol);
+        }
+        truncated.push(Span::styled(text, span.style));
+        if used + Span::raw(ellipsis).width() <= max_width {
+            truncated.push(Span::styled(ellipsis, span.style));
+        }
+        break;
+    }
+    truncated
+}
+
+fn draw_preview(f: &mut Frame, app: &App, area: Rect, similarity: Option<u8>) {
+    let max_lines = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = app
+        .file_diff
+        .lines()
+        .take(max_lines)
+        .map(|line| {
+            let color = if line.starts_with("+++") || line.starts_with("---") {
+                Color::White
+            } else if line.starts_with('+') {
+                palette().added
+            } else if line.starts_with('-') {
+                palette().removed
+            } else if line.starts_with("@@") {
+                Color::Cyan
+            } else {
+                Color::Gray
+            };
+            Line::from(Span::styled(line, Style::default().fg(color)))
+        })
+        .collect();
+
+    let preview = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title(match similarity {
+                Some(percent) => format!(" Preview - {}% similar (p to hide) ", percent),
+                None => " Preview (p to hide) ".to_string(),
+            })
+            .border_style(Style::default().fg(Color::DarkGray)),
+    );
+    f.render_widget(preview, area);
+}
+
+fn draw_progress_popup(f: &mut Frame, app: &App) {
+    draw_progress(
+        f,
+        &labeled(icons().refresh, "새로고침 진행 중..."),
+        &app.refresh_progress,
+        app.refresh_percentage,
+        "Press ESC to cancel",
+    );
+}
+
+/// Progress of the comparison that runs before the main screen opens.
+pub fn draw_scan_progress(f: &mut Frame, message: &str, percentage: f64) {
+    draw_progress(
+        f,
+        &labeled(icons().processing, "Comparing..."),
+        message,
+        percentage,
+        "Press ESC to stop; the next run continues from here",
+    );
+}
+
+/// Ask whether a scan that passed the entry limit should go on.
+pub fn draw_limit_prompt(f: &mut Frame, processed: usize) {
+    let popup_area = centered_rect(50, 40, f.area());
+    f.render_widget(Clear, popup_area);
+
+    let popup_block = Block::default()
+        .title(format!(" {} ", labeled(icons().warning, "Entry limit")))
+        .title_style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Yellow));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Min(2),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    let message = Paragraph::new(vec![
+        Line::from(Span::styled(
+            format!("The scan has passed {} paths - keep going?", processed),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )),
+        Line::from(Span::styled(
+            "Check that these are the folders you meant to compare.",
+            Style::default().fg(Color::Gray),
+        )),
+    ])
+    .alignment(Alignment::Center)
+    .wrap(Wrap { trim: true });
+    f.render_widget(message, popup_chunks[1]);
+
+    let key = |key| {
+        Span::styled(
+            key,
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    };
+    let buttons = Paragraph::new(vec![Line::from(vec![
+        key("c"),
+        Span::raw(" - Continue  "),
+        key("m"),
+        Span::raw(" - Sizes and times only  "),
+        key("a/Esc"),
+        Span::raw(" - Abort"),
+    ])])
+    .alignment(Alignment::Center);
+    f.render_widget(buttons, popup_chunks[2]);
+}
+
+fn draw_progress(f: &mut Frame, title: &str, message: &str, percentage: f64, help: &str) {
+    let popup_area = centered_rect(50, 20, f.area());
+
+    f.render_widget(Clear, popup_area);
+
+    let popup_block = Block::default()
+        .title(format!(" {} ", title))
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(2),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    let message = Paragraph::new(message.to_string())
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::White));
+    f.render_widget(message, popup_chunks[0]);
+
+    let progress = Gauge::default()
+        .block(Block::default().borders(Borders::ALL))
+        .gauge_style(Style::default().fg(Color::Cyan).bg(Color::Black))
+        .percent((percentage * 100.0) as u16)
+        .label(format!("{:.1}%", percentage * 100.0));
+    f.render_widget(progress, popup_chunks[1]);
+
+    let help = Paragraph::new(help)
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::Gray));
+    f.render_widget(help, popup_chunks[2]);
+}
+
+fn draw_copy_confirm_popup(f: &mut Frame, app: &App) {
+    if let Some(copy_info) = &app.copy_info {
+        // Room for the copy options
+        let has_options = copy_info.overwrite_count > 0 || copy_info.differences.is_some();
+        let (percent_x, percent_y) = if has_options { (80, 60) } else { (50, 25) };
+        let popup_area = if copy_info.from_left_to_right {
+            panel_centered_rect(percent_x, percent_y, f.area(), true)
+        } else {
+            panel_centered_rect(percent_x, percent_y, f.area(), false)
+        };
+
+        f.render_widget(Clear, popup_area);
+
+        let title = if copy_info.from_left_to_right {
+            format!(" {} ", labeled(icons().copy_right, "Copy to RIGHT panel"))
+        } else {
+            format!(" {} ", labeled(icons().copy_left, "Copy to LEFT panel"))
+        };
+
+        let popup_block = Block::default()
+            .title(title)
             .title_style(
                 Style::default()
                     .fg(Color::Green)
@@ -427,15 +1404,18 @@ fn draw_copy_confirm_popup(f: &mut Frame, app: &App) {
             .split(popup_inner);
 
         draw_copy_paths(f, copy_info, popup_chunks[1], popup_area.width);
-        draw_copy_info(f, copy_info, popup_chunks[3]);
+        draw_copy_info(f, app, copy_info, popup_chunks[3]);
+        if has_options {
+            draw_copy_options(f, copy_info, popup_chunks[4]);
+        }
         draw_copy_buttons(f, popup_chunks[5]);
     }
 }
 
 fn draw_copy_paths(f: &mut Frame, copy_info: &CopyInfo, area: Rect, popup_width: u16) {
     let max_path_width = popup_width.saturating_sub(4) as usize;
-    let left_path = truncate_path(&copy_info.source_path.display().to_string(), max_path_width);
-    let right_path = truncate_path(&copy_info.target_path.display().to_string(), max_path_width);
+    let left_path = truncate_path(&escape_name(&copy_info.source_path), max_path_width);
+    let right_path = truncate_path(&escape_name(&copy_info.target_path), max_path_width);
 
     let paths = Paragraph::new(vec![
         Line::from(vec![Span::styled(
@@ -452,7 +1432,7 @@ fn draw_copy_paths(f: &mut Frame, copy_info: &CopyInfo, area: Rect, popup_width:
     f.render_widget(paths, area);
 }
 
-fn draw_copy_info(f: &mut Frame, copy_info: &CopyInfo, area: Rect) {
+fn draw_copy_info(f: &mut Frame, app: &App, copy_info: &CopyInfo, area: Rect) {
     let file_text = if copy_info.file_count == 1 {
         format!("{} file", copy_info.file_count)
     } else {
@@ -467,7 +1447,22 @@ fn draw_copy_info(f: &mut Frame, copy_info: &CopyInfo, area: Rect) {
         format!("{} folders", copy_info.folder_count)
     };
 
-    let size_text = format_file_size(Some(copy_info.total_bytes));
+    // What actually goes over the wire, next to the full size when less
+    let transfer_text = app.format_size(copy_info.transfer_bytes);
+    let size_text = if copy_info.transfer_bytes.is_none() {
+        format!(
+            "{}, counting what to transfer...",
+            app.format_size(Some(copy_info.total_bytes)).trim_start()
+        )
+    } else if copy_info.transfer_bytes == Some(copy_info.total_bytes) {
+        format!("{} to transfer", transfer_text.trim_start())
+    } else {
+        format!(
+            "{} to transfer (of {})",
+            transfer_text.trim_start(),
+            app.format_size(Some(copy_info.total_bytes)).trim_start()
+        )
+    };
 
     let mut info_lines = vec![Line::from(vec![Span::styled(
         file_text,
@@ -496,6 +1491,62 @@ fn draw_copy_info(f: &mut Frame, copy_info: &CopyInfo, area: Rect) {
     f.render_widget(info, area);
 }
 
+fn draw_copy_options(f: &mut Frame, copy_info: &CopyInfo, area: Rect) {
+    let mut lines = Vec::new();
+
+    if copy_info.differences.is_some() {
+        let check = if copy_info.only_differences {
+            "[x]"
+        } else {
+            "[ ]"
+        };
+        lines.push(Line::from(vec![
+            Span::styled(
+                format!("{} Only differences ", check),
+                Style::default().fg(Color::White),
+            ),
+            Span::styled("(d)", Style::default().fg(Color::DarkGray)),
+        ]));
+    }
+
+    if copy_info.overwrite_count > 0 {
+        let warning = if copy_info.overwrite_count == 1 {
+            "Will overwrite 1 existing file".to_string()
+        } else {
+            format!(
+                "Will overwrite {} existing files",
+                copy_info.overwrite_count
+            )
+        };
+        lines.push(Line::from(vec![Span::styled(
+            warning,
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )]));
+        for policy in OverwritePolicy::ALL {
+            let style = if policy == copy_info.overwrite_policy {
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            lines.push(Line::from(vec![Span::styled(
+                format!(" {} ", policy.label()),
+                style,
+            )]));
+        }
+        lines.push(Line::from(vec![Span::styled(
+            "Tab - change",
+            Style::default().fg(Color::DarkGray),
+        )]));
+    }
+
+    f.render_widget(Paragraph::new(lines).alignment(Alignment::Center), area);
+}
+
 fn draw_copy_buttons(f: &mut Frame, area: Rect) {
     let buttons = Paragraph::new(vec![Line::from(vec![
         Span::styled(
@@ -515,6 +1566,125 @@ fn draw_copy_buttons(f: &mut Frame, area: Rect) {
     f.render_widget(buttons, area);
 }
 
+fn draw_copy_progress_popup(f: &mut Frame, app: &App) {
+    let from_left_to_right = app
+        .copy_info
+        .as_ref()
+        .is_none_or(|copy_info| copy_info.from_left_to_right);
+    let popup_area = panel_centered_rect(80, 50, f.area(), from_left_to_right);
+
+    f.render_widget(Clear, popup_area);
+
+    let (title, color) = if app.copy_error.is_some() {
+        (labeled(icons().warning, "Copy failed"), Color::Red)
+    } else if from_left_to_right {
+        (
+            labeled(icons().copy_right, "Copying to RIGHT panel"),
+            Color::Green,
+        )
+    } else {
+        (
+            labeled(icons().copy_left, "Copying to LEFT panel"),
+            Color::Green,
+        )
+    };
+
+    let backend = match app.copy_backend {
+        CopyBackend::Builtin => "",
+        CopyBackend::Rsync => " (rsync)",
+    };
+    let popup_block = Block::default()
+        .title(format!(" {}{} ", title, backend))
+        .title_style(Style::default().fg(color).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(color));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    if let Some(error) = &app.copy_error {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)])
+            .split(popup_inner);
+        let message = Paragraph::new(error.as_str())
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true })
+            .style(Style::default().fg(Color::White));
+        f.render_widget(message, chunks[0]);
+        let help = Paragraph::new("Press ESC to close and refresh")
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::Gray));
+        f.render_widget(help, chunks[1]);
+        return;
+    }
+
+    let Some(progress) = &app.copy_progress else {
+        return;
+    };
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+            Constraint::Min(0),
+        ])
+        .split(popup_inner);
+
+    let max_path_width = popup_inner.width as usize;
+    let current_file = Paragraph::new(truncate_path(
+        &escape_name(&progress.current_file),
+        max_path_width,
+    ))
+    .alignment(Alignment::Center)
+    .style(Style::default().fg(Color::Cyan));
+    f.render_widget(current_file, popup_chunks[0]);
+
+    let file_gauge = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title(" File "))
+        .gauge_style(Style::default().fg(Color::Cyan).bg(Color::Black))
+        .ratio(progress.file_ratio())
+        .label(format!(
+            "{} / {}",
+            app.format_size(Some(progress.file_bytes)).trim(),
+            app.format_size(Some(progress.file_total)).trim()
+        ));
+    f.render_widget(file_gauge, popup_chunks[1]);
+
+    let total_title = if progress.files_skipped > 0 {
+        format!(
+            " Total - {}/{} files, {} skipped ",
+            progress.files_done + progress.files_skipped,
+            progress.file_count,
+            progress.files_skipped
+        )
+    } else {
+        format!(" Total - {}/{} files ", progress.files_done, progress.file_count)
+    };
+    let total_gauge = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title(total_title))
+        .gauge_style(Style::default().fg(Color::Green).bg(Color::Black))
+        .ratio(progress.total_ratio())
+        .label(format!(
+            "{} / {} ({:.1}%)",
+            app.format_size(Some(progress.copied_bytes)).trim(),
+            app.format_size(Some(progress.total_bytes)).trim(),
+            progress.total_ratio() * 100.0
+        ));
+    f.render_widget(total_gauge, popup_chunks[2]);
+
+    let rate = Paragraph::new(format!(
+        "{}/s",
+        app.format_size(Some(progress.bytes_per_sec as u64)).trim()
+    ))
+    .alignment(Alignment::Center)
+    .style(Style::default().fg(Color::Magenta));
+    f.render_widget(rate, popup_chunks[3]);
+}
+
 fn draw_delete_confirm_popup(f: &mut Frame, app: &App) {
     if let Some(delete_info) = &app.delete_info {
         let popup_area = panel_centered_rect(50, 25, f.area(), delete_info.is_left);
@@ -522,9 +1692,9 @@ fn draw_delete_confirm_popup(f: &mut Frame, app: &App) {
         f.render_widget(Clear, popup_area);
 
         let title = if delete_info.is_left {
-            " 🗑️ Delete from LEFT panel "
+            format!(" {} ", labeled(icons().delete, "Delete from LEFT panel"))
         } else {
-            " 🗑️ Delete from RIGHT panel "
+            format!(" {} ", labeled(icons().delete, "Delete from RIGHT panel"))
         };
 
         let popup_block = Block::default()
@@ -553,18 +1723,18 @@ fn draw_delete_confirm_popup(f: &mut Frame, app: &App) {
             .split(popup_inner);
 
         draw_delete_path(f, delete_info, popup_chunks[1], popup_area.width);
-        draw_delete_info(f, delete_info, popup_chunks[3]);
+        draw_delete_info(f, app, delete_info, popup_chunks[3]);
         draw_delete_buttons(f, popup_chunks[5]);
     }
 }
 
 fn draw_delete_path(f: &mut Frame, delete_info: &DeleteInfo, area: Rect, popup_width: u16) {
     let max_path_width = popup_width.saturating_sub(4) as usize;
-    let path = truncate_path(&delete_info.path.display().to_string(), max_path_width);
+    let path = truncate_path(&escape_name(&delete_info.path), max_path_width);
 
     let paths = Paragraph::new(vec![
         Line::from(vec![Span::styled(
-            "⚠️  Are you sure you want to delete?",
+            labeled(icons().warning, "Are you sure you want to delete?"),
             Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
         )]),
         Line::from(vec![Span::styled(
@@ -576,7 +1746,7 @@ fn draw_delete_path(f: &mut Frame, delete_info: &DeleteInfo, area: Rect, popup_w
     f.render_widget(paths, area);
 }
 
-fn draw_delete_info(f: &mut Frame, delete_info: &DeleteInfo, area: Rect) {
+fn draw_delete_info(f: &mut Frame, app: &App, delete_info: &DeleteInfo, area: Rect) {
     let file_text = if delete_info.file_count == 1 {
         format!("{} file", delete_info.file_count)
     } else {
@@ -591,7 +1761,7 @@ fn draw_delete_info(f: &mut Frame, delete_info: &DeleteInfo, area: Rect) {
         format!("{} folders", delete_info.folder_count)
     };
 
-    let size_text = format_file_size(Some(delete_info.total_bytes));
+    let size_text = app.format_size(Some(delete_info.total_bytes));
 
     let mut info_lines = vec![Line::from(vec![Span::styled(
         file_text,
@@ -637,14 +1807,908 @@ fn draw_delete_buttons(f: &mut Frame, area: Rect) {
     f.render_widget(buttons, area);
 }
 
-fn draw_file_view(f: &mut Frame, app: &mut App) {
-    let paragraph = Paragraph::new(app.file_diff.as_str()).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title("File Diff (Press Enter to go back)"),
-    );
+fn draw_restore_confirm_popup(f: &mut Frame, app: &App) {
+    let popup_area = centered_rect(60, 50, f.area());
+    f.render_widget(Clear, popup_area);
 
-    f.render_widget(paragraph, f.area());
+    let popup_block = Block::default()
+        .title(format!(
+            " {} ",
+            labeled(icons().warning, "Backups left by a killed copy")
+        ))
+        .title_style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Yellow));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    let message = Paragraph::new(vec![
+        Line::from(Span::styled(
+            "Restore these files from before the killed copy?",
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )),
+        Line::from(Span::styled(
+            "Each replaces what is under its name now.",
+            Style::default().fg(Color::Gray),
+        )),
+    ])
+    .alignment(Alignment::Center)
+    .wrap(Wrap { trim: true });
+    f.render_widget(message, popup_chunks[0]);
+
+    let max_path_width = popup_chunks[1].width as usize;
+    let mut lines: Vec<Line> = app
+        .stale_backups
+        .iter()
+        .take(popup_chunks[1].height as usize)
+        .map(|backup| Line::from(truncate_path(&escape_name(backup), max_path_width)))
+        .collect();
+    let hidden = app.stale_backups.len().saturating_sub(lines.len());
+    if hidden > 0 {
+        lines.pop();
+        lines.push(Line::from(Span::styled(
+            format!("... and {} more", hidden + 1),
+            Style::default().fg(Color::Gray),
+        )));
+    }
+    f.render_widget(Paragraph::new(lines), popup_chunks[1]);
+
+    let buttons = Paragraph::new(vec![Line::from(vec![
+        Span::styled(
+            "Enter",
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(" - Restore  "),
+        Span::styled(
+            "Esc",
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(" - Keep"),
+    ])])
+    .alignment(Alignment::Center);
+    f.render_widget(buttons, popup_chunks[2]);
+}
+
+fn draw_quit_confirm_popup(f: &mut Frame, app: &App) {
+    let (question, consequence) = if app.copy_progress.is_some() {
+        let consequence = match app.copy_backend {
+            CopyBackend::Builtin => "It will be stopped and what it copied so far removed again.",
+            CopyBackend::Rsync => "rsync will be stopped, and what it copied so far stays.",
+        };
+        ("A copy is running - quit anyway?", consequence)
+    } else if app.is_refreshing {
+        ("A refresh is running - quit anyway?", "It will be stopped.")
+    } else {
+        ("Quit?", "Nothing is running any more.")
+    };
+
+    let popup_area = centered_rect(50, 40, f.area());
+    f.render_widget(Clear, popup_area);
+
+    let popup_block = Block::default()
+        .title(format!(" {} ", labeled(icons().warning, "Quit")))
+        .title_style(
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Yellow));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Min(2),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    let message = Paragraph::new(vec![
+        Line::from(Span::styled(
+            question,
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )),
+        Line::from(Span::styled(consequence, Style::default().fg(Color::Gray))),
+    ])
+    .alignment(Alignment::Center)
+    .wrap(Wrap { trim: true });
+    f.render_widget(message, popup_chunks[1]);
+
+    let buttons = Paragraph::new(vec![Line::from(vec![
+        Span::styled(
+            "y",
+            Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(" - Quit  "),
+        Span::styled(
+            "n/Esc",
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(" - Keep running"),
+    ])])
+    .alignment(Alignment::Center);
+    f.render_widget(buttons, popup_chunks[2]);
+}
+
+fn draw_hash_popup(f: &mut Frame, app: &App) {
+    let Some(info) = &app.hash_info else {
+        return;
+    };
+    let popup_area = centered_rect(80, 60, f.area());
+    f.render_widget(Clear, popup_area);
+
+    let popup_block = Block::default()
+        .title(format!(
+            " {} of {} ",
+            info.algorithm.name(),
+            escape_name(&*info.path)
+        ))
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let same = matches!(
+        (&info.left, &info.right),
+        (HashSide::Done(Ok(left)), HashSide::Done(Ok(right))) if left == right
+    );
+    let side = |label: &'static str, file_type: Option<&'static str>, hash: &HashSide| {
+        let (text, style) = match hash {
+            HashSide::Done(Ok(hash)) => (
+                hash.clone(),
+                Style::default().fg(if same {
+                    palette().same
+                } else {
+                    palette().different
+                }),
+            ),
+            HashSide::Done(Err(error)) => (error.clone(), Style::default().fg(Color::Red)),
+            HashSide::Hashing(done, total) => (
+                format!(
+                    "Hashing... {}%",
+                    (*done * 100).checked_div(*total).unwrap_or(0).min(100)
+                ),
+                Style::default().fg(Color::DarkGray),
+            ),
+            HashSide::Missing => (
+                "(missing)".to_string(),
+                Style::default().fg(palette().one_sided),
+            ),
+        };
+        let mut title = vec![Span::styled(
+            label,
+            Style::default().add_modifier(Modifier::BOLD),
+        )];
+        if let Some(file_type) = file_type {
+            title.push(Span::styled(
+                format!(" ({})", file_type),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        vec![Line::from(title), Line::from(Span::styled(text, style))]
+    };
+    let mut lines = side("Left", info.types[0], &info.left);
+    lines.extend(side("Right", info.types[1], &info.right));
+    lines.push(Line::from(""));
+    lines.push(Line::from(match (&info.left, &info.right) {
+        (HashSide::Done(Ok(_)), HashSide::Done(Ok(_))) if same => "The contents are identical",
+        (HashSide::Done(Ok(_)), HashSide::Done(Ok(_))) => "The contents differ",
+        _ => "",
+    }));
+    if let Some(copied) = &info.copied {
+        lines.push(Line::from(Span::styled(
+            copied.as_str(),
+            Style::default().fg(Color::Yellow),
+        )));
+    }
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(2), Constraint::Length(1)])
+        .split(popup_inner);
+    f.render_widget(
+        Paragraph::new(lines).wrap(Wrap { trim: false }),
+        popup_chunks[0],
+    );
+
+    let key = |key: &'static str| {
+        Span::styled(
+            key,
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    };
+    let buttons = Paragraph::new(Line::from(vec![
+        key("l"),
+        Span::raw(" - Copy left  "),
+        key("r"),
+        Span::raw(" - Copy right  "),
+        key("Esc"),
+        Span::raw(" - Close"),
+    ]))
+    .alignment(Alignment::Center);
+    f.render_widget(buttons, popup_chunks[1]);
+}
+
+/// The changes made to the folders this session, latest at the bottom.
+fn draw_session_log_popup(f: &mut Frame, app: &App) {
+    let operations = &app.session_log.operations;
+    let popup_area = centered_rect(90, 70, f.area());
+    f.render_widget(Clear, popup_area);
+
+    let popup_block = Block::default()
+        .title(format!(" Session log - {} changes ", operations.len()))
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)])
+        .split(popup_inner);
+
+    let lines: Vec<Line> = if operations.is_empty() {
+        vec![Line::from(Span::styled(
+            "Nothing has been changed yet",
+            Style::default().fg(Color::DarkGray),
+        ))]
+    } else {
+        let end = operations.len() - app.session_log_scroll.min(operations.len() - 1);
+        let start = end.saturating_sub(popup_chunks[0].height as usize);
+        operations[start..end]
+            .iter()
+            .map(|operation| {
+                let style = if operation.error.is_some() {
+                    Style::default().fg(Color::Red)
+                } else {
+                    Style::default()
+                };
+                Line::from(Span::styled(operation.line(), style))
+            })
+            .collect()
+    };
+    f.render_widget(Paragraph::new(lines), popup_chunks[0]);
+
+    let key = |key: &'static str| {
+        Span::styled(
+            key,
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    };
+    let buttons = Paragraph::new(Line::from(vec![
+        key("↑/↓"),
+        Span::raw(" - Scroll  "),
+        key("Esc"),
+        Span::raw(" - Close"),
+    ]))
+    .alignment(Alignment::Center);
+    f.render_widget(buttons, popup_chunks[1]);
+}
+
+fn draw_diffstat_popup(f: &mut Frame, app: &App) {
+    let Some(diffstat) = &app.diffstat else {
+        return;
+    };
+    let popup_area = centered_rect(90, 70, f.area());
+    f.render_widget(Clear, popup_area);
+
+    let folder = if diffstat.folder.as_os_str().is_empty() {
+        "the roots".to_string()
+    } else {
+        format!("{}/", escape_name(&diffstat.folder))
+    };
+    let popup_block = Block::default()
+        .title(format!(" Diffstat of {} ", folder))
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    // `name | 12 ++++----` like git, the bars scaled to the busiest file
+    let names: Vec<String> = diffstat
+        .files
+        .iter()
+        .map(|(path, _)| escape_name(path.strip_prefix(&diffstat.folder).unwrap_or(path)))
+        .collect();
+    let totals = diffstat
+        .files
+        .iter()
+        .filter_map(|(_, changes)| *changes)
+        .fold((0, 0), |(removed, added), (r, a)| (removed + r, added + a));
+    let busiest = diffstat
+        .files
+        .iter()
+        .filter_map(|(_, changes)| changes.map(|(removed, added)| removed + added))
+        .max()
+        .unwrap_or(0);
+    let width = popup_chunks[0].width as usize;
+    let count_width = busiest.max(1).to_string().len().max(3);
+    let bar_room = (width / 3).max(10);
+    let name_width = width.saturating_sub(count_width + bar_room + 5).max(10);
+
+    let counting = diffstat.files.len() < diffstat.total;
+    let lines: Vec<Line> = if diffstat.total == 0 {
+        vec![Line::from(Span::styled(
+            "No files differ here",
+            Style::default().fg(Color::DarkGray),
+        ))]
+    } else {
+        let end = (diffstat.scroll + popup_chunks[0].height as usize).min(names.len());
+        (diffstat.scroll.min(end)..end)
+            .map(|index| {
+                let name = truncate_path(&names[index], name_width);
+                let mut spans = vec![Span::raw(format!(" {:<name_width$} | ", name))];
+                match diffstat.files[index].1 {
+                    Some((removed, added)) => {
+                        let total = removed + added;
+                        // Every changed file gets at least one mark
+                        let scale = |lines: usize| {
+                            if busiest <= bar_room {
+                                lines
+                            } else {
+                                (lines * bar_room).div_ceil(busiest)
+                            }
+                        };
+                        spans.push(Span::raw(format!("{:>count_width$} ", total)));
+                        spans.push(Span::styled(
+                            "+".repeat(scale(added)),
+                            Style::default().fg(palette().added),
+                        ));
+                        spans.push(Span::styled(
+                            "-".repeat(scale(removed)),
+                            Style::default().fg(palette().removed),
+                        ));
+                    }
+                    None => spans.push(Span::styled(
+                        format!("{:>count_width$}", "Bin"),
+                        Style::default().fg(Color::DarkGray),
+                    )),
+                }
+                Line::from(spans)
+            })
+            .collect()
+    };
+    f.render_widget(Paragraph::new(lines), popup_chunks[0]);
+
+    let plural = |n: usize, word: &str| {
+        if n == 1 {
+            format!("{} {}", n, word)
+        } else {
+            format!("{} {}s", n, word)
+        }
+    };
+    let summary = if counting {
+        format!(
+            " Counting lines: {} of {} files...",
+            diffstat.files.len(),
+            diffstat.total
+        )
+    } else {
+        format!(
+            " {} changed, {}(+), {}(-)",
+            plural(diffstat.files.len(), "file"),
+            plural(totals.1, "insertion"),
+            plural(totals.0, "deletion")
+        )
+    };
+    f.render_widget(
+        Paragraph::new(summary).style(Style::default().add_modifier(Modifier::BOLD)),
+        popup_chunks[1],
+    );
+
+    let key = |key: &'static str| {
+        Span::styled(
+            key,
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )
+    };
+    let buttons = Paragraph::new(Line::from(vec![
+        key("↑/↓"),
+        Span::raw(" - Scroll  "),
+        key("Esc"),
+        Span::raw(if counting { " - Stop" } else { " - Close" }),
+    ]))
+    .alignment(Alignment::Center);
+    f.render_widget(buttons, popup_chunks[2]);
+}
+
+fn draw_timestamp_confirm_popup(f: &mut Frame, app: &App) {
+    let Some(info) = &app.timestamp_info else {
+        return;
+    };
+    let popup_area = centered_rect(50, 50, f.area());
+
+    f.render_widget(Clear, popup_area);
+
+    let popup_block = Block::default()
+        .title(" Equalize timestamps ")
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(2),
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    let path = Paragraph::new(truncate_path(
+        &escape_name(&*info.path),
+        popup_inner.width as usize,
+    ))
+    .alignment(Alignment::Center)
+    .style(Style::default().fg(Color::White));
+    f.render_widget(path, popup_chunks[0]);
+
+    // Down to the millisecond, since drift is often below a second
+    let exact_time = |time: Option<std::time::SystemTime>| {
+        time.map(|time| {
+            chrono::DateTime::<chrono::Local>::from(time)
+                .format("%Y-%m-%d %H:%M:%S%.3f")
+                .to_string()
+        })
+        .unwrap_or_else(|| "unknown".to_string())
+    };
+    let times = Paragraph::new(vec![
+        Line::from(vec![
+            Span::styled("Left:  ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                exact_time(info.left_modified),
+                Style::default().fg(Color::Cyan),
+            ),
+        ]),
+        Line::from(vec![
+            Span::styled("Right: ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                exact_time(info.right_modified),
+                Style::default().fg(Color::Yellow),
+            ),
+        ]),
+    ])
+    .alignment(Alignment::Center);
+    f.render_widget(times, popup_chunks[2]);
+
+    let choices: Vec<Line> = TimestampSync::ALL
+        .iter()
+        .map(|choice| {
+            let style = if *choice == info.choice {
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            Line::from(vec![Span::styled(format!(" {} ", choice.label()), style)])
+        })
+        .collect();
+    f.render_widget(
+        Paragraph::new(choices).alignment(Alignment::Center),
+        popup_chunks[4],
+    );
+
+    draw_copy_buttons(f, popup_chunks[5]);
+}
+
+fn draw_permissions_confirm_popup(f: &mut Frame, app: &App) {
+    let Some(info) = &app.permissions_info else {
+        return;
+    };
+    let popup_area = centered_rect(50, 50, f.area());
+
+    f.render_widget(Clear, popup_area);
+
+    let popup_block = Block::default()
+        .title(" Equalize permissions ")
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(2),
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    let path = Paragraph::new(truncate_path(
+        &escape_name(&*info.path),
+        popup_inner.width as usize,
+    ))
+    .alignment(Alignment::Center)
+    .style(Style::default().fg(Color::White));
+    f.render_widget(path, popup_chunks[0]);
+
+    let modes = Paragraph::new(vec![
+        Line::from(vec![
+            Span::styled("Left:  ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format_permissions(info.left_mode),
+                Style::default().fg(Color::Cyan),
+            ),
+        ]),
+        Line::from(vec![
+            Span::styled("Right: ", Style::default().fg(Color::Gray)),
+            Span::styled(
+                format_permissions(info.right_mode),
+                Style::default().fg(Color::Yellow),
+            ),
+        ]),
+    ])
+    .alignment(Alignment::Center);
+    f.render_widget(modes, popup_chunks[2]);
+
+    f.render_widget(
+        Paragraph::new(permissions_choices(info)).alignment(Alignment::Center),
+        popup_chunks[4],
+    );
+
+    draw_copy_buttons(f, popup_chunks[5]);
+}
+
+fn permissions_choices(info: &PermissionsInfo) -> Vec<Line<'static>> {
+    // Folders are applied to everything inside that exists on both sides
+    let scope = if info.is_dir { " (recursive)" } else { "" };
+    [true, false]
+        .into_iter()
+        .map(|to_right| {
+            let label = if to_right {
+                "Left permissions to right"
+            } else {
+                "Right permissions to left"
+            };
+            let style = if to_right == info.to_right {
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().fg(Color::Gray)
+            };
+            Line::from(vec![Span::styled(format!(" {}{} ", label, scope), style)])
+        })
+        .collect()
+}
+
+fn draw_name_input_popup(f: &mut Frame, app: &App) {
+    let Some(info) = &app.name_input else {
+        return;
+    };
+    let popup_area = centered_rect(50, 50, f.area());
+
+    f.render_widget(Clear, popup_area);
+
+    let side = if info.is_left { "left" } else { "right" };
+    let title = match info.action {
+        NameAction::CreateFolder => format!(" New folder ({}) ", side),
+        NameAction::Rename => format!(" Rename ({}) ", side),
+    };
+    let popup_block = Block::default()
+        .title(title)
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+
+    let popup_inner = popup_block.inner(popup_area);
+    f.render_widget(popup_block, popup_area);
+
+    let popup_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .split(popup_inner);
+
+    let location = if info.parent.as_os_str().is_empty() {
+        "/".to_string()
+    } else {
+        format!("/{}", escape_name(&info.parent))
+    };
+    let location = Paragraph::new(Line::from(vec![
+        Span::styled("In: ", Style::default().fg(Color::Gray)),
+        Span::styled(
+            truncate_path(&location, (popup_inner.width as usize).saturating_sub(4)),
+            Style::default().fg(Color::White),
+        ),
+    ]))
+    .alignment(Alignment::Center);
+    f.render_widget(location, popup_chunks[0]);
+
+    let input = Paragraph::new(Line::from(vec![Span::styled(
+        format!("{}{}", info.input, icons().cursor),
+        Style::default().fg(Color::Yellow),
+    )]))
+    .alignment(Alignment::Center);
+    f.render_widget(input, popup_chunks[2]);
+
+    if let Some(error) = &info.error {
+        let error = Paragraph::new(error.as_str())
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::Red));
+        f.render_widget(error, popup_chunks[3]);
+    }
+
+    draw_copy_buttons(f, popup_chunks[5]);
+}
+
+/// The folders of a zoomed-in view after the root, for the panel titles.
+fn zoom_breadcrumb(app: &App) -> String {
+    let Some(zoom) = &app.zoom else {
+        return String::new();
+    };
+    let mut breadcrumb: String = zoom
+        .iter()
+        .map(|name| format!(" › {}", escape_name(name)))
+        .collect();
+    breadcrumb.push_str(" (Z: up)");
+    breadcrumb
+}
+
+/// Panel title suffix while the panels scroll independently.
+fn lock_marker(app: &App) -> &'static str {
+    if app.panels_locked {
+        ""
+    } else {
+        " [unlocked]"
+    }
+}
+
+/// The `:` prompt over the bottom row, with the error of the last command.
+fn draw_command_line(f: &mut Frame, app: &App) {
+    let Some(line) = &app.command_line else {
+        return;
+    };
+    let area = f.area();
+    let row = Rect::new(area.x, area.bottom().saturating_sub(1), area.width, 1);
+    f.render_widget(Clear, row);
+
+    let mut spans = vec![
+        Span::styled(":", Style::default().fg(Color::Cyan)),
+        Span::styled(
+            format!("{}{}", line.input, icons().cursor),
+            Style::default().fg(Color::Yellow),
+        ),
+    ];
+    if let Some(error) = &line.error {
+        spans.push(Span::styled(
+            format!("  {}", error),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    f.render_widget(Paragraph::new(Line::from(spans)), row);
+}
+
+/// The message flashed by keys like `f`, over the bottom row.
+fn draw_flash(f: &mut Frame, app: &App) {
+    let Some((message, _)) = &app.flash else {
+        return;
+    };
+    let area = f.area();
+    let row = Rect::new(area.x, area.bottom().saturating_sub(1), area.width, 1);
+    f.render_widget(Clear, row);
+    f.render_widget(
+        Paragraph::new(Span::styled(
+            message.as_str(),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        )),
+        row,
+    );
+}
+
+fn draw_file_view(f: &mut Frame, app: &mut App) {
+    let paragraph = Paragraph::new(app.file_diff.as_str()).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .title("File Diff (Press Enter to go back)"),
+    );
+
+    f.render_widget(paragraph, f.area());
+}
+
+pub fn draw_picker<B: Backend>(terminal: &mut Terminal<B>, picker: &mut Picker) -> anyhow::Result<()> {
+    terminal.draw(|f| {
+        draw_picker_view(f, picker);
+        if is_no_color() {
+            strip_colors(f.buffer_mut());
+        }
+    })?;
+    Ok(())
+}
+
+fn draw_picker_view(f: &mut Frame, picker: &mut Picker) {
+    let area = centered_rect(80, 70, f.area());
+
+    let block = Block::default()
+        .title(format!(" {} ", labeled(icons().picker, "tudiff - Recent comparisons")))
+        .title_style(
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        )
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(3),
+            Constraint::Length(4),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .split(inner);
+
+    let max_path_width = (inner.width.saturating_sub(8) / 2) as usize;
+    let mut rows: Vec<ListItem> = picker
+        .recent
+        .iter()
+        .map(|(left, right)| {
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    truncate_path(&escape_name(left), max_path_width),
+                    Style::default().fg(Color::Cyan),
+                ),
+                Span::styled(format!("  {}  ", icons().pair_separator), Style::default().fg(Color::Gray)),
+                Span::styled(
+                    truncate_path(&escape_name(right), max_path_width),
+                    Style::default().fg(Color::Yellow),
+                ),
+            ]))
+        })
+        .collect();
+    rows.push(ListItem::new(Line::from(Span::styled(
+        labeled(icons().new_paths, "Enter new paths..."),
+        Style::default().fg(Color::Green),
+    ))));
+
+    let list = List::new(rows).highlight_style(
+        Style::default()
+            .bg(Color::Rgb(60, 60, 80))
+            .fg(Color::White)
+            .add_modifier(Modifier::BOLD),
+    );
+    if picker.mode == PickerMode::Recent {
+        f.render_stateful_widget(list, chunks[0], &mut picker.list_state);
+    } else {
+        f.render_widget(list, chunks[0]);
+    }
+
+    if picker.mode == PickerMode::Input {
+        let field = |label: &'static str, index: usize| {
+            let active = picker.active_input == index;
+            let cursor = if active { icons().cursor } else { "" };
+            Line::from(vec![
+                Span::styled(
+                    label,
+                    Style::default().fg(Color::White).add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    format!("{}{}", picker.inputs[index], cursor),
+                    if active {
+                        Style::default().fg(Color::Yellow)
+                    } else {
+                        Style::default().fg(Color::Gray)
+                    },
+                ),
+            ])
+        };
+        let inputs = Paragraph::new(vec![field("Left:  ", 0), field("Right: ", 1)]).block(
+            Block::default()
+                .borders(Borders::TOP)
+                .border_style(Style::default().fg(Color::DarkGray)),
+        );
+        f.render_widget(inputs, chunks[1]);
+    }
+
+    if let Some(error) = &picker.error {
+        let error = Paragraph::new(error.as_str())
+            .alignment(Alignment::Center)
+            .style(Style::default().fg(Color::Red));
+        f.render_widget(error, chunks[2]);
+    }
+
+    let help = match picker.mode {
+        PickerMode::Recent => "Enter - Open │ n - New paths │ e - Edit │ q/Esc - Quit",
+        PickerMode::Input => "Tab - Switch field │ Enter - Next/Open │ Esc - Back",
+    };
+    let help = Paragraph::new(help)
+        .alignment(Alignment::Center)
+        .style(Style::default().fg(Color::Gray));
+    f.render_widget(help, chunks[3]);
 }
 
 pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -21,6 +21,111 @@ pub fn format_file_size(size: Option<u64>) -> String {
     }
 }
 
+/// Exact byte count with thousands separators, e.g. `4,097B`.
+pub fn format_exact_size(size: Option<u64>) -> String {
+    match size {
+        None => "     ".to_string(),
+        Some(size) => {
+            let digits = size.to_string();
+            let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 + 1);
+            for (i, digit) in digits.chars().enumerate() {
+                if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                    grouped.push(',');
+                }
+                grouped.push(digit);
+            }
+            format!("{:>4}B", grouped)
+        }
+    }
+}
+
+/// A size like `1500`, `10K`, `1.5M` or `2G` in bytes, with a `B`, `K`,
+/// `M` or `G` suffix (also `KB`, `KiB` and so on, all powers of 1024), or
+/// none for `default_unit` bytes. None if it isn't one.
+pub fn parse_size(value: &str, default_unit: u64) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(at) => value.split_at(at),
+        None => (value, ""),
+    };
+    let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" => default_unit,
+        "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => return None,
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+    Some((number * multiplier as f64) as u64)
+}
+
+/// `ls -l` style permission string, e.g. `rwxr-xr-x`, including setuid,
+/// setgid and sticky bits.
+pub fn format_permissions(mode: Option<u32>) -> String {
+    let Some(mode) = mode else {
+        return " ".repeat(9);
+    };
+    let bit = |mask: u32, c: char| if mode & mask != 0 { c } else { '-' };
+    let special = |exec: u32, special: u32, set: char| {
+        let (exec, special) = (mode & exec != 0, mode & special != 0);
+        match (exec, special) {
+            (true, true) => set,
+            (false, true) => set.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        }
+    };
+    [
+        bit(0o400, 'r'),
+        bit(0o200, 'w'),
+        special(0o100, 0o4000, 's'),
+        bit(0o040, 'r'),
+        bit(0o020, 'w'),
+        special(0o010, 0o2000, 's'),
+        bit(0o004, 'r'),
+        bit(0o002, 'w'),
+        special(0o001, 0o1000, 't'),
+    ]
+    .iter()
+    .collect()
+}
+
+/// `user:group` for the ids. Names come from a per-thread cache of the user
+/// database; ids without an entry stay numeric.
+pub fn format_owner(owner: Option<(u32, u32)>) -> String {
+    let Some((uid, gid)) = owner else {
+        return String::new();
+    };
+
+    #[cfg(unix)]
+    {
+        use users::{Groups, Users, UsersCache};
+
+        thread_local! {
+            static USERS: UsersCache = UsersCache::new();
+        }
+        USERS.with(|cache| {
+            let user = cache
+                .get_user_by_uid(uid)
+                .map(|user| user.name().to_string_lossy().into_owned())
+                .unwrap_or_else(|| uid.to_string());
+            let group = cache
+                .get_group_by_gid(gid)
+                .map(|group| group.name().to_string_lossy().into_owned())
+                .unwrap_or_else(|| gid.to_string());
+            format!("{}:{}", user, group)
+        })
+    }
+    #[cfg(not(unix))]
+    {
+        format!("{}:{}", uid, gid)
+    }
+}
+
 pub fn format_modified_time(time: Option<SystemTime>) -> String {
     match time {
         None => "           ".to_string(),
@@ -101,7 +206,7 @@ pub fn format_modified_time(time: Option<SystemTime>) -> String {
 }
 
 fn is_leap_year(year: u64) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
 }
 
 fn get_local_timezone_offset() -> i64 {
@@ -129,8 +234,14 @@ fn get_local_timezone_offset() -> i64 {
     })
 }
 
+/// Shorten `path` to `max_width` terminal columns by cutting out its
+/// middle; wide characters take two.
 pub fn truncate_path(path: &str, max_width: usize) -> String {
-    if path.len() <= max_width {
+    use ratatui::style::Style;
+    use ratatui::text::Span;
+
+    let width = |text: &str| Span::raw(text).width();
+    if width(path) <= max_width {
         return path.to_string();
     }
 
@@ -138,17 +249,82 @@ pub fn truncate_path(path: &str, max_width: usize) -> String {
         return "...".to_string();
     }
 
-    let start_len = (max_width - 3) / 2;
-    let end_len = max_width - 3 - start_len;
+    // Cut between graphemes, a byte offset could land inside one
+    let span = Span::raw(path);
+    let graphemes: Vec<&str> = span
+        .styled_graphemes(Style::default())
+        .map(|grapheme| grapheme.symbol)
+        .collect();
+    let fit = |budget: usize, graphemes: &mut dyn Iterator<Item = &&str>| {
+        let (mut count, mut used) = (0, 0);
+        for grapheme in graphemes {
+            let grapheme_width = width(grapheme);
+            if used + grapheme_width > budget {
+                break;
+            }
+            used += grapheme_width;
+            count += 1;
+        }
+        (count, used)
+    };
+    let start_budget = (max_width - 3) / 2;
+    let (start_len, start_used) = fit(start_budget, &mut graphemes.iter());
+    // Columns a wide character at the cut left over go to the end
+    let end_budget = max_width - 3 - start_used;
+    let (end_len, _) = fit(end_budget, &mut graphemes[start_len..].iter().rev());
+    format!(
+        "{}...{}",
+        graphemes[..start_len].concat(),
+        graphemes[graphemes.len() - end_len..].concat()
+    )
+}
 
-    format!("{}...{}", &path[..start_len], &path[path.len() - end_len..])
+/// A file name or path as shown to the user. Control characters such as
+/// newlines and tabs, bidirectional overrides and bytes that aren't UTF-8
+/// are escaped (`\n`, `\t`, `\u{202e}`, `\xff`), so a name can neither
+/// break the layout nor pass for another one.
+pub fn escape_name(name: impl AsRef<std::ffi::OsStr>) -> String {
+    let bytes = name.as_ref().as_encoded_bytes();
+    let mut escaped = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\n' | '\r' | '\t' => escaped.extend(c.escape_default()),
+                // C0 and C1 controls all fit in two hex digits
+                c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+                '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}' => {
+                    escaped.extend(c.escape_unicode())
+                }
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
 }
 
-use std::sync::atomic::{AtomicBool, Ordering};
+/// The path whose raw bytes are `bytes`, as `as_encoded_bytes` gives them.
+/// Elsewhere than on Unix, bytes that aren't UTF-8 are replaced.
+pub fn path_from_bytes(bytes: &[u8]) -> std::path::PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        std::path::PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        std::path::PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::sync::Mutex;
 use std::io::Write;
 
 static LOGGING_ENABLED: AtomicBool = AtomicBool::new(false);
+static PROGRESS_MODE: AtomicU8 = AtomicU8::new(ProgressMode::Dots as u8);
 static LOG_FILE: Mutex<Option<std::fs::File>> = Mutex::new(None);
 
 pub fn init_logging(verbose: bool) {
@@ -200,3 +376,191 @@ fn log_with_level(level: &str, message: &str) {
         }
     }
 }
+
+/// Directory for persistent state such as the comparison history.
+pub fn state_dir() -> Option<std::path::PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+        return Some(std::path::PathBuf::from(dir).join("tudiff"));
+    }
+    std::env::var_os("HOME").map(|home| {
+        std::path::PathBuf::from(home)
+            .join(".local")
+            .join("state")
+            .join("tudiff")
+    })
+}
+
+/// File in `state_dir()/<kind>/` named after a hash of two roots, for state
+/// kept per compared pair.
+pub fn pair_state_file(
+    kind: &str,
+    left: &std::path::Path,
+    right: &std::path::Path,
+) -> Option<std::path::PathBuf> {
+    use sha2::{Digest, Sha256};
+
+    let left = left.canonicalize().unwrap_or_else(|_| left.to_path_buf());
+    let right = right.canonicalize().unwrap_or_else(|_| right.to_path_buf());
+    let mut hasher = Sha256::new();
+    hasher.update(left.to_string_lossy().as_bytes());
+    hasher.update([0]);
+    hasher.update(right.to_string_lossy().as_bytes());
+    let name: String = hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    state_dir().map(|dir| dir.join(kind).join(name))
+}
+
+/// Directory for user configuration.
+pub fn config_dir() -> Option<std::path::PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|d| !d.is_empty()) {
+        return Some(std::path::PathBuf::from(dir).join("tudiff"));
+    }
+    std::env::var_os("HOME").map(|home| {
+        std::path::PathBuf::from(home)
+            .join(".config")
+            .join("tudiff")
+    })
+}
+
+/// How scan progress is reported on stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Dots and status lines for humans
+    Dots,
+    /// Nothing at all
+    Quiet,
+    /// One JSON object per line for wrapper UIs
+    Json,
+}
+
+pub fn set_progress_mode(mode: ProgressMode) {
+    PROGRESS_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+pub fn progress_mode() -> ProgressMode {
+    match PROGRESS_MODE.load(Ordering::Relaxed) {
+        x if x == ProgressMode::Quiet as u8 => ProgressMode::Quiet,
+        x if x == ProgressMode::Json as u8 => ProgressMode::Json,
+        _ => ProgressMode::Dots,
+    }
+}
+
+/// Quote a string as a JSON string literal.
+pub fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A background thread that can be asked to stop early, so quitting
+/// doesn't leave it writing after the terminal is restored.
+pub struct Worker {
+    cancel: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl Worker {
+    /// Run `work` on a new thread. It gets the cancel flag and should return
+    /// soon after it is set.
+    pub fn spawn<F>(work: F) -> Self
+    where
+        F: FnOnce(std::sync::Arc<std::sync::atomic::AtomicBool>) + Send + 'static,
+    {
+        let cancel = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = cancel.clone();
+        Self {
+            cancel,
+            thread: std::thread::spawn(move || work(flag)),
+        }
+    }
+
+    /// Ask the thread to stop and wait until it has.
+    pub fn cancel(self) {
+        self.cancel
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+/// Cleanups not yet run, in the order they were registered.
+type PendingCleanups = Vec<(usize, Box<dyn FnOnce() + Send>)>;
+
+static EXIT_CLEANUPS: Mutex<PendingCleanups> = Mutex::new(Vec::new());
+static NEXT_CLEANUP: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Undoing something outside the process, like creating a temporary
+/// directory. It runs when this is dropped, or, if tudiff is interrupted,
+/// terminated or hung up on before that, from a signal handler.
+pub struct ExitCleanup(usize);
+
+impl ExitCleanup {
+    pub fn new(cleanup: impl FnOnce() + Send + 'static) -> Self {
+        install_cleanup_handler();
+        let id = NEXT_CLEANUP.fetch_add(1, Ordering::Relaxed);
+        pending_cleanups().push((id, Box::new(cleanup)));
+        Self(id)
+    }
+}
+
+impl Drop for ExitCleanup {
+    fn drop(&mut self) {
+        let cleanup = {
+            let mut pending = pending_cleanups();
+            let index = pending.iter().position(|(id, _)| *id == self.0);
+            index.map(|index| pending.remove(index).1)
+        };
+        if let Some(cleanup) = cleanup {
+            cleanup();
+        }
+    }
+}
+
+fn pending_cleanups() -> std::sync::MutexGuard<'static, PendingCleanups> {
+    EXIT_CLEANUPS
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// On the first SIGINT, SIGTERM or SIGHUP, run the pending cleanups (newest
+/// first, like drops) and then end the process as the signal would have.
+#[cfg(unix)]
+fn install_cleanup_handler() {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+
+    static INSTALLED: std::sync::Once = std::sync::Once::new();
+    INSTALLED.call_once(|| {
+        let mut signals = match signal_hook::iterator::Signals::new([SIGINT, SIGTERM, SIGHUP]) {
+            Ok(signals) => signals,
+            Err(e) => {
+                log_error(&format!("Failed to handle signals for cleanup: {}", e));
+                return;
+            }
+        };
+        std::thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                let pending = std::mem::take(&mut *pending_cleanups());
+                for (_, cleanup) in pending.into_iter().rev() {
+                    cleanup();
+                }
+                let _ = signal_hook::low_level::emulate_default_handler(signal);
+            }
+        });
+    });
+}
+
+#[cfg(not(unix))]
+fn install_cleanup_handler() {}
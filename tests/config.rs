@@ -0,0 +1,24 @@
+use ratatui::style::Color;
+use std::path::Path;
+use tudiff::config::Config;
+
+#[test]
+fn hash_inside_values_is_not_a_comment() {
+    let config = Config::parse(
+        "# colors\n\
+         [highlight]\n\
+         inactive = on #3c3c50 # dimmer than selected\n\
+         [normalize]\n\
+         conf = sed 's/#.*//' # drop comments\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.highlight.inactive.bg,
+        Some(Color::Rgb(0x3c, 0x3c, 0x50))
+    );
+    assert_eq!(
+        config.normalize.command_for(Path::new("app.conf")),
+        Some("sed 's/#.*//'")
+    );
+}
@@ -0,0 +1,180 @@
+use crossterm::event::KeyCode;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tudiff::{AppMode, DirectoryComparison, FileStatus, Headless};
+
+/// A left and a right folder under a fresh temporary directory, removed
+/// again when dropped.
+struct Fixture {
+    root: PathBuf,
+}
+
+impl Fixture {
+    fn new(name: &str) -> Self {
+        let root =
+            std::env::temp_dir().join(format!("tudiff-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("left")).unwrap();
+        fs::create_dir_all(root.join("right")).unwrap();
+        Self { root }
+    }
+
+    /// Write `contents` to `path` below the left or right folder.
+    fn file(&self, left: bool, path: &str, contents: &str) -> &Self {
+        let path = self.side(left).join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+        self
+    }
+
+    fn side(&self, left: bool) -> PathBuf {
+        self.root.join(if left { "left" } else { "right" })
+    }
+
+    fn open(&self) -> Headless {
+        let comparison =
+            DirectoryComparison::new_silent(self.side(true), self.side(false)).unwrap();
+        Headless::new(comparison, 120, 30).unwrap()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn rows(headless: &Headless, left: bool) -> Vec<(String, FileStatus)> {
+    headless
+        .items(left)
+        .iter()
+        .filter(|item| !item.path.as_os_str().is_empty())
+        .map(|item| (item.path.to_string_lossy().into_owned(), item.status))
+        .collect()
+}
+
+fn path_of(headless: &Headless) -> &Path {
+    &headless.selected().unwrap().path
+}
+
+#[test]
+fn lists_both_sides_with_their_status() {
+    let fixture = Fixture::new("status");
+    fixture
+        .file(true, "same.txt", "a")
+        .file(false, "same.txt", "a")
+        .file(true, "changed.txt", "old")
+        .file(false, "changed.txt", "new")
+        .file(true, "left.txt", "only here");
+    let headless = fixture.open();
+
+    let left = rows(&headless, true);
+    assert!(left.contains(&("same.txt".to_string(), FileStatus::Same)));
+    assert!(left.contains(&("changed.txt".to_string(), FileStatus::Different)));
+    assert!(left.contains(&("left.txt".to_string(), FileStatus::LeftOnly)));
+    // The right panel keeps a placeholder row in line with the left-only file
+    assert_eq!(headless.items(false).len(), headless.items(true).len());
+
+    let screen = headless.screen();
+    assert!(screen.contains("changed.txt"));
+    assert!(screen.contains("left.txt"));
+}
+
+#[test]
+fn keys_move_the_selection_and_open_folders() {
+    let fixture = Fixture::new("keys");
+    fixture
+        .file(true, "dir/inner.txt", "a")
+        .file(false, "dir/inner.txt", "b")
+        .file(true, "z.txt", "a")
+        .file(false, "z.txt", "a");
+    let mut headless = fixture.open();
+
+    assert_eq!(path_of(&headless), Path::new("dir"));
+    let before = headless.items(true).len();
+    headless.press(KeyCode::Enter).unwrap();
+    assert_ne!(
+        headless.items(true).len(),
+        before,
+        "Enter toggles the folder"
+    );
+    headless.press(KeyCode::Enter).unwrap();
+    assert_eq!(headless.items(true).len(), before);
+
+    headless.press(KeyCode::Down).unwrap();
+    assert_eq!(path_of(&headless), Path::new("z.txt"));
+    headless.press(KeyCode::Char('k')).unwrap();
+    assert_eq!(path_of(&headless), Path::new("dir"));
+}
+
+#[test]
+fn copying_a_file_makes_both_sides_the_same() {
+    let fixture = Fixture::new("copy");
+    fixture
+        .file(true, "a.txt", "new contents")
+        .file(false, "a.txt", "old");
+    let mut headless = fixture.open();
+
+    assert_eq!(headless.selected().unwrap().status, FileStatus::Different);
+    headless.press(KeyCode::Char('>')).unwrap();
+    assert!(headless.app.mode == AppMode::CopyConfirm);
+    headless.press(KeyCode::Enter).unwrap();
+    assert!(headless.settle(Duration::from_secs(10)).unwrap());
+
+    assert_eq!(
+        fs::read_to_string(fixture.side(false).join("a.txt")).unwrap(),
+        "new contents"
+    );
+    assert_eq!(headless.selected().unwrap().status, FileStatus::Same);
+}
+
+#[test]
+fn escape_cancels_a_copy() {
+    let fixture = Fixture::new("cancel");
+    fixture
+        .file(true, "a.txt", "new")
+        .file(false, "a.txt", "old");
+    let mut headless = fixture.open();
+
+    headless.press(KeyCode::Char('>')).unwrap();
+    let quit = headless.press(KeyCode::Esc).unwrap();
+    assert!(!quit);
+    assert!(headless.app.mode == AppMode::DirectoryView);
+    assert_eq!(
+        fs::read_to_string(fixture.side(false).join("a.txt")).unwrap(),
+        "old"
+    );
+}
+
+#[test]
+fn clicking_a_row_selects_it() {
+    let fixture = Fixture::new("click");
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fixture.file(true, name, name).file(false, name, name);
+    }
+    let mut headless = fixture.open();
+
+    let row = headless
+        .screen()
+        .lines()
+        .position(|line| line.contains("c.txt"))
+        .unwrap();
+    let column = headless
+        .screen()
+        .lines()
+        .nth(row)
+        .unwrap()
+        .find("c.txt")
+        .unwrap();
+    headless.click(column as u16, row as u16).unwrap();
+    assert_eq!(path_of(&headless), Path::new("c.txt"));
+}
+
+#[test]
+fn q_quits() {
+    let fixture = Fixture::new("quit");
+    fixture.file(true, "a.txt", "a");
+    let mut headless = fixture.open();
+    assert!(headless.press(KeyCode::Char('q')).unwrap());
+}